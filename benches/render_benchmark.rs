@@ -21,7 +21,7 @@ fn bench(c: &mut Criterion) {
         scene_builder.exec_stmt(stmt).unwrap();
     }
 
-    let scene = scene_builder.create_scene();
+    let scene = scene_builder.create_scene().unwrap();
     let camera = header.make_camera().unwrap();
 
     let mut sampler = RandomSampler::new_with_seed(512, 1);
@@ -1,5 +1,5 @@
 
-use raytracer::{mipmap::ImageWrap, imageio::ImageTexInfo, Float, Point2f};
+use raytracer::{mipmap::ImageWrap, imageio::{ImageTexInfo, ColorSpace}, Float, Point2f};
 use raytracer::imageio;
 use raytracer::imageio::{spectrum_to_image, load_image};
 use std::path::{PathBuf, Path};
@@ -8,8 +8,8 @@ use raytracer::spectrum::Spectrum;
 fn main() -> anyhow::Result<()> {
     let path = std::env::args().nth(1).unwrap();
     let fname = Path::new(&path).file_stem().unwrap().to_str().unwrap();
-    let info = ImageTexInfo::new(path.clone(), ImageWrap::Repeat, 1.0, Some(true), false);
-    let mipmap = imageio::get_mipmap(info)?;
+    let info = ImageTexInfo::new(path.clone(), ImageWrap::Repeat, 1.0, Some(ColorSpace::Srgb), false);
+    let mipmap = imageio::get_mipmap(info, &imageio::DecodedImages::new())?;
 
     for blocked_img in mipmap.pyramid() {
         let dims = blocked_img.dimensions();
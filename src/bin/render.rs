@@ -1,16 +1,14 @@
 use std::error::Error;
 use std::env::args;
 use raytracer::loaders::pbrt::{PbrtHeader, PbrtSceneBuilder};
-use raytracer::integrator::SamplerIntegrator;
+use raytracer::integrator::{SamplerIntegrator, TileOrder};
 use raytracer::integrator::direct_lighting::{DirectLightingIntegrator, LightStrategy};
-use std::fs::File;
-use raytracer::imageio::exr::write_exr;
 use raytracer::integrator::whitted::WhittedIntegrator;
 use raytracer::integrator::path::PathIntegrator;
 use std::path::PathBuf;
 
 use clap::Clap;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use tracing_subscriber::{layer::SubscriberExt, Layer};
 
@@ -26,7 +24,16 @@ struct Opts {
     image_name: Option<String>,
 
     #[clap(long = "samples")]
-    samples: Option<usize>
+    samples: Option<usize>,
+
+    /// Periodically save the raw film to this path during rendering
+    #[clap(long = "checkpoint")]
+    checkpoint: Option<PathBuf>,
+
+    /// Minimum seconds between film checkpoints. Checkpoints are written as tiles finish, so they
+    /// can be later than this for slow tiles.
+    #[clap(long = "checkpoint-interval", default_value = "60")]
+    checkpoint_interval: u64,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -42,22 +49,22 @@ fn main() -> anyhow::Result<()> {
 
     let parsed = pbrt_parser::PbrtParser::parse_with_includes(&opts.scene_file)?;
 
-    let mut header = PbrtHeader::new();
+    let mut header = PbrtHeader::new().with_base_path(base_path.clone());
     for stmt in parsed.header {
         header.exec_stmt(stmt)?;
     }
     let filename = opts.image_name
         .or_else(|| header.film_params.get_one("filename").ok())
         .unwrap_or("render.exr".to_string());
-    assert!(filename.contains(".exr"));
 
 
-    let mut scene_builder = PbrtSceneBuilder::new(base_path);
+    let mut scene_builder = PbrtSceneBuilder::new(base_path)
+        .with_accelerator(std::mem::take(&mut header.accelerator_params));
     for stmt in parsed.world {
         scene_builder.exec_stmt(stmt)?;
     }
 
-    let scene = scene_builder.create_scene();
+    let scene = scene_builder.create_scene()?;
 
     let camera = header.make_camera()?;
     let sampler = header.make_sampler(opts.samples)?;
@@ -68,12 +75,9 @@ fn main() -> anyhow::Result<()> {
         // radiance: WhittedIntegrator {
         //     max_depth: 4
         // }
-        // radiance: DirectLightingIntegrator {
-        //     strategy: LightStrategy::UniformSampleOne,
-        //     max_depth: 4,
-        //     n_light_samples: vec![],
-        // }
-        radiance: PathIntegrator::new(5, 1.0)
+        // radiance: DirectLightingIntegrator::new(LightStrategy::UniformSampleOne, 4),
+        radiance: PathIntegrator::from_params(&mut header.integrator_params)?,
+        tile_order: TileOrder::Morton,
     };
 
     dbg!(&scene);
@@ -83,14 +87,14 @@ fn main() -> anyhow::Result<()> {
         let pool = rayon::ThreadPoolBuilder::new()
             .num_threads(opts.threads)
             .build()?;
-        integrator.render_with_pool(&scene, &film, sampler, &pool);
+        let checkpoint = opts.checkpoint
+            .map(|path| (Duration::from_secs(opts.checkpoint_interval), path));
+        integrator.render_with_pool(&scene, &film, sampler, &pool, checkpoint);
     } else {
         integrator.render(&scene, &film, sampler);
     }
     tracing::info!("Completed rendering in {} s", start.elapsed().as_secs_f64());
 
-    let (img, (w, h)) = film.into_spectrum_buffer();
-    let mut file = File::create(filename)?;
-    write_exr(&mut file, img, (w, h))?;
+    film.save(filename)?;
     Ok(())
 }
\ No newline at end of file
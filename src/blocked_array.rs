@@ -65,6 +65,17 @@ impl<T: Copy, const LOG_BLOCK_SIZE: usize> BlockedArray<T, {LOG_BLOCK_SIZE}> {
         (Self::BLOCK_LEN * block) + (offset_v * Self::BLOCK_SIZE + offset_u)
     }
 
+    /// Get the index of an element of this array. Elements past the end of a row or column can
+    /// still fall inside the padding of the last block, which is uninitialized, so this is
+    /// checked in debug builds.
+    fn element_index(&self, u: usize, v: usize) -> usize {
+        debug_assert!(
+            u < self.u_size && v < self.v_size,
+            "BlockedArray index {:?} out of bounds for size {:?}", (u, v), (self.u_size, self.v_size)
+        );
+        Self::get_index(u, v, self.u_blocks)
+    }
+
     pub fn dimensions(&self) -> (usize, usize) {
         (self.u_size, self.v_size)
     }
@@ -127,7 +138,7 @@ impl<T: Copy, const LOG_BLOCK_SIZE: usize> Index<(usize, usize)> for BlockedArra
 
     fn index(&self, index: (usize, usize)) -> &Self::Output {
         let (u, v) = index;
-        let idx = Self::get_index(u, v, self.u_blocks);
+        let idx = self.element_index(u, v);
         unsafe { self.contents[idx].assume_init_ref() }
     }
 }
@@ -135,7 +146,7 @@ impl<T: Copy, const LOG_BLOCK_SIZE: usize> Index<(usize, usize)> for BlockedArra
 impl<T: Copy, const LOG_BLOCK_SIZE: usize> IndexMut<(usize, usize)> for BlockedArray<T, {LOG_BLOCK_SIZE}> {
     fn index_mut(&mut self, index: (usize, usize)) -> &mut Self::Output {
         let (u, v) = index;
-        let idx = Self::get_index(u, v, self.u_blocks);
+        let idx = self.element_index(u, v);
         unsafe { self.contents[idx].assume_init_mut() }
     }
 }
@@ -207,6 +218,16 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "out of bounds")]
+    fn test_out_of_bounds_panics() {
+        let data = [1, 2, 3, 4, 5].repeat(5);
+        let blocked_array = BlockedArray::with_default_block_size(&data, 5, 5);
+        // inside the padding of the last block
+        let _ = blocked_array[(5, 2)];
+    }
+
     #[test]
     fn test_round_trip() {
         let ulen = 5;
@@ -1,15 +1,15 @@
-use arrayvec::ArrayVec;
+use smallvec::SmallVec;
 use bumpalo::Bump;
 
 use partition::partition;
 
-use crate::{Ray, SurfaceInteraction};
+use crate::{Float, Ray, RayShear, SurfaceInteraction};
 use crate::geometry::bounds::Bounds3f;
 use crate::Point3f;
 use crate::primitive::Primitive;
 use std::time::Instant;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum SplitMethod {
     Middle,
     EqualCounts,
@@ -19,18 +19,42 @@ pub enum SplitMethod {
 pub struct BVH<P: AsRef<dyn Primitive> = Box<dyn Primitive>> {
     pub prims: Vec<P>,
     pub bounds: Bounds3f,
-    nodes: Vec<LinearBVHNode>
+    nodes: Vec<LinearBVHNode>,
+    split_method: SplitMethod,
 }
 
+/// Summary of a BVH's structure, e.g. for checking how it was built.
+#[derive(Clone, Copy, Debug)]
+pub struct BVHStats {
+    pub split_method: SplitMethod,
+    pub n_nodes: usize,
+    pub n_leaves: usize,
+    pub n_prims: usize,
+}
+
+/// The default maximum number of primitives stored in a single leaf node.
+pub const DEFAULT_MAX_PRIMS_IN_NODE: usize = 4;
+
 impl<P: AsRef<dyn Primitive>> BVH<P> {
+    pub fn build(prims: Vec<P>) -> Self {
+        Self::build_with_max_prims(prims, DEFAULT_MAX_PRIMS_IN_NODE)
+    }
+
+    /// Builds a BVH where any node with at most `max_prims_in_node` primitives is made a leaf
+    /// rather than being split further.
+    pub fn build_with_max_prims(prims: Vec<P>, max_prims_in_node: usize) -> Self {
+        Self::build_with_split_method(prims, SplitMethod::Middle, max_prims_in_node)
+    }
+
+    /// Builds a BVH, choosing how to partition the primitives of each node with `split_method`.
     #[tracing::instrument(skip(prims))]
-    pub fn build(mut prims: Vec<P>) -> Self {
+    pub fn build_with_split_method(mut prims: Vec<P>, split_method: SplitMethod, max_prims_in_node: usize) -> Self {
         // TODO: figure out prims type. Rc or Box?
 
         let start = Instant::now();
 
         if prims.is_empty() {
-            return BVH { prims, bounds: Bounds3f::empty(), nodes: Vec::new() }
+            return BVH { prims, bounds: Bounds3f::empty(), nodes: Vec::new(), split_method }
         }
 
         let mut prim_info: Vec<BVHPrimInfo> = prims.iter().enumerate().map(|(i, p)| {
@@ -44,7 +68,8 @@ impl<P: AsRef<dyn Primitive>> BVH<P> {
             &arena,
             &mut prim_info,
             &mut prim_ordering,
-            SplitMethod::Middle
+            split_method,
+            max_prims_in_node.max(1).min(255)
         );
 
         let world_bound = root.bounds();
@@ -59,7 +84,20 @@ impl<P: AsRef<dyn Primitive>> BVH<P> {
         BVH {
             prims,
             bounds: world_bound,
-            nodes: flat_nodes
+            nodes: flat_nodes,
+            split_method,
+        }
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        let n_leaves = self.nodes.iter()
+            .filter(|node| matches!(node.kind, LinearNodeKind::Leaf {..}))
+            .count();
+        BVHStats {
+            split_method: self.split_method,
+            n_nodes: self.nodes.len(),
+            n_leaves,
+            n_prims: self.prims.len(),
         }
     }
 
@@ -68,7 +106,8 @@ impl<P: AsRef<dyn Primitive>> BVH<P> {
         prim_info: &mut [BVHPrimInfo],
 //        range: Range<usize>,
         prim_ordering: &mut Vec<isize>,
-        split_method: SplitMethod
+        split_method: SplitMethod,
+        max_prims_in_node: usize
     ) -> &'a BVHBuildNode<'a> {
 
         // Find the union of the bounding boxes of all primitives in this node,
@@ -80,9 +119,9 @@ impl<P: AsRef<dyn Primitive>> BVH<P> {
 
         let n_prims = prim_info.len();
 
-        // If there is only one primitive or all the centroids lie on the same point
+        // If there are few enough primitives or all the centroids lie on the same point
         // (and therefore can't be partitioned), create a leaf node.
-        if n_prims == 1 || centroid_bounds.is_point() {
+        if n_prims <= max_prims_in_node || centroid_bounds.is_point() {
             let first_prim_idx = prim_ordering.len();
             for prim in prim_info {
                 prim_ordering.push(prim.prim_id as isize)
@@ -110,11 +149,18 @@ impl<P: AsRef<dyn Primitive>> BVH<P> {
             SplitMethod::EqualCounts => {
                 Self::partition_equal_counts(prim_info, ax)
             }
-            _ => unimplemented!()
+
+            SplitMethod::SAH => {
+                if n_prims <= 2 {
+                    Self::partition_equal_counts(prim_info, ax)
+                } else {
+                    Self::partition_sah(prim_info, &node_bounds, &centroid_bounds, ax)
+                }
+            }
         };
 
-        let child1 = Self::recursive_build(arena, part1, prim_ordering, split_method);
-        let child2 = Self::recursive_build(arena, part2, prim_ordering, split_method);
+        let child1 = Self::recursive_build(arena, part1, prim_ordering, split_method, max_prims_in_node);
+        let child2 = Self::recursive_build(arena, part2, prim_ordering, split_method, max_prims_in_node);
 
         arena.alloc(BVHBuildNode::new_interior([child1, child2], ax as u8))
     }
@@ -129,6 +175,59 @@ impl<P: AsRef<dyn Primitive>> BVH<P> {
         prim_info.split_at_mut(mid)
     }
 
+    /// Splits the primitives between buckets along `ax`, choosing the split with the lowest cost
+    /// estimated by the surface area heuristic.
+    fn partition_sah<'p>(
+        prim_info: &'p mut [BVHPrimInfo],
+        node_bounds: &Bounds3f,
+        centroid_bounds: &Bounds3f,
+        ax: usize,
+    ) -> (&'p mut [BVHPrimInfo], &'p mut [BVHPrimInfo]) {
+        const N_BUCKETS: usize = 12;
+        let bucket_of = |prim: &BVHPrimInfo| {
+            let b = (N_BUCKETS as Float * centroid_bounds.offset(&prim.centroid)[ax]) as usize;
+            b.min(N_BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; N_BUCKETS];
+        let mut bucket_bounds = [Bounds3f::empty(); N_BUCKETS];
+        for prim in prim_info.iter() {
+            let b = bucket_of(prim);
+            counts[b] += 1;
+            bucket_bounds[b] = bucket_bounds[b].join(&prim.bounds);
+        }
+
+        let side = |buckets: std::ops::Range<usize>| {
+            buckets.fold((Bounds3f::empty(), 0), |(bounds, count), b| {
+                (bounds.join(&bucket_bounds[b]), count + counts[b])
+            })
+        };
+
+        // the relative cost of splitting after each bucket, where traversing a node costs 1/8 as
+        // much as intersecting a primitive
+        let mut min_cost = Float::INFINITY;
+        let mut split_bucket = 0;
+        for i in 0..N_BUCKETS - 1 {
+            let (b0, count0) = side(0..i + 1);
+            let (b1, count1) = side(i + 1..N_BUCKETS);
+            if count0 == 0 || count1 == 0 {
+                continue;
+            }
+            let cost = 0.125 + (count0 as Float * b0.surface_area() + count1 as Float * b1.surface_area())
+                / node_bounds.surface_area();
+            if cost < min_cost {
+                min_cost = cost;
+                split_bucket = i;
+            }
+        }
+
+        if min_cost == Float::INFINITY {
+            // every centroid fell in the same bucket
+            return Self::partition_equal_counts(prim_info, ax);
+        }
+        partition(prim_info, |prim| bucket_of(prim) <= split_bucket)
+    }
+
     // Returns subtree length
     fn flatten_tree(flat_nodes: &mut Vec<LinearBVHNode>, node: &BVHBuildNode) -> usize {
         let subtree_len = match *node {
@@ -157,15 +256,62 @@ impl<P: AsRef<dyn Primitive>> BVH<P> {
         subtree_len
     }
 
+    /// Recomputes the node bounds from the current primitive bounds while keeping the tree
+    /// topology, which is much cheaper than rebuilding when primitives have only moved.
+    pub fn refit(&mut self) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        // Children are always stored after their parent in the flattened array, so iterating
+        // in reverse visits every node after both of its children.
+        for i in (0..self.nodes.len()).rev() {
+            let bounds = match self.nodes[i].kind {
+                LinearNodeKind::Leaf {first_prim_idx, n_prims} => {
+                    let first = first_prim_idx as usize;
+                    self.prims[first..first + n_prims as usize].iter()
+                        .fold(Bounds3f::empty(), |b, prim| b.join(&prim.as_ref().world_bound()))
+                },
+
+                LinearNodeKind::Interior {second_child_idx, ..} => {
+                    self.nodes[i + 1].bounds.join(&self.nodes[second_child_idx as usize].bounds)
+                }
+            };
+            self.nodes[i].bounds = bounds;
+        }
+
+        self.bounds = self.nodes[0].bounds;
+    }
+
     pub fn intersect(&self, ray: &mut Ray) -> Option<SurfaceInteraction> {
+        self.closest_hit(ray, |prim, ray, shear| prim.intersect_sheared(ray, shear))
+    }
+
+    /// The parametric distance along `ray` to the closest intersection, without returning the
+    /// interaction. Unlike `intersect`, the ray's `t_max` isn't changed.
+    pub fn intersect_t(&self, ray: &Ray) -> Option<Float> {
+        let mut ray = *ray;
+        self.closest_hit(&mut ray, |prim, ray, shear| prim.intersect_t(ray, shear))
+    }
+
+    /// Traverses the nodes along `ray`, calling `intersect_prim` on the primitives in each leaf it
+    /// reaches, along with the ray's shear. `intersect_prim` should shorten `t_max` on a hit, so
+    /// the last hit is the closest.
+    fn closest_hit<'a, T>(
+        &'a self,
+        ray: &mut Ray,
+        mut intersect_prim: impl FnMut(&'a dyn Primitive, &mut Ray, &RayShear) -> Option<T>,
+    ) -> Option<T> {
         if self.nodes.is_empty() {
             return None;
         }
 
         let inverse_dir = 1.0 / ray.dir;
         let dir_is_neg = [ray.dir.x < 0.0, ray.dir.y < 0.0, ray.dir.z < 0.0];
+        let shear = RayShear::new(ray);
 
-        let mut nodes_to_visit = ArrayVec::<[usize; 64]>::new();  // used as a stack
+        // used as a stack, which spills to the heap for unusually deep trees
+        let mut nodes_to_visit = SmallVec::<[usize; 64]>::new();
         let mut current_node_index = 0;
 
         let mut interaction = None;
@@ -181,7 +327,7 @@ impl<P: AsRef<dyn Primitive>> BVH<P> {
                             // sets the variable to be the new (closer, because of the ray t value)
                             // interaction if intersect is Some, or keeps the current interaction
                             // if intersect returns None.
-                            interaction = prim.as_ref().intersect(ray).or(interaction);
+                            interaction = intersect_prim(prim.as_ref(), ray, &shear).or(interaction);
                         }
 
                         if let Some(next_node) = nodes_to_visit.pop() {
@@ -220,8 +366,10 @@ impl<P: AsRef<dyn Primitive>> BVH<P> {
         }
 
         let dir_is_neg = [ray.dir.x < 0.0, ray.dir.y < 0.0, ray.dir.z < 0.0];
+        let shear = RayShear::new(ray);
 
-        let mut nodes_to_visit = ArrayVec::<[usize; 64]>::new();  // used as a stack
+        // used as a stack, which spills to the heap for unusually deep trees
+        let mut nodes_to_visit = SmallVec::<[usize; 64]>::new();
         let mut current_node_index = 0;
 
         loop {
@@ -232,7 +380,7 @@ impl<P: AsRef<dyn Primitive>> BVH<P> {
                     LinearNodeKind::Leaf {first_prim_idx, n_prims} => {
                         for i in 0..n_prims as usize {
                             let prim = &self.prims[first_prim_idx as usize + i];
-                            if prim.as_ref().intersect_test(ray) { return true; }
+                            if prim.as_ref().intersect_test_sheared(ray, &shear) { return true; }
                         }
 
                         if let Some(next_node) = nodes_to_visit.pop() {
@@ -375,7 +523,7 @@ fn apply_permutation<T>(items: &mut [T], indices: &mut [isize]) {
 
 #[cfg(test)]
 mod tests {
-    use cgmath::{Vector3};
+    use cgmath::{Vector3, EuclideanSpace, InnerSpace};
     use rand::{Rng};
     use rand::distributions::{Uniform, UnitSphereSurface};
     use rand::prelude::*;
@@ -397,6 +545,40 @@ mod tests {
         assert_eq!(items, vec!["c", "d", "a", "b", "e"])
     }
 
+    #[test]
+    fn test_sah_bvh_intersect() {
+        let mut rng = StdRng::from_seed([5; 32]);
+        let distr = Uniform::new_inclusive(-10.0, 10.0);
+        let spheres: Vec<Arc<Sphere<Transform>>> = (0..200)
+            .map(|_| {
+                let o2w = Transform::translate(Vec3f::new(rng.sample(distr), rng.sample(distr), rng.sample(distr)));
+                Arc::new(Sphere::whole(o2w, o2w.inverse(), rng.gen_range(0.2, 1.0)))
+            })
+            .collect();
+        let prims = || -> Vec<Box<dyn Primitive>> {
+            spheres.iter()
+                .map(|s| Box::new(GeometricPrimitive::new(s.clone(), None)) as Box<dyn Primitive>)
+                .collect()
+        };
+
+        let bvh = BVH::build_with_split_method(prims(), SplitMethod::SAH, DEFAULT_MAX_PRIMS_IN_NODE);
+        let stats = bvh.stats();
+        assert_eq!(stats.split_method, SplitMethod::SAH);
+        assert_eq!(stats.n_prims, 200);
+        assert_eq!(stats.n_nodes, 2 * stats.n_leaves - 1);
+
+        let list = prims();
+        let sphere_surf = UnitSphereSurface::new();
+        for i in 0..500 {
+            let dir: Vec3f = Vector3::from(sphere_surf.sample(&mut rng)).cast().unwrap();
+            let mut ray = Ray::new((0.0, 0.0, 0.0).into(), dir);
+            let mut bvh_ray = ray;
+            let bvh_isect = bvh.intersect(&mut bvh_ray);
+            let expected_isect = intersect_list(&mut ray, list.as_slice());
+            assert_eq!(bvh_isect.map(|i| i.hit), expected_isect.map(|i| i.hit), "Iteration {}", i);
+        }
+    }
+
     #[test]
     fn test_bvh_intersect_many_nodes() {
         let mut rng = StdRng::from_seed([3; 32]);
@@ -414,9 +596,9 @@ mod tests {
             .map(|(o2w, w2o)| {
                 let sphere = Sphere::whole(*o2w, *w2o, rng.gen_range(0.5, 3.0));
                 let sphere = Arc::new(sphere);
-                let prim2 = GeometricPrimitive { shape: sphere.clone(), material: None, light: None };
+                let prim2 = GeometricPrimitive::new(sphere.clone(), None);
                 prims2.push(Box::new(prim2) as Box<dyn Primitive>);
-                let prim = GeometricPrimitive { shape: sphere, material: None, light: None };
+                let prim = GeometricPrimitive::new(sphere, None);
                 Box::new(prim) as Box<dyn Primitive>
             })
             .collect();
@@ -443,6 +625,139 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_bvh_refit() {
+        let mut rng = StdRng::from_seed([5; 32]);
+        let distr = Uniform::new_inclusive(-10.0, 10.0);
+        let prims: Vec<Box<dyn Primitive>> = (0..50)
+            .map(|_| {
+                let v = Vec3f::new(rng.sample(distr), rng.sample(distr), rng.sample(distr));
+                let o2w = Transform::translate(v);
+                let sphere = Sphere::whole(o2w, o2w.inverse(), rng.gen_range(0.5, 3.0));
+                let prim = GeometricPrimitive::new(Arc::new(sphere), None);
+                Box::new(prim) as Box<dyn Primitive>
+            })
+            .collect();
+
+        let mut bvh = BVH::build(prims);
+        let offset = Vec3f::new(5.0, -3.0, 12.0);
+
+        // Move every sphere in place, keeping the primitive order the BVH was built with
+        for prim in bvh.prims.iter_mut() {
+            let bounds = prim.world_bound();
+            let radius = bounds.diagonal().x / 2.0;
+            let o2w = Transform::translate(bounds.centroid().to_vec() + offset);
+            let sphere = Sphere::whole(o2w, o2w.inverse(), radius);
+            *prim = Box::new(GeometricPrimitive::new(Arc::new(sphere), None));
+        }
+
+        let expected_bounds = bvh.prims.iter()
+            .fold(Bounds3f::empty(), |b, prim| b.join(&prim.world_bound()));
+
+        bvh.refit();
+        assert_eq!(bvh.bounds, expected_bounds);
+        assert_eq!(bvh.nodes[0].bounds, expected_bounds);
+
+        let mut ray = Ray::new(Point3f::new(0.0, 0.0, 0.0) + offset, Vec3f::new(1.0, 0.0, 0.0));
+        let mut expected_ray = ray;
+        let expected_isect = intersect_list(&mut expected_ray, bvh.prims.as_slice());
+        assert_eq!(bvh.intersect(&mut ray).map(|i| i.hit), expected_isect.map(|i| i.hit));
+    }
+
+    #[test]
+    fn test_max_prims_in_node() {
+        let mut rng = StdRng::from_seed([7; 32]);
+        let jitter = Uniform::new_inclusive(-2.0, 2.0);
+        // three well separated clusters of three spheres each
+        let centers: Vec<Vec3f> = [0.0, 10.0, 30.0].iter()
+            .flat_map(|&x| vec![x; 3])
+            .map(|x| Vec3f::new(x + rng.sample(jitter) * 0.25, rng.sample(jitter), rng.sample(jitter)))
+            .collect();
+        let spheres: Vec<Arc<Sphere>> = centers.iter()
+            .map(|&c| {
+                let o2w = Transform::translate(c);
+                Arc::new(Sphere::whole(o2w, o2w.inverse(), 1.0))
+            })
+            .collect();
+        let make_prims = || -> Vec<Box<dyn Primitive>> {
+            spheres.iter()
+                .map(|s| Box::new(GeometricPrimitive::new(s.clone(), None)) as Box<dyn Primitive>)
+                .collect()
+        };
+
+        let leaf_sizes = |bvh: &BVH| -> Vec<u16> {
+            bvh.nodes.iter().filter_map(|node| match node.kind {
+                LinearNodeKind::Leaf { n_prims, .. } => Some(n_prims),
+                _ => None
+            }).collect()
+        };
+
+        let deep = BVH::build_with_max_prims(make_prims(), 1);
+        let shallow = BVH::build_with_max_prims(make_prims(), 4);
+        assert_eq!(leaf_sizes(&deep), vec![1; 9]);
+        assert_eq!(leaf_sizes(&shallow), vec![3; 3]);
+        assert_eq!(shallow.nodes.len(), 5);
+
+        let expected_prims = make_prims();
+        let origin = Point3f::new(15.0, 8.0, -5.0);
+        let sphere_surf = UnitSphereSurface::new();
+        let dirs = centers.iter()
+            .map(|&c| (Point3f::from_vec(c) - origin).normalize())
+            .chain((0..200).map(|_| -> Vec3f { Vector3::from(sphere_surf.sample(&mut rng)).cast().unwrap() }));
+        for (i, dir) in dirs.enumerate() {
+            let ray = Ray::new(origin, dir);
+            let (mut expected_ray, mut deep_ray, mut shallow_ray) = (ray, ray, ray);
+            let expected = intersect_list(&mut expected_ray, expected_prims.as_slice()).map(|i| i.hit);
+            assert_eq!(deep.intersect(&mut deep_ray).map(|i| i.hit), expected, "Ray {}", i);
+            assert_eq!(shallow.intersect(&mut shallow_ray).map(|i| i.hit), expected, "Ray {}", i);
+            assert_eq!(shallow.intersect_test(&ray), expected.is_some(), "Ray {}", i);
+        }
+    }
+
+    #[test]
+    fn test_deep_tree_traversal() {
+        // Spheres at x = 3^i each end up alone on one side of the midpoint split, so the tree is
+        // a chain, and a ray along +x visits the deep side first and pushes every other node.
+        let n = 75;
+        let prims: Vec<Box<dyn Primitive>> = (0..n)
+            .map(|i| {
+                let x = (3.0 as Float).powi(i);
+                let o2w = Transform::translate(Vec3f::new(x, 0.0, 0.0));
+                let sphere = Sphere::whole(o2w, o2w.inverse(), 0.25 * x);
+                Box::new(GeometricPrimitive::new(Arc::new(sphere), None)) as Box<dyn Primitive>
+            })
+            .collect();
+        let bvh = BVH::build_with_max_prims(prims, 1);
+        assert!(bvh.nodes.len() == 2 * n as usize - 1);
+
+        let mut ray = Ray::new(Point3f::new(0.0, 0.0, 0.0), Vec3f::new(1.0, 0.0, 0.0));
+        assert!(bvh.intersect_test(&ray));
+        let isect = bvh.intersect(&mut ray).expect("Should hit the nearest sphere");
+        assert!((isect.hit.p.x - 0.75).abs() < 1e-5, "{:?}", isect.hit.p);
+    }
+
+    #[test]
+    fn test_intersect_t_matches_intersect() {
+        let prims: Vec<Box<dyn Primitive>> = (0..5)
+            .map(|i| {
+                let o2w = Transform::translate(Vec3f::new(i as Float * 3.0, (i % 2) as Float, 0.0));
+                let sphere = Sphere::whole(o2w, o2w.inverse(), 1.0);
+                Box::new(GeometricPrimitive::new(Arc::new(sphere), None)) as Box<dyn Primitive>
+            })
+            .collect();
+        let bvh = BVH::build(prims);
+
+        let ray = Ray::new(Point3f::new(-5.0, 0.5, 0.0), Vec3f::new(1.0, 0.0, 0.0));
+        let t = bvh.intersect_t(&ray).expect("Should hit the first sphere");
+        assert!(ray.t_max.is_infinite());
+        let mut isect_ray = ray;
+        bvh.intersect(&mut isect_ray).unwrap();
+        assert!((t - isect_ray.t_max).abs() < 1e-6, "{} {}", t, isect_ray.t_max);
+
+        let miss = Ray::new(Point3f::new(-5.0, 5.0, 0.0), Vec3f::new(1.0, 0.0, 0.0));
+        assert!(bvh.intersect_t(&miss).is_none());
+    }
+
     fn intersect_test_list(ray: &Ray, prims: &[Box<dyn Primitive>]) -> bool {
         prims.iter().any(|prim| {
             prim.intersect_test(ray)
@@ -1,9 +1,13 @@
 use cgmath::{InnerSpace, EuclideanSpace};
 
-use crate::{Bounds2f, Differential, Float, Lerp, INFINITY, Point2f, Point2i, Point3f, Ray, RayDifferential, Transformable, Vec2f, Vec3f};
+use crate::{abs_dot, consts, Bounds2f, Differential, Float, Lerp, INFINITY, Normal3, Point2f, Point2i, Point3f, Ray, RayDifferential, Transformable, Vec2f, Vec3f};
 use crate::geometry::Transform;
+use crate::interaction::SurfaceHit;
+use crate::light::VisibilityTester;
 use crate::sampling::concentric_sample_disk;
 
+pub mod realistic;
+
 #[derive(Clone, Copy, Debug)]
 pub struct CameraSample {
     pub p_film: Point2f,
@@ -11,9 +15,36 @@ pub struct CameraSample {
     pub time: Float
 }
 
+/// A connection from a point in the scene to the camera, sampled by `Camera::sample_wi`.
+#[derive(Debug)]
+pub struct CameraWiSample {
+    /// The camera's importance for the ray leaving the lens towards the reference point.
+    pub importance: Float,
+
+    /// The direction from the reference point towards the lens.
+    pub wi: Vec3f,
+
+    /// The density of `wi` with respect to solid angle at the reference point.
+    pub pdf: Float,
+
+    /// Where the connection lands on the film.
+    pub p_raster: Point2f,
+
+    pub vis: VisibilityTester,
+}
+
 pub trait Camera: Sync + Send {
     fn generate_ray(&self, sample: CameraSample) -> (Float, Ray);
 
+    /// Samples a point on the lens to connect `reference` to the camera, for tracing paths that
+    /// start from the lights. Returns None if the camera can't be connected to, or if `reference`
+    /// is outside of the camera's view.
+    fn sample_wi(&self, _reference: &SurfaceHit, _u: Point2f) -> Option<CameraWiSample> { None }
+
+    /// The densities with respect to area on the lens and solid angle with which `generate_ray`
+    /// would generate `ray`, or zero if it can't.
+    fn pdf_we(&self, _ray: &Ray) -> (Float, Float) { (0.0, 0.0) }
+
     fn generate_ray_differential(&self, sample: CameraSample) -> (Float, RayDifferential) {
         let (mut weight, ray) = self.generate_ray(sample);
 
@@ -40,6 +71,21 @@ pub trait Camera: Sync + Send {
     }
 }
 
+/// Checks that a screen window covers some area. Windows other than `[-1, 1]^2` crop or stretch the
+/// field of view, e.g. `[-2, 2] x [-1, 1]` for a 2:1 image.
+pub fn check_screen_window(screen_window: &Bounds2f) -> Result<(), String> {
+    let valid = screen_window.min.x < screen_window.max.x
+        && screen_window.min.y < screen_window.max.y;
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid screen window [{:?}, {:?}]: min must be less than max",
+            screen_window.min, screen_window.max
+        ))
+    }
+}
+
 struct CameraProjection {
     pub camera_to_screen: Transform,
     pub screen_to_raster: Transform,
@@ -71,17 +117,23 @@ impl CameraProjection {
 
 pub struct PerspectiveCamera {
     camera_to_world: Transform,
+    world_to_camera: Transform,
     proj: CameraProjection,
+    full_resolution: Point2i,
     shutter_interval: (Float, Float),
     lens_radius: Float,
     focal_dist: Float,
     aspect: Float,
     dx_camera: Vec3f,
     dy_camera: Vec3f,
+    clip_near: Option<Float>,
+    clip_far: Option<Float>,
 }
 
 impl PerspectiveCamera {
-    // TODO: figure out why screen_window has to be [-1, 1]
+    /// `fov` is the angle spanned by `[-1, 1]` in screen space, and `screen_window` is the part of
+    /// screen space that is mapped onto the whole image. The screen window must cover some area;
+    /// callers taking it from user input should check it with `check_screen_window` first.
     pub fn new(
         camera_to_world: Transform,
         full_resolution: Point2i,
@@ -91,6 +143,7 @@ impl PerspectiveCamera {
         focal_dist: Float,
         fov: Float
     ) -> Self {
+        debug_assert!(check_screen_window(&screen_window).is_ok(), "{:?}", screen_window);
         let persp = Transform::perspective(fov, 1.0e-2, 1000.0);
         let proj = CameraProjection::new(persp, full_resolution, screen_window);
         let mut p_min: Point3f = point3f!(0, 0, 0).transform(proj.raster_to_camera);
@@ -103,13 +156,85 @@ impl PerspectiveCamera {
 
         Self {
             camera_to_world,
+            world_to_camera: camera_to_world.inverse(),
             proj,
+            full_resolution,
             shutter_interval,
             lens_radius,
             focal_dist,
             aspect,
             dx_camera,
             dy_camera,
+            clip_near: None,
+            clip_far: None,
+        }
+    }
+
+    /// Restrict generated rays to the camera-space depth range `[clip_near, clip_far]`.
+    pub fn with_clipping(mut self, clip_near: Option<Float>, clip_far: Option<Float>) -> Self {
+        self.clip_near = clip_near;
+        self.clip_far = clip_far;
+        self
+    }
+
+    /// The importance emitted along the world space `ray` leaving the lens, and the raster position
+    /// it corresponds to. Returns None if the ray doesn't pass through the film.
+    fn importance(&self, ray: &Ray) -> Option<(Float, Point2f)> {
+        let cos_theta = ray.dir.dot(self.forward());
+        if cos_theta <= 0.0 {
+            return None;
+        }
+
+        // rays through the lens all meet at the plane of focus, so find the raster position there
+        let focus_dist = if self.lens_radius > 0.0 { self.focal_dist } else { 1.0 };
+        let p_focus = ray.at(focus_dist / cos_theta);
+        let camera_to_raster = self.proj.screen_to_raster * self.proj.camera_to_screen;
+        let p_raster = p_focus.transform(self.world_to_camera).transform(camera_to_raster);
+        let p_raster = Point2f::new(p_raster.x, p_raster.y);
+        if p_raster.x < 0.0 || p_raster.x >= self.full_resolution.x as Float
+            || p_raster.y < 0.0 || p_raster.y >= self.full_resolution.y as Float {
+            return None;
+        }
+
+        // `aspect` is the area of the image on the z = 1 plane
+        let cos2_theta = cos_theta * cos_theta;
+        let importance = 1.0 / (self.aspect * self.lens_area() * cos2_theta * cos2_theta);
+        Some((importance, p_raster))
+    }
+
+    /// The world space viewing direction.
+    fn forward(&self) -> Vec3f {
+        self.camera_to_world.transform(Vec3f::new(0.0, 0.0, 1.0)).normalize()
+    }
+
+    /// A pinhole is treated as having unit area so that it cancels out.
+    fn lens_area(&self) -> Float {
+        if self.lens_radius > 0.0 {
+            consts::PI * self.lens_radius * self.lens_radius
+        } else {
+            1.0
+        }
+    }
+
+    /// Transforms a camera space ray to world space. The camera to world transform may scale, so
+    /// the direction is normalized again, with `t_max` scaled by its length so that a clipped ray
+    /// still ends at the far plane.
+    fn ray_to_world(&self, ray: &Ray) -> Ray {
+        let mut ray = ray.transform(self.camera_to_world);
+        let len = ray.dir.magnitude();
+        ray.dir /= len;
+        ray.t_max *= len;
+        ray
+    }
+
+    /// Advance a camera space ray to the near plane and limit it to the far plane.
+    fn clip_ray(&self, ray: &mut Ray) {
+        let t_near = self.clip_near.map_or(0.0, |near| (near - ray.origin.z) / ray.dir.z);
+        if let Some(far) = self.clip_far {
+            ray.t_max = (far - ray.origin.z) / ray.dir.z - t_near;
+        }
+        if t_near > 0.0 {
+            ray.origin = ray.at(t_near);
         }
     }
 }
@@ -138,8 +263,8 @@ impl Camera for PerspectiveCamera {
             ray.dir = (p_focus - ray.origin).normalize();
         }
 
-        let ray = ray.transform(self.camera_to_world);
-        (1.0, ray)
+        self.clip_ray(&mut ray);
+        (1.0, self.ray_to_world(&ray))
     }
 
     fn generate_ray_differential(&self, sample: CameraSample) -> (Float, RayDifferential) {
@@ -151,7 +276,7 @@ impl Camera for PerspectiveCamera {
         let dir = (p_camera - origin).normalize();
         let mut ray = Ray { origin, dir, time, t_max: INFINITY};
 
-        let ray_diff = if self.lens_radius > 0.0 {
+        let mut ray_diff = if self.lens_radius > 0.0 {
             // Sample point on lens
             let p_lens = self.lens_radius * concentric_sample_disk(sample.p_lens);
 
@@ -170,7 +295,7 @@ impl Camera for PerspectiveCamera {
             let rx_origin = Point3f::new(p_lens.x, p_lens.y, 0.0);
             let rx_dir = (p_focus - rx_origin).normalize();
 
-            let dy = (p_camera + self.dx_camera).to_vec().normalize();
+            let dy = (p_camera + self.dy_camera).to_vec().normalize();
             let ft = self.focal_dist / dy.z;
             let p_focus = Point3f::origin() + (ft * dy);
             let ry_origin = Point3f::new(p_lens.x, p_lens.y, 0.0);
@@ -200,9 +325,45 @@ impl Camera for PerspectiveCamera {
                 })
             }
         };
-        let ray_diff = ray_diff.transform(self.camera_to_world);
+        self.clip_ray(&mut ray_diff.ray);
+        let camera_ray = ray_diff.ray;
+        let mut ray_diff = ray_diff.transform(self.camera_to_world);
+        ray_diff.ray = self.ray_to_world(&camera_ray);
         (1.0, ray_diff)
     }
+
+    fn sample_wi(&self, reference: &SurfaceHit, u: Point2f) -> Option<CameraWiSample> {
+        let p_lens = self.lens_radius * concentric_sample_disk(u);
+        let p_lens = self.camera_to_world.transform(Point3f::new(p_lens.x, p_lens.y, 0.0));
+        let n_lens = self.forward();
+        let lens_hit = SurfaceHit {
+            p: p_lens,
+            p_err: Vec3f::new(0.0, 0.0, 0.0),
+            time: reference.time,
+            n: Normal3(n_lens),
+        };
+
+        let to_lens = p_lens - reference.p;
+        let dist = to_lens.magnitude();
+        let wi = to_lens / dist;
+        let (importance, p_raster) = self.importance(&Ray::new(p_lens, -wi))?;
+        let pdf = dist * dist / (abs_dot(n_lens, wi) * self.lens_area());
+        Some(CameraWiSample {
+            importance,
+            wi,
+            pdf,
+            p_raster,
+            vis: VisibilityTester { p0: *reference, p1: lens_hit },
+        })
+    }
+
+    fn pdf_we(&self, ray: &Ray) -> (Float, Float) {
+        if self.importance(ray).is_none() {
+            return (0.0, 0.0);
+        }
+        let cos_theta = ray.dir.dot(self.forward());
+        (1.0 / self.lens_area(), 1.0 / (self.aspect * cos_theta * cos_theta * cos_theta))
+    }
 }
 
 #[cfg(test)]
@@ -364,4 +525,134 @@ mod tests {
         let angle: Deg<_> = Vec3f::angle(right, left).into();
         assert_abs_diff_eq!(angle, Deg(fov), epsilon = 0.01);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_clip_near_far() {
+        let camera_tf = Transform::camera_look_at((0.0, 0.0, 0.0).into(), (0.0, 0.0, 1.0).into(), (0.0, 1.0, 0.0).into());
+        let res = (16, 16).into();
+        let camera = PerspectiveCamera::new(
+            camera_tf,
+            res,
+            Bounds2f::whole_screen(),
+            (0.0, 1.0),
+            0.0,
+            1.0,
+            60.0
+        ).with_clipping(Some(1.0), Some(5.0));
+
+        let too_close = Bounds3f::with_bounds((-10.0, -10.0, 0.5).into(), (10.0, 10.0, 0.6).into());
+        let visible = Bounds3f::with_bounds((-10.0, -10.0, 2.0).into(), (10.0, 10.0, 2.1).into());
+        let too_far = Bounds3f::with_bounds((-10.0, -10.0, 6.0).into(), (10.0, 10.0, 6.1).into());
+
+        let mut sampler = RandomSampler::new_with_seed(4, 1);
+        let px_bounds = Bounds2i::with_bounds((0, 0).into(), res);
+        for pixel in px_bounds.iter_points() {
+            sampler.start_pixel(pixel.into());
+
+            while sampler.start_next_sample() {
+                let camera_sample = sampler.get_camera_sample(pixel.into());
+                let (_t, ray) = camera.generate_ray(camera_sample);
+                assert!(too_close.intersect_test(&ray).is_none(), format!("{:?}", ray));
+                assert!(visible.intersect_test(&ray).is_some(), format!("{:?}", ray));
+                assert!(too_far.intersect_test(&ray).is_none(), format!("{:?}", ray));
+            }
+        }
+    }
+
+    #[test]
+    fn test_clip_with_scaled_camera() {
+        // camera space distances are doubled in world space
+        let camera_tf = Transform::scale(2.0, 2.0, 2.0);
+        let camera = PerspectiveCamera::new(
+            camera_tf,
+            (16, 16).into(),
+            Bounds2f::whole_screen(),
+            (0.0, 1.0),
+            0.0,
+            1.0,
+            60.0
+        ).with_clipping(Some(1.0), Some(5.0));
+
+        let too_close = Bounds3f::with_bounds((-10.0, -10.0, 1.5).into(), (10.0, 10.0, 1.6).into());
+        let visible = Bounds3f::with_bounds((-10.0, -10.0, 9.0).into(), (10.0, 10.0, 9.1).into());
+        let too_far = Bounds3f::with_bounds((-10.0, -10.0, 10.5).into(), (10.0, 10.0, 10.6).into());
+
+        let mut sampler = RandomSampler::new_with_seed(1, 1);
+        let pixel = Point2i::new(8, 8);
+        sampler.start_pixel(pixel);
+        while sampler.start_next_sample() {
+            let (_, ray) = camera.generate_ray_differential(sampler.get_camera_sample(pixel));
+            let ray = ray.ray;
+            assert!((ray.dir.magnitude() - 1.0).abs() < 1e-5, "{:?}", ray);
+            assert!(too_close.intersect_test(&ray).is_none(), "{:?}", ray);
+            assert!(visible.intersect_test(&ray).is_some(), "{:?}", ray);
+            assert!(too_far.intersect_test(&ray).is_none(), "{:?}", ray);
+        }
+    }
+
+    #[test]
+    fn test_ray_differentials_match_finite_differences() {
+        let camera_tf = Transform::camera_look_at((0.0, 1.0, -3.0).into(), (0.0, 0.0, 0.0).into(), (0.0, 1.0, 0.0).into());
+        let res = (32, 24).into();
+        let camera = PerspectiveCamera::new(
+            camera_tf,
+            res,
+            Bounds2f::whole_screen(),
+            (0.0, 1.0),
+            0.0,
+            1.0,
+            45.0
+        );
+
+        let mut sampler = RandomSampler::new_with_seed(4, 1);
+        let px_bounds = Bounds2i::with_bounds((0, 0).into(), res);
+        for pixel in px_bounds.iter_points() {
+            sampler.start_pixel(pixel.into());
+
+            while sampler.start_next_sample() {
+                let camera_sample = sampler.get_camera_sample(pixel.into());
+                let (_wt, ray_diff) = camera.generate_ray_differential(camera_sample);
+                let diff = ray_diff.diff.unwrap();
+
+                let (_wt, ray) = camera.generate_ray(camera_sample);
+                let (_wt, rx) = camera.generate_ray(CameraSample { p_film: camera_sample.p_film + Vec2f::new(1.0, 0.0), ..camera_sample });
+                let (_wt, ry) = camera.generate_ray(CameraSample { p_film: camera_sample.p_film + Vec2f::new(0.0, 1.0), ..camera_sample });
+
+                assert_abs_diff_eq!(ray_diff.ray.origin, ray.origin, epsilon = 1e-4);
+                assert_abs_diff_eq!(ray_diff.ray.dir, ray.dir, epsilon = 1e-4);
+                assert_abs_diff_eq!(diff.rx_origin, rx.origin, epsilon = 1e-4);
+                assert_abs_diff_eq!(diff.rx_dir, rx.dir, epsilon = 1e-4);
+                assert_abs_diff_eq!(diff.ry_origin, ry.origin, epsilon = 1e-4);
+                assert_abs_diff_eq!(diff.ry_dir, ry.dir, epsilon = 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wide_screen_window() {
+        let res = Point2i::new(64, 32);
+        let fov = 60.0 as Float;
+        let screen_window = Bounds2f::with_bounds(Point2f::new(-2.0, -1.0), Point2f::new(2.0, 1.0));
+        let camera = PerspectiveCamera::new(Transform::IDENTITY, res, screen_window, (0.0, 1.0), 0.0, 1.0, fov);
+
+        // the extent of the image on the z = 1 plane, through the middle of each edge
+        let edge = |x: Float, y: Float| {
+            let sample = CameraSample { p_film: Point2f::new(x, y), p_lens: Point2f::new(0.5, 0.5), time: 0.0 };
+            let (_, ray) = camera.generate_ray(sample);
+            ray.dir / ray.dir.z
+        };
+        let width = edge(res.x as Float, 16.0).x - edge(0.0, 16.0).x;
+        let height = edge(32.0, 0.0).y - edge(32.0, res.y as Float).y;
+
+        assert_abs_diff_eq!(height, 2.0 * (fov.to_radians() / 2.0).tan(), epsilon = 1e-4);
+        assert_abs_diff_eq!(width, 2.0 * height, epsilon = 1e-4);
+    }
+
+    #[test]
+    fn test_check_screen_window() {
+        assert!(check_screen_window(&Bounds2f::whole_screen()).is_ok());
+        assert!(check_screen_window(&Bounds2f::with_bounds(Point2f::new(0.0, -1.0), Point2f::new(0.5, 1.0))).is_ok());
+        assert!(check_screen_window(&Bounds2f::with_bounds(Point2f::new(-1.0, 0.5), Point2f::new(1.0, 0.5))).is_err());
+        assert!(check_screen_window(&Bounds2f::with_bounds(Point2f::new(1.0, -1.0), Point2f::new(-1.0, 1.0))).is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use cgmath::{InnerSpace, EuclideanSpace};
+use rayon::prelude::*;
+
+use crate::{Bounds2f, Float, INFINITY, Lerp, Normal3, Point2f, Point2i, Point3f, Ray, Transformable, Vec2f, Vec3f};
+use crate::camera::{Camera, CameraSample};
+use crate::geometry::Transform;
+use crate::reflection::refract;
+use crate::sampling::radical_inverse;
+
+/// Number of radial segments of the film for which exit pupil bounds are precomputed
+const N_EXIT_PUPIL_BOUNDS: usize = 64;
+
+/// Number of rays traced to find the exit pupil bounds of each segment
+const N_EXIT_PUPIL_SAMPLES: usize = 1 << 15;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LensElementInterface {
+    /// Zero for the aperture stop
+    pub curvature_radius: Float,
+    /// Distance along the axis to the next element towards the film
+    pub thickness: Float,
+    /// Index of refraction of the medium on the film side of the interface
+    pub eta: Float,
+    pub aperture_radius: Float,
+}
+
+/// A camera that simulates a system of spherical lens elements, based on the description in pbrt.
+/// The lens system is laid out along the camera space z axis with the film at z = 0.
+pub struct RealisticCamera {
+    camera_to_world: Transform,
+    shutter_interval: (Float, Float),
+    simple_weighting: bool,
+    element_interfaces: Vec<LensElementInterface>,
+    full_resolution: Point2i,
+    film_diagonal: Float,
+    exit_pupil_bounds: Vec<Bounds2f>,
+}
+
+impl RealisticCamera {
+    /// `lens_data` contains rows of `radius thickness eta aperture_diameter` in millimeters, ordered
+    /// from the scene side to the film side. A row with radius 0 is the aperture stop, whose diameter
+    /// is limited to `aperture_diameter` (also in mm). The rear element is moved so that the plane at
+    /// `focus_distance` is in focus. `film_diagonal` is in millimeters.
+    pub fn new(
+        camera_to_world: Transform,
+        shutter_interval: (Float, Float),
+        lens_data: &[Float],
+        aperture_diameter: Float,
+        focus_distance: Float,
+        simple_weighting: bool,
+        full_resolution: Point2i,
+        film_diagonal: Float,
+    ) -> Result<Self, String> {
+        if lens_data.is_empty() || lens_data.len() % 4 != 0 {
+            return Err(format!("Expected lens data in rows of 4 values, got {} values", lens_data.len()));
+        }
+
+        let element_interfaces = lens_data.chunks_exact(4)
+            .map(|row| {
+                let (radius, thickness, eta) = (row[0], row[1], row[2]);
+                let mut diameter = row[3];
+                if radius == 0.0 {
+                    if aperture_diameter > diameter {
+                        tracing::warn!("Aperture diameter {} is larger than the maximum {} for the lens system", aperture_diameter, diameter);
+                    } else {
+                        diameter = aperture_diameter;
+                    }
+                }
+                LensElementInterface {
+                    curvature_radius: radius * 0.001,
+                    thickness: thickness * 0.001,
+                    eta,
+                    aperture_radius: diameter * 0.001 / 2.0,
+                }
+            })
+            .collect();
+
+        let mut camera = Self {
+            camera_to_world,
+            shutter_interval,
+            simple_weighting,
+            element_interfaces,
+            full_resolution,
+            film_diagonal: film_diagonal * 0.001,
+            exit_pupil_bounds: Vec::new(),
+        };
+
+        let rear_thickness = camera.focus_thick_lens(focus_distance)?;
+        camera.element_interfaces.last_mut().unwrap().thickness = rear_thickness;
+
+        let half_diagonal = camera.film_diagonal / 2.0;
+        let exit_pupil_bounds = (0..N_EXIT_PUPIL_BOUNDS).into_par_iter()
+            .map(|i| {
+                let r0 = i as Float / N_EXIT_PUPIL_BOUNDS as Float * half_diagonal;
+                let r1 = (i + 1) as Float / N_EXIT_PUPIL_BOUNDS as Float * half_diagonal;
+                camera.bound_exit_pupil(r0, r1)
+            })
+            .collect();
+        camera.exit_pupil_bounds = exit_pupil_bounds;
+
+        Ok(camera)
+    }
+
+    fn lens_rear_z(&self) -> Float {
+        self.element_interfaces.last().unwrap().thickness
+    }
+
+    fn lens_front_z(&self) -> Float {
+        self.element_interfaces.iter().map(|e| e.thickness).sum()
+    }
+
+    fn rear_element_radius(&self) -> Float {
+        self.element_interfaces.last().unwrap().aperture_radius
+    }
+
+    /// The physical area of the film in camera space, centered on the axis.
+    fn film_physical_extent(&self) -> Bounds2f {
+        let aspect = self.full_resolution.y as Float / self.full_resolution.x as Float;
+        let x = (self.film_diagonal * self.film_diagonal / (1.0 + aspect * aspect)).sqrt();
+        let y = aspect * x;
+        Bounds2f::with_bounds(Point2f::new(-x / 2.0, -y / 2.0), Point2f::new(x / 2.0, y / 2.0))
+    }
+
+    /// Traces a camera space ray starting on the film side through the lens system, returning the
+    /// ray leaving the front element or `None` if it was blocked.
+    fn trace_lenses_from_film(&self, r_camera: &Ray) -> Option<Ray> {
+        let mut element_z = 0.0;
+        let mut r_lens = flip_z(r_camera);
+
+        for (i, element) in self.element_interfaces.iter().enumerate().rev() {
+            element_z -= element.thickness;
+
+            let (t, n) = if element.curvature_radius == 0.0 {
+                // a ray reflected at the previous interface could be moving away from the stop
+                if r_lens.dir.z >= 0.0 { return None }
+                ((element_z - r_lens.origin.z) / r_lens.dir.z, None)
+            } else {
+                let z_center = element_z + element.curvature_radius;
+                let (t, n) = intersect_spherical_element(element.curvature_radius, z_center, &r_lens)?;
+                (t, Some(n))
+            };
+
+            let p_hit = r_lens.at(t);
+            if p_hit.x * p_hit.x + p_hit.y * p_hit.y > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+            r_lens.origin = p_hit;
+
+            if let Some(n) = n {
+                let eta_i = if element.eta != 0.0 { element.eta } else { 1.0 };
+                let eta_t = match i {
+                    0 => 1.0,
+                    _ if self.element_interfaces[i - 1].eta == 0.0 => 1.0,
+                    _ => self.element_interfaces[i - 1].eta,
+                };
+                r_lens.dir = refract(-r_lens.dir.normalize(), n, eta_i / eta_t)?;
+            }
+        }
+
+        Some(flip_z(&r_lens))
+    }
+
+    /// Traces a camera space ray starting on the scene side through the lens system, returning the
+    /// ray leaving the rear element or `None` if it was blocked.
+    fn trace_lenses_from_scene(&self, r_camera: &Ray) -> Option<Ray> {
+        let mut element_z = -self.lens_front_z();
+        let mut r_lens = flip_z(r_camera);
+
+        for (i, element) in self.element_interfaces.iter().enumerate() {
+            let (t, n) = if element.curvature_radius == 0.0 {
+                ((element_z - r_lens.origin.z) / r_lens.dir.z, None)
+            } else {
+                let z_center = element_z + element.curvature_radius;
+                let (t, n) = intersect_spherical_element(element.curvature_radius, z_center, &r_lens)?;
+                (t, Some(n))
+            };
+
+            let p_hit = r_lens.at(t);
+            if p_hit.x * p_hit.x + p_hit.y * p_hit.y > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+            r_lens.origin = p_hit;
+
+            if let Some(n) = n {
+                let eta_i = match i {
+                    0 => 1.0,
+                    _ if self.element_interfaces[i - 1].eta == 0.0 => 1.0,
+                    _ => self.element_interfaces[i - 1].eta,
+                };
+                let eta_t = if element.eta != 0.0 { element.eta } else { 1.0 };
+                r_lens.dir = refract(-r_lens.dir.normalize(), n, eta_i / eta_t)?;
+            }
+
+            element_z += element.thickness;
+        }
+
+        Some(flip_z(&r_lens))
+    }
+
+    /// Returns the z coordinates of the principal plane and focal point for a ray parallel to the
+    /// axis entering the lens system and the corresponding exiting ray.
+    fn compute_cardinal_points(r_in: &Ray, r_out: &Ray) -> (Float, Float) {
+        let tf = -r_out.origin.x / r_out.dir.x;
+        let fz = -r_out.at(tf).z;
+        let tp = (r_in.origin.x - r_out.origin.x) / r_out.dir.x;
+        let pz = -r_out.at(tp).z;
+        (pz, fz)
+    }
+
+    fn compute_thick_lens_approximation(&self) -> Option<([Float; 2], [Float; 2])> {
+        // a small offset from the axis so that the paraxial approximation holds
+        let x = 0.001 * self.film_diagonal;
+
+        let r_scene = Ray {
+            origin: Point3f::new(x, 0.0, self.lens_front_z() + 1.0),
+            dir: Vec3f::new(0.0, 0.0, -1.0),
+            t_max: INFINITY,
+            time: 0.0,
+        };
+        let r_film = self.trace_lenses_from_scene(&r_scene)?;
+        let (pz0, fz0) = Self::compute_cardinal_points(&r_scene, &r_film);
+
+        let r_film = Ray {
+            origin: Point3f::new(x, 0.0, self.lens_rear_z() - 1.0),
+            dir: Vec3f::new(0.0, 0.0, 1.0),
+            t_max: INFINITY,
+            time: 0.0,
+        };
+        let r_scene = self.trace_lenses_from_film(&r_film)?;
+        let (pz1, fz1) = Self::compute_cardinal_points(&r_film, &r_scene);
+
+        Some(([pz0, pz1], [fz0, fz1]))
+    }
+
+    /// Computes the distance from the film to the rear element that brings the plane at
+    /// `focus_distance` into focus.
+    fn focus_thick_lens(&self, focus_distance: Float) -> Result<Float, String> {
+        let (pz, fz) = self.compute_thick_lens_approximation()
+            .ok_or_else(|| "Paraxial rays could not pass through the lens system".to_string())?;
+
+        let f = fz[0] - pz[0];
+        let z = -focus_distance;
+        let c = (pz[1] - z - pz[0]) * (pz[1] - z - 4.0 * f - pz[0]);
+        if c <= 0.0 {
+            return Err(format!("Focus distance {} is too short for the lens system", focus_distance));
+        }
+        let delta = 0.5 * (pz[1] - z + pz[0] - c.sqrt());
+        Ok(self.lens_rear_z() + delta)
+    }
+
+    /// Finds a bound on the rear element of the directions that make it through the lens system
+    /// from points on the film between distances `p_film_x0` and `p_film_x1` from the axis.
+    fn bound_exit_pupil(&self, p_film_x0: Float, p_film_x1: Float) -> Bounds2f {
+        let rear_radius = self.rear_element_radius();
+        let proj_rear_bounds = Bounds2f::with_bounds(
+            Point2f::new(-1.5 * rear_radius, -1.5 * rear_radius),
+            Point2f::new(1.5 * rear_radius, 1.5 * rear_radius),
+        );
+
+        let mut pupil_min = Point2f::new(INFINITY, INFINITY);
+        let mut pupil_max = Point2f::new(-INFINITY, -INFINITY);
+        for i in 0..N_EXIT_PUPIL_SAMPLES {
+            let p_film = Point3f::new(
+                Float::lerp((i as Float + 0.5) / N_EXIT_PUPIL_SAMPLES as Float, p_film_x0, p_film_x1),
+                0.0,
+                0.0
+            );
+            let u = (radical_inverse(2, i as u64), radical_inverse(3, i as u64));
+            let p_rear = Point3f::new(
+                Float::lerp(u.0, proj_rear_bounds.min.x, proj_rear_bounds.max.x),
+                Float::lerp(u.1, proj_rear_bounds.min.y, proj_rear_bounds.max.y),
+                self.lens_rear_z()
+            );
+
+            // skip tracing points already inside the bounds
+            let inside = p_rear.x >= pupil_min.x && p_rear.x <= pupil_max.x
+                && p_rear.y >= pupil_min.y && p_rear.y <= pupil_max.y;
+            let ray = Ray { origin: p_film, dir: p_rear - p_film, t_max: INFINITY, time: 0.0 };
+            if inside || self.trace_lenses_from_film(&ray).is_some() {
+                pupil_min = Point2f::new(pupil_min.x.min(p_rear.x), pupil_min.y.min(p_rear.y));
+                pupil_max = Point2f::new(pupil_max.x.max(p_rear.x), pupil_max.y.max(p_rear.y));
+            }
+        }
+
+        if pupil_min.x > pupil_max.x {
+            return proj_rear_bounds;
+        }
+
+        // expand by roughly the spacing between samples to account for the ones that were missed
+        let expand = 2.0 * proj_rear_bounds.diagonal().magnitude() / (N_EXIT_PUPIL_SAMPLES as Float).sqrt();
+        let expand = Vec2f::new(expand, expand);
+        Bounds2f::with_bounds(pupil_min - expand, pupil_max + expand)
+    }
+
+    /// Samples a point on the rear element for a point on the film, returning the point and
+    /// the area of the exit pupil bounds it was sampled from.
+    fn sample_exit_pupil(&self, p_film: Point2f, lens_sample: Point2f) -> (Point3f, Float) {
+        let r_film = (p_film.x * p_film.x + p_film.y * p_film.y).sqrt();
+        let r_index = (r_film / (self.film_diagonal / 2.0) * N_EXIT_PUPIL_BOUNDS as Float) as usize;
+        let pupil_bounds = self.exit_pupil_bounds[r_index.min(N_EXIT_PUPIL_BOUNDS - 1)];
+
+        let p_lens = Point2f::new(
+            Float::lerp(lens_sample.x, pupil_bounds.min.x, pupil_bounds.max.x),
+            Float::lerp(lens_sample.y, pupil_bounds.min.y, pupil_bounds.max.y),
+        );
+
+        // the bounds were computed along the x axis, so rotate to the film point's angle
+        let (sin_theta, cos_theta) = if r_film != 0.0 {
+            (p_film.y / r_film, p_film.x / r_film)
+        } else {
+            (0.0, 1.0)
+        };
+        let p_rear = Point3f::new(
+            cos_theta * p_lens.x - sin_theta * p_lens.y,
+            sin_theta * p_lens.x + cos_theta * p_lens.y,
+            self.lens_rear_z()
+        );
+        (p_rear, pupil_bounds.area())
+    }
+}
+
+impl Camera for RealisticCamera {
+    fn generate_ray(&self, sample: CameraSample) -> (Float, Ray) {
+        let s = Point2f::new(
+            sample.p_film.x / self.full_resolution.x as Float,
+            sample.p_film.y / self.full_resolution.y as Float,
+        );
+        let extent = self.film_physical_extent();
+        let p_film = Point3f::new(
+            -Float::lerp(s.x, extent.min.x, extent.max.x),
+            Float::lerp(s.y, extent.min.y, extent.max.y),
+            0.0
+        );
+
+        let (p_rear, pupil_area) = self.sample_exit_pupil(Point2f::new(p_film.x, p_film.y), sample.p_lens);
+        let time = Float::lerp(sample.time, self.shutter_interval.0, self.shutter_interval.1);
+        let r_film = Ray { origin: p_film, dir: p_rear - p_film, t_max: INFINITY, time };
+
+        match self.trace_lenses_from_film(&r_film) {
+            None => (0.0, r_film.transform(self.camera_to_world)),
+            Some(ray) => {
+                let mut ray = ray.transform(self.camera_to_world);
+                ray.dir = ray.dir.normalize();
+
+                let cos_theta = r_film.dir.normalize().z;
+                let cos4_theta = (cos_theta * cos_theta) * (cos_theta * cos_theta);
+                let weight = if self.simple_weighting {
+                    cos4_theta * pupil_area / self.exit_pupil_bounds[0].area()
+                } else {
+                    let shutter_time = self.shutter_interval.1 - self.shutter_interval.0;
+                    shutter_time * cos4_theta * pupil_area / (self.lens_rear_z() * self.lens_rear_z())
+                };
+                (weight, ray)
+            }
+        }
+    }
+}
+
+/// Converts between camera space and lens space, where the lens system lies along -z.
+fn flip_z(ray: &Ray) -> Ray {
+    Ray {
+        origin: Point3f::new(ray.origin.x, ray.origin.y, -ray.origin.z),
+        dir: Vec3f::new(ray.dir.x, ray.dir.y, -ray.dir.z),
+        t_max: INFINITY,
+        time: ray.time,
+    }
+}
+
+/// Intersects a ray with a spherical lens element centered on the axis at `z_center`, returning
+/// the parametric distance and the normal facing the ray origin.
+fn intersect_spherical_element(radius: Float, z_center: Float, ray: &Ray) -> Option<(Float, Normal3)> {
+    let o = ray.origin.to_vec() - Vec3f::new(0.0, 0.0, z_center);
+    let a = ray.dir.magnitude2();
+    let b = 2.0 * ray.dir.dot(o);
+    let c = o.magnitude2() - radius * radius;
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 { return None }
+    let root = discriminant.sqrt();
+    let q = if b < 0.0 { -0.5 * (b - root) } else { -0.5 * (b + root) };
+    let (t0, t1) = (q / a, c / q);
+
+    // which intersection is the lens surface depends on the ray direction and the element's curvature
+    let use_closer_t = (ray.dir.z > 0.0) ^ (radius < 0.0);
+    let t = if use_closer_t { t0.min(t1) } else { t0.max(t1) };
+    if t < 0.0 { return None }
+
+    let n = Normal3((o + t * ray.dir).normalize()).faceforward(-ray.dir);
+    Some((t, n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A biconvex lens with 50mm radii, 5mm thick with an index of 1.5, whose rear surface is 40mm
+    /// from the film.
+    const BICONVEX: [Float; 8] = [
+        50.0, 5.0, 1.5, 20.0,
+        -50.0, 40.0, 1.0, 20.0,
+    ];
+
+    fn axis_crossing_z(ray: &Ray) -> Float {
+        let t = -ray.origin.x / ray.dir.x;
+        ray.at(t).z
+    }
+
+    #[test]
+    fn test_single_lens_focus() {
+        let focus_distance = 10.0;
+        let camera = RealisticCamera::new(
+            Transform::IDENTITY,
+            (0.0, 1.0),
+            &BICONVEX,
+            20.0,
+            focus_distance,
+            true,
+            Point2i::new(32, 32),
+            35.0
+        ).unwrap();
+
+        // Thick lens equation gives an effective focal length of ~50.85mm and a back focal
+        // distance of ~49.15mm. Focused at 10m, the image is ~0.26mm further back.
+        let f = 0.050847;
+        let image_distance = f * focus_distance / (focus_distance - f);
+        let expected_rear_thickness = 0.049153 + (image_distance - f);
+        assert!((camera.lens_rear_z() - expected_rear_thickness).abs() < 5e-5, "{}", camera.lens_rear_z());
+
+        // An on-axis point at the focus distance should be imaged onto the film plane
+        let p_scene = Point3f::new(0.0, 0.0, focus_distance);
+        let p_lens = Point3f::new(1e-4, 0.0, camera.lens_front_z());
+        let ray = Ray { origin: p_scene, dir: (p_lens - p_scene).normalize(), t_max: INFINITY, time: 0.0 };
+        let r_film = camera.trace_lenses_from_scene(&ray).unwrap();
+        assert!(axis_crossing_z(&r_film).abs() < 1e-4, "{}", axis_crossing_z(&r_film));
+
+        // A ray parallel to the axis focuses at the back focal distance behind the rear surface,
+        // which is behind the film since the lens was moved out to focus at 10m.
+        let ray = Ray {
+            origin: Point3f::new(1e-4, 0.0, camera.lens_front_z() + 1.0),
+            dir: Vec3f::new(0.0, 0.0, -1.0),
+            t_max: INFINITY,
+            time: 0.0
+        };
+        let r_film = camera.trace_lenses_from_scene(&ray).unwrap();
+        let expected_z = camera.lens_rear_z() - 0.049153;
+        assert!((axis_crossing_z(&r_film) - expected_z).abs() < 1e-4, "{}", axis_crossing_z(&r_film));
+    }
+
+    #[test]
+    fn test_generated_rays_leave_front_of_lens() {
+        let camera = RealisticCamera::new(
+            Transform::IDENTITY,
+            (0.0, 1.0),
+            &BICONVEX,
+            20.0,
+            10.0,
+            true,
+            Point2i::new(8, 8),
+            35.0
+        ).unwrap();
+
+        let sample = CameraSample { p_film: Point2f::new(4.0, 4.0), p_lens: Point2f::new(0.5, 0.5), time: 0.0 };
+        let (weight, ray) = camera.generate_ray(sample);
+        assert!(weight > 0.0);
+        assert!(ray.dir.z > 0.0);
+        assert!(ray.origin.z > camera.lens_rear_z());
+    }
+}
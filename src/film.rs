@@ -4,15 +4,75 @@ use crate::spectrum::{Spectrum, xyz_to_rgb, CoefficientSpectrum};
 use cgmath::vec2;
 use smallvec::SmallVec;
 use parking_lot::Mutex;
-use image::{ImageBuffer, Rgb};
+use image::{ImageBuffer, Rgb, Rgba, Luma};
 use arrayvec::ArrayVec;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use image::hdr::HdrEncoder;
+use crate::imageio::spectrum_to_image;
+use crate::imageio::exr::write_exr;
+use crate::imageio::pfm::write_pfm;
 
 const FILTER_TABLE_WIDTH: usize = 16;
 
+const RAW_FILM_MAGIC: &[u8; 8] = b"FTNFILM4";
+
 #[derive(Default, Debug, PartialEq, Clone, Copy)]
 pub struct Pixel {
     pub xyz: [Float; 3],
     pub filter_weight_sum: Float,
+    pub lum_sum: Float,
+    pub lum_sq_sum: Float,
+    pub n_samples: u32,
+    /// Filter weighted sum of sample coverage, 1 for samples that hit geometry and 0 for ones
+    /// that escaped.
+    pub alpha_sum: Float,
+    /// Like `xyz`, but only summing the samples that hit geometry.
+    pub covered_xyz: [Float; 3],
+}
+
+impl Pixel {
+    /// Estimated variance of the pixel's mean luminance, i.e. the sample variance of the
+    /// luminance divided by the number of samples.
+    pub fn variance(&self) -> Float {
+        if self.n_samples < 2 {
+            return 0.0;
+        }
+        let n = self.n_samples as Float;
+        let sample_variance = (self.lum_sq_sum - self.lum_sum * self.lum_sum / n) / (n - 1.0);
+        Float::max(0.0, sample_variance) / n
+    }
+}
+
+/// A float that can be added to concurrently, stored as its bits.
+#[derive(Debug, Default)]
+struct AtomicFloat(AtomicU32);
+
+impl AtomicFloat {
+    fn new(v: Float) -> Self {
+        Self(AtomicU32::new(v.to_bits()))
+    }
+
+    fn load(&self) -> Float {
+        Float::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, v: Float) {
+        self.0.store(v.to_bits(), Ordering::Relaxed)
+    }
+
+    fn add(&self, v: Float) {
+        let mut old = self.0.load(Ordering::Relaxed);
+        loop {
+            let new = (Float::from_bits(old) + v).to_bits();
+            match self.0.compare_exchange_weak(old, new, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => old = current,
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -22,13 +82,29 @@ pub struct Film<F: Filter> {
     pub diagonal: Float,
     pub filter: F,
     pub pixels: Mutex<Vec<Pixel>>,
+    /// Unfiltered xyz contributions made at arbitrary film positions with `add_splat`, e.g. by
+    /// light tracing, rather than through the camera sample of the pixel.
+    splat: Vec<[AtomicFloat; 3]>,
+    /// Factor applied to the splats in the final image, see `set_splat_scale`.
+    splat_scale: AtomicFloat,
     filter_table: [[Float; FILTER_TABLE_WIDTH]; FILTER_TABLE_WIDTH],
+    outlier_threshold: Option<Float>,
+    track_coverage: bool,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
 struct FilmTilePixel {
     contrib_sum: CoefficientSpectrum<3>,
     filter_weight_sum: Float,
+    lum_sum: Float,
+    lum_sq_sum: Float,
+    n_samples: u32,
+    alpha_sum: Float,
+    covered_contrib_sum: CoefficientSpectrum<3>,
+    /// The film pixel's luminance sum and sample count when the tile was made, so that outlier
+    /// rejection can use samples from earlier passes. Not merged back into the film.
+    film_lum_sum: Float,
+    film_n_samples: u32,
 }
 
 #[derive(Debug)]
@@ -39,24 +115,47 @@ pub struct FilmTile {
     pixels: Vec<FilmTilePixel>,
 }
 
+/// Checks that a crop window, given in NDC, covers some of the image.
+pub fn check_crop_window(crop_window: &Bounds2f) -> Result<(), String> {
+    let valid = crop_window.min.x < crop_window.max.x
+        && crop_window.min.y < crop_window.max.y
+        && crop_window.min.x < 1.0 && crop_window.max.x > 0.0
+        && crop_window.min.y < 1.0 && crop_window.max.y > 0.0;
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid crop window [{:?}, {:?}]: min must be less than max and overlap [0, 1]",
+            crop_window.min, crop_window.max
+        ))
+    }
+}
+
 impl<F: Filter> Film<F> {
+    /// Panics if `crop_window` is empty or lies outside of the image (see `check_crop_window`).
     pub fn new(
         resolution: Point2i,
         crop_window: Bounds2f,
         filter: F,
         diagonal: Float
     ) -> Self {
-        let low_x = (resolution.x as Float * crop_window.min.x).ceil() as i32;
-        let low_y = (resolution.y as Float * crop_window.min.y).ceil() as i32;
-        let high_x = (resolution.x as Float * crop_window.max.x).ceil() as i32;
-        let high_y = (resolution.y as Float * crop_window.max.y).ceil() as i32;
+        if let Err(msg) = check_crop_window(&crop_window) {
+            panic!("{}", msg);
+        }
+        let full_bounds = Bounds2f::with_bounds(
+            Point2f::new(0.0, 0.0),
+            Point2f::new(resolution.x as Float, resolution.y as Float)
+        );
+        let low = full_bounds.lerp(crop_window.min);
+        let high = full_bounds.lerp(crop_window.max);
 
         let cropped_pixel_bounds = Bounds2i::with_bounds(
-            Point2i::new(low_x, low_y),
-            Point2i::new(high_x, high_y)
+            Point2i::new(low.x.ceil() as i32, low.y.ceil() as i32),
+            Point2i::new(high.x.ceil() as i32, high.y.ceil() as i32)
         );
 
         let pixels = vec![Default::default(); cropped_pixel_bounds.area() as usize];
+        let splat = (0..cropped_pixel_bounds.area()).map(|_| Default::default()).collect();
 
         let mut filter_table = [[0.0f32; FILTER_TABLE_WIDTH]; FILTER_TABLE_WIDTH];
         for (y, row) in filter_table.iter_mut().enumerate() {
@@ -76,10 +175,36 @@ impl<F: Filter> Film<F> {
             diagonal,
             filter,
             pixels: Mutex::new(pixels),
+            splat,
+            splat_scale: AtomicFloat::new(1.0),
             filter_table,
+            outlier_threshold: None,
+            track_coverage: false,
         }
     }
 
+    /// Records which camera samples hit geometry, for the alpha channel of `into_rgba_image`.
+    /// Finding this costs the renderer an extra intersection test per camera sample, so without
+    /// it every sample counts as covered.
+    pub fn with_coverage(mut self) -> Self {
+        self.track_coverage = true;
+        self
+    }
+
+    pub fn tracks_coverage(&self) -> bool {
+        self.track_coverage
+    }
+
+    /// Scales down samples whose luminance is more than `k` times the mean luminance of the samples
+    /// taken before them in the same pixel, to `k` times that mean. That includes samples already
+    /// merged into the film when the sample's tile was made, e.g. by an earlier pass or a resumed
+    /// render. Each pixel's samples all land in the same tile and are taken in a fixed order, so the
+    /// result doesn't depend on how tiles are scheduled.
+    pub fn with_outlier_rejection(mut self, k: Float) -> Self {
+        self.outlier_threshold = Some(k);
+        self
+    }
+
     /// The range of pixel values that must be sampled,
     /// this is larger than the size of the image to allow pixels
     /// at the edge to have an equal number of samples.
@@ -93,23 +218,37 @@ impl<F: Filter> Film<F> {
     }
 
     pub fn get_film_tile(&self, sample_bounds: Bounds2i) -> FilmTile {
-        let p0x = (sample_bounds.min.x as Float - 0.5 - self.filter.radius().0.x).ceil() as i32;
-        let p0y = (sample_bounds.min.y as Float - 0.5 - self.filter.radius().0.y).ceil() as i32;
-
-        let p1x = (sample_bounds.max.x as Float - 0.5 + self.filter.radius().0.x + 1.0).ceil() as i32;
-        let p1y = (sample_bounds.max.y as Float - 0.5 - self.filter.radius().0.y + 1.0).ceil() as i32;
-
-        let p0 = Point2i::new(p0x, p0y);
-        let p1 = Point2i::new(p1x, p1y);
+        // The pixels reached by samples in the bounds, using the same (exclusive) upper bound as
+        // add_sample_to_tile so the footprint is the same on every side.
+        let radius = self.filter.radius().0;
+        let p0 = Point2i::new(
+            (sample_bounds.min.x as Float - 0.5 - radius.x).ceil() as i32,
+            (sample_bounds.min.y as Float - 0.5 - radius.y).ceil() as i32,
+        );
+        let p1 = Point2i::new(
+            (sample_bounds.max.x as Float - 0.5 + radius.x).floor() as i32 + 1,
+            (sample_bounds.max.y as Float - 0.5 + radius.y).floor() as i32 + 1,
+        );
 
         let tile_pixel_bounds = Bounds2i::with_bounds(p0, p1).intersection(&self.cropped_pixel_bounds);
 
-        FilmTile {
+        let mut tile = FilmTile {
             pixel_bounds: tile_pixel_bounds,
             filter_radius: self.filter.radius().0,
             inv_filter_radius: self.filter.radius().1,
             pixels: vec![Default::default(); tile_pixel_bounds.area().max(0) as usize],
+        };
+        if self.outlier_threshold.is_some() {
+            let pixels = self.pixels.lock();
+            for pixel in tile_pixel_bounds.iter_points() {
+                let film_pixel = &pixels[self.get_pixel_idx(pixel.into())];
+                let idx = tile.get_pixel_idx(pixel.into());
+                let tile_pixel = &mut tile.pixels[idx];
+                tile_pixel.film_lum_sum = film_pixel.lum_sum;
+                tile_pixel.film_n_samples = film_pixel.n_samples;
+            }
         }
+        tile
     }
 
     pub fn get_pixel_idx(&self, p: Point2i) -> usize {
@@ -128,16 +267,58 @@ impl<F: Filter> Film<F> {
                 merge_pixel.xyz[i] += xyz[i];
             }
             merge_pixel.filter_weight_sum += film_tile_pixel.filter_weight_sum;
+            merge_pixel.lum_sum += film_tile_pixel.lum_sum;
+            merge_pixel.lum_sq_sum += film_tile_pixel.lum_sq_sum;
+            merge_pixel.n_samples += film_tile_pixel.n_samples;
+            merge_pixel.alpha_sum += film_tile_pixel.alpha_sum;
+            let covered_xyz = film_tile_pixel.covered_contrib_sum.to_xyz();
+            for i in 0..3 {
+                merge_pixel.covered_xyz[i] += covered_xyz[i];
+            }
         }
     }
 
     // this satisfies the borrow checker when borrowing mutably to merge film tile, since the tile doesn't need to hold a reference
     // to the filter table and instead it is passed every time.
     pub fn add_sample_to_tile(&self, tile: &mut FilmTile, p_film: Point2f, radiance: Spectrum, sample_weight: Float) {
+        self.add_sample_with_alpha_to_tile(tile, p_film, radiance, 1.0, sample_weight)
+    }
+
+    /// Adds a sample along with its coverage `alpha`, which is 1 if the camera ray hit geometry
+    /// and 0 if it escaped to the background.
+    pub fn add_sample_with_alpha_to_tile(
+        &self,
+        tile: &mut FilmTile,
+        p_film: Point2f,
+        mut radiance: Spectrum,
+        alpha: Float,
+        sample_weight: Float
+    ) {
         let p_film_discrete = p_film - vec2(0.5, 0.5);
         let p0: Point2i = (p_film_discrete - tile.filter_radius).map(|v| v.ceil()).cast().unwrap();
+        // exclusive upper bound: the last pixel within the filter radius, plus one
         let p1: Point2i = (p_film_discrete + tile.filter_radius).map(|v| v.floor()).cast::<i32>().unwrap() + Vec2i::new(1, 1);
 
+        // Luminance moments are tracked for the pixel containing the sample, unfiltered
+        let p_pixel: Point2i = p_film.map(|v| v.floor()).cast().unwrap();
+        let bounds = tile.pixel_bounds;
+        if p_pixel.x >= bounds.min.x && p_pixel.x < bounds.max.x && p_pixel.y >= bounds.min.y && p_pixel.y < bounds.max.y {
+            let mut lum = (radiance * sample_weight).luminance();
+            let idx = tile.get_pixel_idx(p_pixel);
+            let pixel = &mut tile.pixels[idx];
+            let n_samples = pixel.film_n_samples + pixel.n_samples;
+            if let (Some(k), true) = (self.outlier_threshold, n_samples > 0) {
+                let mean = (pixel.film_lum_sum + pixel.lum_sum) / n_samples as Float;
+                if mean > 0.0 && lum > k * mean {
+                    radiance = radiance * (k * mean / lum);
+                    lum = k * mean;
+                }
+            }
+            pixel.lum_sum += lum;
+            pixel.lum_sq_sum += lum * lum;
+            pixel.n_samples += 1;
+        }
+
         let p0 = p0.max(tile.pixel_bounds.min);
         let p1 = p1.min(tile.pixel_bounds.max);
 
@@ -165,23 +346,185 @@ impl<F: Filter> Film<F> {
                 let filter_weight = self.filter_table[y_idx][x_idx];
                 let idx = tile.get_pixel_idx(Point2i::new(x, y));
                 let pixel = &mut tile.pixels[idx];
-                pixel.contrib_sum += radiance * sample_weight * filter_weight;
+                let contrib = radiance * sample_weight * filter_weight;
+                pixel.contrib_sum += contrib;
                 pixel.filter_weight_sum += filter_weight;
+                pixel.alpha_sum += alpha * filter_weight;
+                pixel.covered_contrib_sum += contrib * alpha;
             }
         }
     }
 
-    pub fn into_image_buffer(self) -> ImageBuffer<Rgb<f32>, Vec<f32>> {
-        let pixels = self.pixels.into_inner();
-        let rgb_flat_buffer: Vec<Float> = pixels.into_iter().flat_map(|pixel| {
-            let mut rgb = xyz_to_rgb(pixel.xyz);
-            if pixel.filter_weight_sum != 0.0 {
-                let inv_wt = 1.0 / pixel.filter_weight_sum;
-                for val in &mut rgb {
-                    *val = Float::max(0.0, *val * inv_wt);
+    /// Replaces the pixels with `image`, given in row major order over the cropped pixel bounds,
+    /// for integrators that compute the final value of each pixel themselves.
+    pub fn set_image(&self, image: &[Spectrum]) {
+        let mut pixels = self.pixels.lock();
+        assert_eq!(image.len(), pixels.len(), "Image doesn't match the film's dimensions");
+        for (pixel, l) in pixels.iter_mut().zip(image) {
+            *pixel = Pixel {
+                xyz: l.to_xyz(),
+                filter_weight_sum: 1.0,
+                alpha_sum: 1.0,
+                covered_xyz: l.to_xyz(),
+                ..Default::default()
+            };
+        }
+    }
+
+    /// Adds `value` to the pixel containing `p_film`, without filtering. Splats are accumulated
+    /// separately from the camera samples and multiplied by the splat scale in the final image.
+    pub fn add_splat(&self, p_film: Point2f, value: Spectrum) {
+        if value.has_nans() || value.luminance().is_infinite() {
+            tracing::warn!("Ignoring splat with NaN or infinite value {:?} at {:?}", value, p_film);
+            return;
+        }
+        let p: Point2i = p_film.map(|v| v.floor()).cast().unwrap();
+        let bounds = self.cropped_pixel_bounds;
+        if p.x < bounds.min.x || p.x >= bounds.max.x || p.y < bounds.min.y || p.y >= bounds.max.y {
+            return;
+        }
+        let xyz = value.to_xyz();
+        let splat = &self.splat[self.get_pixel_idx(p)];
+        for (s, &v) in splat.iter().zip(&xyz) {
+            s.add(v);
+        }
+    }
+
+    /// Sets the factor the splats are multiplied by in the final image. Integrators that splat
+    /// one estimate of the whole image per camera sample set this to one over the number of
+    /// samples per pixel, like pbrt's `splatScale`. It is 1 by default.
+    pub fn set_splat_scale(&self, scale: Float) {
+        self.splat_scale.store(scale);
+    }
+
+    /// The RGB value of a pixel's splats, scaled for the final image.
+    fn splat_rgb(&self, splat: &[AtomicFloat; 3]) -> [Float; 3] {
+        let splat_scale = self.splat_scale.load();
+        xyz_to_rgb([
+            splat[0].load() * splat_scale,
+            splat[1].load() * splat_scale,
+            splat[2].load() * splat_scale,
+        ])
+    }
+
+    /// The final RGB value of a pixel: its filtered samples plus its scaled splats.
+    fn pixel_rgb(&self, pixel: &Pixel, splat: &[AtomicFloat; 3]) -> [Float; 3] {
+        let mut rgb = xyz_to_rgb(pixel.xyz);
+        if pixel.filter_weight_sum != 0.0 {
+            let inv_wt = 1.0 / pixel.filter_weight_sum;
+            for val in &mut rgb {
+                *val = Float::max(0.0, *val * inv_wt);
+            }
+        }
+
+        for (val, splat_val) in rgb.iter_mut().zip(&self.splat_rgb(splat)) {
+            *val += splat_val;
+        }
+        rgb
+    }
+
+    /// Writes the accumulated pixel buffer (unnormalized xyz, filter weights and luminance moments)
+    /// and the unscaled splats to `path` so that the render can be inspected or resumed with
+    /// `load_raw`.
+    pub fn save_raw(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        // copy so rendering threads aren't blocked on IO
+        let pixels = self.pixels.lock().clone();
+        let (width, height) = self.cropped_pixel_bounds.dimensions();
+
+        // write to a temporary file first so a crash mid-write doesn't clobber the last checkpoint
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            writer.write_all(RAW_FILM_MAGIC)?;
+            writer.write_all(&(width as u32).to_le_bytes())?;
+            writer.write_all(&(height as u32).to_le_bytes())?;
+            for pixel in &pixels {
+                let [x, y, z] = pixel.xyz;
+                let [cx, cy, cz] = pixel.covered_xyz;
+                for &val in &[x, y, z, pixel.filter_weight_sum, pixel.lum_sum, pixel.lum_sq_sum, pixel.alpha_sum, cx, cy, cz] {
+                    writer.write_all(&val.to_le_bytes())?;
                 }
+                writer.write_all(&pixel.n_samples.to_le_bytes())?;
+            }
+            for splat in &self.splat {
+                for val in splat {
+                    writer.write_all(&val.load().to_le_bytes())?;
+                }
+            }
+            writer.flush()?;
+        }
+        std::fs::rename(tmp_path, path)
+    }
+
+    /// Replaces the pixel buffer and splats with ones previously written by `save_raw`. The saved
+    /// film must have the same cropped dimensions as this one.
+    pub fn load_raw(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        use std::io::{Error, ErrorKind};
+
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != RAW_FILM_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a raw film file"));
+        }
+
+        let mut buf = [0u8; 4];
+        let mut read_u32 = |reader: &mut BufReader<File>| -> std::io::Result<u32> {
+            reader.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        };
+
+        let (width, height) = self.cropped_pixel_bounds.dimensions();
+        let saved_width = read_u32(&mut reader)?;
+        let saved_height = read_u32(&mut reader)?;
+        if (saved_width, saved_height) != (width as u32, height as u32) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Raw film is {}x{} but film is {}x{}", saved_width, saved_height, width, height)
+            ));
+        }
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for _ in 0..(width * height) {
+            let mut vals = [0.0; 10];
+            for val in &mut vals {
+                *val = Float::from_bits(read_u32(&mut reader)?);
+            }
+            pixels.push(Pixel {
+                xyz: [vals[0], vals[1], vals[2]],
+                filter_weight_sum: vals[3],
+                lum_sum: vals[4],
+                lum_sq_sum: vals[5],
+                alpha_sum: vals[6],
+                covered_xyz: [vals[7], vals[8], vals[9]],
+                n_samples: read_u32(&mut reader)?,
+            });
+        }
+        let mut splats = Vec::with_capacity(pixels.len());
+        for _ in 0..pixels.len() {
+            let mut splat = [0.0; 3];
+            for val in &mut splat {
+                *val = Float::from_bits(read_u32(&mut reader)?);
             }
-            ArrayVec::from(rgb)
+            splats.push(splat);
+        }
+
+        // hold the pixel lock so the splats aren't replaced under a concurrent save_raw
+        let mut film_pixels = self.pixels.lock();
+        *film_pixels = pixels;
+        for (film_splat, splat) in self.splat.iter().zip(&splats) {
+            for (film_val, &val) in film_splat.iter().zip(splat) {
+                film_val.store(val);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_image_buffer(self) -> ImageBuffer<Rgb<f32>, Vec<f32>> {
+        let pixels = self.pixels.lock();
+        let rgb_flat_buffer: Vec<Float> = pixels.iter().zip(&self.splat).flat_map(|(pixel, splat)| {
+            ArrayVec::from(self.pixel_rgb(pixel, splat))
         }).collect();
 
         let (width, height) = self.cropped_pixel_bounds.dimensions();
@@ -192,22 +535,95 @@ impl<F: Filter> Film<F> {
         ).expect("Invalid dimensions when creating image buffer")
     }
     
-    pub fn into_spectrum_buffer(self) -> (Vec<Spectrum>, (u32, u32)) {
-        let pixels = self.pixels.into_inner();
-        let spectrum_buf = pixels.into_iter()
-            .map(|p| {
-                let rgb = Spectrum::from(xyz_to_rgb(p.xyz));
-                if p.filter_weight_sum != 0.0 {
-                    let inv_wt = 1.0 / p.filter_weight_sum;
-                    rgb.map(|x| Float::max(0.0, x * inv_wt))
-                } else {
-                    rgb
+    /// RGBA image with straight (not premultiplied) alpha giving the fraction of each pixel covered
+    /// by geometry (see `with_coverage`). Escaped samples are treated as transparent black, so the
+    /// color is the average over only the covered samples, and the radiance of the background they
+    /// saw is left out. Splats are added to the color as in `into_image_buffer`.
+    pub fn into_rgba_image(self) -> ImageBuffer<Rgba<f32>, Vec<f32>> {
+        let pixels = self.pixels.lock();
+        let rgba_flat_buffer: Vec<Float> = pixels.iter().zip(&self.splat).flat_map(|(pixel, splat)| {
+            let mut rgba = [0.0; 4];
+            if pixel.filter_weight_sum != 0.0 && pixel.alpha_sum > 0.0 {
+                // dividing by the covered weight rather than the total weight un-premultiplies
+                let inv_wt = 1.0 / pixel.alpha_sum;
+                for (val, &c) in rgba.iter_mut().zip(&xyz_to_rgb(pixel.covered_xyz)) {
+                    *val = Float::max(0.0, c * inv_wt);
                 }
-            })
+                rgba[3] = pixel.alpha_sum / pixel.filter_weight_sum;
+            }
+            for (val, splat_val) in rgba[..3].iter_mut().zip(&self.splat_rgb(splat)) {
+                *val += splat_val;
+            }
+            ArrayVec::from(rgba)
+        }).collect();
+
+        let (width, height) = self.cropped_pixel_bounds.dimensions();
+        ImageBuffer::from_vec(
+            width as u32,
+            height as u32,
+            rgba_flat_buffer
+        ).expect("Invalid dimensions when creating image buffer")
+    }
+
+    /// Grayscale image of the estimated variance of each pixel's luminance.
+    pub fn into_variance_image(self) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+        let pixels = self.pixels.into_inner();
+        let variance_buffer: Vec<Float> = pixels.iter().map(Pixel::variance).collect();
+
+        let (width, height) = self.cropped_pixel_bounds.dimensions();
+        ImageBuffer::from_vec(
+            width as u32,
+            height as u32,
+            variance_buffer
+        ).expect("Invalid dimensions when creating image buffer")
+    }
+
+    pub fn into_spectrum_buffer(self) -> (Vec<Spectrum>, (u32, u32)) {
+        self.spectrum_buffer()
+    }
+
+    /// The normalized RGB value of each pixel, along with the image dimensions.
+    pub fn spectrum_buffer(&self) -> (Vec<Spectrum>, (u32, u32)) {
+        let pixels = self.pixels.lock();
+        let spectrum_buf = pixels.iter()
+            .zip(&self.splat)
+            .map(|(p, splat)| Spectrum::from(self.pixel_rgb(p, splat)))
             .collect();
         let (w, h) = self.cropped_pixel_bounds.dimensions();
         (spectrum_buf, (w as u32, h as u32))
     }
+
+    /// Writes the image to `path`, choosing the format from the extension: `.exr`, `.hdr` and
+    /// `.pfm` are written as linear floating point, `.png` is clamped and gamma corrected.
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let ext = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        let (img, (w, h)) = self.spectrum_buffer();
+
+        match ext.as_deref() {
+            Some("exr") => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                write_exr(&mut writer, img, (w, h))?;
+            },
+            Some("hdr") => {
+                let pixels: Vec<Rgb<f32>> = img.iter().map(|s| Rgb(s.into_array())).collect();
+                let writer = BufWriter::new(File::create(path)?);
+                HdrEncoder::new(writer).encode(&pixels, w as usize, h as usize)?;
+            },
+            Some("pfm") => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                write_pfm(&mut writer, &img, (w, h))?;
+            },
+            Some("png") => {
+                let img: Vec<Spectrum> = img.into_iter().map(|s| s.clamp(0.0, 1.0)).collect();
+                spectrum_to_image(&img, (w as usize, h as usize)).save(path)?;
+            },
+            _ => anyhow::bail!("Unsupported image format for {:?}", path),
+        }
+        Ok(())
+    }
 }
 
 impl FilmTile {
@@ -225,13 +641,20 @@ impl FilmTile {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::filter::BoxFilter;
+    use crate::filter::{BoxFilter, TriangleFilter};
     use image::ConvertBuffer;
     use std::fs::File;
     use std::ops::Deref;
     use approx::relative_eq;
 
 
+    #[test]
+    #[should_panic(expected = "Invalid crop window")]
+    fn test_inverted_crop_window() {
+        let crop_window = ((0.0, 1.0), (1.0, 0.0)).into();
+        Film::new(Point2i::new(10, 10), crop_window, BoxFilter::default(), 1.0);
+    }
+
     #[test]
     fn test_add_one_sample() {
         let crop_window = ((0.0, 0.0), (1.0, 1.0)).into();
@@ -255,5 +678,270 @@ mod tests {
 //        encoder.encode(pixels.as_slice(), img.width() as usize, img.height() as usize).unwrap();
     }
 
+    #[test]
+    fn test_triangle_filter_reconstruction() {
+        let crop_window = ((0.0, 0.0), (1.0, 1.0)).into();
+        let radius = 2.0;
+        let film = Film::new(Point2i::new(10, 10), crop_window, TriangleFilter::new(Vec2f::new(radius, radius)), 1.0);
+
+        // a single sample at the center of pixel (5, 5)
+        let mut tile = film.get_film_tile(((5, 5), (6, 6)).into());
+        film.add_sample_to_tile(&mut tile, Point2f::new(5.5, 5.5), Spectrum::uniform(1.0), 1.0);
+        film.merge_film_tile(tile);
+
+        // The filter is looked up from a table, which evaluates the tent at the middle of each of
+        // the table's cells
+        let table_weight = |d: i32| {
+            let cell = ((d.abs() as Float * FILTER_TABLE_WIDTH as Float / radius) as usize).min(FILTER_TABLE_WIDTH - 1);
+            radius - (cell as Float + 0.5) * radius / FILTER_TABLE_WIDTH as Float
+        };
+
+        let pixels = film.pixels.lock();
+        let mut total_weight = 0.0;
+        for p in film.cropped_pixel_bounds.iter_points() {
+            let (dx, dy) = (p.0 - 5, p.1 - 5);
+            let pixel = &pixels[film.get_pixel_idx(p.into())];
+            let expected = if dx.abs() <= 2 && dy.abs() <= 2 {
+                table_weight(dx) * table_weight(dy)
+            } else {
+                0.0
+            };
+            assert!((pixel.filter_weight_sum - expected).abs() < 1e-5, "Pixel {:?}: {} != {}", p, pixel.filter_weight_sum, expected);
+            assert!((pixel.xyz[1] - expected).abs() < 1e-4, "Pixel {:?}: {:?}", p, pixel.xyz);
+            total_weight += pixel.filter_weight_sum;
+        }
+
+        let axis_sum: Float = (-2..=2).map(table_weight).sum();
+        assert!((total_weight - axis_sum * axis_sum).abs() < 1e-4);
+        // the neighbors on either side get the same weight in x and y
+        assert_eq!(pixels[film.get_pixel_idx(Point2i::new(5, 7))], pixels[film.get_pixel_idx(Point2i::new(7, 5))]);
+        assert_eq!(pixels[film.get_pixel_idx(Point2i::new(5, 3))], pixels[film.get_pixel_idx(Point2i::new(3, 5))]);
+    }
+
+    #[test]
+    fn test_tile_bounds_near_edge() {
+        let crop_window = ((0.0, 0.0), (1.0, 1.0)).into();
+        let radius = Vec2f::new(2.3, 2.3);
+        let film = Film::new(Point2i::new(10, 10), crop_window, BoxFilter::new(radius), 1.0);
+
+        // Samples in pixels [3, 5) x [7, 9) have discrete positions in [2.5, 4.5] x [6.5, 8.5],
+        // so reach pixels [ceil(0.2), floor(6.8)] x [ceil(4.2), floor(10.8)], clipped to the image
+        let tile = film.get_film_tile(((3, 7), (5, 9)).into());
+        assert_eq!(tile.pixel_bounds, Bounds2i::from(((1, 5), (7, 10))));
+
+        // symmetric in x and y away from the edges
+        let tile = film.get_film_tile(((4, 4), (6, 6)).into());
+        assert_eq!(tile.pixel_bounds, Bounds2i::from(((2, 2), (8, 8))));
+
+        // The bottom row of sample bounds still reaches the last row of the image
+        let sample_bounds = film.sample_bounds();
+        let bottom = Bounds2i::with_bounds(
+            Point2i::new(sample_bounds.min.x, sample_bounds.max.y - 1),
+            sample_bounds.max
+        );
+        assert_eq!(film.get_film_tile(bottom).pixel_bounds, Bounds2i::from(((0, 9), (10, 10))));
+
+        // With one sample in the center of every sampled pixel, rendered in small tiles, the edge
+        // pixels get the same support on all sides
+        for tile_bounds in sample_bounds.iter_tiles(2) {
+            let mut tile = film.get_film_tile(tile_bounds);
+            for (px, py) in tile_bounds.iter_points() {
+                let p = Point2f::new(px as Float + 0.5, py as Float + 0.5);
+                film.add_sample_to_tile(&mut tile, p, Spectrum::uniform(1.0), 1.0);
+            }
+            film.merge_film_tile(tile);
+        }
+        let pixels = film.pixels.lock();
+        let weight = |x, y| pixels[film.get_pixel_idx(Point2i::new(x, y))].filter_weight_sum;
+        assert!(weight(5, 5) > 0.0);
+        for &(x, y) in &[(5, 0), (5, 9), (0, 5), (9, 5), (0, 0), (9, 9)] {
+            assert_eq!(weight(x, y), weight(5, 5), "Pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn test_half_covered_pixel_alpha() {
+        let crop_window = ((0.0, 0.0), (1.0, 1.0)).into();
+        let film = Film::new(Point2i::new(3, 1), crop_window, BoxFilter::default(), 1.0);
+
+        let mut tile = film.get_film_tile(film.sample_bounds());
+        // the left pixel has one sample hitting geometry and one escaping to a bright background,
+        // the middle only hits and the right only escapes
+        let color = Spectrum::new([0.8, 0.4, 0.2]);
+        film.add_sample_with_alpha_to_tile(&mut tile, Point2f::new(0.25, 0.5), color, 1.0, 1.0);
+        film.add_sample_with_alpha_to_tile(&mut tile, Point2f::new(0.75, 0.5), Spectrum::uniform(5.0), 0.0, 1.0);
+        film.add_sample_with_alpha_to_tile(&mut tile, Point2f::new(1.5, 0.5), color, 1.0, 1.0);
+        film.add_sample_with_alpha_to_tile(&mut tile, Point2f::new(2.5, 0.5), Spectrum::uniform(5.0), 0.0, 1.0);
+        film.merge_film_tile(tile);
+
+        // the background is still part of the plain image
+        let (rgb, _) = film.spectrum_buffer();
+        assert!(rgb[0][1] > 2.0, "{:?}", rgb[0]);
+
+        let img = film.into_rgba_image();
+        let half = img.get_pixel(0, 0).0;
+        let full = img.get_pixel(1, 0).0;
+        assert_eq!(img.get_pixel(2, 0).0, [0.0; 4]);
+        assert!((half[3] - 0.5).abs() < 1e-5, "{:?}", half);
+        assert!((full[3] - 1.0).abs() < 1e-5, "{:?}", full);
+        // straight alpha: the covered part of the pixel has the same color in both
+        for c in 0..3 {
+            assert!((half[c] - full[c]).abs() < 1e-4, "{:?} {:?}", half, full);
+            assert!((full[c] - color[c]).abs() < 1e-3, "{:?}", full);
+        }
+    }
+
+    #[test]
+    fn test_save_load_raw() {
+        let crop_window = ((0.0, 0.0), (1.0, 1.0)).into();
+        let film = Film::new(Point2i::new(8, 6), crop_window, BoxFilter::default(), 1.0);
+
+        let mut tile = film.get_film_tile(film.sample_bounds());
+        for pixel in film.sample_bounds().iter_points() {
+            let p = Point2f::new(pixel.0 as Float + 0.3, pixel.1 as Float + 0.6);
+            let radiance = Spectrum::new([pixel.0 as Float * 0.1, pixel.1 as Float * 0.2, 0.5]);
+            film.add_sample_to_tile(&mut tile, p, radiance, 1.0);
+        }
+        film.merge_film_tile(tile);
+        film.add_splat(Point2f::new(3.5, 2.5), Spectrum::new([1.0, 2.0, 3.0]));
+
+        let path = std::env::temp_dir().join("fountain_test_save_load_raw.film");
+        film.save_raw(&path).unwrap();
+
+        let loaded = Film::new(Point2i::new(8, 6), crop_window, BoxFilter::default(), 1.0);
+        loaded.load_raw(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(*film.pixels.lock(), *loaded.pixels.lock());
+        assert_eq!(film.into_image_buffer(), loaded.into_image_buffer());
+
+        let wrong_size = Film::new(Point2i::new(4, 6), crop_window, BoxFilter::default(), 1.0);
+        let path = std::env::temp_dir().join("fountain_test_save_load_raw_wrong.film");
+        Film::new(Point2i::new(8, 6), crop_window, BoxFilter::default(), 1.0).save_raw(&path).unwrap();
+        assert!(wrong_size.load_raw(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_splat() {
+        let crop_window = ((0.0, 0.0), (1.0, 1.0)).into();
+        let film = Film::new(Point2i::new(4, 3), crop_window, BoxFilter::default(), 1.0);
+        film.add_splat(Point2f::new(2.7, 1.2), Spectrum::uniform(2.0));
+        film.add_splat(Point2f::new(2.1, 1.9), Spectrum::uniform(2.0));
+        // off the film
+        film.add_splat(Point2f::new(4.5, 1.5), Spectrum::uniform(2.0));
+
+        // as if rendered with two samples per pixel, so the splats are averaged over them
+        film.set_splat_scale(0.5);
+        // a sample in the splatted pixel doesn't change how the splats are scaled
+        let mut tile = film.get_film_tile(film.sample_bounds());
+        film.add_sample_to_tile(&mut tile, Point2f::new(2.5, 1.5), Spectrum::uniform(0.0), 1.0);
+        film.merge_film_tile(tile);
+
+        let expected = |x, y| if (x, y) == (2, 1) { 2.0 } else { 0.0 };
+        let (img, _) = film.spectrum_buffer();
+        for (i, s) in img.iter().enumerate() {
+            let (x, y) = (i as u32 % 4, i as u32 / 4);
+            for &val in &s.into_array() {
+                assert!((val - expected(x, y)).abs() < 1e-4, "({}, {}): {:?}", x, y, s);
+            }
+        }
+
+        let img = film.into_rgba_image();
+        for (x, y, pixel) in img.enumerate_pixels() {
+            for &val in &pixel.0[..3] {
+                assert!((val - expected(x, y)).abs() < 1e-4, "({}, {}): {:?}", x, y, pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn test_variance_image() {
+        let crop_window = ((0.0, 0.0), (1.0, 1.0)).into();
+        let film = Film::new(Point2i::new(2, 1), crop_window, BoxFilter::default(), 1.0);
+
+        let mut tile = film.get_film_tile(film.sample_bounds());
+        for i in 0..8 {
+            film.add_sample_to_tile(&mut tile, Point2f::new(0.5, 0.5), Spectrum::uniform(0.5), 1.0);
+            let alternating = if i % 2 == 0 { 0.0 } else { 1.0 };
+            film.add_sample_to_tile(&mut tile, Point2f::new(1.5, 0.5), Spectrum::uniform(alternating), 1.0);
+        }
+        film.merge_film_tile(tile);
+
+        let variance = film.into_variance_image();
+        assert!(variance.get_pixel(0, 0)[0].abs() < 1e-6);
+        assert!(variance.get_pixel(1, 0)[0] > 0.01);
+    }
+
+    #[test]
+    fn test_outlier_rejection() {
+        let crop_window = ((0.0, 0.0), (1.0, 1.0)).into();
+        let film = Film::new(Point2i::new(2, 1), crop_window, BoxFilter::default(), 1.0)
+            .with_outlier_rejection(10.0);
+
+        // both pixels get a few normal samples
+        let mut tile = film.get_film_tile(film.sample_bounds());
+        for _ in 0..4 {
+            film.add_sample_to_tile(&mut tile, Point2f::new(0.5, 0.5), Spectrum::uniform(1.0), 1.0);
+            film.add_sample_to_tile(&mut tile, Point2f::new(1.5, 0.5), Spectrum::uniform(1.0), 1.0);
+        }
+
+        // a firefly in the first pixel is clamped to 10x the running mean, the second pixel gets a
+        // slightly brighter but normal sample
+        film.add_sample_to_tile(&mut tile, Point2f::new(0.5, 0.5), Spectrum::uniform(1e4), 1.0);
+        film.add_sample_to_tile(&mut tile, Point2f::new(1.5, 0.5), Spectrum::uniform(2.0), 1.0);
+        film.merge_film_tile(tile);
+
+        {
+            let pixels = film.pixels.lock();
+            // the second pixel's samples add up to 4 + 2 and the first's to 4 + 10
+            let unit_y = pixels[1].xyz[1] / 6.0;
+            assert!(unit_y > 0.0);
+            assert!((pixels[0].xyz[1] - 14.0 * unit_y).abs() < 1e-3 * unit_y, "{:?}", pixels[0]);
+            assert!((pixels[0].lum_sum - 14.0).abs() < 1e-3, "{:?}", pixels[0]);
+        }
+
+        // the first sample of a pixel in a new tile is checked against the samples already in the
+        // film, so it's clamped to 10x the second pixel's mean of 6 / 5
+        let mut tile = film.get_film_tile(film.sample_bounds());
+        film.add_sample_to_tile(&mut tile, Point2f::new(1.5, 0.5), Spectrum::uniform(1e4), 1.0);
+        film.merge_film_tile(tile);
+        let pixels = film.pixels.lock();
+        assert!((pixels[1].lum_sum - 18.0).abs() < 1e-3, "{:?}", pixels[1]);
+    }
+
+    #[test]
+    fn test_save_hdr() {
+        let crop_window = ((0.0, 0.0), (1.0, 1.0)).into();
+        let film = Film::new(Point2i::new(4, 3), crop_window, BoxFilter::default(), 1.0);
+
+        let mut tile = film.get_film_tile(film.sample_bounds());
+        for pixel in film.sample_bounds().iter_points() {
+            let p = Point2f::new(pixel.0 as Float + 0.5, pixel.1 as Float + 0.5);
+            let radiance = Spectrum::new([pixel.0 as Float * 0.5, pixel.1 as Float * 2.0, 4.0]);
+            film.add_sample_to_tile(&mut tile, p, radiance, 1.0);
+        }
+        film.merge_film_tile(tile);
+
+        let path = std::env::temp_dir().join("fountain_test_save.hdr");
+        film.save(&path).unwrap();
+        let decoder = image::hdr::HdrDecoder::new(BufReader::new(File::open(&path).unwrap())).unwrap();
+        let meta = decoder.metadata();
+        let loaded = decoder.read_image_hdr().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((meta.width, meta.height), (4, 3));
+        let (expected, _) = film.spectrum_buffer();
+        for (loaded, expected) in loaded.iter().zip(expected.iter()) {
+            for c in 0..3 {
+                // RGBE only keeps 8 bits of mantissa per channel
+                assert!(relative_eq!(loaded[c], expected[c], max_relative = 1e-2, epsilon = 1e-2),
+                    "{:?} {:?}", loaded, expected);
+            }
+        }
+
+        assert!(film.save(std::env::temp_dir().join("fountain_test_save.bmp")).is_err());
+    }
+
 }
 
@@ -1,4 +1,5 @@
 use crate::{Float, Point2f, Vec2f};
+use crate::consts::PI;
 
 pub trait Filter {
     fn evaluate(&self, p: Point2f) -> Float;
@@ -6,12 +7,32 @@ pub trait Filter {
     fn radius(&self) -> (Vec2f, Vec2f);
 }
 
+impl<F: Filter + ?Sized> Filter for Box<F> {
+    fn evaluate(&self, p: Point2f) -> Float {
+        (**self).evaluate(p)
+    }
+
+    fn radius(&self) -> (Vec2f, Vec2f) {
+        (**self).radius()
+    }
+}
+
+fn inv_radius(radius: Vec2f) -> Vec2f {
+    Vec2f::new(1.0 / radius.x, 1.0 / radius.y)
+}
+
 #[derive(Debug)]
 pub struct BoxFilter {
     pub radius: Vec2f,
     pub inv_radius: Vec2f,
 }
 
+impl BoxFilter {
+    pub fn new(radius: Vec2f) -> Self {
+        Self { radius, inv_radius: inv_radius(radius) }
+    }
+}
+
 impl Filter for BoxFilter {
     fn evaluate(&self, _p: Point2f) -> Float {
         1.0
@@ -30,4 +51,179 @@ impl Default for BoxFilter {
             radius, inv_radius
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub struct TriangleFilter {
+    pub radius: Vec2f,
+    pub inv_radius: Vec2f,
+}
+
+impl TriangleFilter {
+    pub fn new(radius: Vec2f) -> Self {
+        Self { radius, inv_radius: inv_radius(radius) }
+    }
+}
+
+impl Filter for TriangleFilter {
+    fn evaluate(&self, p: Point2f) -> Float {
+        Float::max(0.0, self.radius.x - p.x.abs()) * Float::max(0.0, self.radius.y - p.y.abs())
+    }
+
+    fn radius(&self) -> (Vec2f, Vec2f) {
+        (self.radius, self.inv_radius)
+    }
+}
+
+#[derive(Debug)]
+pub struct GaussianFilter {
+    pub radius: Vec2f,
+    pub inv_radius: Vec2f,
+    alpha: Float,
+    exp_x: Float,
+    exp_y: Float,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: Vec2f, alpha: Float) -> Self {
+        Self {
+            radius,
+            inv_radius: inv_radius(radius),
+            alpha,
+            exp_x: (-alpha * radius.x * radius.x).exp(),
+            exp_y: (-alpha * radius.y * radius.y).exp(),
+        }
+    }
+
+    /// The gaussian shifted down so that it goes to zero at the filter radius
+    fn gaussian(&self, d: Float, exp_v: Float) -> Float {
+        Float::max(0.0, (-self.alpha * d * d).exp() - exp_v)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn evaluate(&self, p: Point2f) -> Float {
+        self.gaussian(p.x, self.exp_x) * self.gaussian(p.y, self.exp_y)
+    }
+
+    fn radius(&self) -> (Vec2f, Vec2f) {
+        (self.radius, self.inv_radius)
+    }
+}
+
+#[derive(Debug)]
+pub struct MitchellFilter {
+    pub radius: Vec2f,
+    pub inv_radius: Vec2f,
+    b: Float,
+    c: Float,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: Vec2f, b: Float, c: Float) -> Self {
+        Self { radius, inv_radius: inv_radius(radius), b, c }
+    }
+
+    /// The Mitchell-Netravali cubic, with `x` in [-1, 1] scaled to its support of [-2, 2]
+    fn mitchell_1d(&self, x: Float) -> Float {
+        let (b, c) = (self.b, self.c);
+        let x = (2.0 * x).abs();
+        if x > 1.0 {
+            ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) * (1.0 / 6.0)
+        } else {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b)) * (1.0 / 6.0)
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn evaluate(&self, p: Point2f) -> Float {
+        self.mitchell_1d(p.x * self.inv_radius.x) * self.mitchell_1d(p.y * self.inv_radius.y)
+    }
+
+    fn radius(&self) -> (Vec2f, Vec2f) {
+        (self.radius, self.inv_radius)
+    }
+}
+
+/// A sinc filter windowed by a second, wider sinc lobe with `tau` cycles in the filter radius.
+#[derive(Debug)]
+pub struct LanczosSincFilter {
+    pub radius: Vec2f,
+    pub inv_radius: Vec2f,
+    tau: Float,
+}
+
+impl LanczosSincFilter {
+    pub fn new(radius: Vec2f, tau: Float) -> Self {
+        Self { radius, inv_radius: inv_radius(radius), tau }
+    }
+
+    fn windowed_sinc(&self, x: Float, radius: Float) -> Float {
+        let x = x.abs();
+        if x > radius {
+            return 0.0;
+        }
+        sinc(x) * sinc(x / self.tau)
+    }
+}
+
+impl Filter for LanczosSincFilter {
+    fn evaluate(&self, p: Point2f) -> Float {
+        self.windowed_sinc(p.x, self.radius.x) * self.windowed_sinc(p.y, self.radius.y)
+    }
+
+    fn radius(&self) -> (Vec2f, Vec2f) {
+        (self.radius, self.inv_radius)
+    }
+}
+
+fn sinc(x: Float) -> Float {
+    let x = x.abs();
+    if x < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_triangle_filter_tent() {
+        let filter = TriangleFilter::new(Vec2f::new(2.0, 2.0));
+        let center = filter.evaluate(Point2f::new(0.0, 0.0));
+        assert_eq!(center, 4.0);
+
+        let mut prev = center;
+        for i in 1..=20 {
+            let x = i as Float * 0.1;
+            let v = filter.evaluate(Point2f::new(x, 0.0));
+            assert!(v < prev);
+            assert_eq!(v, filter.evaluate(Point2f::new(-x, 0.0)));
+            prev = v;
+        }
+        assert_eq!(filter.evaluate(Point2f::new(2.0, 0.5)), 0.0);
+        assert_eq!(filter.evaluate(Point2f::new(0.5, 2.5)), 0.0);
+    }
+
+    #[test]
+    fn test_sinc_filter_zero_crossings() {
+        let filter = LanczosSincFilter::new(Vec2f::new(4.0, 4.0), 3.0);
+        assert_abs_diff_eq!(filter.evaluate(Point2f::new(0.0, 0.0)), 1.0);
+
+        // Zeros of the sinc at nonzero integers, and of the window at multiples of tau
+        for &x in &[1.0, 2.0, 3.0, -1.0, -2.0, -3.0] {
+            assert_abs_diff_eq!(filter.evaluate(Point2f::new(x, 0.0)), 0.0, epsilon = 1e-6);
+            assert_abs_diff_eq!(filter.evaluate(Point2f::new(0.0, x)), 0.0, epsilon = 1e-6);
+        }
+
+        // Negative lobe between the first and second zero crossings
+        assert!(filter.evaluate(Point2f::new(1.5, 0.0)) < 0.0);
+        assert_eq!(filter.evaluate(Point2f::new(4.5, 0.0)), 0.0);
+    }
+}
@@ -114,5 +114,25 @@ mod tests {
         let expected = Spectrum::uniform(0.611180067);
         assert_eq!(fresnel.evaluate(cos_theta_wi), expected)
     }
+
+    #[test]
+    fn test_fresnel_conductor_copper() {
+        // RGB approximations of copper's complex index of refraction
+        let fresnel = FresnelConductor {
+            eta_i: Spectrum::uniform(1.0),
+            eta_t: Spectrum::new([0.2004, 0.9240, 1.1022]),
+            k: Spectrum::new([3.9129, 2.4528, 2.1421]),
+        };
+        // at normal incidence, R = ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2)
+        let expected = [0.95215, 0.61967, 0.51056];
+        for &cos_i in &[1.0, -1.0] {
+            let r = fresnel.evaluate(cos_i);
+            for i in 0..3 {
+                assert!((r[i] - expected[i]).abs() < 1e-4, "{:?}", r);
+            }
+        }
+
+        assert_eq!(FresnelNoOp.evaluate(0.3), Spectrum::uniform(1.0));
+    }
 }
 
@@ -0,0 +1,170 @@
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, SquareMatrix};
+
+use crate::{Bounds3f, Float, Transform, Vec3f};
+
+/// A transform that varies over time between two keyframes. The keyframes are decomposed into
+/// translation, rotation and scale, which are interpolated separately so that e.g. a rotating
+/// object doesn't shrink partway through the motion.
+#[derive(Clone, Copy, Debug)]
+pub struct AnimatedTransform {
+    start_transform: Transform,
+    end_transform: Transform,
+    start_time: Float,
+    end_time: Float,
+    actually_animated: bool,
+    has_rotation: bool,
+    translation: [Vec3f; 2],
+    rotation: [Quaternion<Float>; 2],
+    scale: [Matrix4<Float>; 2],
+}
+
+impl AnimatedTransform {
+    pub fn new(start_transform: Transform, start_time: Float, end_transform: Transform, end_time: Float) -> Self {
+        let (t0, r0, s0) = decompose(&start_transform.t);
+        let (t1, mut r1, s1) = decompose(&end_transform.t);
+        // interpolate along the shorter path
+        if r0.dot(r1) < 0.0 {
+            r1 = -r1;
+        }
+        Self {
+            start_transform,
+            end_transform,
+            start_time,
+            end_time,
+            actually_animated: start_transform.t != end_transform.t,
+            has_rotation: r0.dot(r1) < 0.9995,
+            translation: [t0, t1],
+            rotation: [r0, r1],
+            scale: [s0, s1],
+        }
+    }
+
+    /// A transform that doesn't change over time.
+    pub fn fixed(transform: Transform) -> Self {
+        Self::new(transform, 0.0, transform, 1.0)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.actually_animated
+    }
+
+    pub fn interpolate(&self, time: Float) -> Transform {
+        if !self.actually_animated || time <= self.start_time {
+            return self.start_transform;
+        }
+        if time >= self.end_time {
+            return self.end_transform;
+        }
+
+        let dt = (time - self.start_time) / (self.end_time - self.start_time);
+        let translation = (1.0 - dt) * self.translation[0] + dt * self.translation[1];
+        let rotation = self.rotation[0].slerp(self.rotation[1], dt);
+        let scale = self.scale[0] * (1.0 - dt) + self.scale[1] * dt;
+
+        // Keyframes that flatten the object along some axis interpolate to a scale with no
+        // inverse, so use the nearest keyframe as given instead.
+        let scale = match scale.invert() {
+            Some(scale_inv) => Transform::new(scale, scale_inv),
+            None if dt < 0.5 => return self.start_transform,
+            None => return self.end_transform,
+        };
+        Transform::translate(translation) * Transform::from_mat(Matrix4::from(rotation)) * scale
+    }
+
+    /// Bounds `b` over the whole range of motion.
+    pub fn motion_bounds(&self, b: Bounds3f) -> Bounds3f {
+        if !self.actually_animated {
+            return self.start_transform.transform(b);
+        }
+        let endpoints = self.start_transform.transform(b).join(&self.end_transform.transform(b));
+        if !self.has_rotation {
+            // translation and scale are interpolated linearly, so the extremes are at the endpoints
+            return endpoints;
+        }
+
+        // rotating corners can sweep outside of the endpoints' bounds, so sample the motion
+        const N_STEPS: usize = 128;
+        (1..N_STEPS).fold(endpoints, |bounds, i| {
+            let t = self.start_time + (self.end_time - self.start_time) * i as Float / N_STEPS as Float;
+            bounds.join(&self.interpolate(t).transform(b))
+        })
+    }
+}
+
+/// Decomposes an affine transformation matrix into its translation, rotation and the remaining
+/// scale (and shear), such that `m = T * R * S`.
+fn decompose(m: &Matrix4<Float>) -> (Vec3f, Quaternion<Float>, Matrix4<Float>) {
+    let translation = m.w.truncate();
+
+    // the upper 3x3 part, with translation and projection removed
+    let mut m = *m;
+    m.w = cgmath::vec4(0.0, 0.0, 0.0, 1.0);
+    m.x.w = 0.0;
+    m.y.w = 0.0;
+    m.z.w = 0.0;
+
+    // polar decomposition by repeatedly averaging the matrix with its inverse transpose
+    let mut r = m;
+    for _ in 0..100 {
+        let r_it = match r.transpose().invert() {
+            Some(r_it) => r_it,
+            None => break,
+        };
+        let r_next = (r + r_it) * 0.5;
+        let mut norm: Float = 0.0;
+        for i in 0..3 {
+            let diff = (r[0][i] - r_next[0][i]).abs() + (r[1][i] - r_next[1][i]).abs() + (r[2][i] - r_next[2][i]).abs();
+            norm = norm.max(diff);
+        }
+        r = r_next;
+        if norm < 0.0001 {
+            break;
+        }
+    }
+
+    let rotation = Quaternion::from(Matrix3::from_cols(r.x.truncate(), r.y.truncate(), r.z.truncate())).normalize();
+    let scale = r.invert().unwrap_or_else(Matrix4::identity) * m;
+    (translation, rotation, scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{assert_abs_diff_eq, Deg};
+    use crate::Point3f;
+
+    #[test]
+    fn test_interpolate() {
+        let start = Transform::translate(Vec3f::new(1.0, 0.0, 0.0)) * Transform::scale(2.0, 2.0, 2.0);
+        let end = Transform::translate(Vec3f::new(3.0, 0.0, 0.0))
+            * Transform::rotate_z(Deg(90.0))
+            * Transform::scale(2.0, 2.0, 2.0);
+        let anim = AnimatedTransform::new(start, 0.0, end, 1.0);
+        assert!(anim.is_animated());
+
+        let p = Point3f::new(1.0, 0.0, 0.0);
+        assert_abs_diff_eq!(anim.interpolate(0.0).transform(p), Point3f::new(3.0, 0.0, 0.0), epsilon = 1e-5);
+        assert_abs_diff_eq!(anim.interpolate(1.0).transform(p), Point3f::new(3.0, 2.0, 0.0), epsilon = 1e-5);
+
+        // halfway through, rotated 45 degrees without shrinking
+        let half = anim.interpolate(0.5).transform(p);
+        let s = Float::sqrt(2.0);
+        assert_abs_diff_eq!(half, Point3f::new(2.0 + s, s, 0.0), epsilon = 1e-4);
+
+        // the corner (1, 1) sweeps out further in y than at either endpoint
+        let bounds = anim.motion_bounds(bounds3f!((-1, -1, -1), (1, 1, 1)));
+        assert!(bounds.max.y >= 2.0 * s - 1e-3, "{:?}", bounds);
+    }
+
+    #[test]
+    fn test_interpolate_flattened() {
+        // both keyframes flatten z, so no interpolated transform can be inverted
+        let start = Transform::scale(1.0, 1.0, 0.0);
+        let end = Transform::translate(Vec3f::new(2.0, 0.0, 0.0)) * Transform::scale(1.0, 1.0, 0.0);
+        let anim = AnimatedTransform::new(start, 0.0, end, 1.0);
+
+        let p = Point3f::new(1.0, 1.0, 5.0);
+        assert_abs_diff_eq!(anim.interpolate(0.25).transform(p), Point3f::new(1.0, 1.0, 0.0), epsilon = 1e-5);
+        assert_abs_diff_eq!(anim.interpolate(0.75).transform(p), Point3f::new(3.0, 1.0, 0.0), epsilon = 1e-5);
+    }
+}
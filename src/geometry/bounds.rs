@@ -64,6 +64,25 @@ impl<S: Scalar> Bounds2<S> {
         let y = self.max.y - self.min.y;
         (x, y)
     }
+
+    /// Whether `p` lies within the bounds, including on the boundary.
+    pub fn inside(&self, p: Point2<S>) -> bool {
+        p.x >= self.min.x && p.x <= self.max.x && p.y >= self.min.y && p.y <= self.max.y
+    }
+
+    /// Grows the bounds by `delta` on every side.
+    pub fn expand(&self, delta: S) -> Self {
+        let d = Vector2::new(delta, delta);
+        Self::with_bounds(self.min - d, self.max + d)
+    }
+
+    /// The point `t` of the way from `min` to `max` in each dimension.
+    pub fn lerp(&self, t: Point2<S>) -> Point2<S> {
+        Point2::new(
+            self.min.x + (self.max.x - self.min.x) * t.x,
+            self.min.y + (self.max.y - self.min.y) * t.y,
+        )
+    }
 }
 
 impl<S: Scalar, T> From<(T, T)> for Bounds2<S> where Point2<S>: From<T> {
@@ -82,6 +101,19 @@ impl Bounds2<i32> {
         (y1..y2).flat_map(move |y| (x1..x2).map(move |x| (x, y)))
     }
 
+    /// Like `iter_points`, but x runs backwards on every other row so that consecutive points are
+    /// always adjacent.
+    pub fn iter_points_serpentine(self) -> impl Iterator<Item=(i32, i32)> {
+        let x1 = self.min.x;
+        let x2 = self.max.x;
+        let y1 = self.min.y;
+        let y2 = self.max.y;
+
+        (y1..y2).flat_map(move |y| (x1..x2).map(move |x| {
+            if (y - y1) % 2 == 0 { (x, y) } else { (x2 - 1 - (x - x1), y) }
+        }))
+    }
+
     pub fn iter_tiles(self, tile_size: usize) -> impl Iterator<Item=Bounds2i> {
         let xmin = self.min.x;
         let xmax = self.max.x;
@@ -166,6 +198,16 @@ impl <S: Scalar> Bounds3<S> {
         self.max - self.min
     }
 
+    pub fn surface_area(&self) -> S {
+        let d = self.diagonal();
+        S::from(2) * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn volume(&self) -> S {
+        let d = self.diagonal();
+        d.x * d.y * d.z
+    }
+
     pub fn maximum_extent(&self) -> u8 {
         let d = self.diagonal();
         if d.x > d.y && d.x > d.z {
@@ -262,6 +304,22 @@ mod test {
         assert_eq!(expected, points);
     }
 
+    #[test]
+    fn test_bounds_iter_serpentine() {
+        let bounds = Bounds2i::with_bounds(Point2i::new(-1, -2), Point2i::new(2, 1));
+        let points: Vec<_> = bounds.iter_points_serpentine().collect();
+        let expected = vec![
+            (-1, -2), (0, -2), (1, -2),
+            (1, -1), (0, -1), (-1, -1),
+            (-1, 0), (0, 0), (1, 0)
+        ];
+        assert_eq!(expected, points);
+
+        let mut sorted = points.clone();
+        sorted.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(sorted, bounds.iter_points().collect::<Vec<_>>());
+    }
+
     #[test]
     fn test_bounds_iter_tiles() {
         let small_bounds = Bounds2i::with_bounds((0, 0).into(), (2, 2).into());
@@ -288,6 +346,30 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_bounds2_helpers() {
+        let bounds = Bounds2f::with_bounds(Point2f::new(-1.0, 0.0), Point2f::new(1.0, 2.0));
+        assert!(bounds.inside(Point2f::new(1.0, 2.0)));
+        assert!(bounds.inside(Point2f::new(-1.0, 1.0)));
+        assert!(!bounds.inside(Point2f::new(1.5, 1.0)));
+
+        let expanded = bounds.expand(0.5);
+        assert_eq!(expanded, Bounds2f::with_bounds(Point2f::new(-1.5, -0.5), Point2f::new(1.5, 2.5)));
+        assert_eq!(expanded.lerp(Point2f::new(0.5, 0.5)), bounds.lerp(Point2f::new(0.5, 0.5)));
+        assert_eq!(bounds.lerp(Point2f::new(0.0, 1.0)), Point2f::new(-1.0, 2.0));
+    }
+
+    #[test]
+    fn test_bounds3_surface_area_and_volume() {
+        let cube = bounds3f!((0, 0, 0), (1, 1, 1));
+        assert_eq!(cube.surface_area(), 6.0);
+        assert_eq!(cube.volume(), 1.0);
+
+        let bounds = Bounds3::with_bounds(Point3::new(-1, 0, 2), Point3::new(1, 3, 6));
+        assert_eq!(bounds.surface_area(), 2 * (2 * 3 + 3 * 4 + 4 * 2));
+        assert_eq!(bounds.volume(), 24);
+    }
+
     #[test]
     fn test_bounds3f_intersect() {
         // basic hit
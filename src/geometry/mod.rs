@@ -4,13 +4,15 @@ use cgmath::InnerSpace;
 
 pub use bounds::*;
 pub use transform::*;
+pub use animated_transform::*;
 
-use crate::{Point3f, Vec3f};
+use crate::{ComponentWiseExt, Point3f, Vec3f};
 use crate::err_float::{next_float_down, next_float_up};
 use crate::Float;
 
 pub mod bounds;
 pub mod transform;
+pub mod animated_transform;
 
 pub fn distance(p1: Point3f, p2: Point3f) -> Float {
     (p1 - p2).magnitude()
@@ -50,14 +52,14 @@ pub fn max_dimension(v: Vec3f) -> usize {
     }
 }
 
+/// Builds an orthonormal basis around the unit vector `v1`, using the branchless construction from
+/// Duff et al. 2017, "Building an Orthonormal Basis, Revisited".
 pub fn coordinate_system(v1: Vec3f) -> (Vec3f, Vec3f) {
-    let v2 = if v1.x.abs() > v1.y.abs() {
-        Vec3f::new(-v1.z, 0.0, v1.x).normalize()
-    } else {
-        Vec3f::new(0.0, v1.z, -v1.y).normalize()
-    };
-
-    let v3 = v1.cross(v2);
+    let sign = Float::copysign(1.0, v1.z);
+    let a = -1.0 / (sign + v1.z);
+    let b = v1.x * v1.y * a;
+    let v2 = Vec3f::new(1.0 + sign * v1.x * v1.x * a, sign * b, -sign * v1.x);
+    let v3 = Vec3f::new(b, sign + v1.y * v1.y * a, -v1.y);
     (v2, v3)
 }
 
@@ -105,6 +107,34 @@ impl Ray {
     }
 }
 
+/// The permutation and shear that transform a ray to point along +z, used by the ray-triangle
+/// intersection. These only depend on the ray direction, so accelerators compute them once per ray
+/// and reuse them for every primitive they test (see `Primitive::intersect_sheared`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayShear {
+    pub(crate) kx: usize,
+    pub(crate) ky: usize,
+    pub(crate) kz: usize,
+    pub(crate) shear_x: Float,
+    pub(crate) shear_y: Float,
+    pub(crate) shear_z: Float,
+}
+
+impl RayShear {
+    pub fn new(ray: &Ray) -> Self {
+        let kz = max_dimension(ray.dir.abs());
+        let kx = (kz + 1) % 3;
+        let ky = (kx + 1) % 3;
+        let dir = permute_vec(ray.dir, kx, ky, kz);
+        Self {
+            kx, ky, kz,
+            shear_x: -dir.x / dir.z,
+            shear_y: -dir.y / dir.z,
+            shear_z: 1.0 / dir.z,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Differential {
     pub rx_origin: Point3f,
@@ -226,3 +256,36 @@ impl From<Normal3> for Vec3f {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+    use rand::distributions::UnitSphereSurface;
+
+    #[test]
+    fn test_coordinate_system_orthonormal() {
+        let mut rng = StdRng::from_seed([7; 32]);
+        let sphere_surf = UnitSphereSurface::new();
+        let axes = [
+            Vec3f::new(1.0, 0.0, 0.0), Vec3f::new(0.0, 1.0, 0.0), Vec3f::new(0.0, 0.0, 1.0),
+            Vec3f::new(0.0, 0.0, -1.0), Vec3f::new(1e-4, 0.0, -1.0).normalize(),
+            Vec3f::new(0.0, -1e-4, -1.0).normalize(),
+        ];
+        let random = (0..10000).map(|_| {
+            let [x, y, z] = sphere_surf.sample(&mut rng);
+            Vec3f::new(x as Float, y as Float, z as Float).normalize()
+        });
+
+        for v1 in axes.iter().copied().chain(random) {
+            let (v2, v3) = coordinate_system(v1);
+            for &v in &[v2, v3] {
+                assert!((v.magnitude() - 1.0).abs() < 1e-5, "{:?} {:?}", v1, v);
+            }
+            assert!(v1.dot(v2).abs() < 1e-5, "{:?}", v1);
+            assert!(v1.dot(v3).abs() < 1e-5, "{:?}", v1);
+            assert!(v2.dot(v3).abs() < 1e-5, "{:?}", v1);
+            // Right-handed like the cross product construction
+            assert!((v1.cross(v2) - v3).magnitude() < 1e-5, "{:?}", v1);
+        }
+    }
+}
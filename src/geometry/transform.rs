@@ -1,5 +1,5 @@
 use crate::{Float, Point3f, Vec3f, Normal3, Bounds3f, Ray, SurfaceInteraction, ComponentWiseExt, RayDifferential, Differential};
-use cgmath::{Matrix4, SquareMatrix, InnerSpace, Transform as cgTransform, Rad};
+use cgmath::{Matrix, Matrix4, SquareMatrix, InnerSpace, Transform as cgTransform, Rad};
 use crate::err_float::gamma;
 use crate::interaction::{SurfaceHit, DiffGeom, TextureDifferentials};
 
@@ -31,6 +31,8 @@ impl Transform {
         Self { t, invt }
     }
 
+    /// A transform from the 16 elements of a matrix in column-major order, as in pbrt's
+    /// `Transform` and `ConcatTransform` statements.
     pub fn from_flat(mat: [Float; 16]) -> Self {
         let m = Matrix4::new(
             mat[0], mat[1], mat[2], mat[3],
@@ -72,10 +74,34 @@ impl Transform {
         Self::new(m, m_inv)
     }
 
+    /// Rotation by `theta` about `axis`, which doesn't need to be normalized.
     pub fn rotate(theta: impl Into<Rad<Float>>, axis: Vec3f) -> Self {
         let a = axis.normalize();
-        let m = Matrix4::from_axis_angle(a, theta);
-        Self::from_mat(m)
+        let Rad(theta) = theta.into();
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let k = 1.0 - cos_theta;
+
+        // Rodrigues' rotation formula, with arguments in column-major order
+        let m = Matrix4::new(
+            a.x * a.x + (1.0 - a.x * a.x) * cos_theta,
+            a.x * a.y * k + a.z * sin_theta,
+            a.x * a.z * k - a.y * sin_theta,
+            0.0,
+
+            a.x * a.y * k - a.z * sin_theta,
+            a.y * a.y + (1.0 - a.y * a.y) * cos_theta,
+            a.y * a.z * k + a.x * sin_theta,
+            0.0,
+
+            a.x * a.z * k + a.y * sin_theta,
+            a.y * a.z * k - a.x * sin_theta,
+            a.z * a.z + (1.0 - a.z * a.z) * cos_theta,
+            0.0,
+
+            0.0, 0.0, 0.0, 1.0,
+        );
+        // rotation matrices are orthogonal
+        Self::new(m, m.transpose())
     }
 
     pub fn rotate_x(theta: impl Into<Rad<Float>>) -> Self {
@@ -386,9 +412,47 @@ impl Transformable for SurfaceInteraction<'_> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use cgmath::vec3;
+    use cgmath::{vec3, Deg};
     use cgmath::{assert_abs_diff_eq};
 
+    #[test]
+    fn test_from_flat() {
+        let identity = Transform::from_flat([
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ]);
+        assert_eq!(identity.t, Transform::IDENTITY.t);
+        assert_eq!(identity.invt, Transform::IDENTITY.invt);
+
+        let translation = Transform::from_flat([
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            2.0, -3.0, 4.0, 1.0,
+        ]);
+        let expected = Transform::translate(vec3(2.0, -3.0, 4.0));
+        assert_eq!(translation.t, expected.t);
+        assert_eq!(translation.invt, expected.invt);
+    }
+
+    #[test]
+    fn test_rotate_axis() {
+        let assert_tf_eq = |a: Transform, b: Transform| {
+            assert_abs_diff_eq!(a.t, b.t, epsilon = 1e-6);
+            assert_abs_diff_eq!(a.invt, b.invt, epsilon = 1e-6);
+        };
+        assert_tf_eq(Transform::rotate(Deg(90.0), vec3(2.0, 0.0, 0.0)), Transform::rotate_x(Deg(90.0)));
+        assert_tf_eq(Transform::rotate(Deg(-90.0), vec3(0.0, 1.0, 0.0)), Transform::rotate_y(Deg(-90.0)));
+        assert_tf_eq(Transform::rotate(Deg(90.0), vec3(0.0, 0.0, 1.0)), Transform::rotate_z(Deg(90.0)));
+
+        // a third of a turn about the diagonal cycles the axes x -> y -> z -> x
+        let diagonal = Transform::rotate(Deg(120.0), vec3(1.0, 1.0, 1.0));
+        assert_tf_eq(diagonal, Transform::rotate_z(Deg(90.0)) * Transform::rotate_x(Deg(90.0)));
+        assert_abs_diff_eq!(diagonal.transform(vec3(1.0, 0.0, 0.0)), vec3(0.0, 1.0, 0.0), epsilon = 1e-6);
+    }
+
     #[test]
     fn test_look_at() {
         let pos = (0.0, 0.0, -1.0).into();
@@ -16,31 +16,67 @@ use std::fmt::{Formatter, Debug};
 use std::time::Instant;
 
 pub mod exr;
+pub mod pfm;
+
+/// How the stored values of an image map to linear radiometric values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// Values are already linear, e.g. HDR formats or data textures like normal maps.
+    Linear,
+    /// Values are encoded with the sRGB transfer curve, as is usual for 8 bit formats.
+    Srgb,
+}
+
+impl ColorSpace {
+    /// The color space an image is assumed to be in when none is given: linear for EXR files and
+    /// sRGB for anything else.
+    pub fn from_extension(path: &Path) -> anyhow::Result<Self> {
+        match path.extension() {
+            Some(ext) if ext == "exr" => Ok(ColorSpace::Linear),
+            Some(_) => Ok(ColorSpace::Srgb),
+            None => anyhow::bail!("No extension on image file {:?}", path),
+        }
+    }
+
+    pub fn to_linear(self, s: Spectrum) -> Spectrum {
+        match self {
+            ColorSpace::Linear => s,
+            ColorSpace::Srgb => s.map(inverse_srgb),
+        }
+    }
+}
 
 #[derive(PartialEq, Eq, Hash)]
 pub struct ImageTexInfo {
     pub filename: PathBuf,
     pub wrap_mode: ImageWrap,
     // FIXME: ugly workaround
-    pub scale_float_bits: u32,
-    pub gamma: Option<bool>,
+    /// Per-channel scale applied to the linear texel values, stored as bits so the info can be
+    /// hashed.
+    pub scale_bits: [u32; 3],
+    /// The color space of the image file, or `None` to guess it from the file extension.
+    pub color_space: Option<ColorSpace>,
     pub flip_y: bool,
 }
 
 impl ImageTexInfo {
-    pub fn new(filename: impl Into<PathBuf>, wrap_mode: ImageWrap, scale: Float, gamma: Option<bool>, flip_y: bool) -> Self {
-        let scale_float_bits = scale.to_bits();
+    pub fn new(filename: impl Into<PathBuf>, wrap_mode: ImageWrap, scale: Spectrum, color_space: Option<ColorSpace>, flip_y: bool) -> Self {
+        let scale_bits = [scale[0].to_bits(), scale[1].to_bits(), scale[2].to_bits()];
         Self {
             filename: filename.into(),
             wrap_mode,
-            scale_float_bits,
-            gamma,
+            scale_bits,
+            color_space,
             flip_y
         }
     }
 
-    pub fn scale(&self) -> Float {
-        Float::from_bits(self.scale_float_bits)
+    pub fn scale(&self) -> Spectrum {
+        Spectrum::new([
+            Float::from_bits(self.scale_bits[0]),
+            Float::from_bits(self.scale_bits[1]),
+            Float::from_bits(self.scale_bits[2]),
+        ])
     }
 }
 
@@ -49,15 +85,15 @@ impl Debug for ImageTexInfo {
         f.debug_struct("ImageTexInfo")
             .field("filename", &self.filename)
             .field("wrap_mode", &self.wrap_mode)
-            .field("scale", &f32::from_bits(self.scale_float_bits))
-            .field("gamma", &self.gamma)
+            .field("scale", &self.scale())
+            .field("color_space", &self.color_space)
             .field("flip_y", &self.flip_y)
             .finish()
     }
 }
 
-#[tracing::instrument(skip(info))]
-pub fn get_mipmap(info: ImageTexInfo) -> anyhow::Result<Arc<MIPMap<Spectrum>>> {
+#[tracing::instrument(skip(info, images))]
+pub fn get_mipmap(info: ImageTexInfo, images: &DecodedImages) -> anyhow::Result<Arc<MIPMap<Spectrum>>> {
     // Global cache of mipmaps that have been loaded.
     static MIPMAPS: Lazy<Mutex<HashMap<ImageTexInfo, Arc<MIPMap<Spectrum>>>>> = Lazy::new(|| {
         Mutex::new(HashMap::new())
@@ -71,38 +107,64 @@ pub fn get_mipmap(info: ImageTexInfo) -> anyhow::Result<Arc<MIPMap<Spectrum>>> {
         },
         Entry::Vacant(e) => {
             let info = e.key();
-            let mipmap = load_mipmap(info)?;
+            let mipmap = load_mipmap(info, images)?;
             Ok(e.insert(Arc::new(mipmap)).clone())
         },
     }
 }
 
-#[tracing::instrument(skip(info))]
-pub fn load_mipmap(info: &ImageTexInfo) -> anyhow::Result<MIPMap<Spectrum>> {
-    let start = Instant::now();
-    let (mut image, dims) = load_image(&info.filename)?;
-
-    // TODO: more robust handling of gamma correction/color spaces
-    let gamma = match info.gamma {
-        Some(g) => g,
-        None => {
-            if let Some(ext) = info.filename.extension() {
-                match ext {
-                    s if s == "exr" => false,
-                    _ => true
-                }
-            } else {
-                anyhow::bail!("No extension on image file {:?}", &info.filename)
-            }
+/// The texels of an image file as stored, before any color space conversion, scale or flip.
+struct DecodedImage {
+    texels: Vec<Spectrum>,
+    dims: (usize, usize),
+}
+
+/// A cache of decoded image files, so that textures using the same file with different scales or
+/// color spaces only decode it once. Mipmaps keep their own converted copies of the texels, so a
+/// scene loader only keeps one of these (in its `Context`) while it's creating textures.
+#[derive(Default)]
+pub struct DecodedImages {
+    images: Mutex<HashMap<PathBuf, Arc<DecodedImage>>>,
+    /// How many files have been decoded into this cache.
+    #[cfg(test)]
+    decode_count: std::sync::atomic::AtomicUsize,
+}
+
+impl DecodedImages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(&self, path: &Path) -> anyhow::Result<Arc<DecodedImage>> {
+        if let Some(image) = self.images.lock().get(path) {
+            return Ok(image.clone());
         }
+        // decode without holding the lock, so other files can be loaded meanwhile
+        let (texels, dims) = load_image(path)?;
+        #[cfg(test)]
+        self.decode_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let image = self.images.lock()
+            .entry(path.to_path_buf())
+            .or_insert_with(|| Arc::new(DecodedImage { texels, dims }))
+            .clone();
+        Ok(image)
+    }
+}
+
+#[tracing::instrument(skip(info, images))]
+pub fn load_mipmap(info: &ImageTexInfo, images: &DecodedImages) -> anyhow::Result<MIPMap<Spectrum>> {
+    let start = Instant::now();
+    let decoded = images.get(&info.filename)?;
+    let mut image = decoded.texels.clone();
+    let dims = decoded.dims;
+
+    let color_space = match info.color_space {
+        Some(color_space) => color_space,
+        None => ColorSpace::from_extension(&info.filename)?,
     };
 
     image.iter_mut().for_each(|s| {
-        *s = if gamma {
-            s.map(inverse_gamma_correct)
-        } else {
-            *s
-        } * info.scale()
+        *s = color_space.to_linear(*s) * info.scale()
     });
 
     if info.flip_y {
@@ -120,7 +182,7 @@ pub fn load_mipmap(info: &ImageTexInfo) -> anyhow::Result<MIPMap<Spectrum>> {
         image,
         info.wrap_mode
     );
-    tracing::debug!(time = ?start.elapsed().as_millis(), gamma, scale = ?info.scale());
+    tracing::debug!(time = ?start.elapsed().as_millis(), ?color_space, scale = ?info.scale());
     Ok(mipmap)
 }
 
@@ -166,17 +228,81 @@ pub fn gamma_correct(v: Float) -> Float {
     }
 }
 
-pub fn inverse_gamma_correct(v: Float) -> Float {
+/// Decodes an sRGB encoded value to linear.
+pub fn inverse_srgb(v: Float) -> Float {
     if v <= 0.04045 {
-        v * 1.0 / 12.92
+        v / 12.92
     } else {
-        ((v + 0.055) * 1.0 / 1.055).powf(2.4)
+        ((v + 0.055) / 1.055).powf(2.4)
     }
 }
 
+/// Legacy name for `inverse_srgb`.
+pub fn inverse_gamma_correct(v: Float) -> Float {
+    inverse_srgb(v)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use approx::assert_abs_diff_eq;
+    use crate::Point2f;
+
+    #[test]
+    fn test_color_space_to_linear() {
+        // sRGB 0.5 (i.e. 128/255) is roughly 21% linear
+        let decoded = ColorSpace::Srgb.to_linear(Spectrum::new([0.5, 0.0, 1.0]));
+        assert_abs_diff_eq!(decoded, Spectrum::new([0.214_041, 0.0, 1.0]), epsilon = 1e-5);
+        assert_abs_diff_eq!(inverse_srgb(gamma_correct(0.3)), 0.3, epsilon = 1e-5);
 
+        let linear = Spectrum::new([0.5, 0.25, 0.75]);
+        assert_eq!(ColorSpace::Linear.to_linear(linear), linear);
+
+        assert_eq!(ColorSpace::from_extension(Path::new("normal.png")).unwrap(), ColorSpace::Srgb);
+        assert_eq!(ColorSpace::from_extension(Path::new("sky.exr")).unwrap(), ColorSpace::Linear);
+        assert!(ColorSpace::from_extension(Path::new("noext")).is_err());
+    }
 
+    #[test]
+    fn test_spectrum_scale_tints_mipmap() {
+        let path = std::env::temp_dir().join("fountain_test_scale_tint.png");
+        let img = image::RgbImage::from_pixel(2, 2, Rgb([255, 255, 51]));
+        img.save(&path).unwrap();
+
+        let scale = Spectrum::new([1.0, 0.5, 2.0]);
+        let info = ImageTexInfo::new(&path, ImageWrap::Repeat, scale, Some(ColorSpace::Linear), false);
+        assert_eq!(info.scale(), scale);
+        let mipmap = load_mipmap(&info, &DecodedImages::new()).unwrap();
+        assert_abs_diff_eq!(mipmap.lookup_trilinear_width(Point2f::new(0.5, 0.5), 0.0), Spectrum::new([1.0, 0.5, 0.4]), epsilon = 1e-5);
+
+        let info = ImageTexInfo::new(&path, ImageWrap::Repeat, Spectrum::uniform(3.0), Some(ColorSpace::Linear), false);
+        let mipmap = load_mipmap(&info, &DecodedImages::new()).unwrap();
+        assert_abs_diff_eq!(mipmap.lookup_trilinear_width(Point2f::new(0.5, 0.5), 0.0), Spectrum::new([3.0, 3.0, 0.6]), epsilon = 1e-5);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_scales_share_decoded_image() {
+        let path = std::env::temp_dir().join("fountain_test_shared_decode.png");
+        let img = image::RgbImage::from_pixel(2, 2, Rgb([255, 255, 255]));
+        img.save(&path).unwrap();
+
+        let images = DecodedImages::new();
+        let info = ImageTexInfo::new(&path, ImageWrap::Repeat, Spectrum::uniform(1.0), Some(ColorSpace::Linear), false);
+        let mipmap = get_mipmap(info, &images).unwrap();
+        assert_abs_diff_eq!(mipmap.lookup_trilinear_width(Point2f::new(0.5, 0.5), 0.0), Spectrum::uniform(1.0), epsilon = 1e-5);
+
+        let info = ImageTexInfo::new(&path, ImageWrap::Repeat, Spectrum::uniform(2.0), Some(ColorSpace::Linear), false);
+        let mipmap = get_mipmap(info, &images).unwrap();
+        assert_abs_diff_eq!(mipmap.lookup_trilinear_width(Point2f::new(0.5, 0.5), 0.0), Spectrum::uniform(2.0), epsilon = 1e-5);
+
+        assert_eq!(images.decode_count.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        // a separate cache, e.g. another scene's, decodes the file again
+        let other_images = DecodedImages::new();
+        let info = ImageTexInfo::new(&path, ImageWrap::Repeat, Spectrum::uniform(4.0), Some(ColorSpace::Linear), false);
+        get_mipmap(info, &other_images).unwrap();
+        assert_eq!(other_images.decode_count.load(std::sync::atomic::Ordering::Relaxed), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
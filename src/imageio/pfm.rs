@@ -0,0 +1,18 @@
+use std::io::Write;
+use crate::spectrum::Spectrum;
+
+/// Writes an RGB image in the Portable Float Map format. PFM stores scanlines from bottom to top,
+/// so the rows of `img` are written in reverse.
+pub fn write_pfm<W: Write>(writer: &mut W, img: &[Spectrum], dims: (u32, u32)) -> std::io::Result<()> {
+    let (w, h) = dims;
+    // a negative scale factor marks the data as little-endian
+    write!(writer, "PF\n{} {}\n-1\n", w, h)?;
+    for row in img.chunks_exact(w as usize).rev().take(h as usize) {
+        for s in row {
+            for &val in &s.into_array() {
+                writer.write_all(&val.to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
@@ -0,0 +1,664 @@
+use std::sync::Once;
+
+use bumpalo::Bump;
+use cgmath::{InnerSpace, Zero};
+
+use crate::{abs_dot, Float, Normal3, Point2f, Ray, RayDifferential, Vec3f};
+use crate::camera::Camera;
+use crate::integrator::IntegratorRadiance;
+use crate::interaction::SurfaceHit;
+use crate::light::{Light, LightFlags};
+use crate::loaders::{ParamSet, ParamError};
+use crate::material::TransportMode;
+use crate::reflection::BxDFType;
+use crate::reflection::bsdf::Bsdf;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::spectrum::Spectrum;
+
+/// Bidirectional path tracing. For each camera sample a subpath is traced from the camera and
+/// another from a light, and every prefix of one is connected to every prefix of the other. The
+/// strategies that can produce the same path are combined with multiple importance sampling, and
+/// connections of light subpaths directly to the camera are splatted onto the film.
+///
+/// Lights at infinity aren't supported: camera subpaths that escape the scene end there, and those
+/// lights are never chosen to start light subpaths since they can't sample rays leaving them.
+pub struct BDPTIntegrator {
+    max_depth: u16,
+}
+
+impl BDPTIntegrator {
+    pub fn new(max_depth: u16) -> Self {
+        Self { max_depth }
+    }
+
+    /// Construct from the scene's `Integrator` parameters, reading `maxdepth` (default 5).
+    pub fn from_params(params: &mut ParamSet) -> Result<Self, ParamError> {
+        let max_depth = params.get_one_or::<i32>("maxdepth", 5)?.max(0) as u16;
+        Ok(Self::new(max_depth))
+    }
+
+    fn camera_subpath<'a>(
+        &self,
+        ray: &RayDifferential,
+        scene: &'a Scene,
+        camera: &dyn Camera,
+        sampler: &mut dyn Sampler,
+        arena: &'a Bump,
+    ) -> Vec<Vertex<'a>> {
+        let max_vertices = self.max_depth as usize + 2;
+        let mut path = Vec::with_capacity(max_vertices);
+        let (_, pdf_dir) = camera.pdf_we(&ray.ray);
+        let beta = Spectrum::uniform(1.0);
+        let hit = SurfaceHit {
+            p: ray.ray.origin,
+            p_err: Vec3f::zero(),
+            time: ray.ray.time,
+            n: Normal3(Vec3f::zero()),
+        };
+        path.push(Vertex::endpoint(VertexKind::Camera, hit, None, beta));
+
+        let ray = RayDifferential { ray: ray.ray, diff: ray.diff };
+        random_walk(ray, scene, sampler, arena, beta, pdf_dir, max_vertices - 1, TransportMode::Radiance, &mut path);
+        path
+    }
+
+    fn light_subpath<'a>(
+        &self,
+        scene: &'a Scene,
+        sampler: &mut dyn Sampler,
+        arena: &'a Bump,
+        time: Float,
+    ) -> Vec<Vertex<'a>> {
+        let max_vertices = self.max_depth as usize + 1;
+        let mut path = Vec::with_capacity(max_vertices);
+        let n_lights = scene.num_lights();
+        if n_lights == 0 {
+            return path;
+        }
+        let light_num = ((sampler.get_1d() * n_lights as Float) as usize).min(n_lights - 1);
+        let light_pdf = 1.0 / n_lights as Float;
+        let light = scene.light(light_num);
+
+        let le = match light.sample_le(sampler.get_2d(), sampler.get_2d(), time) {
+            Some(le) if le.pdf_pos > 0.0 && le.pdf_dir > 0.0 && !le.radiance.is_black() => le,
+            _ => return path,
+        };
+        let hit = SurfaceHit { p: le.ray.origin, p_err: Vec3f::zero(), time, n: le.n_light };
+        let mut vertex = Vertex::endpoint(VertexKind::Light, hit, Some(light), le.radiance);
+        vertex.pdf_fwd = le.pdf_pos * light_pdf;
+        path.push(vertex);
+
+        let beta = le.radiance * abs_dot(le.n_light.0, le.ray.dir) / (light_pdf * le.pdf_pos * le.pdf_dir);
+        let ray = RayDifferential { ray: le.ray, diff: None };
+        random_walk(ray, scene, sampler, arena, beta, le.pdf_dir, max_vertices - 1, TransportMode::Importance, &mut path);
+        path
+    }
+
+    /// The unweighted contribution of the path made of the first `s` vertices of the light subpath
+    /// and the first `t` of the camera subpath, along with the raster position to splat it to when
+    /// `t` is 1. Connecting to the camera or to a light samples a new endpoint, which is returned
+    /// so the MIS weight can be computed with it.
+    #[allow(clippy::too_many_arguments)]
+    fn connect<'a>(
+        light_path: &[Vertex<'a>],
+        camera_path: &[Vertex<'a>],
+        s: usize,
+        t: usize,
+        scene: &'a Scene,
+        camera: &dyn Camera,
+        sampler: &mut dyn Sampler,
+        light_pdf: Float,
+    ) -> (Spectrum, Option<Vertex<'a>>, Option<Point2f>) {
+        let black = Spectrum::uniform(0.0);
+
+        if s == 0 {
+            // the camera subpath hit a light by itself
+            let pt = &camera_path[t - 1];
+            let l = if pt.is_light() { pt.le * pt.beta } else { black };
+            (l, None, None)
+        } else if t == 1 {
+            let qs = &light_path[s - 1];
+            if !qs.is_connectible() {
+                return (black, None, None);
+            }
+            let cam = match camera.sample_wi(&qs.hit, sampler.get_2d()) {
+                Some(cam) if cam.pdf > 0.0 && cam.importance > 0.0 => cam,
+                _ => return (black, None, None),
+            };
+            let sampled = Vertex::endpoint(
+                VertexKind::Camera,
+                cam.vis.p1,
+                None,
+                Spectrum::uniform(cam.importance / cam.pdf),
+            );
+            let mut l = qs.beta * qs.f(&sampled, TransportMode::Importance) * sampled.beta;
+            if qs.is_on_surface() {
+                l *= abs_dot(cam.wi, qs.ns.0);
+            }
+            if !l.is_black() && !cam.vis.unoccluded(scene) {
+                l = black;
+            }
+            (l, Some(sampled), Some(cam.p_raster))
+        } else if s == 1 {
+            let pt = &camera_path[t - 1];
+            if !pt.is_connectible() {
+                return (black, None, None);
+            }
+            let n_lights = scene.num_lights();
+            let light_num = ((sampler.get_1d() * n_lights as Float) as usize).min(n_lights - 1);
+            let light = scene.light(light_num);
+            let li = light.sample_incident_radiance(&pt.hit, sampler.get_2d());
+            if li.pdf == 0.0 || li.radiance.is_black() {
+                return (black, None, None);
+            }
+            let mut sampled = Vertex::endpoint(
+                VertexKind::Light,
+                li.vis.p1,
+                Some(light),
+                li.radiance / (li.pdf * light_pdf),
+            );
+            sampled.pdf_fwd = sampled.pdf_light_origin(pt, light_pdf);
+            let mut l = pt.beta * pt.f(&sampled, TransportMode::Radiance) * sampled.beta;
+            if pt.is_on_surface() {
+                l *= abs_dot(li.wi, pt.ns.0);
+            }
+            if !l.is_black() && !li.vis.unoccluded(scene) {
+                l = black;
+            }
+            (l, Some(sampled), None)
+        } else {
+            let qs = &light_path[s - 1];
+            let pt = &camera_path[t - 1];
+            if !qs.is_connectible() || !pt.is_connectible() {
+                return (black, None, None);
+            }
+            let mut l = qs.beta * qs.f(pt, TransportMode::Importance) * pt.f(qs, TransportMode::Radiance) * pt.beta;
+            if !l.is_black() {
+                l *= geometry_term(scene, qs, pt);
+            }
+            (l, None, None)
+        }
+    }
+}
+
+impl IntegratorRadiance for BDPTIntegrator {
+    fn preprocess(&mut self, _scene: &Scene, _sampler: &mut dyn Sampler) {
+    }
+
+    /// BDPT needs the camera to start its subpaths, so it can only find the radiance along rays
+    /// passed to `camera_ray_radiance`. Any other ray gets black.
+    fn incident_radiance(
+        &self,
+        _ray: &mut RayDifferential,
+        _scene: &Scene,
+        _sampler: &mut dyn Sampler,
+        _arena: &Bump,
+        _depth: u16,
+    ) -> Spectrum {
+        static WARN_NOT_FROM_CAMERA: Once = Once::new();
+        WARN_NOT_FROM_CAMERA.call_once(|| {
+            tracing::warn!("BDPT can only trace rays from the camera, returning black");
+        });
+        Spectrum::uniform(0.0)
+    }
+
+    fn camera_ray_radiance(
+        &self,
+        ray: &mut RayDifferential,
+        scene: &Scene,
+        camera: &dyn Camera,
+        sampler: &mut dyn Sampler,
+        arena: &Bump,
+        splat: &mut dyn FnMut(Point2f, Spectrum),
+    ) -> Spectrum {
+        let camera_path = self.camera_subpath(ray, scene, camera, sampler, arena);
+        let light_path = self.light_subpath(scene, sampler, arena, ray.ray.time);
+        let light_pdf = 1.0 / scene.num_lights().max(1) as Float;
+
+        let mut radiance = Spectrum::uniform(0.0);
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                // a path of s + t vertices has s + t - 2 bounces, and the camera can't be seen
+                // directly from a light
+                if s + t < 2 || s + t - 2 > self.max_depth as usize || (s == 1 && t == 1) {
+                    continue;
+                }
+
+                let (l, sampled, p_raster) =
+                    Self::connect(&light_path, &camera_path, s, t, scene, camera, sampler, light_pdf);
+                if l.is_black() {
+                    continue;
+                }
+                let l = l * mis_weight(&light_path, &camera_path, sampled.as_ref(), s, t, camera, light_pdf);
+                match p_raster {
+                    Some(p_raster) => splat(p_raster, l),
+                    None => radiance += l,
+                }
+            }
+        }
+        radiance
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+/// A vertex of a camera or light subpath.
+struct Vertex<'a> {
+    kind: VertexKind,
+    hit: SurfaceHit,
+    /// The shading normal at surfaces, or the geometric normal at endpoints.
+    ns: Normal3,
+    /// The direction back along the subpath.
+    wo: Vec3f,
+    bsdf: Option<Bsdf<'a>>,
+    /// The light at a light endpoint, or the area light at a surface that emits.
+    light: Option<&'a dyn Light>,
+    /// The radiance emitted back along the subpath, at surfaces.
+    le: Spectrum,
+    /// Throughput of the subpath up to and including this vertex.
+    beta: Spectrum,
+    /// Whether the subpath was continued with a specular bounce, so it can't be connected to.
+    delta: bool,
+    /// The density with respect to area with which the subpath sampled this vertex.
+    pdf_fwd: Float,
+    /// The density with respect to area with which this vertex would be sampled by the subpath
+    /// going in the other direction.
+    pdf_rev: Float,
+}
+
+impl<'a> Vertex<'a> {
+    fn endpoint(kind: VertexKind, hit: SurfaceHit, light: Option<&'a dyn Light>, beta: Spectrum) -> Self {
+        Self {
+            kind,
+            hit,
+            ns: hit.n,
+            wo: Vec3f::zero(),
+            bsdf: None,
+            light,
+            le: Spectrum::uniform(0.0),
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn is_on_surface(&self) -> bool {
+        !self.hit.n.0.is_zero()
+    }
+
+    fn is_light(&self) -> bool {
+        self.light.is_some()
+    }
+
+    fn is_delta_light(&self) -> bool {
+        self.kind == VertexKind::Light && self.light.map_or(false, |light| light.flags().is_delta_light())
+    }
+
+    fn is_connectible(&self) -> bool {
+        match self.kind {
+            VertexKind::Camera => true,
+            VertexKind::Light => match self.light.map(|light| light.flags()) {
+                Some(LightFlags::DeltaDirection) => false,
+                _ => true,
+            },
+            VertexKind::Surface => self.bsdf.as_ref()
+                .map_or(false, |bsdf| bsdf.num_components(BxDFType::all() & !BxDFType::SPECULAR) > 0),
+        }
+    }
+
+    /// Converts a density with respect to solid angle at this vertex to one with respect to area
+    /// at `next`.
+    fn convert_density(&self, pdf: Float, next: &Vertex) -> Float {
+        let w = next.hit.p - self.hit.p;
+        let dist_sq = w.magnitude2();
+        if dist_sq == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / dist_sq;
+        if next.is_on_surface() {
+            pdf *= abs_dot(next.hit.n.0, w / dist_sq.sqrt());
+        }
+        pdf
+    }
+
+    /// The BSDF for scattering from the direction back along the subpath towards `next`.
+    fn f(&self, next: &Vertex, mode: TransportMode) -> Spectrum {
+        let wi = (next.hit.p - self.hit.p).normalize();
+        match &self.bsdf {
+            Some(bsdf) => bsdf.f(self.wo, wi, BxDFType::all()) * self.correct_shading_normal(wi, mode),
+            None => Spectrum::uniform(0.0),
+        }
+    }
+
+    /// Corrects for the asymmetry of scattering with shading normals when tracing importance.
+    fn correct_shading_normal(&self, wi: Vec3f, mode: TransportMode) -> Float {
+        match mode {
+            TransportMode::Radiance => 1.0,
+            TransportMode::Importance => {
+                let num = abs_dot(self.wo, self.ns.0) * abs_dot(wi, self.hit.n.0);
+                let denom = abs_dot(self.wo, self.hit.n.0) * abs_dot(wi, self.ns.0);
+                if denom == 0.0 { 0.0 } else { num / denom }
+            }
+        }
+    }
+
+    /// The density with respect to area of sampling `next` from this vertex, arriving from `prev`.
+    fn pdf(&self, camera: &dyn Camera, prev: Option<&Vertex>, next: &Vertex) -> Float {
+        if self.kind == VertexKind::Light {
+            return self.pdf_light(next);
+        }
+        let wn = next.hit.p - self.hit.p;
+        if wn.magnitude2() == 0.0 {
+            return 0.0;
+        }
+        let wn = wn.normalize();
+        let pdf = match (self.kind, &self.bsdf) {
+            (VertexKind::Camera, _) => camera.pdf_we(&Ray::new(self.hit.p, wn)).1,
+            (_, Some(bsdf)) => {
+                let prev = prev.expect("Surface vertices should have a predecessor");
+                let wp = (prev.hit.p - self.hit.p).normalize();
+                bsdf.pdf(wp, wn, BxDFType::all())
+            }
+            _ => 0.0,
+        };
+        self.convert_density(pdf, next)
+    }
+
+    /// The density with respect to area of the light at this vertex emitting towards `next`.
+    fn pdf_light(&self, next: &Vertex) -> Float {
+        let light = self.light.expect("Should be a light vertex");
+        let w = next.hit.p - self.hit.p;
+        let dist_sq = w.magnitude2();
+        let w = w / dist_sq.sqrt();
+        let (_, pdf_dir) = light.pdf_le(&Ray::new(self.hit.p, w), self.hit.n);
+        let mut pdf = pdf_dir / dist_sq;
+        if next.is_on_surface() {
+            pdf *= abs_dot(next.hit.n.0, w);
+        }
+        pdf
+    }
+
+    /// The density with respect to area of a light subpath starting at this vertex, including the
+    /// probability of choosing its light.
+    fn pdf_light_origin(&self, next: &Vertex, light_pdf: Float) -> Float {
+        let light = self.light.expect("Should be a light vertex");
+        let w = (next.hit.p - self.hit.p).normalize();
+        let (pdf_pos, _) = light.pdf_le(&Ray::new(self.hit.p, w), self.hit.n);
+        pdf_pos * light_pdf
+    }
+}
+
+/// Extends `path` with up to `max_vertices` vertices by tracing `ray` and sampling BSDFs, where
+/// `pdf` is the density of the ray's direction with respect to solid angle at the last vertex.
+#[allow(clippy::too_many_arguments)]
+fn random_walk<'a>(
+    mut ray: RayDifferential,
+    scene: &'a Scene,
+    sampler: &mut dyn Sampler,
+    arena: &'a Bump,
+    mut beta: Spectrum,
+    pdf: Float,
+    max_vertices: usize,
+    mode: TransportMode,
+    path: &mut Vec<Vertex<'a>>,
+) {
+    if max_vertices == 0 {
+        return;
+    }
+    let mut pdf_fwd = pdf;
+    let mut bounces = 0;
+
+    loop {
+        let mut si = match scene.intersect(&mut ray.ray) {
+            Some(si) => si,
+            None => break,
+        };
+        let bsdf = match si.compute_scattering_functions(&ray, arena, true, mode) {
+            Some(bsdf) => bsdf,
+            None => {
+                // Skip over null bsdf without incrementing bounces
+                ray = si.hit.spawn_ray_with_dfferentials(ray.ray.dir, None);
+                continue;
+            }
+        };
+
+        let prev = path.last().expect("Subpaths should start with an endpoint");
+        let mut vertex = Vertex {
+            kind: VertexKind::Surface,
+            hit: si.hit,
+            ns: si.shading_n,
+            wo: si.wo,
+            bsdf: None,
+            light: si.primitive.and_then(|prim| prim.area_light()).map(|light| light.as_light()),
+            le: si.le(si.wo),
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        };
+        vertex.pdf_fwd = prev.convert_density(pdf_fwd, &vertex);
+
+        bounces += 1;
+        if bounces >= max_vertices {
+            vertex.bsdf = Some(bsdf);
+            path.push(vertex);
+            break;
+        }
+
+        let sample = bsdf.sample_f(si.wo, sampler.get_2d(), BxDFType::all());
+        let sample = match sample.filter(|s| !s.f.is_black() && s.pdf > 0.0) {
+            Some(sample) => sample,
+            None => {
+                vertex.bsdf = Some(bsdf);
+                path.push(vertex);
+                break;
+            }
+        };
+        beta *= sample.f * abs_dot(sample.wi, si.shading_n.0) / sample.pdf;
+        pdf_fwd = sample.pdf;
+        let mut pdf_rev = bsdf.pdf(sample.wi, si.wo, BxDFType::all());
+        if sample.sampled_type.contains(BxDFType::SPECULAR) {
+            vertex.delta = true;
+            pdf_fwd = 0.0;
+            pdf_rev = 0.0;
+        }
+        beta *= vertex.correct_shading_normal(sample.wi, mode);
+        ray = si.hit.spawn_ray_with_dfferentials(sample.wi, None);
+
+        let prev_pdf_rev = vertex.convert_density(pdf_rev, prev);
+        let n = path.len();
+        path[n - 1].pdf_rev = prev_pdf_rev;
+        vertex.bsdf = Some(bsdf);
+        path.push(vertex);
+
+        if beta.is_black() {
+            break;
+        }
+    }
+}
+
+/// The generalized geometry term between two vertices, or zero if they can't see each other.
+fn geometry_term(scene: &Scene, v0: &Vertex, v1: &Vertex) -> Float {
+    let d = v0.hit.p - v1.hit.p;
+    let dist_sq = d.magnitude2();
+    let d = d / dist_sq.sqrt();
+    let mut g = 1.0 / dist_sq;
+    if v0.is_on_surface() {
+        g *= abs_dot(v0.ns.0, d);
+    }
+    if v1.is_on_surface() {
+        g *= abs_dot(v1.ns.0, d);
+    }
+    if scene.intersect_p(v0.hit, v1.hit) { 0.0 } else { g }
+}
+
+/// The balance heuristic weight of the strategy connecting `s` light subpath vertices to `t`
+/// camera subpath vertices, relative to all the other strategies that could have sampled the same
+/// path. `sampled` is the endpoint sampled by the connection, if any, which replaces the last
+/// vertex of the subpath of length 1.
+fn mis_weight(
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    sampled: Option<&Vertex>,
+    s: usize,
+    t: usize,
+    camera: &dyn Camera,
+    light_pdf: Float,
+) -> Float {
+    if s + t == 2 {
+        return 1.0;
+    }
+
+    let qs = match s {
+        0 => None,
+        1 => sampled,
+        _ => Some(&light_path[s - 1]),
+    };
+    let pt = if t == 1 { sampled.expect("Connecting to the camera samples a vertex") } else { &camera_path[t - 1] };
+    let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+    let pt_minus = if t > 1 { Some(&camera_path[t - 2]) } else { None };
+
+    // the reverse densities at the connection, as if each subpath had gone on to sample the
+    // other's vertices
+    let pt_pdf_rev = match qs {
+        Some(qs) => qs.pdf(camera, qs_minus, pt),
+        None => pt.pdf_light_origin(pt_minus.expect("A light hit by the camera subpath has a predecessor"), light_pdf),
+    };
+    let pt_minus_pdf_rev = pt_minus.map(|pt_minus| match qs {
+        Some(qs) => pt.pdf(camera, Some(qs), pt_minus),
+        None => pt.pdf_light(pt_minus),
+    });
+    let qs_pdf_rev = qs.map(|qs| pt.pdf(camera, pt_minus, qs));
+    let qs_minus_pdf_rev = qs_minus.map(|qs_minus| qs.unwrap().pdf(camera, Some(pt), qs_minus));
+
+    let remap0 = |pdf: Float| if pdf != 0.0 { pdf } else { 1.0 };
+    let mut sum_ri = 0.0;
+
+    // strategies with fewer camera vertices, where the connection vertices can't be specular
+    let mut ri = 1.0;
+    for i in (1..t).rev() {
+        let (pdf_fwd, pdf_rev, delta) = if i == t - 1 {
+            (pt.pdf_fwd, pt_pdf_rev, false)
+        } else if i == t - 2 {
+            let v = &camera_path[i];
+            (v.pdf_fwd, pt_minus_pdf_rev.unwrap(), v.delta)
+        } else {
+            let v = &camera_path[i];
+            (v.pdf_fwd, v.pdf_rev, v.delta)
+        };
+        ri *= remap0(pdf_rev) / remap0(pdf_fwd);
+        if !delta && !camera_path[i - 1].delta {
+            sum_ri += ri;
+        }
+    }
+
+    // strategies with fewer light vertices
+    let mut ri = 1.0;
+    for i in (0..s).rev() {
+        let v = if i + 1 == s { qs.unwrap() } else { &light_path[i] };
+        let (pdf_rev, delta) = if i + 1 == s {
+            (qs_pdf_rev.unwrap(), false)
+        } else if i + 2 == s {
+            (qs_minus_pdf_rev.unwrap(), v.delta)
+        } else {
+            (v.pdf_rev, v.delta)
+        };
+        ri *= remap0(pdf_rev) / remap0(v.pdf_fwd);
+        let delta_light = if i > 0 { light_path[i - 1].delta } else { v.is_delta_light() };
+        if !delta && !delta_light {
+            sum_ri += ri;
+        }
+    }
+
+    1.0 / (1.0 + sum_ri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use cgmath::Deg;
+    use crate::{Bounds2f, Point2i, Point3f, Transform};
+    use crate::bvh::BVH;
+    use crate::camera::PerspectiveCamera;
+    use crate::film::Film;
+    use crate::filter::BoxFilter;
+    use crate::integrator::{SamplerIntegrator, TileOrder};
+    use crate::integrator::path::PathIntegrator;
+    use crate::material::matte::MatteMaterial;
+    use crate::primitive::{GeometricPrimitive, Primitive};
+    use crate::sampler::random::RandomSampler;
+    use crate::shapes::sphere::Sphere;
+
+    fn render_mean<R: IntegratorRadiance>(radiance: R, scene: &Scene, spp: usize) -> Float {
+        let resolution = Point2i::new(16, 16);
+        let camera = PerspectiveCamera::new(
+            Transform::translate(Vec3f::new(0.0, 0.0, 1.5)) * Transform::rotate_x(Deg(180.0)),
+            resolution,
+            Bounds2f::whole_screen(),
+            (0.0, 1.0),
+            0.0,
+            1.0,
+            60.0
+        );
+        let film = Film::new(resolution, Bounds2f::unit(), BoxFilter::default(), 1.0);
+        let mut integrator = SamplerIntegrator {
+            camera: Box::new(camera),
+            radiance,
+            tile_order: TileOrder::Scanline,
+        };
+        integrator.render_parallel(scene, &film, RandomSampler::new_with_seed(spp, 0), None);
+        let (img, _) = film.into_spectrum_buffer();
+        img.iter().map(|s| s[0]).sum::<Float>() / img.len() as Float
+    }
+
+    #[test]
+    fn test_bdpt_matches_path_tracer() {
+        // a diffuse floor lit by a small spherical light above the camera
+        let o2w = Transform::translate(Vec3f::new(0.0, 0.0, -100.0));
+        let floor = GeometricPrimitive::new(
+            Arc::new(Sphere::whole(o2w, o2w.inverse(), 100.0)),
+            Some(Arc::new(MatteMaterial::constant(Spectrum::uniform(0.5)))),
+        );
+        let o2w = Transform::translate(Vec3f::new(0.0, 0.0, 2.5));
+        let emitter = GeometricPrimitive::with_area_light(
+            Arc::new(Sphere::whole(o2w, o2w.inverse(), 0.25)),
+            Some(Arc::new(MatteMaterial::constant(Spectrum::uniform(0.0)))),
+            Spectrum::uniform(10.0),
+            1,
+        );
+        let prims: Vec<Box<dyn Primitive>> = vec![Box::new(floor), Box::new(emitter)];
+        let scene = Scene::new(BVH::build(prims), vec![], vec![]);
+
+        let bdpt = render_mean(BDPTIntegrator::new(3), &scene, 16);
+        let path = render_mean(PathIntegrator::new(3, 1.0), &scene, 64);
+        assert!(bdpt > 0.0);
+        assert!((bdpt - path).abs() < 0.05 * path, "{} {}", bdpt, path);
+    }
+
+    #[test]
+    fn test_bdpt_incident_radiance_is_black() {
+        let o2w = Transform::translate(Vec3f::new(0.0, 0.0, 2.5));
+        let emitter = GeometricPrimitive::with_area_light(
+            Arc::new(Sphere::whole(o2w, o2w.inverse(), 0.25)),
+            None,
+            Spectrum::uniform(10.0),
+            1,
+        );
+        let prims: Vec<Box<dyn Primitive>> = vec![Box::new(emitter)];
+        let scene = Scene::new(BVH::build(prims), vec![], vec![]);
+
+        // without the camera there is nothing to start a subpath from, even looking at a light
+        let mut ray = RayDifferential { ray: Ray::new(Point3f::new(0.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, 1.0)), diff: None };
+        let mut sampler = RandomSampler::new_with_seed(1, 0);
+        let radiance = BDPTIntegrator::new(3).incident_radiance(&mut ray, &scene, &mut sampler, &Bump::new(), 0);
+        assert_eq!(radiance, Spectrum::uniform(0.0));
+    }
+}
@@ -1,11 +1,13 @@
-use crate::integrator::{IntegratorRadiance, uniform_sample_one_light};
-use crate::sampler::Sampler;
+use crate::integrator::{IntegratorRadiance, uniform_sample_one_light, sample_one_light, estimate_direct, mis_mode_from_params, compute_light_power_distribution};
+use crate::sampler::{Sampler, SampleArrayId};
 use bumpalo::Bump;
-use crate::{RayDifferential, SurfaceInteraction};
+use crate::{Float, RayDifferential, SurfaceInteraction};
 use crate::spectrum::{Spectrum};
 use crate::scene::Scene;
 use crate::material::TransportMode;
 use crate::reflection::bsdf::Bsdf;
+use crate::loaders::{ParamSet, ParamError};
+use crate::sampling::{Distribution1D, MisMode};
 
 pub enum LightStrategy {
 
@@ -14,22 +16,64 @@ pub enum LightStrategy {
     UniformSampleAll,
 
     /// Takes a single sample from one of the lights, chosen at random.
-    UniformSampleOne
+    UniformSampleOne,
+
+    /// Takes a single sample from one of the lights, chosen with probability proportional to its
+    /// power.
+    PowerSampleOne,
 }
 
 pub struct DirectLightingIntegrator {
     pub strategy: LightStrategy,
     pub max_depth: u16,
+    pub mis_mode: MisMode,
     pub n_light_samples: Vec<usize>,
-//    pub light_sample_ids:
+
+    /// The light and BSDF sample arrays for each light at each depth, stored depth-major.
+    pub light_sample_ids: Vec<(SampleArrayId, SampleArrayId)>,
+
+    /// The distribution lights are chosen from with `PowerSampleOne`.
+    pub light_distribution: Option<Distribution1D>,
 }
 
 impl DirectLightingIntegrator {
+    pub fn new(strategy: LightStrategy, max_depth: u16) -> Self {
+        Self {
+            strategy,
+            max_depth,
+            mis_mode: MisMode::default(),
+            n_light_samples: vec![],
+            light_sample_ids: vec![],
+            light_distribution: None,
+        }
+    }
 
+    /// Construct from the scene's `Integrator` parameters, reading `strategy` ("all", "one" or
+    /// "power", default "all"), `maxdepth` (default 5) and `mis` ("power" or "balance", default "power").
+    pub fn from_params(params: &mut ParamSet) -> Result<Self, ParamError> {
+        let strategy: String = params.get_one_or("strategy", "all".to_string())?;
+        let strategy = match strategy.as_str() {
+            "one" => LightStrategy::UniformSampleOne,
+            "power" => LightStrategy::PowerSampleOne,
+            "all" => LightStrategy::UniformSampleAll,
+            other => {
+                tracing::warn!("Unknown light sampling strategy \"{}\", using \"all\"", other);
+                LightStrategy::UniformSampleAll
+            }
+        };
+        let max_depth = params.get_one_or::<i32>("maxdepth", 5)?.max(0) as u16;
+        let mut integrator = Self::new(strategy, max_depth);
+        integrator.mis_mode = mis_mode_from_params(params)?;
+        Ok(integrator)
+    }
 }
 
 impl IntegratorRadiance for DirectLightingIntegrator {
     fn preprocess(&mut self, scene: &Scene, sampler: &mut dyn Sampler) {
+        if let LightStrategy::PowerSampleOne = self.strategy {
+            self.light_distribution = compute_light_power_distribution(scene);
+        }
+
         if let LightStrategy::UniformSampleAll = self.strategy {
 
             // Store the number of samples to be used for each light.
@@ -38,10 +82,12 @@ impl IntegratorRadiance for DirectLightingIntegrator {
                 .map(|light| sampler.round_count(light.n_samples()))
                 .collect();
 
+            self.light_sample_ids.clear();
             for _ in 0..self.max_depth {
                 for &n_samples in &self.n_light_samples {
-                    sampler.request_2d_array(n_samples);
-                    sampler.request_2d_array(n_samples);
+                    let u_light_id = sampler.request_2d_array(n_samples);
+                    let u_scattering_id = sampler.request_2d_array(n_samples);
+                    self.light_sample_ids.push((u_light_id, u_scattering_id));
                 }
             }
         }
@@ -66,17 +112,20 @@ impl IntegratorRadiance for DirectLightingIntegrator {
                 if let Some(bsdf) = bsdf {
 
                     // Add emitted light if ray hit an area light source.
-                    radiance += intersect.emitted_radiance(intersect.wo);
+                    radiance += intersect.le(intersect.wo);
 
                     radiance += match self.strategy {
                         LightStrategy::UniformSampleAll => {
+                            let n_lights = scene.lights.len();
+                            let depth = depth as usize;
                             uniform_sample_all_lights(
                                 &intersect,
                                 &bsdf,
                                 scene,
                                 arena,
                                 sampler,
-                                &self.n_light_samples
+                                self.light_sample_ids.get(depth * n_lights..(depth + 1) * n_lights),
+                                self.mis_mode,
                             )
                         },
                         LightStrategy::UniformSampleOne => {
@@ -86,8 +135,21 @@ impl IntegratorRadiance for DirectLightingIntegrator {
                                 scene,
                                 arena,
                                 sampler,
+                                self.mis_mode,
                             )
-                        }
+                        },
+                        LightStrategy::PowerSampleOne => match &self.light_distribution {
+                            Some(distribution) => sample_one_light(
+                                &intersect,
+                                &bsdf,
+                                scene,
+                                arena,
+                                sampler,
+                                distribution,
+                                self.mis_mode,
+                            ),
+                            None => Spectrum::uniform(0.0),
+                        },
                     };
 
                     if depth + 1 < self.max_depth {
@@ -105,32 +167,146 @@ impl IntegratorRadiance for DirectLightingIntegrator {
     }
 }
 
+/// Estimates direct lighting from every light, averaging over each light's sample arrays. If
+/// `light_sample_ids` is `None` (i.e. the arrays weren't requested for this depth) a single
+/// sample is taken from each light instead.
 fn uniform_sample_all_lights(
     intersect: &SurfaceInteraction,
     bsdf: &Bsdf,
     scene: &Scene,
     arena: &Bump,
     sampler: &mut dyn Sampler,
-    n_light_samples: &[usize],
+    light_sample_ids: Option<&[(SampleArrayId, SampleArrayId)]>,
+    mis: MisMode,
 ) -> Spectrum {
-    unimplemented!()
-//    scene.lights.iter().zip(n_light_samples).map(|(light, &n_samples)| {
-//        // TODO: sampler return optional arrays
-//        let u_light_array = sampler.get_2d_array(n_samples);
-//        let u_scattering_array = sampler.get_2d_array(n_samples);
-//
-//        u_light_array.iter().zip(u_scattering_array)
-//            .map(|(&u_light, &u_scattering)| {
-//                estimate_direct(
-//                    bsdf,
-//                    intersect,
-//                    u_scattering,
-//                    *light,
-//                    u_light,
-//                    scene,
-//                    arena,
-////                    sampler, // TODO: ??? would be needed for volumes
-//                )
-//            }).sum::<Spectrum>() / (n_samples as Float)
-//    }).sum()
+    scene.lights.iter().enumerate().map(|(i, light)| {
+        let light = light.as_ref();
+        match light_sample_ids {
+            Some(ids) => {
+                let (u_light_id, u_scattering_id) = ids[i];
+                let u_light_array = sampler.get_2d_array(u_light_id);
+                let u_scattering_array = sampler.get_2d_array(u_scattering_id);
+                let n_samples = u_light_array.len();
+
+                u_light_array.iter().zip(u_scattering_array)
+                    .map(|(&u_light, &u_scattering)| {
+                        estimate_direct(bsdf, intersect, u_scattering, light, u_light, scene, arena, mis)
+                    }).sum::<Spectrum>() / (n_samples as Float)
+            },
+            None => {
+                let u_light = sampler.get_2d();
+                let u_scattering = sampler.get_2d();
+                estimate_direct(bsdf, intersect, u_scattering, light, u_light, scene, arena, mis)
+            }
+        }
+    }).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::{Point2i, Point3f, Ray, Transform, Vec3f};
+    use crate::bvh::BVH;
+    use crate::material::matte::MatteMaterial;
+    use crate::primitive::{GeometricPrimitive, Primitive};
+    use crate::sampler::random::RandomSampler;
+    use crate::shapes::sphere::Sphere;
+
+    fn sphere(center: Vec3f, radius: Float) -> Arc<Sphere> {
+        let o2w = Transform::translate(center);
+        Arc::new(Sphere::whole(o2w, o2w.inverse(), radius))
+    }
+
+    /// Sample variance of the direct lighting at a point in the penumbra of a sphere occluding a
+    /// spherical area light.
+    fn penumbra_variance(light_samples: usize) -> Float {
+        let ground = GeometricPrimitive::new(
+            sphere(Vec3f::new(0.0, 0.0, -100.0), 100.0),
+            Some(Arc::new(MatteMaterial::constant(Spectrum::uniform(0.5)))),
+        );
+        let occluder = GeometricPrimitive::new(sphere(Vec3f::new(0.0, 0.0, 2.5), 0.5), None);
+        let mut emitter = GeometricPrimitive::new(sphere(Vec3f::new(0.0, 0.0, 5.0), 1.0), None);
+        emitter.set_emitter(Spectrum::uniform(10.0), light_samples);
+        let prims: Vec<Box<dyn Primitive>> = vec![Box::new(ground), Box::new(occluder), Box::new(emitter)];
+        let scene = Scene::new(BVH::build(prims), vec![], vec![]);
+
+        let mut integrator = DirectLightingIntegrator::new(LightStrategy::UniformSampleAll, 1);
+        let mut sampler = RandomSampler::new_with_seed(1, 0);
+        integrator.preprocess(&scene, &mut sampler);
+
+        let arena = Bump::new();
+        let n = 400;
+        let values: Vec<Float> = (0..n).map(|i| {
+            sampler.start_pixel(Point2i::new(i, 0));
+            assert!(sampler.start_next_sample());
+            let ray = Ray::new(Point3f::new(1.0, 0.0, 1.0), Vec3f::new(0.0, 0.0, -1.0));
+            let mut ray = RayDifferential { ray, diff: None };
+            integrator.incident_radiance(&mut ray, &scene, &mut sampler, &arena, 0)[0]
+        }).collect();
+
+        let mean = values.iter().sum::<Float>() / n as Float;
+        assert!(mean > 0.0);
+        values.iter().map(|v| (v - mean) * (v - mean)).sum::<Float>() / (n - 1) as Float
+    }
+
+    #[test]
+    fn test_more_light_samples_reduce_penumbra_variance() {
+        let var_1 = penumbra_variance(1);
+        let var_16 = penumbra_variance(16);
+        assert!(var_16 < 0.25 * var_1, "{} {}", var_1, var_16);
+    }
+
+    /// Mean and sample variance of one-sample direct lighting at the top of a diffuse ground, lit by
+    /// 50 point lights of increasing intensity on a ring above it.
+    fn many_lights_estimate(strategy: LightStrategy) -> (Float, Float) {
+        use crate::light::Light;
+        use crate::light::point::PointLight;
+
+        let ground = GeometricPrimitive::new(
+            sphere(Vec3f::new(0.0, 0.0, -100.0), 100.0),
+            Some(Arc::new(MatteMaterial::constant(Spectrum::uniform(0.5)))),
+        );
+        let prims: Vec<Box<dyn Primitive>> = vec![Box::new(ground)];
+        // every light is at the same distance and angle from the shading point, so its
+        // contribution is proportional to its power
+        let lights: Vec<Box<dyn Light>> = (0..50)
+            .map(|i| {
+                let phi = i as Float * 2.0 * crate::consts::PI / 50.0;
+                let p = Vec3f::new(phi.cos(), phi.sin(), 2.0);
+                let intensity = Spectrum::uniform(((i + 1) * (i + 1)) as Float / 100.0);
+                Box::new(PointLight::new(Transform::translate(p), intensity)) as Box<dyn Light>
+            })
+            .collect();
+        let scene = Scene::new(BVH::build(prims), lights, vec![]);
+
+        let mut integrator = DirectLightingIntegrator::new(strategy, 1);
+        let mut sampler = RandomSampler::new_with_seed(1, 0);
+        integrator.preprocess(&scene, &mut sampler);
+
+        let arena = Bump::new();
+        let n = 400;
+        let values: Vec<Float> = (0..n).map(|i| {
+            sampler.start_pixel(Point2i::new(i, 0));
+            assert!(sampler.start_next_sample());
+            let ray = Ray::new(Point3f::new(0.0, 0.0, 1.0), Vec3f::new(0.0, 0.0, -1.0));
+            let mut ray = RayDifferential { ray, diff: None };
+            integrator.incident_radiance(&mut ray, &scene, &mut sampler, &arena, 0)[0]
+        }).collect();
+
+        let mean = values.iter().sum::<Float>() / n as Float;
+        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<Float>() / (n - 1) as Float;
+        (mean, variance)
+    }
+
+    #[test]
+    fn test_power_light_sampling_reduces_variance() {
+        let (uniform_mean, uniform_var) = many_lights_estimate(LightStrategy::UniformSampleOne);
+        let (power_mean, power_var) = many_lights_estimate(LightStrategy::PowerSampleOne);
+        let (all_mean, _) = many_lights_estimate(LightStrategy::UniformSampleAll);
+
+        assert!((power_mean - all_mean).abs() < 1e-3 * all_mean, "{} {}", power_mean, all_mean);
+        assert!((uniform_mean - all_mean).abs() < 0.15 * all_mean, "{} {}", uniform_mean, all_mean);
+        assert!(power_var < 0.01 * uniform_var, "{} {}", power_var, uniform_var);
+    }
 }
@@ -0,0 +1,235 @@
+use bumpalo::Bump;
+
+use crate::{abs_dot, Bounds2i, Float, RayDifferential, SurfaceInteraction};
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::integrator::{for_each_queued, russian_roulette};
+use crate::loaders::{ParamSet, ParamError};
+use crate::material::TransportMode;
+use crate::reflection::BxDFType;
+use crate::reflection::bsdf::Bsdf;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::spectrum::Spectrum;
+
+/// Traces particles from the lights and, wherever they land on a non-specular surface, connects
+/// them to the camera and splats their contribution onto the film. This finds caustics that are
+/// hard to reach from the camera, but can't render specular surfaces that the camera sees directly
+/// or lights seen by the camera.
+pub struct LightTracerIntegrator {
+    pub camera: Box<dyn Camera>,
+    max_depth: u16,
+    rr_threshold: Float,
+}
+
+impl LightTracerIntegrator {
+    pub fn new(camera: Box<dyn Camera>, max_depth: u16, rr_threshold: Float) -> Self {
+        Self { camera, max_depth, rr_threshold }
+    }
+
+    /// Construct from the scene's `Integrator` parameters, reading `maxdepth` (default 5) and
+    /// `rrthreshold` (default 1).
+    pub fn from_params(camera: Box<dyn Camera>, params: &mut ParamSet) -> Result<Self, ParamError> {
+        let max_depth = params.get_one_or::<i32>("maxdepth", 5)?.max(0) as u16;
+        let rr_threshold = params.get_one_or("rrthreshold", 1.0)?;
+        Ok(Self::new(camera, max_depth, rr_threshold))
+    }
+
+    /// Traces one particle per sample of each pixel in the film, in parallel on the current rayon
+    /// pool.
+    pub fn render(&self, scene: &Scene, film: &Film<impl Filter + Sync>, sampler: impl Sampler) {
+        let pixel_bounds = film.cropped_pixel_bounds;
+        // particles may land anywhere on the image, so when the film is cropped each one carries
+        // the energy for the whole image
+        let full_area = (film.full_resolution.x * film.full_resolution.y) as Float;
+        let splat_scale = full_area / pixel_bounds.area() as Float;
+        // each sample's particle is an estimate of the whole image
+        film.set_splat_scale(1.0 / sampler.samples_per_pixel() as Float);
+
+        let tiles: Vec<_> = pixel_bounds.iter_tiles(16)
+            .map(|tile| {
+                let tile_id = (tile.min.y * pixel_bounds.max.x + tile.min.x) as u64;
+                (tile, sampler.clone_with_seed(tile_id))
+            })
+            .collect();
+        for_each_queued(tiles, |(tile, tile_sampler)| {
+            self.render_tile(scene, film, tile_sampler, tile, splat_scale)
+        });
+    }
+
+    fn render_tile(
+        &self,
+        scene: &Scene,
+        film: &Film<impl Filter + Sync>,
+        mut tile_sampler: impl Sampler,
+        tile: Bounds2i,
+        splat_scale: Float,
+    ) {
+        let mut arena = Bump::new();
+
+        for pixel in tile.iter_points() {
+            tile_sampler.start_pixel(pixel.into());
+            while tile_sampler.start_next_sample() {
+                // the camera sample is only used for the particle's time
+                let camera_sample = tile_sampler.get_camera_sample(pixel.into());
+                let (_, camera_ray) = self.camera.generate_ray(camera_sample);
+                self.trace_particle(scene, film, &mut tile_sampler, &arena, camera_ray.time, splat_scale);
+                arena.reset();
+            }
+        }
+    }
+
+    fn trace_particle(
+        &self,
+        scene: &Scene,
+        film: &Film<impl Filter + Sync>,
+        sampler: &mut dyn Sampler,
+        arena: &Bump,
+        time: Float,
+        splat_scale: Float,
+    ) {
+        let n_lights = scene.num_lights();
+        if n_lights == 0 {
+            return;
+        }
+        let light_num = ((sampler.get_1d() * n_lights as Float) as usize).min(n_lights - 1);
+        let light_pdf = 1.0 / n_lights as Float;
+
+        let le = match scene.light(light_num).sample_le(sampler.get_2d(), sampler.get_2d(), time) {
+            Some(le) if le.pdf_pos > 0.0 && le.pdf_dir > 0.0 && !le.radiance.is_black() => le,
+            _ => return,
+        };
+        let mut beta = le.radiance * abs_dot(le.n_light.0, le.ray.dir) / (light_pdf * le.pdf_pos * le.pdf_dir);
+        let mut ray = RayDifferential { ray: le.ray, diff: None };
+        let mut bounces = 0;
+
+        loop {
+            let mut si = match scene.intersect(&mut ray.ray) {
+                Some(si) => si,
+                None => break,
+            };
+
+            let bsdf = match si.compute_scattering_functions(&ray, arena, true, TransportMode::Importance) {
+                Some(bsdf) => bsdf,
+                None => {
+                    // Skip over null bsdf without incrementing bounces
+                    ray = si.hit.spawn_ray_with_dfferentials(ray.ray.dir, None);
+                    continue;
+                }
+            };
+
+            if bsdf.num_components(BxDFType::all() & !BxDFType::SPECULAR) > 0 {
+                self.connect_to_camera(&si, &bsdf, beta, scene, film, sampler, splat_scale);
+            }
+
+            if bounces >= self.max_depth {
+                break;
+            }
+
+            let bsdf_sample = bsdf.sample_f(si.wo, sampler.get_2d(), BxDFType::all());
+            if let Some(bsdf_sample) = bsdf_sample.filter(|s| !s.f.is_black() && s.pdf > 0.0) {
+                beta *= bsdf_sample.f * abs_dot(bsdf_sample.wi, si.shading_n.0) / bsdf_sample.pdf;
+                ray = si.hit.spawn_ray_with_dfferentials(bsdf_sample.wi, None);
+            } else {
+                break;
+            }
+
+            if beta.max_component_value() < self.rr_threshold && bounces > 3
+                && !russian_roulette(&mut beta, sampler, 0.05) {
+                break;
+            }
+            bounces += 1;
+        }
+    }
+
+    /// Splats the contribution of a particle arriving at `si` with throughput `beta` to wherever
+    /// the point is seen by the camera, if it is.
+    #[allow(clippy::too_many_arguments)]
+    fn connect_to_camera(
+        &self,
+        si: &SurfaceInteraction,
+        bsdf: &Bsdf,
+        beta: Spectrum,
+        scene: &Scene,
+        film: &Film<impl Filter + Sync>,
+        sampler: &mut dyn Sampler,
+        splat_scale: Float,
+    ) {
+        let cam = match self.camera.sample_wi(&si.hit, sampler.get_2d()) {
+            Some(cam) if cam.pdf > 0.0 && cam.importance > 0.0 => cam,
+            _ => return,
+        };
+        let f = bsdf.f(si.wo, cam.wi, BxDFType::all()) * abs_dot(cam.wi, si.shading_n.0);
+        if !f.is_black() && cam.vis.unoccluded(scene) {
+            film.add_splat(cam.p_raster, beta * f * (cam.importance * splat_scale / cam.pdf));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use cgmath::Deg;
+    use crate::{Bounds2f, Point2i, Transform, Vec3f};
+    use crate::bvh::BVH;
+    use crate::camera::PerspectiveCamera;
+    use crate::filter::BoxFilter;
+    use crate::integrator::{SamplerIntegrator, TileOrder};
+    use crate::integrator::direct_lighting::{DirectLightingIntegrator, LightStrategy};
+    use crate::light::Light;
+    use crate::light::point::PointLight;
+    use crate::material::matte::MatteMaterial;
+    use crate::primitive::{GeometricPrimitive, Primitive};
+    use crate::sampler::random::RandomSampler;
+    use crate::shapes::sphere::Sphere;
+
+    fn mean_value(film: Film<BoxFilter>) -> Float {
+        let (img, _) = film.into_spectrum_buffer();
+        img.iter().map(|s| s[0]).sum::<Float>() / img.len() as Float
+    }
+
+    #[test]
+    fn test_light_tracer_matches_direct_lighting() {
+        // a diffuse wall lit by a point light, seen head on by the camera
+        let o2w = Transform::translate(Vec3f::new(0.0, 0.0, -100.0));
+        let wall = GeometricPrimitive::new(
+            Arc::new(Sphere::whole(o2w, o2w.inverse(), 100.0)),
+            Some(Arc::new(MatteMaterial::constant(Spectrum::uniform(1.0)))),
+        );
+        let prims: Vec<Box<dyn Primitive>> = vec![Box::new(wall)];
+        let light = PointLight::new(Transform::translate(Vec3f::new(0.0, 0.0, 1.0)), Spectrum::uniform(1.0));
+        let lights: Vec<Box<dyn Light>> = vec![Box::new(light)];
+        let scene = Scene::new(BVH::build(prims), lights, vec![]);
+
+        let resolution = Point2i::new(16, 16);
+        let camera = || Box::new(PerspectiveCamera::new(
+            Transform::translate(Vec3f::new(0.0, 0.0, 10.0)) * Transform::rotate_x(Deg(180.0)),
+            resolution,
+            Bounds2f::whole_screen(),
+            (0.0, 1.0),
+            0.0,
+            1.0,
+            30.0
+        ));
+        let new_film = || Film::new(resolution, Bounds2f::unit(), BoxFilter::default(), 1.0);
+
+        let film = new_film();
+        let integrator = LightTracerIntegrator::new(camera(), 1, 1.0);
+        integrator.render(&scene, &film, RandomSampler::new_with_seed(64, 0));
+        let traced = mean_value(film);
+        assert!(traced > 0.0);
+
+        let film = new_film();
+        let mut reference = SamplerIntegrator {
+            camera: camera(),
+            radiance: DirectLightingIntegrator::new(LightStrategy::UniformSampleAll, 1),
+            tile_order: TileOrder::Scanline,
+        };
+        reference.render_parallel(&scene, &film, RandomSampler::new_with_seed(4, 0), None);
+        let expected = mean_value(film);
+
+        assert!((traced - expected).abs() < 0.1 * expected, "{} {}", traced, expected);
+    }
+}
@@ -1,27 +1,154 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use bumpalo::Bump;
 use cgmath::InnerSpace;
-use rayon::prelude::*;
+use parking_lot::Mutex;
+use crossbeam_channel::TrySendError;
 
 use crate::{abs_dot, Bounds2i, Differential, Float, RayDifferential, SurfaceInteraction, Point2f};
 use crate::camera::Camera;
 use crate::film::Film;
-use crate::filter::BoxFilter;
+use crate::filter::Filter;
 use crate::reflection::bsdf::Bsdf;
 use crate::reflection::BxDFType;
 use crate::sampler::Sampler;
 use crate::scene::Scene;
 use crate::spectrum::{Spectrum};
 use crate::light::Light;
-use crate::sampling::power_heuristic;
+use crate::sampling::{Distribution1D, MisMode};
+use crate::loaders::{ParamSet, ParamError};
+use crate::morton::morton2;
 
 pub mod whitted;
 pub mod direct_lighting;
 pub mod path;
+pub mod light_tracer;
+pub mod sppm;
+pub mod bdpt;
+pub mod volpath;
+pub mod visibility;
+pub mod uv;
 
 
 pub struct SamplerIntegrator<R: IntegratorRadiance> {
     pub camera: Box<dyn Camera>,
     pub radiance: R,
+    pub tile_order: TileOrder,
+}
+
+/// The order in which tiles are handed out to worker threads by `render_parallel`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileOrder {
+    Scanline,
+    /// Z-order on the tile origins, so that consecutively rendered tiles are spatially close.
+    Morton,
+}
+
+impl Default for TileOrder {
+    fn default() -> Self {
+        TileOrder::Scanline
+    }
+}
+
+/// Writes the film's raw pixel buffer to disk during a render, at most once per interval. The
+/// interval is only checked when a worker finishes a tile, so with slow tiles a checkpoint can be
+/// written up to a tile's render time late.
+struct Checkpointer {
+    interval: Duration,
+    path: PathBuf,
+    last_save: Mutex<Instant>,
+}
+
+impl Checkpointer {
+    fn new((interval, path): (Duration, PathBuf)) -> Self {
+        Self { interval, path, last_save: Mutex::new(Instant::now()) }
+    }
+
+    /// Saves the film if the interval has elapsed. Only one thread will save at a time, others
+    /// return immediately and continue rendering.
+    fn maybe_save<F: Filter>(&self, film: &Film<F>) {
+        if let Some(mut last_save) = self.last_save.try_lock() {
+            if last_save.elapsed() >= self.interval {
+                if let Err(e) = film.save_raw(&self.path) {
+                    tracing::warn!("Failed to write film checkpoint to {:?}: {}", self.path, e);
+                }
+                *last_save = Instant::now();
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// Scratch memory for rendering tiles, kept by each worker thread between tiles.
+    static TILE_ARENA: RefCell<Bump> = RefCell::new(Bump::new());
+}
+
+/// Estimates the time remaining in a render from the wall clock time taken by completed tiles.
+struct TileEta {
+    start: Instant,
+    n_tiles: usize,
+    completed: AtomicUsize,
+}
+
+impl TileEta {
+    fn new(n_tiles: usize) -> Self {
+        Self { start: Instant::now(), n_tiles, completed: AtomicUsize::new(0) }
+    }
+
+    /// Records a completed tile and returns the estimated time until all tiles are done.
+    fn complete_tile(&self) -> Duration {
+        let completed = self.completed.fetch_add(1, Ordering::Relaxed) + 1;
+        let remaining = self.n_tiles.saturating_sub(completed);
+        self.start.elapsed().mul_f64(remaining as f64 / completed as f64)
+    }
+}
+
+/// Feeds `items` in order through a bounded channel to one worker per thread of the current rayon
+/// pool. Idle workers take the next item from the shared queue, so slow items don't hold up the
+/// rest. The producer processes items itself whenever the queue is full, so this can't deadlock
+/// even on a single thread pool.
+fn for_each_queued<T: Send>(items: impl IntoIterator<Item=T> + Send, work: impl Fn(T) + Sync) {
+    let n_workers = rayon::current_num_threads();
+    let (sender, receiver) = crossbeam_channel::bounded(2 * n_workers);
+    let work = &work;
+    rayon::scope(|s| {
+        for _ in 0..n_workers {
+            let receiver = receiver.clone();
+            s.spawn(move |_| receiver.iter().for_each(work));
+        }
+
+        for mut item in items {
+            loop {
+                match sender.try_send(item) {
+                    Ok(()) => break,
+                    Err(TrySendError::Full(unsent)) => {
+                        item = unsent;
+                        if let Ok(queued) = receiver.try_recv() {
+                            work(queued);
+                        }
+                    },
+                    Err(TrySendError::Disconnected(_)) => unreachable!("receiver is held by the producer"),
+                }
+            }
+        }
+        drop(sender);
+        receiver.iter().for_each(work);
+    });
+}
+
+fn order_tiles<T>(tiles: &mut [(Bounds2i, T)], sample_bounds: Bounds2i, order: TileOrder) {
+    match order {
+        TileOrder::Scanline => {},
+        TileOrder::Morton => {
+            tiles.sort_by_key(|(tile, _)| {
+                let offset = tile.min - sample_bounds.min;
+                morton2(offset.x as u32, offset.y as u32)
+            })
+        },
+    }
 }
 
 pub trait IntegratorRadiance: Sync + Send {
@@ -36,6 +163,22 @@ pub trait IntegratorRadiance: Sync + Send {
         depth: u16,
     ) -> Spectrum;
 
+    /// The radiance arriving along a ray leaving `camera`. Integrators that also find paths
+    /// reaching other parts of the image (e.g. by connecting light paths to the camera) pass
+    /// those contributions to `splat` with their raster positions. By default this is just
+    /// `incident_radiance`.
+    fn camera_ray_radiance(
+        &self,
+        ray: &mut RayDifferential,
+        scene: &Scene,
+        _camera: &dyn Camera,
+        sampler: &mut dyn Sampler,
+        arena: &Bump,
+        _splat: &mut dyn FnMut(Point2f, Spectrum),
+    ) -> Spectrum {
+        self.incident_radiance(ray, scene, sampler, arena, 0)
+    }
+
     #[allow(non_snake_case)]
     fn specular_reflect(
         &self,
@@ -190,8 +333,31 @@ impl<R: IntegratorRadiance> SamplerIntegrator<R> {
         bar
     }
 
-    pub fn render_with_pool(&mut self, scene: &Scene, film: &Film<BoxFilter>, sampler: impl Sampler, pool: &rayon::ThreadPool) {
-        pool.install(|| self.render_parallel(scene, film, sampler))
+    pub fn render_with_pool(
+        &mut self,
+        scene: &Scene,
+        film: &Film<impl Filter + Sync>,
+        sampler: impl Sampler,
+        pool: &rayon::ThreadPool,
+        checkpoint: Option<(Duration, PathBuf)>,
+    ) {
+        pool.install(|| self.render_parallel(scene, film, sampler, checkpoint))
+    }
+
+    /// Renders in parallel on a new thread pool with `n_threads` threads, or rayon's default number
+    /// of threads if `n_threads` is 0.
+    pub fn render_parallel_with_threads(
+        &mut self,
+        scene: &Scene,
+        film: &Film<impl Filter + Sync>,
+        sampler: impl Sampler,
+        n_threads: usize,
+    ) -> Result<(), rayon::ThreadPoolBuildError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(n_threads)
+            .build()?;
+        self.render_with_pool(scene, film, sampler, &pool, None);
+        Ok(())
     }
 
     pub fn iter_tiles(&self, sample_bounds: Bounds2i, sampler: impl Sampler) -> impl Iterator<Item=(Bounds2i, impl Sampler)> {
@@ -203,42 +369,107 @@ impl<R: IntegratorRadiance> SamplerIntegrator<R> {
             })
     }
 
-    pub fn render(&mut self, scene: &Scene, film: &Film<BoxFilter>, mut sampler: impl Sampler) {
+    pub fn render(&mut self, scene: &Scene, film: &Film<impl Filter + Sync>, mut sampler: impl Sampler) {
         self.radiance.preprocess(scene, &mut sampler);
 //        let total_samples = sample_bounds.area() * self.sampler.samples_per_pixel() as i32;
 //        let progress = indicatif::ProgressBar::new(total_samples as u64);
         let progress = Self::make_progress_bar(film.sample_bounds().area() as u64);
         self.iter_tiles(film.sample_bounds(), sampler)
             .for_each(|(tile, tile_sampler)| {
-                self.render_tile(scene, film, tile_sampler, tile, &progress)
+                self.render_tile(scene, film, tile_sampler, tile, 0, &progress, &mut Bump::new())
             });
        progress.finish();
     }
 
-    pub fn render_parallel(&mut self, scene: &Scene, film: &Film<BoxFilter>, mut sampler: impl Sampler) {
+    /// Renders tiles in parallel on the current rayon pool. If `checkpoint` is given, the film's raw
+    /// pixel buffer is written to the path (see `Film::save_raw`) on the first tile completion
+    /// after each interval has elapsed.
+    pub fn render_parallel(
+        &mut self,
+        scene: &Scene,
+        film: &Film<impl Filter + Sync>,
+        mut sampler: impl Sampler,
+        checkpoint: Option<(Duration, PathBuf)>,
+    ) {
+        self.radiance.preprocess(scene, &mut sampler);
+        self.render_tiles_parallel(scene, film, sampler, checkpoint, 0);
+    }
+
+    /// Renders in parallel with a sampler chosen at runtime, such as the one made from a scene
+    /// file's `Sampler` statement.
+    pub fn render_dyn(&mut self, scene: &Scene, film: &Film<impl Filter + Sync>, sampler: Box<dyn Sampler>) {
+        self.render_parallel(scene, film, sampler, None);
+    }
+
+    /// Continues a render into a film that already holds `completed_samples` samples per pixel,
+    /// e.g. one restored with `Film::load_raw`. The sampler's samples per pixel is the total for the
+    /// finished render, and only the remaining samples are taken.
+    pub fn resume(
+        &mut self,
+        scene: &Scene,
+        film: &Film<impl Filter + Sync>,
+        mut sampler: impl Sampler,
+        completed_samples: u64,
+    ) {
         self.radiance.preprocess(scene, &mut sampler);
-        let tiles: Vec<_> = self.iter_tiles(film.sample_bounds(), sampler).collect();
+        self.render_tiles_parallel(scene, film, sampler, None, completed_samples);
+    }
+
+    fn render_tiles_parallel(
+        &self,
+        scene: &Scene,
+        film: &Film<impl Filter + Sync>,
+        sampler: impl Sampler,
+        checkpoint: Option<(Duration, PathBuf)>,
+        first_sample: u64,
+    ) {
+        // integrators that splat do so with an estimate of the whole image for every camera sample
+        film.set_splat_scale(1.0 / sampler.samples_per_pixel() as Float);
+        let mut tiles: Vec<_> = self.iter_tiles(film.sample_bounds(), sampler).collect();
+        order_tiles(&mut tiles, film.sample_bounds(), self.tile_order);
         let progress = Self::make_progress_bar(film.sample_bounds().area() as u64);
-        let prog_ref = &progress; // because of move
-        tiles.into_par_iter().for_each(move |(tile, tile_sampler)| {
-            self.render_tile(scene, film, tile_sampler, tile, &prog_ref);
+        progress.set_style(indicatif::ProgressStyle::default_bar().template("{wide_bar} {percent}% {msg}"));
+        let eta = TileEta::new(tiles.len());
+        let checkpointer = checkpoint.map(Checkpointer::new);
+        for_each_queued(tiles, |(tile, tile_sampler)| {
+            // reuse each worker thread's arena across tiles rather than allocating one per tile
+            TILE_ARENA.with(|arena| {
+                let arena = &mut arena.borrow_mut();
+                arena.reset();
+                self.render_tile(scene, film, tile_sampler, tile, first_sample, &progress, arena);
+            });
+            let remaining = eta.complete_tile();
+            progress.set_message(&format!("ETA {}s", remaining.as_secs()));
+            if let Some(checkpointer) = &checkpointer {
+                checkpointer.maybe_save(film);
+            }
         });
         progress.finish()
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn render_tile(&self,
                    scene: &Scene,
-                   film: &Film<BoxFilter>,
+                   film: &Film<impl Filter + Sync>,
                    mut tile_sampler: impl Sampler,
                    tile: Bounds2i,
-                   progress: &indicatif::ProgressBar
+                   first_sample: u64,
+                   progress: &indicatif::ProgressBar,
+                   arena: &mut Bump,
     ) {
-        let mut arena = Bump::new();
-
         let mut film_tile = film.get_film_tile(tile);
+        // splatted paths may land anywhere on the image, so each one carries the energy for the
+        // whole image when the film is cropped
+        let full_area = (film.full_resolution.x * film.full_resolution.y) as Float;
+        let splat_scale = full_area / film.cropped_pixel_bounds.area() as Float;
+        let mut splat = |p_raster, l: Spectrum| film.add_splat(p_raster, l * splat_scale);
 
-        for pixel in tile.iter_points() {
+        for pixel in tile.iter_points_serpentine() {
             tile_sampler.start_pixel(pixel.into());
+            if first_sample > 0 && !tile_sampler.set_sample_number(first_sample) {
+                progress.inc(1);
+                continue;
+            }
 
             while tile_sampler.start_next_sample() {
                 let camera_sample = tile_sampler.get_camera_sample(pixel.into());
@@ -251,23 +482,30 @@ impl<R: IntegratorRadiance> SamplerIntegrator<R> {
                 );
 
                 let mut radiance = Spectrum::uniform(0.0);
+                let mut alpha = 0.0;
 
                 if ray_weight > 0.0 {
-                    radiance = self.radiance.incident_radiance(
+                    // coverage for compositing, tested before the ray is modified by tracing it
+                    if !film.tracks_coverage() || scene.intersect_test(&ray_differential.ray) {
+                        alpha = 1.0;
+                    }
+                    radiance = self.radiance.camera_ray_radiance(
                         &mut ray_differential,
                         scene,
+                        self.camera.as_ref(),
                         &mut tile_sampler,
-                        &arena,
-                        0,
+                        arena,
+                        &mut splat,
                     );
 
                     check_radiance(&radiance, pixel);
                 }
 
-                film.add_sample_to_tile(
+                film.add_sample_with_alpha_to_tile(
                     &mut film_tile,
                     camera_sample.p_film,
                     radiance,
+                    alpha,
                     ray_weight,
                 );
 
@@ -286,12 +524,35 @@ fn check_radiance(l: &Spectrum, pixel: (i32, i32)) {
     assert!(!l.has_nans(), "NaN radiance value for pixel {:?}: {:?}", pixel, l);
 }
 
+/// Randomly terminates a path with probability `q = max(min_survival, 1 - max component of beta)`,
+/// otherwise scaling the throughput `beta` by `1 / (1 - q)` so that the estimate stays unbiased.
+/// Returns whether the path survived.
+pub fn russian_roulette(beta: &mut Spectrum, sampler: &mut dyn Sampler, min_survival: Float) -> bool {
+    let q = Float::max(min_survival, 1.0 - beta.max_component_value());
+    if sampler.get_1d() < q {
+        false
+    } else {
+        *beta /= 1.0 - q;
+        true
+    }
+}
+
+/// Reads the `mis` integrator parameter, warning and falling back to the default for unknown names.
+pub(crate) fn mis_mode_from_params(params: &mut ParamSet) -> Result<MisMode, ParamError> {
+    let name: String = params.get_one_or("mis", "power".to_string())?;
+    Ok(MisMode::from_name(&name).unwrap_or_else(|| {
+        tracing::warn!("Unknown MIS heuristic \"{}\", using \"power\"", name);
+        MisMode::default()
+    }))
+}
+
 pub fn uniform_sample_one_light(
     intersect: &SurfaceInteraction,
     bsdf: &Bsdf,
     scene: &Scene,
     arena: &Bump,
     sampler: &mut dyn Sampler,
+    mis: MisMode,
 ) -> Spectrum {
     let n_lights = scene.lights.len();
     if n_lights == 0 { return Spectrum::uniform(0.0) }
@@ -301,9 +562,47 @@ pub fn uniform_sample_one_light(
 
     let u_light = sampler.get_2d();
     let u_scattering = sampler.get_2d();
-    n_lights as Float * estimate_direct(bsdf, intersect, u_scattering, light, u_light, scene, arena)
+    n_lights as Float * estimate_direct(bsdf, intersect, u_scattering, light, u_light, scene, arena, mis)
+}
+
+/// A distribution for choosing the scene's lights in proportion to the luminance of their power,
+/// or None if the scene has no lights.
+pub fn compute_light_power_distribution(scene: &Scene) -> Option<Distribution1D> {
+    if scene.lights.is_empty() {
+        return None;
+    }
+    let power = scene.lights.iter().map(|light| light.power().luminance()).collect();
+    Some(Distribution1D::new(power))
+}
+
+/// Chooses one of the scene's lights from `distribution`, returning it and the probability it was
+/// chosen with.
+pub fn sample_light<'s>(scene: &'s Scene, distribution: &Distribution1D, u: Float) -> (&'s dyn Light, Float) {
+    let (light_num, pmf) = distribution.sample_discrete(u);
+    (scene.lights[light_num].as_ref(), pmf)
 }
 
+/// Like `uniform_sample_one_light`, but chooses the light from `light_distribution`, which should
+/// come from `compute_light_power_distribution`. With many lights of different strengths this
+/// spends most samples on the ones that matter.
+pub fn sample_one_light(
+    intersect: &SurfaceInteraction,
+    bsdf: &Bsdf,
+    scene: &Scene,
+    arena: &Bump,
+    sampler: &mut dyn Sampler,
+    light_distribution: &Distribution1D,
+    mis: MisMode,
+) -> Spectrum {
+    let (light, pmf) = sample_light(scene, light_distribution, sampler.get_1d());
+    if pmf == 0.0 { return Spectrum::uniform(0.0) }
+
+    let u_light = sampler.get_2d();
+    let u_scattering = sampler.get_2d();
+    estimate_direct(bsdf, intersect, u_scattering, light, u_light, scene, arena, mis) / pmf
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn estimate_direct(
     bsdf: &Bsdf,
     intersect: &SurfaceInteraction,
@@ -312,6 +611,7 @@ pub fn estimate_direct(
     u_light: Point2f,
     scene: &Scene,
     _arena: &Bump,
+    mis: MisMode,
 //    sampler: &mut dyn Sampler,
 ) -> Spectrum {
     let bsdf_flags = BxDFType::all() & !BxDFType::SPECULAR;
@@ -333,7 +633,7 @@ pub fn estimate_direct(
             radiance += if light.flags().is_delta_light() {
                 f * light_sample.radiance / light_sample.pdf
             } else {
-                let weight = power_heuristic(1, light_sample.pdf, 1, scattering_pdf);
+                let weight = mis.weight(1, light_sample.pdf, 1, scattering_pdf);
                 f * light_sample.radiance * weight / light_sample.pdf
             }
         }
@@ -359,7 +659,7 @@ pub fn estimate_direct(
                 if light_pdf == 0.0 {
                     return radiance;
                 }
-                power_heuristic(1, scatter.pdf, 1, light_pdf)
+                mis.weight(1, scatter.pdf, 1, light_pdf)
             };
             let mut ray = intersect.hit.spawn_ray(scatter.wi);
 
@@ -367,18 +667,10 @@ pub fn estimate_direct(
             let si = scene.intersect(&mut ray);
 
             let incident_radiance = if let Some(si) = si {
-                si.primitive.unwrap().area_light()
-                    .filter(|l| {
-                        // FIXME: Comparing trait object references also compares the vtable pointer
-                        //  (even though it should have a Light vtable?). This compares the data
-                        //  pointers which is what we want. Should have read the docs more carefully.
-                        std::ptr::eq(
-                            l.as_light() as *const dyn Light as *const u8,
-                            light as *const dyn Light as *const u8
-                        )
-                    })
-                    // TODO: just call emitted on light?
-                    .map_or(Spectrum::uniform(0.0), |_| si.emitted_radiance(-scatter.wi))
+                let hit_light = si.primitive
+                    .and_then(|prim| prim.area_light())
+                    .map_or(false, |l| l.is_same_light(light));
+                if hit_light { si.le(-scatter.wi) } else { Spectrum::uniform(0.0) }
             } else {
                 // TODO: how to get differentials
                 light.environment_emitted_radiance(&RayDifferential { ray, diff: None })
@@ -393,3 +685,275 @@ pub fn estimate_direct(
 
     radiance
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::BoxFilter;
+    use crate::bvh::BVH;
+    use crate::camera::PerspectiveCamera;
+    use crate::geometry::Transform;
+    use crate::sampler::random::RandomSampler;
+    use crate::sampler::{SampleArrayId, SamplerState};
+    use crate::{Bounds2f, Point2i};
+
+    #[test]
+    fn test_queued_tiles_processed_once() {
+        let sample_bounds = Bounds2i::with_bounds(Point2i::new(0, 0), Point2i::new(200, 130));
+        let tiles: Vec<Bounds2i> = sample_bounds.iter_tiles(16).collect();
+        for &n_threads in &[1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(n_threads).build().unwrap();
+            let counts: Vec<AtomicUsize> = tiles.iter().map(|_| AtomicUsize::new(0)).collect();
+            pool.install(|| {
+                for_each_queued(tiles.iter().enumerate(), |(i, _tile)| {
+                    counts[i].fetch_add(1, Ordering::Relaxed);
+                })
+            });
+            assert!(counts.iter().all(|c| c.load(Ordering::Relaxed) == 1), "{} threads", n_threads);
+        }
+    }
+
+    #[test]
+    fn test_russian_roulette_unbiased() {
+        let mut sampler = RandomSampler::new_with_seed(1, 0);
+        let beta = Spectrum::new([0.3, 0.1, 0.2]);
+        let n = 100_000;
+        let mut sum = Spectrum::uniform(0.0);
+        let mut survived = 0;
+        for _ in 0..n {
+            let mut b = beta;
+            if russian_roulette(&mut b, &mut sampler, 0.05) {
+                survived += 1;
+                sum += b;
+            }
+        }
+
+        // Survival probability is the max component, 0.3
+        assert!((survived as Float / n as Float - 0.3).abs() < 0.01);
+        let mean = sum / n as Float;
+        for i in 0..3 {
+            assert!((mean[i] - beta[i]).abs() < 0.01 * beta[i] + 0.002, "{:?}", mean);
+        }
+    }
+
+    /// Returns a uniform random value for every camera ray
+    struct NoiseRadiance;
+
+    impl IntegratorRadiance for NoiseRadiance {
+        fn preprocess(&mut self, _scene: &Scene, _sampler: &mut dyn Sampler) {}
+
+        fn incident_radiance(&self, _ray: &mut RayDifferential, _scene: &Scene, sampler: &mut dyn Sampler, _arena: &Bump, _depth: u16) -> Spectrum {
+            Spectrum::uniform(sampler.get_1d())
+        }
+    }
+
+    fn noise_integrator() -> SamplerIntegrator<NoiseRadiance> {
+        let camera = PerspectiveCamera::new(
+            Transform::IDENTITY,
+            Point2i::new(16, 16),
+            Bounds2f::whole_screen(),
+            (0.0, 1.0),
+            0.0,
+            1.0,
+            60.0
+        );
+        SamplerIntegrator { camera: Box::new(camera), radiance: NoiseRadiance, tile_order: TileOrder::Scanline }
+    }
+
+    #[test]
+    fn test_render_with_threads() {
+        let scene = Scene::new(BVH::build(Vec::new()), vec![], vec![]);
+        let new_film = || Film::new(Point2i::new(40, 24), Bounds2f::unit(), BoxFilter::default(), 1.0);
+        let mut integrator = noise_integrator();
+
+        let single = new_film();
+        integrator.render_parallel_with_threads(&scene, &single, RandomSampler::new_with_seed(4, 0), 1).unwrap();
+
+        let multi = new_film();
+        integrator.render_parallel_with_threads(&scene, &multi, RandomSampler::new_with_seed(4, 0), 4).unwrap();
+
+        assert_eq!(single.into_image_buffer(), multi.into_image_buffer());
+    }
+
+    #[test]
+    fn test_render_dyn_boxed_sampler() {
+        let scene = Scene::new(BVH::build(Vec::new()), vec![], vec![]);
+        let new_film = || Film::new(Point2i::new(40, 24), Bounds2f::unit(), BoxFilter::default(), 1.0);
+        let mut integrator = noise_integrator();
+
+        let concrete = new_film();
+        integrator.render_parallel(&scene, &concrete, RandomSampler::new_with_seed(4, 0), None);
+
+        let boxed = new_film();
+        integrator.render_dyn(&scene, &boxed, Box::new(RandomSampler::new_with_seed(4, 0)));
+
+        let (img, _) = boxed.spectrum_buffer();
+        let mean = img.iter().map(|s| s[0]).sum::<Float>() / img.len() as Float;
+        assert!((mean - 0.5).abs() < 0.05, "{}", mean);
+        assert_eq!(concrete.into_image_buffer(), boxed.into_image_buffer());
+    }
+
+    #[test]
+    fn test_reused_arena_matches_per_tile_arena() {
+        use std::sync::Arc;
+        use crate::Vec3f;
+        use crate::integrator::path::PathIntegrator;
+        use crate::light::Light;
+        use crate::light::point::PointLight;
+        use crate::material::matte::MatteMaterial;
+        use crate::primitive::{GeometricPrimitive, Primitive};
+        use crate::shapes::sphere::Sphere;
+
+        // a scene whose shading allocates in the arena
+        let o2w = Transform::translate(Vec3f::new(0.0, 0.0, 4.0));
+        let sphere = GeometricPrimitive::new(
+            Arc::new(Sphere::whole(o2w, o2w.inverse(), 2.0)),
+            Some(Arc::new(MatteMaterial::constant(Spectrum::uniform(0.5)))),
+        );
+        let prims: Vec<Box<dyn Primitive>> = vec![Box::new(sphere)];
+        let lights: Vec<Box<dyn Light>> = vec![Box::new(PointLight::new(Transform::IDENTITY, Spectrum::uniform(5.0)))];
+        let scene = Scene::new(BVH::build(prims), lights, vec![]);
+
+        let resolution = Point2i::new(40, 24);
+        let new_film = || Film::new(resolution, Bounds2f::unit(), BoxFilter::default(), 1.0);
+        let mut integrator = SamplerIntegrator {
+            camera: Box::new(PerspectiveCamera::new(Transform::IDENTITY, resolution, Bounds2f::whole_screen(), (0.0, 1.0), 0.0, 1.0, 60.0)),
+            radiance: PathIntegrator::new(3, 1.0),
+            tile_order: TileOrder::Scanline,
+        };
+
+        let per_tile = new_film();
+        integrator.render(&scene, &per_tile, RandomSampler::new_with_seed(4, 0));
+
+        let reused = new_film();
+        integrator.render_parallel_with_threads(&scene, &reused, RandomSampler::new_with_seed(4, 0), 2).unwrap();
+
+        assert_eq!(per_tile.into_image_buffer(), reused.into_image_buffer());
+    }
+
+    /// A sampler whose values depend only on the pixel, the sample's index in the pixel and how
+    /// many values the sample has used, so a render takes the same samples however it's split up.
+    #[derive(Clone)]
+    struct HashSampler {
+        state: SamplerState,
+        samples_per_pixel: usize,
+        pixel: Point2i,
+        sample_num: u64,
+        dimension: u64,
+    }
+
+    impl HashSampler {
+        fn new(samples_per_pixel: usize) -> Self {
+            Self {
+                state: SamplerState::new(samples_per_pixel),
+                samples_per_pixel,
+                pixel: Point2i::new(0, 0),
+                sample_num: 0,
+                dimension: 0,
+            }
+        }
+
+        fn next(&mut self) -> Float {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            (self.pixel.x, self.pixel.y, self.sample_num, self.dimension).hash(&mut hasher);
+            self.dimension += 1;
+            (hasher.finish() >> 40) as Float / (1u64 << 24) as Float
+        }
+    }
+
+    impl Sampler for HashSampler {
+        fn start_pixel(&mut self, pixel: Point2i) {
+            self.state.start_pixel(pixel);
+            self.pixel = pixel;
+            self.sample_num = 0;
+        }
+
+        fn start_next_sample(&mut self) -> bool {
+            self.sample_num += 1;
+            self.dimension = 0;
+            self.state.start_next_sample()
+        }
+
+        fn get_1d(&mut self) -> Float {
+            self.next()
+        }
+
+        fn get_2d(&mut self) -> Point2f {
+            Point2f::new(self.next(), self.next())
+        }
+
+        fn request_1d_array(&mut self, len: usize) -> SampleArrayId {
+            self.state.request_1d_array(len)
+        }
+
+        fn request_2d_array(&mut self, len: usize) -> SampleArrayId {
+            self.state.request_2d_array(len)
+        }
+
+        fn get_1d_array(&self, id: SampleArrayId) -> &[Float] {
+            self.state.get_1d_array(id)
+        }
+
+        fn get_2d_array(&self, id: SampleArrayId) -> &[Point2f] {
+            self.state.get_2d_array(id)
+        }
+
+        fn clone_with_seed(&self, _seed: u64) -> Self {
+            self.clone()
+        }
+
+        fn clone_box(&self, seed: u64) -> Box<dyn Sampler> {
+            Box::new(self.clone_with_seed(seed))
+        }
+
+        fn samples_per_pixel(&self) -> usize {
+            self.samples_per_pixel
+        }
+
+        fn set_sample_number(&mut self, sample_num: u64) -> bool {
+            self.sample_num = sample_num;
+            self.state.set_sample_number(sample_num)
+        }
+    }
+
+    #[test]
+    fn test_resume_matches_full_render() {
+        let scene = Scene::new(BVH::build(Vec::new()), vec![], vec![]);
+        let new_film = || Film::new(Point2i::new(16, 16), Bounds2f::unit(), BoxFilter::default(), 1.0);
+        let mut integrator = noise_integrator();
+
+        let full = new_film();
+        integrator.render_parallel(&scene, &full, HashSampler::new(64), None);
+
+        let resumed = new_film();
+        integrator.render_parallel(&scene, &resumed, HashSampler::new(32), None);
+        integrator.resume(&scene, &resumed, HashSampler::new(64), 32);
+
+        assert!(resumed.pixels.lock().iter().all(|p| p.n_samples == 64));
+        let (full, _) = full.into_spectrum_buffer();
+        let (resumed, _) = resumed.into_spectrum_buffer();
+        // the two halves of the resumed pixels are summed separately, so allow for rounding
+        for (full, resumed) in full.iter().zip(&resumed) {
+            assert!((full[0] - resumed[0]).abs() < 1e-5, "{} {}", full[0], resumed[0]);
+        }
+        let mean = full.iter().map(|s| s[0]).sum::<Float>() / full.len() as Float;
+        assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+    }
+
+    #[test]
+    fn test_morton_tile_order_is_permutation() {
+        let sample_bounds = Bounds2i::with_bounds((-8, 0).into(), (100, 70).into());
+        let scanline: Vec<_> = sample_bounds.iter_tiles(16).map(|tile| (tile, ())).collect();
+
+        let mut morton = scanline.clone();
+        order_tiles(&mut morton, sample_bounds, TileOrder::Morton);
+        assert_ne!(scanline, morton);
+
+        let key = |(tile, _): &(Bounds2i, ())| (tile.min.x, tile.min.y);
+        let mut sorted_scanline = scanline.clone();
+        sorted_scanline.sort_by_key(key);
+        morton.sort_by_key(key);
+        assert_eq!(sorted_scanline, morton);
+    }
+}
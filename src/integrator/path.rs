@@ -1,4 +1,4 @@
-use crate::integrator::{IntegratorRadiance, uniform_sample_one_light};
+use crate::integrator::{IntegratorRadiance, uniform_sample_one_light, russian_roulette, mis_mode_from_params};
 use crate::sampler::Sampler;
 use crate::scene::Scene;
 use crate::spectrum::Spectrum;
@@ -6,15 +6,32 @@ use crate::{Float, RayDifferential, abs_dot};
 use bumpalo::Bump;
 use crate::material::TransportMode;
 use crate::reflection::BxDFType;
+use crate::loaders::{ParamSet, ParamError};
+use crate::sampling::MisMode;
 
 pub struct PathIntegrator {
     max_depth: u16,
     rr_threshold: Float,
+    mis_mode: MisMode,
 }
 
 impl PathIntegrator {
     pub fn new(max_depth: u16, rr_threshold: f32) -> Self {
-        PathIntegrator { max_depth, rr_threshold }
+        PathIntegrator { max_depth, rr_threshold, mis_mode: MisMode::default() }
+    }
+
+    /// Sets the heuristic used to weight light and BSDF samples in direct lighting.
+    pub fn with_mis_mode(mut self, mis_mode: MisMode) -> Self {
+        self.mis_mode = mis_mode;
+        self
+    }
+
+    /// Construct from the scene's `Integrator` parameters, reading `maxdepth` (default 5),
+    /// `rrthreshold` (default 1) and `mis` ("power" or "balance", default "power").
+    pub fn from_params(params: &mut ParamSet) -> Result<Self, ParamError> {
+        let max_depth = params.get_one_or::<i32>("maxdepth", 5)?.max(0) as u16;
+        let rr_threshold = params.get_one_or("rrthreshold", 1.0)?;
+        Ok(Self::new(max_depth, rr_threshold).with_mis_mode(mis_mode_from_params(params)?))
     }
 }
 
@@ -44,7 +61,7 @@ impl IntegratorRadiance for PathIntegrator {
             // possibly add emitted light at intersection
             if bounces == 0 || specular_bounce {
                 if let Some(si) = &si {
-                    path_radiance += throughput * si.emitted_radiance(-ray.ray.dir);
+                    path_radiance += throughput * si.le(-ray.ray.dir);
                 } else {
                     path_radiance += throughput * scene.environment_emitted_radiance(ray);
                 }
@@ -60,7 +77,7 @@ impl IntegratorRadiance for PathIntegrator {
                 // Sample illumination from lights to find path contribution
                 // But skip for perfectly specular BSDFs
                 if bsdf.num_components(BxDFType::all() & !BxDFType::SPECULAR) > 0 {
-                    let direct = throughput * uniform_sample_one_light(&si, &bsdf, scene, arena, sampler);
+                    let direct = throughput * uniform_sample_one_light(&si, &bsdf, scene, arena, sampler, self.mis_mode);
                     path_radiance += direct;
                 }
 
@@ -81,13 +98,9 @@ impl IntegratorRadiance for PathIntegrator {
             }
 
             // Possibly terminate the path with Russian roulette
-            if throughput.max_component_value() < self.rr_threshold && bounces > 3 {
-                let q = Float::max(0.05, 1.0 - throughput.max_component_value());
-                if sampler.get_1d() < q {
-                    break;
-                } else {
-                    throughput /= 1.0 - q;
-                }
+            if throughput.max_component_value() < self.rr_threshold && bounces > 3
+                && !russian_roulette(&mut throughput, sampler, 0.05) {
+                break;
             }
             bounces += 1;
         }
@@ -0,0 +1,437 @@
+use bumpalo::Bump;
+use rayon::prelude::*;
+
+use crate::{abs_dot, distance_sq, Float, Point2i, Point3f, RayDifferential, Vec3f};
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::geometry::bounds::Bounds3f;
+use crate::integrator::uniform_sample_one_light;
+use crate::loaders::{ParamSet, ParamError};
+use crate::material::TransportMode;
+use crate::reflection::BxDFType;
+use crate::reflection::bsdf::Bsdf;
+use crate::sampler::Sampler;
+use crate::sampler::random::RandomSampler;
+use crate::sampling::MisMode;
+use crate::scene::Scene;
+use crate::spectrum::Spectrum;
+
+/// Number of photons traced by each task of the photon pass.
+const PHOTON_CHUNK_SIZE: usize = 4096;
+
+/// Stochastic progressive photon mapping. Each iteration traces a camera path for every pixel to
+/// its first diffuse surface (following specular bounces), then traces photons from the lights and
+/// gathers those that land within a radius of each of those visible points. The radius shrinks
+/// as photons are gathered, so the estimate converges, and paths through specular surfaces
+/// (caustics, and their reflections) are found that can't be sampled from the camera.
+pub struct SPPMIntegrator {
+    pub camera: Box<dyn Camera>,
+    n_iterations: usize,
+    /// If zero, one photon per pixel is traced each iteration.
+    photons_per_iteration: usize,
+    max_depth: u16,
+    initial_radius: Float,
+}
+
+/// The statistics accumulated for a pixel over all iterations.
+struct SPPMPixel {
+    radius: Float,
+    /// Sum of the emitted and directly lit radiance found by the camera paths.
+    ld: Spectrum,
+    /// Photon flux gathered within `radius`, scaled down as the radius shrinks.
+    tau: Spectrum,
+    /// The (fractional) number of photons that `tau` is made of.
+    n: Float,
+}
+
+/// Where a pixel's camera path ended on a diffuse surface in the current iteration.
+struct VisiblePoint<'a> {
+    p: Point3f,
+    wo: Vec3f,
+    bsdf: Bsdf<'a>,
+    /// Throughput of the camera path to the point.
+    beta: Spectrum,
+}
+
+/// A photon arriving from `wi` with throughput `beta` near the visible point `vp`.
+struct PhotonDeposit {
+    vp: usize,
+    wi: Vec3f,
+    beta: Spectrum,
+}
+
+/// Visible points hashed into a uniform grid over the scene bounds, with cells as wide as the
+/// largest search radius. Each point is stored in every cell its search radius overlaps.
+struct VisiblePointGrid {
+    bounds: Bounds3f,
+    cell_size: Float,
+    buckets: Vec<Vec<usize>>,
+}
+
+impl VisiblePointGrid {
+    fn new(bounds: Bounds3f, points: &[Option<(Point3f, Float)>]) -> Self {
+        let max_radius = points.iter().flatten().map(|&(_, r)| r).fold(0.0, Float::max);
+        let mut grid = Self {
+            bounds,
+            cell_size: Float::max(2.0 * max_radius, 1e-4),
+            buckets: (0..points.len()).map(|_| vec![]).collect(),
+        };
+
+        for (i, (p, r)) in points.iter().enumerate().filter_map(|(i, p)| p.map(|p| (i, p))) {
+            let lo = grid.cell(p - Vec3f::new(r, r, r));
+            let hi = grid.cell(p + Vec3f::new(r, r, r));
+            for z in lo[2]..=hi[2] {
+                for y in lo[1]..=hi[1] {
+                    for x in lo[0]..=hi[0] {
+                        let bucket = grid.bucket([x, y, z]);
+                        grid.buckets[bucket].push(i);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell(&self, p: Point3f) -> [i32; 3] {
+        let offset = (p - self.bounds.min) / self.cell_size;
+        [offset.x.floor() as i32, offset.y.floor() as i32, offset.z.floor() as i32]
+    }
+
+    fn bucket(&self, cell: [i32; 3]) -> usize {
+        let hash = (cell[0] as u32).wrapping_mul(73_856_093)
+            ^ (cell[1] as u32).wrapping_mul(19_349_663)
+            ^ (cell[2] as u32).wrapping_mul(83_492_791);
+        hash as usize % self.buckets.len()
+    }
+
+    /// The visible points that may be within their search radius of `p`.
+    fn candidates(&self, p: Point3f) -> &[usize] {
+        &self.buckets[self.bucket(self.cell(p))]
+    }
+}
+
+impl SPPMIntegrator {
+    pub fn new(
+        camera: Box<dyn Camera>,
+        n_iterations: usize,
+        photons_per_iteration: usize,
+        max_depth: u16,
+        initial_radius: Float,
+    ) -> Self {
+        Self { camera, n_iterations, photons_per_iteration, max_depth, initial_radius }
+    }
+
+    /// Construct from the scene's `Integrator` parameters, reading `numiterations` (default 64),
+    /// `photonsperiteration` (default one per pixel), `maxdepth` (default 5) and `radius`
+    /// (default 1).
+    pub fn from_params(camera: Box<dyn Camera>, params: &mut ParamSet) -> Result<Self, ParamError> {
+        let n_iterations = params.get_one_or::<i32>("numiterations", 64)?.max(1) as usize;
+        let photons_per_iteration = params.get_one_or::<i32>("photonsperiteration", -1)?.max(0) as usize;
+        let max_depth = params.get_one_or::<i32>("maxdepth", 5)?.max(0) as u16;
+        let initial_radius = params.get_one_or("radius", 1.0)?;
+        Ok(Self::new(camera, n_iterations, photons_per_iteration, max_depth, initial_radius))
+    }
+
+    /// Renders all of the iterations and writes the final estimate to the film. The camera pass
+    /// takes one sample from `sampler` per pixel each iteration.
+    pub fn render(&self, scene: &Scene, film: &Film<impl Filter + Sync>, mut sampler: impl Sampler) {
+        let pixel_bounds = film.cropped_pixel_bounds;
+        let n_pixels = pixel_bounds.area() as usize;
+        let photons_per_iteration = if self.photons_per_iteration == 0 {
+            n_pixels
+        } else {
+            self.photons_per_iteration
+        };
+
+        let mut pixels: Vec<_> = (0..n_pixels)
+            .map(|_| SPPMPixel {
+                radius: self.initial_radius,
+                ld: Spectrum::uniform(0.0),
+                tau: Spectrum::uniform(0.0),
+                n: 0.0,
+            })
+            .collect();
+
+        let mut arena = Bump::new();
+        for iteration in 0..self.n_iterations {
+            {
+                let mut visible_points: Vec<Option<VisiblePoint>> = (0..n_pixels).map(|_| None).collect();
+                for pixel in pixel_bounds.iter_points() {
+                    let idx = film.get_pixel_idx(pixel.into());
+                    sampler.start_pixel(pixel.into());
+                    sampler.start_next_sample();
+                    visible_points[idx] = self.trace_camera_path(scene, &mut sampler, &arena, pixel.into(), &mut pixels[idx]);
+                }
+
+                let lookup_points: Vec<_> = visible_points.iter().zip(&pixels)
+                    .map(|(vp, pixel)| vp.as_ref()
+                        .filter(|vp| !vp.beta.is_black())
+                        .map(|vp| (vp.p, pixel.radius)))
+                    .collect();
+                let grid = VisiblePointGrid::new(scene.world_bound(), &lookup_points);
+
+                let n_chunks = (photons_per_iteration + PHOTON_CHUNK_SIZE - 1) / PHOTON_CHUNK_SIZE;
+                let deposits: Vec<Vec<PhotonDeposit>> = (0..n_chunks).into_par_iter()
+                    .map(|chunk| {
+                        let n_photons = PHOTON_CHUNK_SIZE.min(photons_per_iteration - chunk * PHOTON_CHUNK_SIZE);
+                        let seed = ((iteration as u64) << 32) | chunk as u64;
+                        self.trace_photons(scene, &grid, &lookup_points, n_photons, seed)
+                    })
+                    .collect();
+
+                // the BSDFs at the visible points live in this thread's arena, so the gathered
+                // photons are evaluated here rather than while tracing
+                let mut phi = vec![Spectrum::uniform(0.0); n_pixels];
+                let mut m = vec![0u32; n_pixels];
+                for deposit in deposits.iter().flatten() {
+                    let vp = visible_points[deposit.vp].as_ref().unwrap();
+                    phi[deposit.vp] += deposit.beta * vp.bsdf.f(vp.wo, deposit.wi, BxDFType::all());
+                    m[deposit.vp] += 1;
+                }
+
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    if m[i] == 0 {
+                        continue;
+                    }
+                    let vp = visible_points[i].as_ref().unwrap();
+                    // keep 2/3 of the new photons, shrinking the radius so that the density stays
+                    // consistent
+                    let gamma = 2.0 / 3.0;
+                    let n_gathered = m[i] as Float;
+                    let n_new = pixel.n + gamma * n_gathered;
+                    let r_new = pixel.radius * Float::sqrt(n_new / (pixel.n + n_gathered));
+                    pixel.tau = (pixel.tau + vp.beta * phi[i]) * (r_new * r_new) / (pixel.radius * pixel.radius);
+                    pixel.n = n_new;
+                    pixel.radius = r_new;
+                }
+            }
+            arena.reset();
+        }
+
+        let n_iterations = self.n_iterations as Float;
+        let n_photons = n_iterations * photons_per_iteration as Float;
+        let image: Vec<Spectrum> = pixels.iter()
+            .map(|p| {
+                let indirect = p.tau / (n_photons * std::f32::consts::PI * p.radius * p.radius);
+                p.ld / n_iterations + indirect
+            })
+            .collect();
+        film.set_image(&image);
+    }
+
+    /// Follows a camera ray through specular bounces until it reaches a diffuse surface (or a
+    /// glossy one at the last bounce), adding the emitted and directly lit radiance along the
+    /// way to the pixel.
+    fn trace_camera_path<'a>(
+        &self,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        arena: &'a Bump,
+        pixel: Point2i,
+        stats: &mut SPPMPixel,
+    ) -> Option<VisiblePoint<'a>> {
+        let camera_sample = sampler.get_camera_sample(pixel);
+        let (weight, mut ray) = self.camera.generate_ray_differential(camera_sample);
+        if weight == 0.0 {
+            return None;
+        }
+        let mut beta = Spectrum::uniform(weight);
+        let mut specular_bounce = false;
+        let mut depth = 0;
+
+        while depth < self.max_depth {
+            let mut si = match scene.intersect(&mut ray.ray) {
+                Some(si) => si,
+                None => {
+                    stats.ld += beta * scene.environment_emitted_radiance(&ray);
+                    return None;
+                }
+            };
+
+            let bsdf = match si.compute_scattering_functions(&ray, arena, true, TransportMode::Radiance) {
+                Some(bsdf) => bsdf,
+                None => {
+                    // Skip over null bsdf without incrementing depth
+                    ray = si.hit.spawn_ray_with_dfferentials(ray.ray.dir, ray.diff);
+                    continue;
+                }
+            };
+
+            let wo = -ray.ray.dir;
+            if depth == 0 || specular_bounce {
+                stats.ld += beta * si.le(wo);
+            }
+            stats.ld += beta * uniform_sample_one_light(&si, &bsdf, scene, arena, sampler, MisMode::default());
+
+            let is_diffuse = bsdf.num_components(BxDFType::DIFFUSE | BxDFType::REFLECTION | BxDFType::TRANSMISSION) > 0;
+            let is_glossy = bsdf.num_components(BxDFType::GLOSSY | BxDFType::REFLECTION | BxDFType::TRANSMISSION) > 0;
+            if is_diffuse || (is_glossy && depth == self.max_depth - 1) {
+                return Some(VisiblePoint { p: si.hit.p, wo, bsdf, beta });
+            }
+
+            let bsdf_sample = bsdf.sample_f(wo, sampler.get_2d(), BxDFType::all())
+                .filter(|s| !s.f.is_black() && s.pdf > 0.0)?;
+            specular_bounce = bsdf_sample.sampled_type.contains(BxDFType::SPECULAR);
+            beta *= bsdf_sample.f * abs_dot(bsdf_sample.wi, si.shading_n.0) / bsdf_sample.pdf;
+            ray = si.hit.spawn_ray_with_dfferentials(bsdf_sample.wi, ray.diff);
+            depth += 1;
+        }
+        None
+    }
+
+    /// Traces `n_photons` photons from the lights, recording each one that arrives within the
+    /// search radius of a visible point after at least one bounce. Photons arriving directly from
+    /// the lights are skipped since the camera pass already includes direct lighting.
+    fn trace_photons(
+        &self,
+        scene: &Scene,
+        grid: &VisiblePointGrid,
+        points: &[Option<(Point3f, Float)>],
+        n_photons: usize,
+        seed: u64,
+    ) -> Vec<PhotonDeposit> {
+        let mut deposits = vec![];
+        let n_lights = scene.num_lights();
+        if n_lights == 0 {
+            return deposits;
+        }
+        let light_pdf = 1.0 / n_lights as Float;
+        let mut sampler = RandomSampler::new_with_seed(1, seed);
+        let mut arena = Bump::new();
+
+        for _ in 0..n_photons {
+            let light_num = ((sampler.get_1d() * n_lights as Float) as usize).min(n_lights - 1);
+            // photons are spread over the default shutter interval of [0, 1]
+            let time = sampler.get_1d();
+            let le = match scene.light(light_num).sample_le(sampler.get_2d(), sampler.get_2d(), time) {
+                Some(le) if le.pdf_pos > 0.0 && le.pdf_dir > 0.0 && !le.radiance.is_black() => le,
+                _ => continue,
+            };
+            let mut beta = le.radiance * abs_dot(le.n_light.0, le.ray.dir) / (light_pdf * le.pdf_pos * le.pdf_dir);
+            let mut ray = RayDifferential { ray: le.ray, diff: None };
+            let mut depth = 0;
+
+            while depth < self.max_depth {
+                let mut si = match scene.intersect(&mut ray.ray) {
+                    Some(si) => si,
+                    None => break,
+                };
+
+                if depth > 0 {
+                    let p = si.hit.p;
+                    for &vp in grid.candidates(p) {
+                        let (vp_p, radius) = points[vp].unwrap();
+                        if distance_sq(vp_p, p) <= radius * radius {
+                            deposits.push(PhotonDeposit { vp, wi: -ray.ray.dir, beta });
+                        }
+                    }
+                }
+
+                let bsdf = match si.compute_scattering_functions(&ray, &arena, true, TransportMode::Importance) {
+                    Some(bsdf) => bsdf,
+                    None => {
+                        // Skip over null bsdf without incrementing depth
+                        ray = si.hit.spawn_ray_with_dfferentials(ray.ray.dir, None);
+                        continue;
+                    }
+                };
+
+                let wo = -ray.ray.dir;
+                let bsdf_sample = match bsdf.sample_f(wo, sampler.get_2d(), BxDFType::all()) {
+                    Some(s) if !s.f.is_black() && s.pdf > 0.0 => s,
+                    _ => break,
+                };
+                let beta_new = beta * bsdf_sample.f * abs_dot(bsdf_sample.wi, si.shading_n.0) / bsdf_sample.pdf;
+
+                // terminate with a probability based on how much the bounce reduced the throughput,
+                // so that surviving photons keep roughly the same power
+                let q = Float::max(0.0, 1.0 - beta_new.luminance() / beta.luminance());
+                if sampler.get_1d() < q {
+                    break;
+                }
+                beta = beta_new / (1.0 - q);
+                ray = si.hit.spawn_ray_with_dfferentials(bsdf_sample.wi, None);
+                depth += 1;
+            }
+            arena.reset();
+        }
+        deposits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use cgmath::Deg;
+    use crate::{Bounds2f, Point2i, Transform};
+    use crate::bvh::BVH;
+    use crate::camera::PerspectiveCamera;
+    use crate::filter::BoxFilter;
+    use crate::light::Light;
+    use crate::light::point::PointLight;
+    use crate::material::Material;
+    use crate::material::matte::MatteMaterial;
+    use crate::material::mirror::MirrorMaterial;
+    use crate::primitive::{GeometricPrimitive, Primitive};
+    use crate::shapes::triangle::TriangleMesh;
+    use crate::texture::ConstantTexture;
+
+    /// A square in the plane at height `z`, made of two triangles.
+    fn square(z: Float, half_width: Float, material: Arc<dyn Material>) -> Vec<Box<dyn Primitive>> {
+        let w = half_width;
+        let mesh = TriangleMesh::new(
+            Transform::IDENTITY,
+            vec![0, 1, 2, 0, 2, 3],
+            vec![
+                Point3f::new(-w, -w, z), Point3f::new(w, -w, z),
+                Point3f::new(w, w, z), Point3f::new(-w, w, z),
+            ],
+            None,
+            None,
+            None,
+            false,
+        );
+        Arc::new(mesh).iter_triangles()
+            .map(|shape| Box::new(GeometricPrimitive::new(Arc::new(shape), Some(material.clone()))) as Box<dyn Primitive>)
+            .collect()
+    }
+
+    #[test]
+    fn test_mirror_caustic_irradiance() {
+        // A diffuse floor at z = 0 lit by a point light at z = 1, with a mirror ceiling at z = 2.
+        // Near the origin the floor sees the light directly, and its mirror image at z = 3 through
+        // the ceiling, which is only found by the photons.
+        let albedo = 0.5;
+        let mut prims = square(0.0, 50.0, Arc::new(MatteMaterial::constant(Spectrum::uniform(albedo))));
+        let mirror = MirrorMaterial::new(Arc::new(ConstantTexture(Spectrum::uniform(1.0))));
+        prims.extend(square(2.0, 50.0, Arc::new(mirror)));
+        let light = PointLight::new(Transform::translate(Vec3f::new(0.0, 0.0, 1.0)), Spectrum::uniform(1.0));
+        let lights: Vec<Box<dyn Light>> = vec![Box::new(light)];
+        let scene = Scene::new(BVH::build(prims), lights, vec![]);
+
+        // looking straight down with a narrow view, so the irradiance is nearly constant
+        let resolution = Point2i::new(8, 8);
+        let camera = PerspectiveCamera::new(
+            Transform::translate(Vec3f::new(0.0, 0.0, 1.5)) * Transform::rotate_x(Deg(180.0)),
+            resolution,
+            Bounds2f::whole_screen(),
+            (0.0, 1.0),
+            0.0,
+            1.0,
+            5.0
+        );
+        let integrator = SPPMIntegrator::new(Box::new(camera), 8, 100_000, 2, 0.1);
+        let film = Film::new(resolution, Bounds2f::unit(), BoxFilter::default(), 1.0);
+        integrator.render(&scene, &film, RandomSampler::new_with_seed(8, 0));
+
+        let (img, _) = film.into_spectrum_buffer();
+        let mean = img.iter().map(|s| s[0]).sum::<Float>() / img.len() as Float;
+
+        // E = I cos / d^2 from the light at distance 1 and from its image at distance 3
+        let direct = albedo / std::f32::consts::PI;
+        let caustic = albedo / std::f32::consts::PI / 9.0;
+        assert!((mean - (direct + caustic)).abs() < 0.25 * caustic, "{} {} {}", mean, direct, caustic);
+    }
+}
@@ -0,0 +1,67 @@
+use bumpalo::Bump;
+
+use crate::RayDifferential;
+use crate::integrator::IntegratorRadiance;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::spectrum::Spectrum;
+
+/// Shows the texture coordinates of the first surface hit as red and green, for checking the uv
+/// mapping of loaded meshes. Rays that miss are black.
+#[derive(Default)]
+pub struct UVIntegrator;
+
+impl IntegratorRadiance for UVIntegrator {
+    fn preprocess(&mut self, _scene: &Scene, _sampler: &mut dyn Sampler) {}
+
+    fn incident_radiance(&self, ray: &mut RayDifferential, scene: &Scene, _sampler: &mut dyn Sampler, _arena: &Bump, _depth: u16) -> Spectrum {
+        match scene.intersect(&mut ray.ray) {
+            Some(si) => Spectrum::from([si.uv.x, si.uv.y, 0.0]),
+            None => Spectrum::uniform(0.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use approx::assert_abs_diff_eq;
+    use cgmath::InnerSpace;
+    use crate::{Point2f, Point3f, Ray, Transform, Vec3f};
+    use crate::bvh::BVH;
+    use crate::primitive::{GeometricPrimitive, Primitive};
+    use crate::sampler::random::RandomSampler;
+    use crate::shapes::triangle::TriangleMesh;
+
+    #[test]
+    fn test_uv_colors_at_vertices() {
+        let vertices = vec![Point3f::new(0.0, 0.0, 0.0), Point3f::new(1.0, 0.0, 0.0), Point3f::new(0.0, 1.0, 0.0)];
+        let uvs = vec![Point2f::new(0.1, 0.2), Point2f::new(0.9, 0.3), Point2f::new(0.4, 0.8)];
+        let mesh = Arc::new(TriangleMesh::new(
+            Transform::identity(), vec![0, 1, 2], vertices.clone(), None, None, Some(uvs.clone()), false
+        ));
+        let prims: Vec<Box<dyn Primitive>> = mesh.iter_triangles()
+            .map(|tri| Box::new(GeometricPrimitive::new(Arc::new(tri), None)) as Box<dyn Primitive>)
+            .collect();
+        let scene = Scene::new(BVH::build(prims), vec![], vec![]);
+
+        let integrator = UVIntegrator;
+        let mut sampler = RandomSampler::new_with_seed(1, 0);
+        let arena = Bump::new();
+        let centroid = Point3f::new(1.0 / 3.0, 1.0 / 3.0, 0.0);
+        for (&p, &uv) in vertices.iter().zip(&uvs) {
+            // aim just inside the triangle from each vertex
+            let target = p + 1e-3 * (centroid - p).normalize();
+            let mut ray = RayDifferential {
+                ray: Ray::new(target + Vec3f::new(0.0, 0.0, 1.0), Vec3f::new(0.0, 0.0, -1.0)),
+                diff: None,
+            };
+            let color = integrator.incident_radiance(&mut ray, &scene, &mut sampler, &arena, 0);
+            assert_abs_diff_eq!(color, Spectrum::from([uv.x, uv.y, 0.0]), epsilon = 1e-2);
+        }
+
+        let mut miss = RayDifferential { ray: Ray::new(Point3f::new(2.0, 2.0, 1.0), Vec3f::new(0.0, 0.0, -1.0)), diff: None };
+        assert_eq!(integrator.incident_radiance(&mut miss, &scene, &mut sampler, &arena, 0), Spectrum::uniform(0.0));
+    }
+}
@@ -0,0 +1,72 @@
+use bumpalo::Bump;
+
+use crate::RayDifferential;
+use crate::integrator::IntegratorRadiance;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::spectrum::Spectrum;
+
+/// Returns white where camera rays hit something and black where they don't, without doing any
+/// shading. Useful for measuring the throughput of the acceleration structure on its own.
+#[derive(Default)]
+pub struct VisibilityIntegrator;
+
+impl IntegratorRadiance for VisibilityIntegrator {
+    fn preprocess(&mut self, _scene: &Scene, _sampler: &mut dyn Sampler) {}
+
+    fn incident_radiance(&self, ray: &mut RayDifferential, scene: &Scene, _sampler: &mut dyn Sampler, _arena: &Bump, _depth: u16) -> Spectrum {
+        match scene.intersect(&mut ray.ray) {
+            Some(_) => Spectrum::uniform(1.0),
+            None => Spectrum::uniform(0.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use approx::assert_abs_diff_eq;
+    use crate::{Bounds2f, Point2i, Transform};
+    use crate::bvh::BVH;
+    use crate::camera::PerspectiveCamera;
+    use crate::film::Film;
+    use crate::filter::BoxFilter;
+    use crate::integrator::{SamplerIntegrator, TileOrder};
+    use crate::primitive::{GeometricPrimitive, Primitive};
+    use crate::sampler::random::RandomSampler;
+    use crate::shapes::sphere::Sphere;
+
+    #[test]
+    fn test_enclosed_camera_sees_white() {
+        // the camera is inside a sphere, so every ray hits it
+        let enclosure = GeometricPrimitive::new(
+            Arc::new(Sphere::whole(Transform::IDENTITY, Transform::IDENTITY, 10.0)),
+            None,
+        );
+        let prims: Vec<Box<dyn Primitive>> = vec![Box::new(enclosure)];
+        let scene = Scene::new(BVH::build(prims), vec![], vec![]);
+
+        let resolution = Point2i::new(8, 8);
+        let film = Film::new(resolution, Bounds2f::unit(), BoxFilter::default(), 1.0);
+        let mut integrator = SamplerIntegrator {
+            camera: Box::new(PerspectiveCamera::new(
+                Transform::IDENTITY,
+                resolution,
+                Bounds2f::whole_screen(),
+                (0.0, 1.0),
+                0.0,
+                1.0,
+                90.0
+            )),
+            radiance: VisibilityIntegrator,
+            tile_order: TileOrder::Scanline,
+        };
+        integrator.render_parallel(&scene, &film, RandomSampler::new_with_seed(2, 0), None);
+
+        let (img, _) = film.into_spectrum_buffer();
+        for s in img {
+            assert_abs_diff_eq!(s, Spectrum::uniform(1.0), epsilon = 1e-5);
+        }
+    }
+}
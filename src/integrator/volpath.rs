@@ -0,0 +1,358 @@
+use bumpalo::Bump;
+
+use crate::{abs_dot, Float, Point2f, RayDifferential, SurfaceInteraction, Vec3f};
+use crate::integrator::{IntegratorRadiance, russian_roulette, mis_mode_from_params};
+use crate::interaction::SurfaceHit;
+use crate::light::Light;
+use crate::loaders::{ParamSet, ParamError};
+use crate::material::TransportMode;
+use crate::medium::{Medium, MediumInteraction};
+use crate::reflection::BxDFType;
+use crate::reflection::bsdf::Bsdf;
+use crate::sampler::Sampler;
+use crate::sampling::MisMode;
+use crate::scene::Scene;
+use crate::spectrum::Spectrum;
+
+/// A path tracer that also scatters light inside participating media. At each bounce the medium
+/// the ray travels through samples whether it scatters before reaching the next surface, and paths
+/// continue from scattering events in media by sampling the phase function. Direct lighting is
+/// attenuated by the transmittance of the media between the point and the light.
+///
+/// Media are entered and left through primitives with a `MediumInterface`. Camera rays start
+/// outside of any medium.
+pub struct VolPathIntegrator {
+    max_depth: u16,
+    rr_threshold: Float,
+    mis_mode: MisMode,
+}
+
+impl VolPathIntegrator {
+    pub fn new(max_depth: u16, rr_threshold: Float) -> Self {
+        Self { max_depth, rr_threshold, mis_mode: MisMode::default() }
+    }
+
+    /// Sets the heuristic used to weight light and BSDF or phase function samples in direct
+    /// lighting.
+    pub fn with_mis_mode(mut self, mis_mode: MisMode) -> Self {
+        self.mis_mode = mis_mode;
+        self
+    }
+
+    /// Construct from the scene's `Integrator` parameters, reading `maxdepth` (default 5),
+    /// `rrthreshold` (default 1) and `mis` ("power" or "balance", default "power").
+    pub fn from_params(params: &mut ParamSet) -> Result<Self, ParamError> {
+        let max_depth = params.get_one_or::<i32>("maxdepth", 5)?.max(0) as u16;
+        let rr_threshold = params.get_one_or("rrthreshold", 1.0)?;
+        Ok(Self::new(max_depth, rr_threshold).with_mis_mode(mis_mode_from_params(params)?))
+    }
+}
+
+impl IntegratorRadiance for VolPathIntegrator {
+    fn preprocess(&mut self, _scene: &Scene, _sampler: &mut dyn Sampler) {
+    }
+
+    fn incident_radiance(
+        &self,
+        ray: &mut RayDifferential,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        arena: &Bump,
+        _depth: u16,
+    ) -> Spectrum {
+        let mut path_radiance = Spectrum::uniform(0.0);
+        let mut beta = Spectrum::uniform(1.0);
+        let mut bounces = 0;
+        let mut specular_bounce = false;
+        let mut medium: Option<&dyn Medium> = None;
+
+        loop {
+            let si = scene.intersect(&mut ray.ray);
+
+            // the ray either scatters in the medium it's travelling through or reaches the surface
+            let mi = match medium {
+                Some(medium) => {
+                    let (weight, mi) = medium.sample(&ray.ray, sampler);
+                    beta *= weight;
+                    mi
+                }
+                None => None,
+            };
+            if beta.is_black() {
+                break;
+            }
+
+            if let Some(mi) = mi {
+                if bounces >= self.max_depth {
+                    break;
+                }
+                let scatterer = Scatterer::Medium(&mi);
+                path_radiance += beta * sample_one_light(&scatterer, medium, scene, sampler, self.mis_mode);
+
+                // the phase function is sampled exactly, so the throughput is unchanged
+                let (_, wi) = mi.phase.sample_p(mi.wo, sampler.get_2d());
+                *ray = mi.hit.spawn_ray_with_dfferentials(wi, None);
+                specular_bounce = false;
+            } else {
+                // possibly add emitted light at intersection
+                if bounces == 0 || specular_bounce {
+                    if let Some(si) = &si {
+                        path_radiance += beta * si.le(-ray.ray.dir);
+                    } else {
+                        path_radiance += beta * scene.environment_emitted_radiance(ray);
+                    }
+                }
+
+                let mut si = match si {
+                    Some(si) if bounces < self.max_depth => si,
+                    _ => break,
+                };
+
+                let bsdf = match si.compute_scattering_functions(ray, arena, true, TransportMode::Radiance) {
+                    Some(bsdf) => bsdf,
+                    None => {
+                        // pass through surfaces that only mark the boundaries of media, without
+                        // counting a bounce
+                        medium = si.medium(ray.ray.dir, medium);
+                        *ray = si.hit.spawn_ray_with_dfferentials(ray.ray.dir, ray.diff);
+                        continue;
+                    }
+                };
+
+                if bsdf.num_components(BxDFType::all() & !BxDFType::SPECULAR) > 0 {
+                    let scatterer = Scatterer::Surface(&si, &bsdf);
+                    path_radiance += beta * sample_one_light(&scatterer, medium, scene, sampler, self.mis_mode);
+                }
+
+                let bsdf_sample = bsdf.sample_f(si.wo, sampler.get_2d(), BxDFType::all());
+                if let Some(bsdf_sample) = bsdf_sample.filter(|s| !s.f.is_black() && s.pdf > 0.0) {
+                    beta *= bsdf_sample.f * abs_dot(bsdf_sample.wi, si.shading_n.0) / bsdf_sample.pdf;
+                    specular_bounce = bsdf_sample.sampled_type.contains(BxDFType::SPECULAR);
+                    medium = si.medium(bsdf_sample.wi, medium);
+                    *ray = si.hit.spawn_ray_with_dfferentials(bsdf_sample.wi, ray.diff);
+                } else {
+                    break;
+                }
+            }
+
+            // Possibly terminate the path with Russian roulette
+            if beta.max_component_value() < self.rr_threshold && bounces > 3
+                && !russian_roulette(&mut beta, sampler, 0.05) {
+                break;
+            }
+            bounces += 1;
+        }
+        path_radiance
+    }
+}
+
+/// Where a path scatters: at a surface with its BSDF, or inside a medium.
+enum Scatterer<'a, 'i> {
+    Surface(&'a SurfaceInteraction<'i>, &'a Bsdf<'a>),
+    Medium(&'a MediumInteraction),
+}
+
+impl<'a, 'i> Scatterer<'a, 'i> {
+    fn hit(&self) -> &SurfaceHit {
+        match self {
+            Scatterer::Surface(si, _) => &si.hit,
+            Scatterer::Medium(mi) => &mi.hit,
+        }
+    }
+
+    /// The value of the BSDF (with the cosine factor) or phase function for light arriving from
+    /// `wi`, and the density of sampling `wi` from it.
+    fn f(&self, wi: Vec3f) -> (Spectrum, Float) {
+        match self {
+            Scatterer::Surface(si, bsdf) => {
+                let flags = BxDFType::all() & !BxDFType::SPECULAR;
+                (bsdf.f(si.wo, wi, flags) * abs_dot(wi, si.shading_n.0), bsdf.pdf(si.wo, wi, flags))
+            }
+            Scatterer::Medium(mi) => {
+                let p = mi.phase.p(mi.wo, wi);
+                (Spectrum::uniform(p), p)
+            }
+        }
+    }
+
+    /// Samples an incident direction, returning the value as for `f`, the direction, its density
+    /// and whether it was sampled from a specular BSDF.
+    fn sample(&self, u: Point2f) -> Option<(Spectrum, Vec3f, Float, bool)> {
+        match self {
+            Scatterer::Surface(si, bsdf) => {
+                let flags = BxDFType::all() & !BxDFType::SPECULAR;
+                let scatter = bsdf.sample_f(si.wo, u, flags)?;
+                let f = scatter.f * abs_dot(scatter.wi, si.shading_n.0);
+                Some((f, scatter.wi, scatter.pdf, scatter.sampled_type.contains(BxDFType::SPECULAR)))
+            }
+            Scatterer::Medium(mi) => {
+                let (p, wi) = mi.phase.sample_p(mi.wo, u);
+                Some((Spectrum::uniform(p), wi, p, false))
+            }
+        }
+    }
+
+    /// The medium that a ray leaving in direction `w` travels through.
+    fn medium<'m>(&self, w: Vec3f, current: Option<&'m dyn Medium>) -> Option<&'m dyn Medium>
+        where 'i: 'm
+    {
+        match self {
+            Scatterer::Surface(si, _) => si.medium(w, current),
+            Scatterer::Medium(_) => current,
+        }
+    }
+}
+
+/// Estimates direct lighting from one uniformly chosen light, like `uniform_sample_one_light`, but
+/// through the media in `medium` around the scattering point.
+fn sample_one_light<'i>(
+    scatterer: &Scatterer<'_, 'i>,
+    medium: Option<&'i dyn Medium>,
+    scene: &Scene,
+    sampler: &mut dyn Sampler,
+    mis: MisMode,
+) -> Spectrum {
+    let n_lights = scene.num_lights();
+    if n_lights == 0 { return Spectrum::uniform(0.0) }
+
+    let light_num = ((sampler.get_1d() * n_lights as Float) as usize).min(n_lights - 1);
+    let light = scene.light(light_num);
+    n_lights as Float * estimate_direct(scatterer, medium, light, scene, sampler, mis)
+}
+
+fn estimate_direct<'i>(
+    scatterer: &Scatterer<'_, 'i>,
+    medium: Option<&'i dyn Medium>,
+    light: &dyn Light,
+    scene: &Scene,
+    sampler: &mut dyn Sampler,
+    mis: MisMode,
+) -> Spectrum {
+    let mut radiance = Spectrum::uniform(0.0);
+    let u_light = sampler.get_2d();
+    let u_scattering = sampler.get_2d();
+    let hit = scatterer.hit();
+
+    // Sample light source with multiple importance sampling
+    let light_sample = light.sample_incident_radiance(hit, u_light);
+    if light_sample.pdf > 0.0 && !light_sample.radiance.is_black() {
+        let (f, scattering_pdf) = scatterer.f(light_sample.wi);
+        if !f.is_black() {
+            let tr = light_sample.vis.transmittance(scene, scatterer.medium(light_sample.wi, medium), sampler);
+            if !tr.is_black() {
+                let weight = if light.flags().is_delta_light() {
+                    1.0
+                } else {
+                    mis.weight(1, light_sample.pdf, 1, scattering_pdf)
+                };
+                radiance += f * tr * light_sample.radiance * weight / light_sample.pdf;
+            }
+        }
+    }
+
+    // Sample the BSDF or phase function with multiple importance sampling, unless the light is a
+    // delta distribution that can't be hit
+    if !light.flags().is_delta_light() {
+        let sample = scatterer.sample(u_scattering).filter(|(f, _, pdf, _)| !f.is_black() && *pdf > 0.0);
+        if let Some((f, wi, pdf, sampled_specular)) = sample {
+            let weight = if sampled_specular {
+                1.0
+            } else {
+                let light_pdf = light.pdf_incident_radiance(hit, wi);
+                if light_pdf == 0.0 {
+                    return radiance;
+                }
+                mis.weight(1, pdf, 1, light_pdf)
+            };
+
+            let ray = hit.spawn_ray(wi);
+            let (si, tr) = scene.intersect_tr(ray, scatterer.medium(wi, medium), sampler);
+            let incident_radiance = if let Some(si) = si {
+                let hit_light = si.primitive
+                    .and_then(|prim| prim.area_light())
+                    .map_or(false, |l| l.is_same_light(light));
+                if hit_light { si.le(-wi) } else { Spectrum::uniform(0.0) }
+            } else {
+                light.environment_emitted_radiance(&RayDifferential { ray, diff: None })
+            };
+
+            if !incident_radiance.is_black() {
+                radiance += f * tr * incident_radiance * weight / pdf;
+            }
+        }
+    }
+
+    radiance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::{consts, Bounds2f, Point2i, Transform};
+    use crate::bvh::BVH;
+    use crate::camera::PerspectiveCamera;
+    use crate::film::Film;
+    use crate::filter::BoxFilter;
+    use crate::integrator::{SamplerIntegrator, TileOrder};
+    use crate::light::point::PointLight;
+    use crate::medium::MediumInterface;
+    use crate::medium::homogeneous::HomogeneousMedium;
+    use crate::primitive::{GeometricPrimitive, Primitive};
+    use crate::sampler::random::RandomSampler;
+    use crate::shapes::sphere::Sphere;
+
+    fn render_center(fog: Option<Arc<dyn Medium>>) -> Float {
+        // a unit sphere of fog between the camera and a point light behind it
+        let mut boundary = GeometricPrimitive::new(
+            Arc::new(Sphere::whole(Transform::IDENTITY, Transform::IDENTITY, 1.0)),
+            None,
+        );
+        if let Some(fog) = fog {
+            boundary = boundary.with_medium_interface(MediumInterface::new(Some(fog), None));
+        }
+        let prims: Vec<Box<dyn Primitive>> = vec![Box::new(boundary)];
+        let light = PointLight::new(Transform::translate(Vec3f::new(0.0, 0.0, -1.5)), Spectrum::uniform(1.0));
+        let lights: Vec<Box<dyn Light>> = vec![Box::new(light)];
+        let scene = Scene::new(BVH::build(prims), lights, vec![]);
+
+        let resolution = Point2i::new(1, 1);
+        let camera = PerspectiveCamera::new(
+            Transform::translate(Vec3f::new(0.0, 0.0, 5.0)) * Transform::rotate_x(cgmath::Deg(180.0)),
+            resolution,
+            Bounds2f::whole_screen(),
+            (0.0, 1.0),
+            0.0,
+            1.0,
+            1.0
+        );
+        let film = Film::new(resolution, Bounds2f::unit(), BoxFilter::default(), 1.0);
+        let mut integrator = SamplerIntegrator {
+            camera: Box::new(camera),
+            radiance: VolPathIntegrator::new(1, 1.0),
+            tile_order: TileOrder::Scanline,
+        };
+        integrator.render_parallel(&scene, &film, RandomSampler::new_with_seed(65536, 0), None);
+        let (img, _) = film.into_spectrum_buffer();
+        img[0][0]
+    }
+
+    #[test]
+    fn test_fog_single_scattering() {
+        assert_eq!(render_center(None), 0.0);
+
+        let (sigma_a, sigma_s, g) = (0.05, 0.2, 0.5);
+        let fog = HomogeneousMedium::new(Spectrum::uniform(sigma_a), Spectrum::uniform(sigma_s), g);
+        let l = render_center(Some(Arc::new(fog)));
+
+        // Along the axis every scattering point z sees the light straight ahead at distance
+        // z + 1.5, through a total of 2 units of fog on the way to the light and the camera. So the
+        // singly scattered radiance is the integral over z of
+        //     sigma_s * exp(-2 sigma_t) * p_hg(-1) / (z + 1.5)^2
+        // which with depth 1 is all the integrator finds.
+        let sigma_t = sigma_a + sigma_s;
+        let p_forward = (1.0 - g * g) / (4.0 * consts::PI * (1.0 - g).powi(3));
+        let expected = sigma_s * (-2.0 * sigma_t).exp() * p_forward * (1.0 / 0.5 - 1.0 / 2.5);
+        assert!((l - expected).abs() < 0.05 * expected, "{} {}", l, expected);
+    }
+}
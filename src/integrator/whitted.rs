@@ -7,11 +7,21 @@ use crate::reflection::BxDFType;
 use crate::sampler::Sampler;
 use crate::scene::Scene;
 use crate::spectrum::Spectrum;
+use crate::loaders::{ParamSet, ParamError};
 
 pub struct WhittedIntegrator {
     pub max_depth: u16,
 }
 
+impl WhittedIntegrator {
+    /// Construct from the scene's `Integrator` parameters, reading `maxdepth` (default 5).
+    pub fn from_params(params: &mut ParamSet) -> Result<Self, ParamError> {
+        Ok(Self {
+            max_depth: params.get_one_or::<i32>("maxdepth", 5)?.max(0) as u16,
+        })
+    }
+}
+
 impl IntegratorRadiance for WhittedIntegrator {
     fn preprocess(&mut self, _scene: &Scene, _sampler: &mut dyn Sampler) {
         // unimplemented!()
@@ -55,9 +65,14 @@ impl IntegratorRadiance for WhittedIntegrator {
                         }
                     }
 
+                    // trace rays for perfect specular reflection and refraction, e.g. for glass
                     if depth + 1 < self.max_depth {
-                        radiance += self.specular_reflect(ray, &intersect, &bsdf, scene, sampler, arena, depth);
-                        radiance += self.specular_transmit(ray, &intersect, &bsdf, scene, sampler, arena, depth);
+                        if bsdf.num_components(BxDFType::REFLECTION | BxDFType::SPECULAR) > 0 {
+                            radiance += self.specular_reflect(ray, &intersect, &bsdf, scene, sampler, arena, depth);
+                        }
+                        if bsdf.num_components(BxDFType::TRANSMISSION | BxDFType::SPECULAR) > 0 {
+                            radiance += self.specular_transmit(ray, &intersect, &bsdf, scene, sampler, arena, depth);
+                        }
                     }
                 } else {
                     unimplemented!()
@@ -70,5 +85,75 @@ impl IntegratorRadiance for WhittedIntegrator {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::{Float, Point2i, Point3f, Ray, Transform, Vec3f};
+    use crate::bvh::BVH;
+    use crate::light::Light;
+    use crate::light::distant::DistantLight;
+    use crate::material::Material;
+    use crate::material::glass::GlassMaterial;
+    use crate::material::matte::MatteMaterial;
+    use crate::primitive::{GeometricPrimitive, Primitive};
+    use crate::sampler::random::RandomSampler;
+    use crate::shapes::sphere::Sphere;
+
+    fn sphere_prim(center: Vec3f, radius: Float, material: Arc<dyn Material>) -> Box<dyn Primitive> {
+        let o2w = Transform::translate(center);
+        let shape = Arc::new(Sphere::whole(o2w, o2w.inverse(), radius));
+        Box::new(GeometricPrimitive::new(shape, Some(material)))
+    }
+
+    /// The radiance seen looking straight down at a red floor, with a glass sphere in between if
+    /// `glass` is set.
+    fn radiance_looking_down(glass: bool, max_depth: u16) -> Spectrum {
+        let floor = sphere_prim(Vec3f::new(0.0, 0.0, -1000.0), 1000.0, Arc::new(MatteMaterial::constant(Spectrum::new([0.8, 0.1, 0.1]))));
+        let mut prims = vec![floor];
+        if glass {
+            prims.push(sphere_prim(Vec3f::new(0.0, 0.0, 2.0), 1.0, Arc::new(GlassMaterial::constant(Spectrum::uniform(1.0), Spectrum::uniform(1.0), 1.5))));
+        }
+        // a low light, so the sphere's shadow falls away from the point below it
+        let light: Box<dyn Light> = Box::new(DistantLight::new(Spectrum::uniform(3.0), Vec3f::new(1.0, 0.0, 0.3)));
+        let scene = Scene::new(BVH::build(prims), vec![light], vec![]);
+
+        let integrator = WhittedIntegrator { max_depth };
+        let mut sampler = RandomSampler::new_with_seed(1, 0);
+        sampler.start_pixel(Point2i::new(0, 0));
+        assert!(sampler.start_next_sample());
+        let arena = Bump::new();
+        let ray = Ray::new(Point3f::new(0.001, 0.001, 10.0), Vec3f::new(0.0, 0.0, -1.0));
+        let mut ray = RayDifferential { ray, diff: None };
+        integrator.incident_radiance(&mut ray, &scene, &mut sampler, &arena, 0)
+    }
+
+    #[test]
+    fn test_floor_visible_through_glass() {
+        let direct = radiance_looking_down(false, 5);
+        let refracted = radiance_looking_down(true, 5);
+        assert!(direct[0] > 2.0 * direct[1]);
+
+        // the floor is seen through the sphere, dimmed a little by the reflection at each interface
+        assert!(refracted[0] > 2.0 * refracted[1], "{:?}", refracted);
+        assert!(refracted[0] > 0.8 * direct[0] && refracted[0] < direct[0], "{:?} {:?}", refracted, direct);
+
+        // without enough depth to pass through both surfaces of the sphere, the floor isn't seen
+        let shallow = radiance_looking_down(true, 2);
+        assert!(shallow[0] < 0.1 * direct[0], "{:?}", shallow);
+    }
+
+    #[test]
+    fn test_from_params_max_depth() {
+        let mut params = ParamSet::new();
+        params.with("maxdepth", 7);
+        assert_eq!(WhittedIntegrator::from_params(&mut params).unwrap().max_depth, 7);
 
+        let mut params = ParamSet::new();
+        assert_eq!(WhittedIntegrator::from_params(&mut params).unwrap().max_depth, 5);
 
+        let mut params = ParamSet::new();
+        params.with("maxdepth", 7.0 as Float);
+        assert!(WhittedIntegrator::from_params(&mut params).is_err());
+    }
+}
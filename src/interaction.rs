@@ -5,6 +5,7 @@ use bumpalo::Bump;
 use cgmath::{EuclideanSpace, InnerSpace, Matrix2, Vector2, Zero};
 use crate::reflection::bsdf::Bsdf;
 use crate::primitive::Primitive;
+use crate::medium::Medium;
 use crate::spectrum::Spectrum;
 
 pub const SHADOW_EPSILON: Float = 0.0001;
@@ -115,9 +116,17 @@ impl<'i> SurfaceInteraction<'i> {
         allow_multiple_lobes: bool,
         mode: TransportMode,
     ) -> Option<Bsdf<'a>> {
+        self.compute_differentials(ray);
+        let primitive = self.primitive.expect("Should have a prim at this point");
+        // surfaces without a material don't scatter, and rays should continue through them
+        primitive.material()?;
+        Some(primitive.compute_scattering_functions(self, arena, mode, allow_multiple_lobes))
+    }
+
+    /// Estimates how the hit point and texture coordinates change across the image from the
+    /// differentials of `ray`, or sets them to zero if it has none.
+    pub fn compute_differentials(&mut self, ray: &RayDifferential) {
         self.tex_diffs = self.compute_tex_differentials(ray).unwrap_or_default();
-        let material = self.primitive.expect("Should have a prim at this point").material()?;
-        Some(material.compute_scattering_functions(self, arena, mode, allow_multiple_lobes))
     }
 
     #[allow(non_snake_case)]
@@ -172,11 +181,23 @@ impl<'i> SurfaceInteraction<'i> {
         })
     }
 
-    pub fn emitted_radiance(&self, w: Vec3f) -> Spectrum {
-        let prim = self.primitive.unwrap();
-        prim.area_light().map_or(Spectrum::uniform(0.0), |light| {
-            light.emitted_radiance(self.hit, w)
-        })
+    /// The radiance emitted from the hit point in direction `w` if the primitive that was hit is an
+    /// area light, or black otherwise.
+    pub fn le(&self, w: Vec3f) -> Spectrum {
+        self.primitive
+            .and_then(|prim| prim.area_light())
+            .map_or(Spectrum::uniform(0.0), |light| light.emitted_radiance(self.hit, w))
+    }
+
+    /// The medium on the side of the surface that `w` points into, where `current` is the medium
+    /// the ray arrived through, which it stays in if the primitive doesn't separate media.
+    pub fn medium<'m>(&self, w: Vec3f, current: Option<&'m dyn Medium>) -> Option<&'m dyn Medium>
+        where 'i: 'm
+    {
+        match self.primitive.and_then(|prim| prim.medium_interface()) {
+            Some(interface) => interface.medium(w, self.hit.n),
+            None => current,
+        }
     }
 }
 
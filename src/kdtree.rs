@@ -0,0 +1,374 @@
+use std::time::Instant;
+
+use arrayvec::ArrayVec;
+
+use crate::{Float, Ray, RayShear, SurfaceInteraction};
+use crate::geometry::bounds::Bounds3f;
+use crate::primitive::Primitive;
+
+const ISECT_COST: Float = 80.0;
+const TRAVERSAL_COST: Float = 1.0;
+const EMPTY_BONUS: Float = 0.5;
+const MAX_PRIMS: usize = 1;
+
+/// A kd-tree over primitives, built using the surface area heuristic as described in pbrt.
+pub struct KdTreeAccel<P: AsRef<dyn Primitive> = Box<dyn Primitive>> {
+    pub prims: Vec<P>,
+    pub bounds: Bounds3f,
+    prim_indices: Vec<u32>,
+    nodes: Vec<KdAccelNode>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum KdAccelNode {
+    Leaf {
+        /// Index into `prim_indices` of the first primitive overlapping the leaf
+        first_prim_idx: u32,
+        n_prims: u32,
+    },
+    /// The child below the split is stored directly after its parent
+    Interior {
+        split_pos: Float,
+        split_axis: u8,
+        above_child_idx: u32,
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum EdgeType {
+    Start,
+    End,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct BoundEdge {
+    t: Float,
+    prim_num: usize,
+    edge_type: EdgeType,
+}
+
+struct KdToDo {
+    node_idx: usize,
+    t_min: Float,
+    t_max: Float,
+}
+
+impl<P: AsRef<dyn Primitive>> KdTreeAccel<P> {
+    #[tracing::instrument(skip(prims))]
+    pub fn build(prims: Vec<P>) -> Self {
+        let start = Instant::now();
+
+        let prim_bounds: Vec<Bounds3f> = prims.iter().map(|p| p.as_ref().world_bound()).collect();
+        let bounds = prim_bounds.iter().fold(Bounds3f::empty(), |b, pb| b.join(pb));
+
+        let mut tree = Self {
+            prims,
+            bounds,
+            prim_indices: Vec::new(),
+            nodes: Vec::new(),
+        };
+
+        if !tree.prims.is_empty() {
+            let max_depth = (8.0 + 1.3 * (tree.prims.len() as Float).log2()).round() as u32;
+            let prim_nums = (0..tree.prims.len()).collect();
+            tree.build_tree(bounds, &prim_bounds, prim_nums, max_depth, 0);
+        }
+
+        tracing::info!("Kd-tree built in {} ms", start.elapsed().as_millis());
+        tree
+    }
+
+    fn build_tree(
+        &mut self,
+        node_bounds: Bounds3f,
+        all_prim_bounds: &[Bounds3f],
+        prim_nums: Vec<usize>,
+        depth: u32,
+        mut bad_refines: u32,
+    ) {
+        let n_prims = prim_nums.len();
+        if n_prims <= MAX_PRIMS || depth == 0 {
+            self.push_leaf(&prim_nums);
+            return;
+        }
+
+        // Choose the split with the lowest SAH cost, starting with the axis of largest extent
+        // and trying the others if that one has no candidate split planes.
+        let old_cost = ISECT_COST * n_prims as Float;
+        let inv_total_sa = 1.0 / node_bounds.surface_area();
+        let d = node_bounds.diagonal();
+
+        let mut best: Option<(usize, usize)> = None;
+        let mut best_cost = Float::INFINITY;
+        let mut axis = node_bounds.maximum_extent() as usize;
+        for _retry in 0..3 {
+            let edges = sorted_edges(all_prim_bounds, &prim_nums, axis);
+
+            let mut n_below = 0;
+            let mut n_above = n_prims;
+            for (i, edge) in edges.iter().enumerate() {
+                if edge.edge_type == EdgeType::End { n_above -= 1; }
+
+                if edge.t > node_bounds.min[axis] && edge.t < node_bounds.max[axis] {
+                    let other0 = (axis + 1) % 3;
+                    let other1 = (axis + 2) % 3;
+                    let below_sa = 2.0 * (d[other0] * d[other1] + (edge.t - node_bounds.min[axis]) * (d[other0] + d[other1]));
+                    let above_sa = 2.0 * (d[other0] * d[other1] + (node_bounds.max[axis] - edge.t) * (d[other0] + d[other1]));
+                    let p_below = below_sa * inv_total_sa;
+                    let p_above = above_sa * inv_total_sa;
+                    let empty_bonus = if n_above == 0 || n_below == 0 { EMPTY_BONUS } else { 0.0 };
+                    let cost = TRAVERSAL_COST
+                        + ISECT_COST * (1.0 - empty_bonus) * (p_below * n_below as Float + p_above * n_above as Float);
+
+                    if cost < best_cost {
+                        best_cost = cost;
+                        best = Some((axis, i));
+                    }
+                }
+
+                if edge.edge_type == EdgeType::Start { n_below += 1; }
+            }
+
+            if best.is_some() { break; }
+            axis = (axis + 1) % 3;
+        }
+
+        if best_cost > old_cost { bad_refines += 1; }
+
+        let (best_axis, best_offset) = match best {
+            Some(best) if !((best_cost > 4.0 * old_cost && n_prims < 16) || bad_refines == 3) => best,
+            _ => {
+                self.push_leaf(&prim_nums);
+                return;
+            }
+        };
+
+        // Classify primitives with respect to the split
+        let edges = sorted_edges(all_prim_bounds, &prim_nums, best_axis);
+        let prims_below: Vec<usize> = edges[..best_offset].iter()
+            .filter(|e| e.edge_type == EdgeType::Start)
+            .map(|e| e.prim_num)
+            .collect();
+        let prims_above: Vec<usize> = edges[best_offset + 1..].iter()
+            .filter(|e| e.edge_type == EdgeType::End)
+            .map(|e| e.prim_num)
+            .collect();
+
+        let split_pos = edges[best_offset].t;
+        let mut bounds_below = node_bounds;
+        bounds_below.max[best_axis] = split_pos;
+        let mut bounds_above = node_bounds;
+        bounds_above.min[best_axis] = split_pos;
+
+        let node_idx = self.nodes.len();
+        self.nodes.push(KdAccelNode::Interior { split_pos, split_axis: best_axis as u8, above_child_idx: 0 });
+        self.build_tree(bounds_below, all_prim_bounds, prims_below, depth - 1, bad_refines);
+
+        let above_idx = self.nodes.len() as u32;
+        if let KdAccelNode::Interior { ref mut above_child_idx, .. } = self.nodes[node_idx] {
+            *above_child_idx = above_idx;
+        } else { unreachable!() }
+        self.build_tree(bounds_above, all_prim_bounds, prims_above, depth - 1, bad_refines);
+    }
+
+    fn push_leaf(&mut self, prim_nums: &[usize]) {
+        let first_prim_idx = self.prim_indices.len() as u32;
+        self.prim_indices.extend(prim_nums.iter().map(|&p| p as u32));
+        self.nodes.push(KdAccelNode::Leaf { first_prim_idx, n_prims: prim_nums.len() as u32 });
+    }
+
+    fn leaf_prims(&self, first_prim_idx: u32, n_prims: u32) -> impl Iterator<Item=&dyn Primitive> {
+        let range = first_prim_idx as usize..(first_prim_idx + n_prims) as usize;
+        self.prim_indices[range].iter().map(move |&i| self.prims[i as usize].as_ref())
+    }
+
+    pub fn intersect(&self, ray: &mut Ray) -> Option<SurfaceInteraction> {
+        let (mut t_min, mut t_max) = if self.nodes.is_empty() {
+            return None;
+        } else {
+            self.bounds.intersect_test(ray)?
+        };
+
+        let inv_dir = 1.0 / ray.dir;
+        let shear = RayShear::new(ray);
+        let mut todo = ArrayVec::<[KdToDo; 64]>::new();
+        let mut node_idx = 0;
+
+        let mut interaction = None;
+
+        loop {
+            // Stop once a closer hit than the remaining nodes has been found
+            if ray.t_max < t_min { break; }
+
+            match self.nodes[node_idx] {
+                KdAccelNode::Interior { split_pos, split_axis, above_child_idx } => {
+                    let axis = split_axis as usize;
+                    let t_plane = (split_pos - ray.origin[axis]) * inv_dir[axis];
+
+                    let below_first = ray.origin[axis] < split_pos
+                        || (ray.origin[axis] == split_pos && ray.dir[axis] <= 0.0);
+                    let (first_child, second_child) = if below_first {
+                        (node_idx + 1, above_child_idx as usize)
+                    } else {
+                        (above_child_idx as usize, node_idx + 1)
+                    };
+
+                    if t_plane > t_max || t_plane <= 0.0 {
+                        node_idx = first_child;
+                    } else if t_plane < t_min {
+                        node_idx = second_child;
+                    } else {
+                        todo.push(KdToDo { node_idx: second_child, t_min: t_plane, t_max });
+                        node_idx = first_child;
+                        t_max = t_plane;
+                    }
+                },
+
+                KdAccelNode::Leaf { first_prim_idx, n_prims } => {
+                    for prim in self.leaf_prims(first_prim_idx, n_prims) {
+                        interaction = prim.intersect_sheared(ray, &shear).or(interaction);
+                    }
+
+                    if let Some(next) = todo.pop() {
+                        node_idx = next.node_idx;
+                        t_min = next.t_min;
+                        t_max = next.t_max;
+                    } else {
+                        break;
+                    }
+                }
+            }
+        }
+
+        interaction
+    }
+
+    pub fn intersect_test(&self, ray: &Ray) -> bool {
+        let (mut t_min, mut t_max) = if self.nodes.is_empty() {
+            return false;
+        } else {
+            match self.bounds.intersect_test(ray) {
+                Some(t) => t,
+                None => return false,
+            }
+        };
+
+        let inv_dir = 1.0 / ray.dir;
+        let shear = RayShear::new(ray);
+        let mut todo = ArrayVec::<[KdToDo; 64]>::new();
+        let mut node_idx = 0;
+
+        loop {
+            match self.nodes[node_idx] {
+                KdAccelNode::Interior { split_pos, split_axis, above_child_idx } => {
+                    let axis = split_axis as usize;
+                    let t_plane = (split_pos - ray.origin[axis]) * inv_dir[axis];
+
+                    let below_first = ray.origin[axis] < split_pos
+                        || (ray.origin[axis] == split_pos && ray.dir[axis] <= 0.0);
+                    let (first_child, second_child) = if below_first {
+                        (node_idx + 1, above_child_idx as usize)
+                    } else {
+                        (above_child_idx as usize, node_idx + 1)
+                    };
+
+                    if t_plane > t_max || t_plane <= 0.0 {
+                        node_idx = first_child;
+                    } else if t_plane < t_min {
+                        node_idx = second_child;
+                    } else {
+                        todo.push(KdToDo { node_idx: second_child, t_min: t_plane, t_max });
+                        node_idx = first_child;
+                        t_max = t_plane;
+                    }
+                },
+
+                KdAccelNode::Leaf { first_prim_idx, n_prims } => {
+                    if self.leaf_prims(first_prim_idx, n_prims).any(|prim| prim.intersect_test_sheared(ray, &shear)) {
+                        return true;
+                    }
+
+                    if let Some(next) = todo.pop() {
+                        node_idx = next.node_idx;
+                        t_min = next.t_min;
+                        t_max = next.t_max;
+                    } else {
+                        break;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+}
+
+fn sorted_edges(all_prim_bounds: &[Bounds3f], prim_nums: &[usize], axis: usize) -> Vec<BoundEdge> {
+    let mut edges: Vec<BoundEdge> = prim_nums.iter()
+        .flat_map(|&prim_num| {
+            let bounds = all_prim_bounds[prim_num];
+            ArrayVec::from([
+                BoundEdge { t: bounds.min[axis], prim_num, edge_type: EdgeType::Start },
+                BoundEdge { t: bounds.max[axis], prim_num, edge_type: EdgeType::End },
+            ])
+        })
+        .collect();
+
+    edges.sort_by(|a, b| {
+        a.t.total_cmp(&b.t).then(a.edge_type.cmp(&b.edge_type))
+    });
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+    use rand::distributions::{Uniform, UnitSphereSurface};
+    use rand::prelude::*;
+
+    use crate::{Transform, Vec3f};
+    use crate::bvh::BVH;
+    use crate::primitive::GeometricPrimitive;
+    use crate::shapes::sphere::Sphere;
+
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_kdtree_matches_bvh() {
+        let mut rng = StdRng::from_seed([3; 32]);
+        let distr = Uniform::new_inclusive(-10.0, 10.0);
+
+        let mut bvh_prims = vec![];
+        let mut kd_prims = vec![];
+        for _ in 0..100 {
+            let v = Vec3f::new(rng.sample(distr), rng.sample(distr), rng.sample(distr));
+            let o2w = Transform::translate(v);
+            let sphere = Arc::new(Sphere::whole(o2w, o2w.inverse(), rng.gen_range(0.5, 3.0)));
+            let prim = GeometricPrimitive::new(sphere.clone(), None);
+            bvh_prims.push(Box::new(prim) as Box<dyn Primitive>);
+            let prim = GeometricPrimitive::new(sphere, None);
+            kd_prims.push(Box::new(prim) as Box<dyn Primitive>);
+        }
+
+        let bvh = BVH::build(bvh_prims);
+        let kdtree = KdTreeAccel::build(kd_prims);
+        assert_eq!(bvh.bounds, kdtree.bounds);
+
+        let sphere_surf = UnitSphereSurface::new();
+        for i in 0..500 {
+            let dir = sphere_surf.sample(&mut rng);
+            let dir: Vec3f = Vector3::from(dir).cast().unwrap();
+            let ray = Ray::new((0.0, 0.0, 0.0).into(), dir);
+
+            let mut bvh_ray = ray;
+            let mut kd_ray = ray;
+            let bvh_isect = bvh.intersect(&mut bvh_ray);
+            let kd_isect = kdtree.intersect(&mut kd_ray);
+
+            assert_eq!(bvh.intersect_test(&ray), kdtree.intersect_test(&ray), "Iteration {}", i);
+            assert_eq!(kd_isect.is_some(), kdtree.intersect_test(&ray), "Iteration {}", i);
+            assert_eq!(bvh_isect.map(|i| i.hit), kd_isect.map(|i| i.hit), "Iteration {}", i);
+        }
+    }
+}
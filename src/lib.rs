@@ -22,6 +22,7 @@ pub mod math;
 pub mod image;
 pub mod scene;
 pub mod bvh;
+pub mod kdtree;
 pub mod morton;
 pub mod primitive;
 pub mod geometry;
@@ -212,7 +213,7 @@ impl ComponentWiseExt for cgmath::Point3<Float>
     }
 }
 
-
+/// A blue-white vertical sky gradient, usable as a scene's `Background`.
 pub fn background(dir: Vec3f) -> Spectrum {
     // scale so t is between 0.0 and 1.0
     let t = 0.5 * (dir.z + 1.0);
@@ -1,7 +1,8 @@
-use crate::{Float, Transform, Vec3f};
+use crate::{Float, Transform, Vec3f, Normal3, Point2f, Ray, coordinate_system};
 use crate::spectrum::{Spectrum};
 use crate::shapes::Shape;
-use crate::light::{AreaLight, Light, LiSample, LightFlags, VisibilityTester, AreaLightBuilder};
+use crate::light::{AreaLight, Light, LiSample, LeSample, LightFlags, VisibilityTester, AreaLightBuilder};
+use crate::sampling::{cosine_sample_hemisphere, ONE_MINUS_EPSILON};
 use crate::interaction::SurfaceHit;
 use cgmath::{Vector3, InnerSpace, Point2};
 use std::sync::Arc;
@@ -9,7 +10,8 @@ use std::sync::Arc;
 #[derive(Clone)]
 pub struct DiffuseAreaLightBuilder {
     pub emit: Spectrum,
-    pub n_samples: usize
+    pub n_samples: usize,
+    pub two_sided: bool,
 }
 
 impl<S: Shape> AreaLightBuilder<S> for DiffuseAreaLightBuilder {
@@ -17,7 +19,7 @@ impl<S: Shape> AreaLightBuilder<S> for DiffuseAreaLightBuilder {
 
     fn create(self, shape: Arc<S>) -> Self::Target {
         let tf = shape.object_to_world().clone();
-        DiffuseAreaLight::new(self.emit, shape, self.n_samples)
+        DiffuseAreaLight::new(self.emit, shape, self.n_samples, self.two_sided)
     }
 }
 
@@ -25,24 +27,29 @@ pub struct DiffuseAreaLight<S: Shape> {
     emit: Spectrum,
     shape: Arc<S>,
     area: Float,
-    n_samples: usize
+    n_samples: usize,
+
+    /// Whether light is emitted from both sides of the surface, rather than only the side the
+    /// surface normal points towards.
+    two_sided: bool,
 }
 
 impl<S: Shape> DiffuseAreaLight<S> {
-    pub fn new(emit: Spectrum, shape: Arc<S>, n_samples: usize) -> Self {
+    pub fn new(emit: Spectrum, shape: Arc<S>, n_samples: usize, two_sided: bool) -> Self {
         let area = shape.area();
         Self {
             emit,
             shape,
             area,
-            n_samples
+            n_samples,
+            two_sided,
         }
     }
 }
 
 impl<S: Shape> AreaLight for DiffuseAreaLight<S> {
     fn emitted_radiance(&self, hit: SurfaceHit, w: Vec3f) -> Spectrum {
-        if hit.n.dot(w) > 0.0 {
+        if self.two_sided || hit.n.dot(w) > 0.0 {
             self.emit
         } else {
             Spectrum::uniform(0.0)
@@ -71,6 +78,11 @@ impl<S: Shape> Light for DiffuseAreaLight<S> {
         self.n_samples
     }
 
+    fn power(&self) -> Spectrum {
+        let sides = if self.two_sided { 2.0 } else { 1.0 };
+        self.emit * sides * self.area * std::f32::consts::PI
+    }
+
     fn sample_incident_radiance(&self, reference: &SurfaceHit, u: Point2<f32>) -> LiSample {
         let p_shape = self.shape.sample_from_ref(reference, u);
         let wi = (p_shape.p - reference.p).normalize();
@@ -79,6 +91,8 @@ impl<S: Shape> Light for DiffuseAreaLight<S> {
             p0: *reference,
             p1: p_shape,
         };
+        // A one-sided light seen from behind contributes nothing, but the pdf is still that of
+        // sampling the point so that it remains consistent with `pdf_incident_radiance`.
         let radiance = self.emitted_radiance(p_shape, -wi);
         LiSample {
             radiance,
@@ -91,4 +105,95 @@ impl<S: Shape> Light for DiffuseAreaLight<S> {
     fn pdf_incident_radiance(&self, reference: &SurfaceHit, wi: Vector3<f32>) -> f32 {
         self.shape.pdf_from_ref(reference, wi)
     }
-}
\ No newline at end of file
+
+    fn sample_le(&self, u1: Point2f, u2: Point2f, time: Float) -> Option<LeSample> {
+        let mut p_shape = self.shape.sample(u1);
+        p_shape.time = time;
+
+        // a two-sided light uses the first dimension to choose which side to emit from
+        let (w, pdf_dir) = if self.two_sided {
+            let (u0, side) = if u2[0] < 0.5 { (2.0 * u2[0], 1.0) } else { (2.0 * (u2[0] - 0.5), -1.0) };
+            let mut w = cosine_sample_hemisphere(Point2f::new(Float::min(u0, ONE_MINUS_EPSILON), u2[1]));
+            w.z *= side;
+            (w, 0.5 * w.z.abs() * std::f32::consts::FRAC_1_PI)
+        } else {
+            let w = cosine_sample_hemisphere(u2);
+            (w, w.z * std::f32::consts::FRAC_1_PI)
+        };
+
+        let n = p_shape.n.0;
+        let (s, t) = coordinate_system(n);
+        let dir = w.x * s + w.y * t + w.z * n;
+        Some(LeSample {
+            radiance: self.emitted_radiance(p_shape, dir),
+            ray: p_shape.spawn_ray(dir),
+            n_light: Normal3(n),
+            pdf_pos: 1.0 / self.area,
+            pdf_dir,
+        })
+    }
+
+    fn pdf_le(&self, ray: &Ray, n_light: Normal3) -> (Float, Float) {
+        let cos_theta = n_light.dot(ray.dir);
+        let pdf_dir = if self.two_sided {
+            0.5 * cos_theta.abs() * std::f32::consts::FRAC_1_PI
+        } else {
+            Float::max(0.0, cos_theta) * std::f32::consts::FRAC_1_PI
+        };
+        (1.0 / self.area, pdf_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point2f, Point3f, Normal3};
+    use crate::shapes::triangle::{Triangle, TriangleMesh};
+
+    /// A 2x2 quad in the z = 0 plane facing +z
+    fn quad() -> Vec<Arc<Triangle>> {
+        let mesh = TriangleMesh::new(
+            Transform::IDENTITY,
+            vec![0, 1, 2, 0, 2, 3],
+            vec![
+                Point3f::new(-1.0, -1.0, 0.0), Point3f::new(1.0, -1.0, 0.0),
+                Point3f::new(1.0, 1.0, 0.0), Point3f::new(-1.0, 1.0, 0.0),
+            ],
+            None,
+            None,
+            None,
+            false,
+        );
+        Arc::new(mesh).iter_triangles().map(Arc::new).collect()
+    }
+
+    fn reference_at(z: Float) -> SurfaceHit {
+        SurfaceHit {
+            p: Point3f::new(0.1, 0.2, z),
+            p_err: Vec3f::new(0.0, 0.0, 0.0),
+            time: 0.0,
+            n: Normal3::new(0.0, 0.0, -z.signum()),
+        }
+    }
+
+    #[test]
+    fn test_one_sided_light_from_behind() {
+        let u = Point2f::new(0.3, 0.6);
+        for tri in quad() {
+            let light = DiffuseAreaLight::new(Spectrum::uniform(1.0), tri.clone(), 1, false);
+
+            let front = light.sample_incident_radiance(&reference_at(2.0), u);
+            assert!(!front.radiance.is_black());
+            assert!(front.pdf > 0.0);
+            assert_eq!(front.pdf, light.pdf_incident_radiance(&reference_at(2.0), front.wi));
+
+            let behind = light.sample_incident_radiance(&reference_at(-2.0), u);
+            assert!(behind.radiance.is_black());
+            assert!(behind.pdf > 0.0);
+            assert_eq!(behind.pdf, light.pdf_incident_radiance(&reference_at(-2.0), behind.wi));
+
+            let two_sided = DiffuseAreaLight::new(Spectrum::uniform(1.0), tri, 1, true);
+            assert!(!two_sided.sample_incident_radiance(&reference_at(-2.0), u).radiance.is_black());
+        }
+    }
+}
@@ -2,7 +2,7 @@ use cgmath::InnerSpace;
 use num::Zero;
 
 use crate::{Float, Normal3, Point2f, Point3f, Transform, Vec3f};
-use crate::bvh::BVH;
+use crate::scene::Accelerator;
 use crate::interaction::SurfaceHit;
 use crate::light::{Light, LightFlags, LiSample, VisibilityTester};
 use crate::spectrum::Spectrum;
@@ -42,13 +42,21 @@ impl Light for DistantLight {
         &Transform::IDENTITY
     }
 
-    fn preprocess(&mut self, scene_prims: &BVH) {
-        let (world_center, world_radius) = scene_prims.bounds.bounding_sphere();
+    /// The power arriving at a disk the size of the scene's bounding sphere.
+    fn power(&self) -> Spectrum {
+        self.radiance * std::f32::consts::PI * self.world_radius * self.world_radius
+    }
+
+    /// Records the scene's bounding sphere, so that shadow rays can be made long enough to leave
+    /// the scene.
+    fn preprocess(&mut self, scene_prims: &Accelerator) {
+        let (world_center, world_radius) = scene_prims.bounds().bounding_sphere();
         self.world_center = world_center;
         self.world_radius = world_radius;
     }
 
     fn sample_incident_radiance(&self, reference: &SurfaceHit, _u: Point2f) -> LiSample {
+        // Any point in the scene is within the bounding sphere's diameter of the reference point
         let p_outside = reference.p + self.dir_to_light * (2.0 * self.world_radius);
 
         let p1 = SurfaceHit {
@@ -74,4 +82,85 @@ impl Light for DistantLight {
     fn pdf_incident_radiance(&self, _reference: &SurfaceHit, _wi: Vec3f) -> f32 {
         0.0
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::bvh::BVH;
+    use crate::primitive::{GeometricPrimitive, Primitive};
+    use crate::scene::Scene;
+    use crate::shapes::sphere::Sphere;
+
+    fn sphere_prim(center: Vec3f, radius: Float) -> Box<dyn Primitive> {
+        let o2w = Transform::translate(center);
+        let sphere = Sphere::whole(o2w, o2w.inverse(), radius);
+        Box::new(GeometricPrimitive::new(Arc::new(sphere), None))
+    }
+
+    #[test]
+    fn test_distant_light_occlusion() {
+        let light = DistantLight::new(Spectrum::uniform(1.0), Vec3f::new(0.0, 0.0, 1.0));
+        let prims = vec![
+            // Occluder far from the reference point, near the edge of the scene bounds
+            sphere_prim(Vec3f::new(0.0, 0.0, 50.0), 1.0),
+            // Geometry that isn't between the reference points and the light
+            sphere_prim(Vec3f::new(10.0, 0.0, -5.0), 1.0),
+        ];
+        let scene = Scene::new(BVH::build(prims), vec![Box::new(light)], vec![]);
+        let light = &scene.lights[0];
+
+        let hit_at = |p: Point3f| SurfaceHit {
+            p,
+            p_err: Vec3f::zero(),
+            time: 0.0,
+            n: Normal3(Vec3f::new(0.0, 0.0, 1.0)),
+        };
+
+        let sample = light.sample_incident_radiance(&hit_at(Point3f::new(0.0, 0.0, 0.0)), Point2f::new(0.5, 0.5));
+        assert_eq!(sample.wi, Vec3f::new(0.0, 0.0, 1.0));
+        assert!(!sample.vis.unoccluded(&scene));
+
+        let sample = light.sample_incident_radiance(&hit_at(Point3f::new(10.0, 0.0, 0.0)), Point2f::new(0.5, 0.5));
+        assert!(sample.vis.unoccluded(&scene));
+    }
+    #[test]
+    fn test_no_shadow_acne_on_terminator() {
+        use crate::Ray;
+
+        // far from the origin, so the intersection points have sizable error bounds
+        let center = Vec3f::new(100.0, 50.0, -200.0);
+        let radius = 3.0;
+        let light = DistantLight::new(Spectrum::uniform(1.0), Vec3f::new(1.0, 0.3, 0.2));
+        let scene = Scene::new(BVH::build(vec![sphere_prim(center, radius)]), vec![Box::new(light)], vec![]);
+        let light = &scene.lights[0];
+
+        // "render" the sphere with orthographic rays looking down -z, so the terminator is in view
+        let res = 128;
+        let mut n_near_terminator = 0;
+        for j in 0..res {
+            for i in 0..res {
+                let x = center.x + radius * (2.0 * (i as Float + 0.5) / res as Float - 1.0);
+                let y = center.y + radius * (2.0 * (j as Float + 0.5) / res as Float - 1.0);
+                let mut ray = Ray::new(Point3f::new(x, y, 0.0), Vec3f::new(0.0, 0.0, -1.0));
+                let isect = match scene.intersect(&mut ray) {
+                    Some(isect) => isect,
+                    None => continue,
+                };
+
+                let sample = light.sample_incident_radiance(&isect.hit, Point2f::new(0.5, 0.5));
+                let cos = isect.hit.n.0.dot(sample.wi);
+                if cos > 0.0 {
+                    assert!(sample.vis.unoccluded(&scene), "acne at {:?}, cos {}", isect.hit.p, cos);
+                    if cos < 0.1 {
+                        n_near_terminator += 1;
+                    }
+                } else if cos < -0.01 {
+                    assert!(!sample.vis.unoccluded(&scene));
+                }
+            }
+        }
+        assert!(n_near_terminator > 0);
+    }
+}
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use cgmath::InnerSpace;
+use num::Zero;
+
+use crate::{consts, Normal3, Point2f, Point3f, Transform, Vec3f, spherical_phi, spherical_theta};
+use crate::interaction::SurfaceHit;
+use crate::light::{Light, LightFlags, LiSample, VisibilityTester};
+use crate::mipmap::MIPMap;
+use crate::spectrum::Spectrum;
+
+/// A point light whose intensity is modulated in each direction by a goniometric diagram, given
+/// as an equirectangular image over the sphere of directions in light space.
+pub struct GonioPhotometricLight {
+    l2w: Transform,
+    w2l: Transform,
+    world_point: Point3f,
+    intensity: Spectrum,
+    diagram: Option<Arc<MIPMap<Spectrum>>>,
+}
+
+impl GonioPhotometricLight {
+    pub fn new(light_to_world: Transform, intensity: Spectrum, diagram: Option<Arc<MIPMap<Spectrum>>>) -> Self {
+        let l2w = light_to_world;
+        let w2l = l2w.inverse();
+        let world_point = l2w.transform(Point3f::new(0.0, 0.0, 0.0));
+        Self {
+            l2w,
+            w2l,
+            world_point,
+            intensity,
+            diagram,
+        }
+    }
+
+    /// The diagram's scale factor for light leaving in the world space direction `w`.
+    fn scale(&self, w: Vec3f) -> Spectrum {
+        let diagram = match &self.diagram {
+            Some(diagram) => diagram,
+            None => return Spectrum::uniform(1.0),
+        };
+        // Goniometric diagrams are measured with y as the up direction
+        let w = self.w2l.transform(w).normalize();
+        let w = Vec3f::new(w.x, w.z, w.y);
+        let st = Point2f::new(
+            spherical_phi(w) * (1.0 / (2.0 * consts::PI)),
+            spherical_theta(w) * consts::FRAC_1_PI
+        );
+        diagram.lookup_trilinear_width(st, 0.0)
+    }
+}
+
+impl Light for GonioPhotometricLight {
+    fn flags(&self) -> LightFlags {
+        LightFlags::DeltaPosition
+    }
+
+    fn light_to_world(&self) -> &Transform {
+        &self.l2w
+    }
+
+    fn world_to_light(&self) -> &Transform {
+        &self.w2l
+    }
+
+    fn power(&self) -> Spectrum {
+        let average_scale = self.diagram.as_ref().map_or(Spectrum::uniform(1.0), |diagram| {
+            diagram.lookup_trilinear_width(Point2f::new(0.5, 0.5), 0.5)
+        });
+        self.intensity * average_scale * 4.0 * consts::PI
+    }
+
+    fn sample_incident_radiance(&self, reference: &SurfaceHit, _u: Point2f) -> LiSample {
+        let wi = (self.world_point - reference.p).normalize();
+        let pdf = 1.0;
+        let p1 = SurfaceHit {
+            p: self.world_point,
+            p_err: Vec3f::zero(),
+            time: reference.time,
+            n: Normal3(Vec3f::zero()),
+        };
+        let vis = VisibilityTester {
+            p0: *reference,
+            p1,
+        };
+        let radiance = self.intensity * self.scale(-wi) / (self.world_point - reference.p).magnitude2();
+        LiSample {
+            radiance,
+            wi,
+            vis,
+            pdf
+        }
+    }
+
+    fn pdf_incident_radiance(&self, _reference: &SurfaceHit, _wi: Vec3f) -> f32 {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use crate::light::point::PointLight;
+    use crate::mipmap::ImageWrap;
+
+    #[test]
+    fn test_uniform_diagram_is_point_light() {
+        let intensity = Spectrum::new([2.0, 3.0, 4.0]);
+        let l2w = Transform::translate(Vec3f::new(1.0, 2.0, 3.0));
+        let diagram = MIPMap::new((4, 2), vec![Spectrum::uniform(1.0); 8], ImageWrap::Repeat);
+        let gonio = GonioPhotometricLight::new(l2w, intensity, Some(Arc::new(diagram)));
+        let point = PointLight::new(l2w, intensity);
+
+        let points = [
+            Point3f::new(0.0, 0.0, 0.0), Point3f::new(5.0, -2.0, 1.0), Point3f::new(1.0, 2.0, -4.0),
+        ];
+        for &p in &points {
+            let reference = SurfaceHit {
+                p,
+                p_err: Vec3f::zero(),
+                time: 0.0,
+                n: Normal3(Vec3f::new(0.0, 0.0, 1.0)),
+            };
+            let u = Point2f::new(0.5, 0.5);
+            let expected = point.sample_incident_radiance(&reference, u);
+            let sample = gonio.sample_incident_radiance(&reference, u);
+            assert_abs_diff_eq!(sample.radiance, expected.radiance, epsilon = 1e-5);
+            assert_abs_diff_eq!(sample.wi, expected.wi);
+            assert_eq!(sample.pdf, expected.pdf);
+        }
+    }
+}
@@ -2,14 +2,16 @@ use std::sync::Arc;
 use crate::mipmap::{MIPMap, ImageWrap};
 use crate::spectrum::Spectrum;
 use crate::sampling::Distribution2D;
-use crate::{Point3f, Float, Point2f, RayDifferential, Transform, Vec3f, spherical_phi, spherical_theta, Normal3};
+use crate::{Point3f, Float, Point2f, RayDifferential, Transform, Vec3f, Normal3, dir_to_equirect_uv, equirect_uv_to_dir};
 use crate::light::{Light, LiSample, LightFlags, VisibilityTester};
-use crate::primitive::Primitive;
-use crate::bvh::BVH;
+use crate::scene::Accelerator;
 use crate::interaction::SurfaceHit;
 use crate::consts;
 use cgmath::{EuclideanSpace, InnerSpace};
 
+/// Light arriving from infinitely far away in every direction, given by an equirectangular
+/// environment map. Directions are looked up in light space, so `light_to_world` rotates the
+/// environment around the scene.
 pub struct InfiniteAreaLight {
     l_map: Arc<MIPMap<Spectrum>>,
     distribution: Distribution2D,
@@ -61,7 +63,7 @@ impl InfiniteAreaLight {
     }
 
     fn compute_distribution(mipmap: &MIPMap<Spectrum>) -> Distribution2D {
-        let (height, width) = mipmap.resolution();
+        let (width, height) = mipmap.resolution();
         let filter = 1.0 / (width.max(height) as Float);
         let mut img = vec![0.0; width * height];
         for j in 0..height {
@@ -90,8 +92,13 @@ impl Light for InfiniteAreaLight {
         &self.world_to_light
     }
 
-    fn preprocess(&mut self, scene_prims: &BVH<Box<dyn Primitive>>) {
-        let (center, radius) = scene_prims.bounds.bounding_sphere();
+    fn power(&self) -> Spectrum {
+        let average_radiance = self.l_map.lookup_trilinear_width(Point2f::new(0.5, 0.5), 0.5);
+        average_radiance * consts::PI * self.world_radius * self.world_radius
+    }
+
+    fn preprocess(&mut self, scene_prims: &Accelerator) {
+        let (center, radius) = scene_prims.bounds().bounding_sphere();
         self.world_center = center;
         self.world_radius = radius;
     }
@@ -102,15 +109,9 @@ impl Light for InfiniteAreaLight {
             unimplemented!()
         }
 
-        // map (u, v) sample to spherical coordinates
-        let theta = uv.y * consts::PI;
-        let phi = uv.x * 2.0 * consts::PI;
         // convert sample point to direction
-        let wi = self.light_to_world.transform(Vec3f::new(
-            theta.sin() * phi.cos(),
-            theta.sin() * phi.sin(),
-            theta.cos()
-        ));
+        let theta = uv.y * consts::PI;
+        let wi = self.light_to_world.transform(equirect_uv_to_dir(uv));
 
         let pdf = if theta.sin() == 0.0 {
             0.0
@@ -140,26 +141,128 @@ impl Light for InfiniteAreaLight {
     }
 
     fn pdf_incident_radiance(&self, _reference: &SurfaceHit, wi: Vec3f) -> Float {
-        let wi = self.world_to_light.transform(wi);
-        let theta = spherical_theta(wi);
-        let phi = spherical_phi(wi);
-        if theta.sin() == 0.0 {
+        let uv = dir_to_equirect_uv(self.world_to_light.transform(wi));
+        let sin_theta = (uv.y * consts::PI).sin();
+        if sin_theta == 0.0 {
             0.0
         } else {
-            self.distribution.pdf(Point2f::new(
-                phi * (1.0 / (2.0 * consts::PI)),
-                theta * consts::FRAC_1_PI
-            )) / (2.0 * consts::PI * consts::PI * theta.sin())
+            self.distribution.pdf(uv) / (2.0 * consts::PI * consts::PI * sin_theta)
         }
     }
 
+    /// Looks up the environment map in the direction of the ray, using the same equirectangular
+    /// mapping as the sampling distribution.
     fn environment_emitted_radiance(&self, ray: &RayDifferential) -> Spectrum {
         let w = self.world_to_light.transform(ray.ray.dir).normalize();
-        let st = Point2f::new(
-            spherical_phi(w) * (1.0 / (2.0 * std::f32::consts::PI)),
-            spherical_theta(w) * std::f32::consts::FRAC_1_PI
-        );
+        let st = dir_to_equirect_uv(w);
         // TODO: Illuminant SpectrumType for full spectral mode
         self.l_map.lookup_trilinear_width(st, 0.0)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use crate::Ray;
+    use crate::bvh::BVH;
+    use crate::scene::Scene;
+
+    fn escaped_ray(dir: Vec3f) -> RayDifferential {
+        RayDifferential { ray: Ray::new(Point3f::new(0.0, 0.0, 0.0), dir), diff: None }
+    }
+
+    #[test]
+    fn test_environment_lookup_for_escaped_rays() {
+        // Upper hemisphere (in light space) is red, lower hemisphere is blue
+        let red = Spectrum::new([1.0, 0.0, 0.0]);
+        let blue = Spectrum::new([0.0, 0.0, 1.0]);
+        let texels = vec![red, red, red, red, blue, blue, blue, blue];
+        let envmap = MIPMap::new((4, 2), texels, ImageWrap::Repeat);
+        let light = InfiniteAreaLight::new_envmap(Arc::new(envmap), Transform::IDENTITY);
+        let scene = Scene::new(BVH::build(Vec::new()), vec![Box::new(light)], vec![]);
+
+        let mut ray = escaped_ray(Vec3f::new(1.0, 0.0, 1.0).normalize());
+        assert!(scene.intersect(&mut ray.ray).is_none());
+        assert_abs_diff_eq!(scene.environment_emitted_radiance(&ray), red, epsilon = 1e-5);
+
+        let ray = escaped_ray(Vec3f::new(1.0, 0.0, -1.0).normalize());
+        assert_abs_diff_eq!(scene.environment_emitted_radiance(&ray), blue, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn test_rotated_environment() {
+        let texels: Vec<Spectrum> = (0..32)
+            .map(|i| Spectrum::new([i as Float / 32.0, (i % 3) as Float / 3.0, 0.5]))
+            .collect();
+        let envmap = Arc::new(MIPMap::new((8, 4), texels, ImageWrap::Repeat));
+        let light = InfiniteAreaLight::new_envmap(envmap.clone(), Transform::IDENTITY);
+        let rotation = Transform::rotate_z(cgmath::Deg(90.0));
+        let rotated = InfiniteAreaLight::new_envmap(envmap, rotation);
+
+        // turning the environment a quarter turn about z brings what was seen along +x round to +y
+        let dir = Vec3f::new(1.0, 0.3, 0.2).normalize();
+        let turned = Vec3f::new(-0.3, 1.0, 0.2).normalize();
+        let radiance = light.environment_emitted_radiance(&escaped_ray(dir));
+        assert_abs_diff_eq!(rotated.environment_emitted_radiance(&escaped_ray(turned)), radiance, epsilon = 1e-4);
+        let unturned = rotated.environment_emitted_radiance(&escaped_ray(dir));
+        assert!((unturned - radiance).into_array().iter().any(|c| c.abs() > 0.01), "{:?} {:?}", unturned, radiance);
+
+        // sampling picks the same environment texels, in rotated directions
+        let reference = SurfaceHit {
+            p: Point3f::origin(),
+            p_err: Vec3f::new(0.0, 0.0, 0.0),
+            time: 0.0,
+            n: Normal3(Vec3f::new(0.0, 0.0, 1.0)),
+        };
+        for &u in &[Point2f::new(0.1, 0.3), Point2f::new(0.6, 0.5), Point2f::new(0.85, 0.7)] {
+            let sample = light.sample_incident_radiance(&reference, u);
+            let rotated_sample = rotated.sample_incident_radiance(&reference, u);
+            assert_abs_diff_eq!(rotated_sample.wi, rotation.transform(sample.wi), epsilon = 1e-4);
+            assert_abs_diff_eq!(rotated_sample.radiance, sample.radiance, epsilon = 1e-4);
+            assert_abs_diff_eq!(rotated_sample.pdf, sample.pdf, epsilon = 1e-3 * sample.pdf);
+            assert_abs_diff_eq!(rotated.pdf_incident_radiance(&reference, rotated_sample.wi), sample.pdf, epsilon = 1e-3 * sample.pdf);
+        }
+    }
+
+    #[test]
+    fn test_uniform_environment() {
+        let radiance = Spectrum::new([0.2, 0.5, 1.0]);
+        let light = InfiniteAreaLight::new_uniform(radiance, Transform::IDENTITY);
+        let dirs = [
+            Vec3f::new(0.0, 0.0, 1.0), Vec3f::new(0.0, 0.0, -1.0), Vec3f::new(0.3, -0.7, 0.2),
+            Vec3f::new(-1.0, 0.0, 0.0),
+        ];
+        for &dir in &dirs {
+            let ray = escaped_ray(dir.normalize());
+            assert_abs_diff_eq!(light.environment_emitted_radiance(&ray), radiance, epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_spherical_texture_matches_environment() {
+        use crate::interaction::{DiffGeom, SurfaceInteraction};
+        use crate::texture::Texture;
+        use crate::texture::image::ImageTexture;
+        use crate::texture::mapping::SphericalMapping2D;
+
+        let texels: Vec<Spectrum> = (0..32)
+            .map(|i| Spectrum::new([i as Float / 32.0, (i % 5) as Float / 5.0, 0.5]))
+            .collect();
+        let envmap = Arc::new(MIPMap::new((8, 4), texels, ImageWrap::Repeat));
+        let light_to_world = Transform::rotate_z(cgmath::Deg(30.0)) * Transform::rotate_x(cgmath::Deg(20.0));
+        let light = InfiniteAreaLight::new_envmap(envmap.clone(), light_to_world);
+        let texture = ImageTexture::new(SphericalMapping2D::new(light_to_world.inverse()), envmap);
+
+        let dirs = [Vec3f::new(0.3, -0.7, 0.2), Vec3f::new(-0.5, 0.1, -0.8), Vec3f::new(0.1, 0.9, 0.4)];
+        for &dir in &dirs {
+            let dir = dir.normalize();
+            let zero = Vec3f::new(0.0, 0.0, 0.0);
+            let geom = DiffGeom { dpdu: zero, dpdv: zero, dndu: Normal3(zero), dndv: Normal3(zero) };
+            let si = SurfaceInteraction::new(
+                Point3f::from_vec(dir * 1000.0), zero, 0.0, Point2f::new(0.0, 0.0), -dir, Normal3(-dir), geom
+            );
+            assert_abs_diff_eq!(texture.evaluate(&si), light.environment_emitted_radiance(&escaped_ray(dir)), epsilon = 1e-4);
+        }
+    }
+}
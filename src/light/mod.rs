@@ -1,15 +1,19 @@
-use crate::{Transform, Point2f, Vec3f, Float, RayDifferential};
+use crate::{Transform, Point2f, Vec3f, Float, Normal3, Ray, RayDifferential};
 use crate::interaction::SurfaceHit;
 use crate::spectrum::Spectrum;
 use crate::scene::Scene;
-use crate::bvh::BVH;
+use crate::scene::Accelerator;
 use std::sync::Arc;
 use crate::shapes::Shape;
+use crate::medium::Medium;
+use crate::sampler::Sampler;
 
 pub mod point;
+pub mod gonio;
 pub mod distant;
 pub mod infinite;
 pub mod diffuse;
+pub mod spot;
 
 pub trait Light: Sync + Send {
     fn flags(&self) -> LightFlags;
@@ -20,7 +24,11 @@ pub trait Light: Sync + Send {
 
     fn n_samples(&self) -> usize { 1 }
 
-    fn preprocess(&mut self, scene_prims: &BVH) {}
+    /// The total power emitted by the light. For lights at infinity this depends on the scene
+    /// bounds, so it is only meaningful after `preprocess`.
+    fn power(&self) -> Spectrum;
+
+    fn preprocess(&mut self, scene_prims: &Accelerator) {}
 
     fn sample_incident_radiance(&self, reference: &SurfaceHit, u: Point2f) -> LiSample;
 
@@ -30,6 +38,14 @@ pub trait Light: Sync + Send {
     fn pdf_incident_radiance(&self, reference: &SurfaceHit, wi: Vec3f) -> Float;
 
     fn environment_emitted_radiance(&self, ray: &RayDifferential) -> Spectrum { Spectrum::uniform(0.0) }
+
+    /// Samples a ray leaving the light at `time`, for tracing paths starting from the lights. `u1`
+    /// chooses the origin and `u2` the direction. Returns None for lights that can't emit rays.
+    fn sample_le(&self, _u1: Point2f, _u2: Point2f, _time: Float) -> Option<LeSample> { None }
+
+    /// The densities of the origin (with respect to area) and direction (with respect to solid
+    /// angle) with which `sample_le` would sample `ray` leaving a point with normal `n_light`.
+    fn pdf_le(&self, _ray: &Ray, _n_light: Normal3) -> (Float, Float) { (0.0, 0.0) }
 }
 
 pub trait AreaLight: Light {
@@ -39,6 +55,16 @@ pub trait AreaLight: Light {
 
     // TODO: this is a hack for upcasting to compare pointers, which probably isn't even needed.
     fn as_light(&self) -> &dyn Light;
+
+    /// Whether `light` refers to this same light object.
+    fn is_same_light(&self, light: &dyn Light) -> bool {
+        // Comparing trait object references also compares the vtable pointers, which aren't
+        // guaranteed to be unique, so only compare the data pointers.
+        std::ptr::eq(
+            self.as_light() as *const dyn Light as *const u8,
+            light as *const dyn Light as *const u8
+        )
+    }
 }
 
 pub trait AreaLightBuilder<S: Shape> {
@@ -59,6 +85,22 @@ pub struct LiSample {
     pub vis: VisibilityTester,
 }
 
+#[derive(Debug)]
+pub struct LeSample {
+    pub radiance: Spectrum,
+
+    pub ray: Ray,
+
+    /// The surface normal at the ray's origin. Lights without a surface use the ray direction.
+    pub n_light: Normal3,
+
+    /// The density of the ray's origin with respect to area.
+    pub pdf_pos: Float,
+
+    /// The density of the ray's direction with respect to solid angle.
+    pub pdf_dir: Float,
+}
+
 pub enum LightFlags {
     DeltaPosition, DeltaDirection, Area, Infinite
 }
@@ -80,6 +122,32 @@ pub struct VisibilityTester {
 
 impl VisibilityTester {
     pub fn unoccluded(&self, scene: &Scene) -> bool {
-        !scene.intersect_test(&self.p0.spawn_ray_to_hit(self.p1))
+        !scene.intersect_p(self.p0, self.p1)
+    }
+
+    /// The fraction of light that gets from `p1` to `p0` through the media in between, where the
+    /// segment leaves `p0` in `medium`. Surfaces with a material block it, while those without one
+    /// only separate media.
+    pub fn transmittance<'s>(&self, scene: &'s Scene, medium: Option<&'s dyn Medium>, sampler: &mut dyn Sampler) -> Spectrum {
+        let mut tr = Spectrum::uniform(1.0);
+        let mut p0 = self.p0;
+        let mut medium = medium;
+        loop {
+            let mut ray = p0.spawn_ray_to_hit(self.p1);
+            let si = scene.intersect(&mut ray);
+            if si.as_ref().and_then(|si| si.primitive).map_or(false, |prim| prim.material().is_some()) {
+                return Spectrum::uniform(0.0);
+            }
+            if let Some(medium) = medium {
+                tr *= medium.tr(&ray, sampler);
+            }
+            match si {
+                Some(si) => {
+                    medium = si.medium(ray.dir, medium);
+                    p0 = si.hit;
+                }
+                None => return tr,
+            }
+        }
     }
 }
\ No newline at end of file
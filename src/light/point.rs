@@ -1,9 +1,10 @@
 use cgmath::{InnerSpace};
 use num::Zero;
 
-use crate::{Normal3, Point2f, Point3f, Transform, Vec3f};
+use crate::{consts, AnimatedTransform, Float, Normal3, Point2f, Point3f, Ray, Transform, Vec3f};
 use crate::interaction::SurfaceHit;
-use crate::light::{Light, LightFlags, LiSample, VisibilityTester};
+use crate::light::{Light, LightFlags, LiSample, LeSample, VisibilityTester};
+use crate::sampling::uniform_sample_sphere;
 use crate::spectrum::Spectrum;
 
 pub struct PointLight {
@@ -11,6 +12,9 @@ pub struct PointLight {
     w2l: Transform,
     world_point: Point3f,
     intensity: Spectrum,
+
+    /// The light's motion over the shutter interval, if it moves.
+    motion: Option<AnimatedTransform>,
 }
 
 impl PointLight {
@@ -22,7 +26,24 @@ impl PointLight {
             l2w,
             w2l,
             world_point,
-            intensity
+            intensity,
+            motion: None,
+        }
+    }
+
+    /// A point light that moves according to `light_to_world` over the shutter interval.
+    pub fn animated(light_to_world: AnimatedTransform, intensity: Spectrum) -> Self {
+        let mut light = Self::new(light_to_world.interpolate(Float::NEG_INFINITY), intensity);
+        if light_to_world.is_animated() {
+            light.motion = Some(light_to_world);
+        }
+        light
+    }
+
+    fn position(&self, time: Float) -> Point3f {
+        match &self.motion {
+            Some(motion) => motion.interpolate(time).transform(Point3f::new(0.0, 0.0, 0.0)),
+            None => self.world_point,
         }
     }
 }
@@ -40,11 +61,16 @@ impl Light for PointLight {
         &self.w2l
     }
 
+    fn power(&self) -> Spectrum {
+        self.intensity * 4.0 * consts::PI
+    }
+
     fn sample_incident_radiance(&self, reference: &SurfaceHit, _u: Point2f) -> LiSample {
-        let wi = (self.world_point - reference.p).normalize();
+        let world_point = self.position(reference.time);
+        let wi = (world_point - reference.p).normalize();
         let pdf = 1.0;
         let p1 = SurfaceHit {
-            p: self.world_point,
+            p: world_point,
             p_err: Vec3f::zero(),
             time: reference.time,
             n: Normal3(Vec3f::zero()),
@@ -53,7 +79,7 @@ impl Light for PointLight {
             p0: *reference,
             p1,
         };
-        let radiance = self.intensity / (self.world_point - reference.p).magnitude2();
+        let radiance = self.intensity / (world_point - reference.p).magnitude2();
         LiSample {
             radiance,
             wi,
@@ -65,4 +91,89 @@ impl Light for PointLight {
     fn pdf_incident_radiance(&self, _reference: &SurfaceHit, _wi: Vec3f) -> f32 {
         0.0
     }
-}
\ No newline at end of file
+
+    fn sample_le(&self, u1: Point2f, _u2: Point2f, time: Float) -> Option<LeSample> {
+        let dir = uniform_sample_sphere(u1);
+        let mut ray = Ray::new(self.position(time), dir);
+        ray.time = time;
+        Some(LeSample {
+            radiance: self.intensity,
+            ray,
+            n_light: Normal3(dir),
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (4.0 * consts::PI),
+        })
+    }
+
+    fn pdf_le(&self, _ray: &Ray, _n_light: Normal3) -> (Float, Float) {
+        (0.0, 1.0 / (4.0 * consts::PI))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use cgmath::Deg;
+    use crate::{Bounds2f, Point2i};
+    use crate::bvh::BVH;
+    use crate::camera::PerspectiveCamera;
+    use crate::film::Film;
+    use crate::filter::BoxFilter;
+    use crate::integrator::{SamplerIntegrator, TileOrder};
+    use crate::integrator::direct_lighting::{DirectLightingIntegrator, LightStrategy};
+    use crate::material::matte::MatteMaterial;
+    use crate::primitive::{GeometricPrimitive, Primitive};
+    use crate::sampler::random::RandomSampler;
+    use crate::scene::Scene;
+    use crate::shapes::sphere::Sphere;
+
+    /// Renders the ground lit by `light` from above, returning the middle row of the image.
+    fn render_ground(light: PointLight) -> Vec<Float> {
+        let o2w = Transform::translate(Vec3f::new(0.0, 0.0, -100.0));
+        let ground = GeometricPrimitive::new(
+            Arc::new(Sphere::whole(o2w, o2w.inverse(), 100.0)),
+            Some(Arc::new(MatteMaterial::constant(Spectrum::uniform(1.0)))),
+        );
+        let prims: Vec<Box<dyn Primitive>> = vec![Box::new(ground)];
+        let lights: Vec<Box<dyn Light>> = vec![Box::new(light)];
+        let scene = Scene::new(BVH::build(prims), lights, vec![]);
+
+        let resolution = Point2i::new(32, 32);
+        let camera = PerspectiveCamera::new(
+            Transform::translate(Vec3f::new(0.0, 0.0, 10.0)) * Transform::rotate_x(Deg(180.0)),
+            resolution,
+            Bounds2f::whole_screen(),
+            (0.0, 1.0),
+            0.0,
+            1.0,
+            30.0
+        );
+        let mut integrator = SamplerIntegrator {
+            camera: Box::new(camera),
+            radiance: DirectLightingIntegrator::new(LightStrategy::UniformSampleAll, 1),
+            tile_order: TileOrder::Scanline,
+        };
+        let film = Film::new(resolution, Bounds2f::unit(), BoxFilter::default(), 1.0);
+        integrator.render_parallel(&scene, &film, RandomSampler::new_with_seed(64, 0), None);
+
+        let (pixels, (width, _)) = film.into_spectrum_buffer();
+        pixels[16 * width as usize..17 * width as usize].iter().map(|p| p[0]).collect()
+    }
+
+    #[test]
+    fn test_moving_light_blurs_highlight() {
+        let intensity = Spectrum::uniform(1.0);
+        let start = Transform::translate(Vec3f::new(-1.5, 0.0, 1.0));
+        let end = Transform::translate(Vec3f::new(1.5, 0.0, 1.0));
+        let moving = render_ground(PointLight::animated(AnimatedTransform::new(start, 0.0, end, 1.0), intensity));
+        let fixed = render_ground(PointLight::new(Transform::translate(Vec3f::new(0.0, 0.0, 1.0)), intensity));
+
+        // the highlight under the light's average position is dimmer, and the light is spread out
+        // towards the ends of its path (about 9 pixels either side of the center)
+        let center = |row: &[Float]| 0.5 * (row[15] + row[16]);
+        let ends = |row: &[Float]| 0.5 * (row[6] + row[25]);
+        assert!(center(&moving) < 0.75 * center(&fixed), "{:?} {:?}", moving, fixed);
+        assert!(ends(&moving) > 1.3 * ends(&fixed), "{:?} {:?}", moving, fixed);
+    }
+}
@@ -0,0 +1,134 @@
+use cgmath::InnerSpace;
+use num::Zero;
+
+use crate::{consts, Float, Normal3, Point2f, Point3f, Transform, Vec3f};
+use crate::interaction::SurfaceHit;
+use crate::light::{Light, LightFlags, LiSample, VisibilityTester};
+use crate::spectrum::Spectrum;
+
+/// A point light that only emits within a cone around its +z axis, with intensity falling off
+/// smoothly between `falloff_start` and `total_width` degrees from the axis.
+pub struct SpotLight {
+    l2w: Transform,
+    w2l: Transform,
+    world_point: Point3f,
+    intensity: Spectrum,
+    cos_total_width: Float,
+    cos_falloff_start: Float,
+}
+
+impl SpotLight {
+    pub fn new(light_to_world: Transform, intensity: Spectrum, total_width: Float, falloff_start: Float) -> Self {
+        let l2w = light_to_world;
+        let w2l = l2w.inverse();
+        let world_point = l2w.transform(Point3f::new(0.0, 0.0, 0.0));
+        Self {
+            l2w,
+            w2l,
+            world_point,
+            intensity,
+            cos_total_width: total_width.to_radians().cos(),
+            cos_falloff_start: falloff_start.to_radians().cos(),
+        }
+    }
+
+    /// The fraction of the intensity emitted in the world space direction `w`.
+    fn falloff(&self, w: Vec3f) -> Float {
+        let cos_theta = self.w2l.transform(w).normalize().z;
+        if cos_theta < self.cos_total_width {
+            0.0
+        } else if cos_theta >= self.cos_falloff_start {
+            1.0
+        } else {
+            let delta = (cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width);
+            (delta * delta) * (delta * delta)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn flags(&self) -> LightFlags {
+        LightFlags::DeltaPosition
+    }
+
+    fn light_to_world(&self) -> &Transform {
+        &self.l2w
+    }
+
+    fn world_to_light(&self) -> &Transform {
+        &self.w2l
+    }
+
+    /// Integrates the intensity over the cone, approximating the falloff region as emitting at half
+    /// intensity.
+    fn power(&self) -> Spectrum {
+        self.intensity * 2.0 * consts::PI * (1.0 - 0.5 * (self.cos_falloff_start + self.cos_total_width))
+    }
+
+    fn sample_incident_radiance(&self, reference: &SurfaceHit, _u: Point2f) -> LiSample {
+        let wi = (self.world_point - reference.p).normalize();
+        let pdf = 1.0;
+        let p1 = SurfaceHit {
+            p: self.world_point,
+            p_err: Vec3f::zero(),
+            time: reference.time,
+            n: Normal3(Vec3f::zero()),
+        };
+        let vis = VisibilityTester {
+            p0: *reference,
+            p1,
+        };
+        let radiance = self.intensity * self.falloff(-wi) / (self.world_point - reference.p).magnitude2();
+        LiSample {
+            radiance,
+            wi,
+            vis,
+            pdf
+        }
+    }
+
+    fn pdf_incident_radiance(&self, _reference: &SurfaceHit, _wi: Vec3f) -> Float {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::point::PointLight;
+
+    #[test]
+    fn test_narrow_spotlight_has_less_power() {
+        let intensity = Spectrum::uniform(5.0);
+        let narrow = SpotLight::new(Transform::identity(), intensity, 10.0, 5.0);
+        let wide = SpotLight::new(Transform::identity(), intensity, 60.0, 50.0);
+        let point = PointLight::new(Transform::identity(), intensity);
+
+        assert!(narrow.power()[0] > 0.0);
+        assert!(narrow.power()[0] < wide.power()[0]);
+        assert!(wide.power()[0] < point.power()[0]);
+    }
+
+    #[test]
+    fn test_spotlight_cone() {
+        let l2w = Transform::translate(Vec3f::new(0.0, 0.0, 2.0));
+        let light = SpotLight::new(l2w, Spectrum::uniform(1.0), 30.0, 20.0);
+        let hit = |p: Point3f| SurfaceHit {
+            p,
+            p_err: Vec3f::zero(),
+            time: 0.0,
+            n: Normal3(Vec3f::new(0.0, 0.0, -1.0)),
+        };
+        let u = Point2f::new(0.5, 0.5);
+
+        // on the axis, in front of and behind the light
+        let on_axis = light.sample_incident_radiance(&hit(Point3f::new(0.0, 0.0, 4.0)), u);
+        assert!((on_axis.radiance[0] - 0.25).abs() < 1e-5);
+        let behind = light.sample_incident_radiance(&hit(Point3f::new(0.0, 0.0, 0.0)), u);
+        assert!(behind.radiance.is_black());
+
+        // 45 degrees off the axis is outside the cone
+        let outside = light.sample_incident_radiance(&hit(Point3f::new(2.0, 0.0, 4.0)), u);
+        assert!(outside.radiance.is_black());
+    }
+}
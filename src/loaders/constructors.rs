@@ -1,24 +1,32 @@
-use crate::loaders::{ParamSet, ParamError, Context};
+use cgmath::InnerSpace;
+use crate::loaders::{ParamSet, ParamError, ParamVal, Context};
 use crate::shapes::sphere::Sphere;
-use crate::{Transform, Float, Point3f, Normal3, Vec3f, Point2f};
+use crate::shapes::cone::Cone;
+use crate::shapes::paraboloid::Paraboloid;
+use crate::{Transform, Float, Point3f, Normal3, Vec3f, Point2f, coordinate_system};
 use crate::material::matte::MatteMaterial;
 use crate::shapes::triangle::TriangleMesh;
+use crate::shapes::heightfield::Heightfield;
 use crate::light::diffuse::DiffuseAreaLightBuilder;
 use crate::spectrum::Spectrum;
-use crate::texture::checkerboard::{Checkerboard2DTexture};
-use crate::texture::mapping::{TexCoordsMap2D, UVMapping};
+use crate::texture::checkerboard::{Checkerboard2DTexture, Checkerboard3DTexture};
+use crate::texture::mapping::{IdentityMapping3D, SphericalMapping2D, TexCoordsMap2D, UVMapping};
 use std::sync::Arc;
 use crate::texture::{Texture, TextureRef};
 use crate::light::distant::DistantLight;
 use crate::light::point::PointLight;
+use crate::light::gonio::GonioPhotometricLight;
+use crate::light::spot::SpotLight;
 use crate::mipmap::ImageWrap;
-use crate::imageio::{ImageTexInfo, get_mipmap};
+use crate::imageio::{ImageTexInfo, ColorSpace, get_mipmap};
 use crate::texture::image::ImageTexture;
 use crate::light::infinite::InfiniteAreaLight;
 use crate::material::glass::GlassMaterial;
 use crate::material::metal::{MetalMaterial, RoughnessTex};
 use crate::material::plastic::PlasticMaterial;
 use crate::material::mirror::MirrorMaterial;
+use crate::material::fourier::FourierMaterial;
+use crate::reflection::fourier::get_fourier_table;
 use crate::texture::uv::UVTexture;
 
 type ParamResult<T> = Result<T, ConstructError>;
@@ -27,6 +35,11 @@ type ParamResult<T> = Result<T, ConstructError>;
 pub enum ConstructError {
     ParamError(ParamError),
     ValueError(String),
+    /// A parameter refers to a texture that was never defined.
+    UnboundTexture {
+        param: &'static str,
+        texture: String,
+    },
 }
 
 impl From<ParamError> for ConstructError {
@@ -36,10 +49,10 @@ impl From<ParamError> for ConstructError {
 }
 
 pub fn make_sphere(mut params: ParamSet, ctx: &Context) -> ParamResult<Sphere<Transform>> {
-    let radius = params.get_one("radius").unwrap_or(1.0);
-    let zmin = params.get_one("zmin").unwrap_or(-radius);
-    let zmax = params.get_one("zmax").unwrap_or(radius);
-    let phimax = params.get_one("phimax").unwrap_or(360.0);
+    let radius = params.get_one_or("radius", 1.0)?;
+    let zmin = params.get_one_or("zmin", -radius)?;
+    let zmax = params.get_one_or("zmax", radius)?;
+    let phimax = params.get_one_or("phimax", 360.0)?;
     let o2w = params.current_transform()?;
     let w2o = o2w.inverse();
     let rev = params.reverse_orientation()?;
@@ -54,32 +67,40 @@ pub fn make_sphere(mut params: ParamSet, ctx: &Context) -> ParamResult<Sphere<Tr
     ))
 }
 
+pub fn make_cone(mut params: ParamSet, _ctx: &Context) -> ParamResult<Cone> {
+    let radius = params.get_one_or("radius", 1.0)?;
+    let height = params.get_one_or("height", 1.0)?;
+    let phimax = params.get_one_or("phimax", 360.0)?;
+    let o2w = params.current_transform()?;
+    let w2o = o2w.inverse();
+    let rev = params.reverse_orientation()?;
+    Ok(Cone::new(o2w, w2o, rev, radius, height, phimax))
+}
+
+pub fn make_paraboloid(mut params: ParamSet, _ctx: &Context) -> ParamResult<Paraboloid> {
+    let radius = params.get_one_or("radius", 1.0)?;
+    let zmin = params.get_one_or("zmin", 0.0)?;
+    let zmax = params.get_one_or("zmax", 1.0)?;
+    let phimax = params.get_one_or("phimax", 360.0)?;
+    let o2w = params.current_transform()?;
+    let w2o = o2w.inverse();
+    let rev = params.reverse_orientation()?;
+    Ok(Paraboloid::new(o2w, w2o, rev, radius, zmin, zmax, phimax))
+}
+
 pub fn make_triangle_mesh(mut params: ParamSet, ctx: &Context) -> ParamResult<TriangleMesh> {
     let tf = params.current_transform()?;
     let indices: Vec<i32> = params.get_one("indices")?;
     let indices = indices.into_iter().map(|i| i as u32).collect();
-    let vertices = params.get_one("P")?;
-    let normals = params.get_one("N").ok();
-    let tangents = params.get_one("S").ok();
-    // TODO: handle float array
-    let tex_coords = params.get_one("uv")
-        .or_else(|_| params.get_one("st"))
-        .ok()
-        .or_else(|| {
-            params
-                .get_one::<Vec<Float>>("uv")
-                .or_else(|_| params.get_one("st"))
-                .ok()
-                .filter(|v| v.len() % 2 == 0)
-                .map(|uvs| {
-                    uvs.chunks_exact(2)
-                        .map(|uv| Point2f::new(uv[0], uv[1]))
-                        .collect()
-                })
-        });
+    let vertices: Vec<Point3f> = params.get_one("P")?;
+    let normals: Vec<Normal3> = params.get_one_or("N", vec![])?;
+    let normals = Some(normals).filter(|n| !n.is_empty());
+    let tangents: Vec<Vec3f> = params.get_one_or("S", vec![])?;
+    let tangents = Some(tangents).filter(|s| !s.is_empty());
+    let tex_coords = get_tex_coords(&mut params, vertices.len())?;
     let reverse_orientation = params.reverse_orientation()?;
 
-    let mesh = TriangleMesh::new(
+    let mut mesh = TriangleMesh::new(
         tf,
         indices,
         vertices,
@@ -88,9 +109,41 @@ pub fn make_triangle_mesh(mut params: ParamSet, ctx: &Context) -> ParamResult<Tr
         tex_coords,
         reverse_orientation
     );
+    weld_if_requested(&mut params, &mut mesh)?;
+    smooth_if_requested(&mut params, &mut mesh)?;
     Ok(mesh)
 }
 
+/// Reads per-vertex texture coordinates from either `"uv"` or `"st"`, given as `point2`s or as a
+/// flat float array of `u, v` pairs.
+fn get_tex_coords(params: &mut ParamSet, n_vertices: usize) -> ParamResult<Option<Vec<Point2f>>> {
+    for &name in &["uv", "st"] {
+        let tex_coords: Vec<Point2f> = match params.get_one(name) {
+            Ok(tex_coords) => tex_coords,
+            Err(_) => match params.get_one::<Vec<Float>>(name) {
+                Ok(flat) => {
+                    if flat.len() % 2 != 0 {
+                        return Err(ConstructError::ValueError(
+                            format!("\"{}\" float array must have an even length, got {}", name, flat.len())
+                        ));
+                    }
+                    flat.chunks_exact(2)
+                        .map(|uv| Point2f::new(uv[0], uv[1]))
+                        .collect()
+                },
+                Err(_) => continue,
+            }
+        };
+        if tex_coords.len() != n_vertices {
+            return Err(ConstructError::ValueError(
+                format!("Got {} \"{}\" values for {} vertices", tex_coords.len(), name, n_vertices)
+            ));
+        }
+        return Ok(Some(tex_coords));
+    }
+    Ok(None)
+}
+
 pub fn make_triangle_mesh_from_ply(mut params: ParamSet, ctx: &Context) -> ParamResult<TriangleMesh> {
     use plydough::PropertyData::*;
     use plydough::ElementData;
@@ -175,7 +228,7 @@ pub fn make_triangle_mesh_from_ply(mut params: ParamSet, ctx: &Context) -> Param
         })
         .expect("Ply file is missing vertex indices");
 
-    let mesh = TriangleMesh::new(
+    let mut mesh = TriangleMesh::new(
         tf,
         indices,
         vertices,
@@ -184,11 +237,44 @@ pub fn make_triangle_mesh_from_ply(mut params: ParamSet, ctx: &Context) -> Param
         tex_coords,
         rev
     );
+    weld_if_requested(&mut params, &mut mesh)?;
+    smooth_if_requested(&mut params, &mut mesh)?;
     let elapsed = start.elapsed().as_millis();
     tracing::debug!("Loaded in {} ms", elapsed);
     Ok(mesh)
 }
 
+pub fn make_heightfield(mut params: ParamSet, ctx: &Context) -> ParamResult<TriangleMesh> {
+    let tf = params.current_transform()?;
+    let rev = params.reverse_orientation()?;
+    let nu: i32 = params.get_one("nu")?;
+    let nv: i32 = params.get_one("nv")?;
+    let z: Vec<Float> = params.get_one("Pz")?;
+    if nu < 2 || nv < 2 || z.len() != (nu * nv) as usize {
+        return Err(ConstructError::ValueError(
+            format!("Heightfield needs nu * nv = {} heights with nu, nv >= 2, got {}", nu * nv, z.len())
+        ));
+    }
+    let heightfield = Heightfield::new(nu as usize, nv as usize, z);
+    Ok(heightfield.to_mesh(tf, rev))
+}
+
+fn weld_if_requested(params: &mut ParamSet, mesh: &mut TriangleMesh) -> ParamResult<()> {
+    if params.get_one_or("weld", false)? {
+        let epsilon = params.get_one_or("weldepsilon", 0.0)?;
+        mesh.weld(epsilon);
+    }
+    Ok(())
+}
+
+/// Generates smooth shading normals for meshes given without them if `"smooth"` is set.
+fn smooth_if_requested(params: &mut ParamSet, mesh: &mut TriangleMesh) -> ParamResult<()> {
+    if params.get_one_or("smooth", false)? {
+        mesh.generate_smooth_normals();
+    }
+    Ok(())
+}
+
 pub fn make_matte(mut params: ParamSet, ctx: &Context) -> ParamResult<MatteMaterial> {
     let diffuse = params.get_texture_or_default("Kd", Spectrum::uniform(0.5))?;
     let sigma = params.get_texture_or_default("sigma", 0.0)?;
@@ -201,13 +287,20 @@ pub fn make_glass(mut params: ParamSet, ctx: &Context) -> ParamResult<GlassMater
     let urough = params.get_texture_or_default("uroughness", 0.0)?;
     let vrough = params.get_texture_or_default("vroughness", 0.0)?;
     let eta = params.get_texture_or_default("eta", 1.5)?;
-    let remap = params.get_one("remaproughness").unwrap_or(true);
+    let remap = params.get_one_or("remaproughness", true)?;
     Ok(GlassMaterial::new(kr, kt, urough, vrough,  eta, remap))
 }
 
 pub fn make_mirror_material(mut params: ParamSet, ctx: &Context) -> ParamResult<MirrorMaterial> {
     let kr = params.get_texture_or_default("Kr", Spectrum::uniform(0.9))?;
-    Ok(MirrorMaterial::new(kr))
+    let mirror = MirrorMaterial::new(kr);
+    match params.get_texture_opt::<Float>("roughness")? {
+        Some(roughness) => {
+            let remap = params.get_one_or("remaproughness", true)?;
+            Ok(mirror.with_roughness(roughness, remap))
+        }
+        None => Ok(mirror),
+    }
 }
 
 pub fn make_metal_material(mut params: ParamSet, ctx: &Context) -> ParamResult<MetalMaterial> {
@@ -215,55 +308,79 @@ pub fn make_metal_material(mut params: ParamSet, ctx: &Context) -> ParamResult<M
     let eta = params.get_texture_or_const("eta")?;
     let k = params.get_texture_or_const("k")?;
     let roughness = params.get_texture_or_default("roughness", 0.01)?;
-    let u_rough = params.get_texture_or_const("uroughness");
-    let v_rough = params.get_texture_or_const("vroughness");
+    let u_rough = params.get_texture_opt::<Float>("uroughness")?;
+    let v_rough = params.get_texture_opt::<Float>("vroughness")?;
+    // Either of uroughness/vroughness overrides the isotropic roughness for its direction
     let rough_tex = match (u_rough, v_rough) {
-        (Ok(u_rough), Ok(v_rough)) => {
-            RoughnessTex::Anisotropic { u_rough, v_rough }
-        },
-        _ => RoughnessTex::Isotropic(roughness)
+        (None, None) => RoughnessTex::Isotropic(roughness),
+        (u_rough, v_rough) => RoughnessTex::Anisotropic {
+            u_rough: u_rough.unwrap_or_else(|| roughness.clone()),
+            v_rough: v_rough.unwrap_or_else(|| roughness.clone()),
+        }
     };
 
-    let remap = params.get_one("remaproughness").unwrap_or(true);
+    let remap = params.get_one_or("remaproughness", true)?;
 
     Ok(MetalMaterial::new(eta, k, rough_tex, remap))
 }
 
 pub fn make_plastic_material(mut params: ParamSet, ctx: &Context) -> ParamResult<PlasticMaterial> {
     let kd = params.get_texture_or_default("Kd", Spectrum::uniform(0.25))?;
-    let ks = params.get_texture_or_default("ks", Spectrum::uniform(0.25))?;
+    let ks = params.get_texture_or_default("Ks", Spectrum::uniform(0.25))?;
     let roughness = params.get_texture_or_default("roughness", 0.1)?;
-    let remap = params.get_one("remaproughness").unwrap_or(true);
+    let remap = params.get_one_or("remaproughness", true)?;
     Ok(PlasticMaterial::new(kd, ks, roughness, remap))
 }
 
+pub fn make_fourier_material(mut params: ParamSet, ctx: &Context) -> ParamResult<FourierMaterial> {
+    let filename: String = params.get_one("filename")?;
+    let path = ctx.resolve(filename);
+    let table = get_fourier_table(path)
+        .map_err(|e| ConstructError::ValueError(format!("{}", e)))?;
+    Ok(FourierMaterial::new(table))
+}
+
 pub fn make_diffuse_area_light(mut params: ParamSet, ctx: &Context) -> ParamResult<DiffuseAreaLightBuilder> {
-    let emit = params.get_one("L").unwrap_or(Spectrum::uniform(1.0));
-    let _two_sided = params.get_one("twosided").unwrap_or(false);
-    let samples = params.get_one("samples").unwrap_or(1) as usize;
-    Ok(DiffuseAreaLightBuilder { emit, n_samples: samples })
+    let emit = params.get_one_or("L", Spectrum::uniform(1.0))?;
+    let two_sided = params.get_one_or("twosided", false)?;
+    let samples = params.get_one_or::<i32>("samples", 1)? as usize;
+    Ok(DiffuseAreaLightBuilder { emit, n_samples: samples, two_sided })
+}
+
+/// Solid textures are defined in the space of the transform active where they're declared.
+fn make_tex_coords_map_3d(params: &mut ParamSet) -> IdentityMapping3D {
+    let tex_to_world = params.current_transform().unwrap_or(Transform::IDENTITY);
+    IdentityMapping3D::new(tex_to_world.inverse())
 }
 
 fn make_tex_coords_map_2d(params: &mut ParamSet) -> Result<Arc<dyn TexCoordsMap2D>, ConstructError> {
-    let map_type = params.get_one("mapping").unwrap_or_else(|_| "uv".to_string());
+    let map_type = params.get_one_or("mapping", "uv".to_string())?;
     match map_type.as_ref() {
         "uv" => {
-            let uscale = params.get_one("uscale").unwrap_or(1.0);
-            let vscale = params.get_one("vscale").unwrap_or(1.0);
-            let udelta = params.get_one("udelta").unwrap_or(0.0);
-            let vdelta = params.get_one("vdelta").unwrap_or(0.0);
+            let uscale = params.get_one_or("uscale", 1.0)?;
+            let vscale = params.get_one_or("vscale", 1.0)?;
+            let udelta = params.get_one_or("udelta", 0.0)?;
+            let vdelta = params.get_one_or("vdelta", 0.0)?;
             let map = UVMapping::new(uscale, vscale, udelta, vdelta);
             Ok(Arc::new(map))
         }
+        "spherical" => {
+            let tex_to_world = params.current_transform().unwrap_or(Transform::IDENTITY);
+            Ok(Arc::new(SphericalMapping2D::new(tex_to_world.inverse())))
+        }
         _ => Err(ConstructError::ValueError(format!("Unknown mapping type {}", map_type)))
     }
 
 }
 
 pub fn make_checkerboard_float(mut params: ParamSet, ctx: &Context) -> ParamResult<Arc<dyn Texture<Output=Float>>> {
-    let mapping = make_tex_coords_map_2d(&mut params)?;
     let tex1 = params.get_texture_or_const::<Float>("tex1")?;
     let tex2 = params.get_texture_or_const::<Float>("tex2")?;
+    if params.get_one_or::<i32>("dimension", 2)? == 3 {
+        let mapping = make_tex_coords_map_3d(&mut params);
+        return Ok(Arc::new(Checkerboard3DTexture::new(tex1, tex2, mapping)));
+    }
+    let mapping = make_tex_coords_map_2d(&mut params)?;
 
     let tex = Arc::new(Checkerboard2DTexture::new(
         tex1,
@@ -274,9 +391,13 @@ pub fn make_checkerboard_float(mut params: ParamSet, ctx: &Context) -> ParamResu
 }
 
 pub fn make_checkerboard_spect(mut params: ParamSet, ctx: &Context) -> ParamResult<Arc<dyn Texture<Output=Spectrum>>> {
-    let mapping = make_tex_coords_map_2d(&mut params)?;
     let tex1 = params.get_texture_or_const::<Spectrum>("tex1")?;
     let tex2 = params.get_texture_or_const::<Spectrum>("tex2")?;
+    if params.get_one_or::<i32>("dimension", 2)? == 3 {
+        let mapping = make_tex_coords_map_3d(&mut params);
+        return Ok(Arc::new(Checkerboard3DTexture::new(tex1, tex2, mapping)));
+    }
+    let mapping = make_tex_coords_map_2d(&mut params)?;
 
     let tex = Arc::new(Checkerboard2DTexture::new(
         tex1,
@@ -300,45 +421,84 @@ pub fn make_imagemap_spect(mut params: ParamSet, ctx: &Context) -> ParamResult<A
             "repeat" => Ok(ImageWrap::Repeat),
             "black" => Ok(ImageWrap::Black),
             "clamp" => Ok(ImageWrap::Clamp),
+            "mirror" => Ok(ImageWrap::Mirror),
             _ => Err(ConstructError::ValueError(format!("Unknown repeat type {}", s)))
         }
     })?;
     let mapping = make_tex_coords_map_2d(&mut params)?;
-    let scale = params.get_one("scale").unwrap_or(1.0);
-    let gamma =  params.get_one("gamma").ok();
+    let scale = Spectrum::uniform(params.get_one_or("scale", 1.0)?);
+    // like pbrt, decode with the sRGB curve by default unless the file is already linear
+    let default_gamma = ColorSpace::from_extension(&path).map_or(true, |c| c == ColorSpace::Srgb);
+    let gamma = params.get_one_or("gamma", default_gamma)?;
+    let color_space = Some(if gamma { ColorSpace::Srgb } else { ColorSpace::Linear });
     let info = ImageTexInfo::new(
         path,
         wrap_mode,
         scale,
-        gamma,
+        color_space,
         true
     );
-    let mipmap = get_mipmap(info).unwrap(); // FIXME: propagate error
+    let mipmap = get_mipmap(info, ctx.decoded_images()).unwrap(); // FIXME: propagate error
     let tex = Arc::new(ImageTexture::new(mapping, mipmap));
     Ok(tex)
 }
 
 pub fn make_distant_light(mut params: ParamSet, ctx: &Context) -> ParamResult<DistantLight> {
-    let radiance = params.get_one("L").unwrap_or(Spectrum::uniform(1.0));
-    let scale = params.get_one("scale").unwrap_or(Spectrum::uniform(1.0));
+    let radiance = params.get_one_or("L", Spectrum::uniform(1.0))?;
+    let scale = params.get_one_or("scale", Spectrum::uniform(1.0))?;
     let radiance = radiance * scale;
-    let from = params.get_one("from").unwrap_or(Point3f::new(0.0, 0.0, 0.0));
-    let to = params.get_one("to").unwrap_or(Point3f::new(0.0, 0.0, 1.0));
+    let from = params.get_one_or("from", Point3f::new(0.0, 0.0, 0.0))?;
+    let to = params.get_one_or("to", Point3f::new(0.0, 0.0, 1.0))?;
     Ok(DistantLight::from_to(from, to, radiance))
 }
 
 pub fn make_point_light(mut params: ParamSet, ctx: &Context) -> ParamResult<PointLight> {
-    let intensity = params.get_one("I").unwrap_or(Spectrum::uniform(1.0));
-    let scale = params.get_one("scale").unwrap_or(Spectrum::uniform(1.0));
+    let intensity = params.get_one_or("I", Spectrum::uniform(1.0))?;
+    let scale = params.get_one_or("scale", Spectrum::uniform(1.0))?;
     let intensity = intensity * scale;
-    let from = params.get_one("from").unwrap_or(Point3f::new(0.0, 0.0, 0.0));
+    let from = params.get_one_or("from", Point3f::new(0.0, 0.0, 0.0))?;
     let light_to_world = Transform::translate(from - Point3f::new(0.0, 0.0, 0.0));
     Ok(PointLight::new(light_to_world, intensity))
 }
 
+pub fn make_spot_light(mut params: ParamSet, ctx: &Context) -> ParamResult<SpotLight> {
+    let intensity = params.get_one_or("I", Spectrum::uniform(1.0))?;
+    let scale = params.get_one_or("scale", Spectrum::uniform(1.0))?;
+    let intensity = intensity * scale;
+    let cone_angle = params.get_one_or("coneangle", 30.0)?;
+    let cone_delta = params.get_one_or("conedelta", 5.0)?;
+    let from = params.get_one_or("from", Point3f::new(0.0, 0.0, 0.0))?;
+    let to = params.get_one_or("to", Point3f::new(0.0, 0.0, 1.0))?;
+    // orient the light's +z axis from `from` towards `to`
+    let (up, _) = coordinate_system((to - from).normalize());
+    let l2w = params.current_transform()? * Transform::camera_look_at(from, to, up);
+    Ok(SpotLight::new(l2w, intensity, cone_angle, cone_angle - cone_delta))
+}
+
+pub fn make_gonio_light(mut params: ParamSet, ctx: &Context) -> ParamResult<GonioPhotometricLight> {
+    let intensity = params.get_one_or("I", Spectrum::uniform(1.0))?;
+    let scale = params.get_one_or("scale", Spectrum::uniform(1.0))?;
+    let intensity = intensity * scale;
+    let l2w = params.current_transform()?;
+    let mapname = params.get_one_or("mapname", String::new())?;
+    let diagram = Some(mapname).filter(|m| !m.is_empty())
+        .map(|filename| {
+            let info = ImageTexInfo::new(
+                ctx.resolve(filename),
+                ImageWrap::Repeat,
+                Spectrum::uniform(1.0),
+                Some(ColorSpace::Linear),
+                false
+            );
+            get_mipmap(info, ctx.decoded_images()).map_err(|e| ConstructError::ValueError(format!("{}", e)))
+        })
+        .transpose()?;
+    Ok(GonioPhotometricLight::new(l2w, intensity, diagram))
+}
+
 pub fn make_infinite_area_light(mut params: ParamSet, ctx: &Context) -> ParamResult<InfiniteAreaLight> {
-    let radiance = params.get_one("L").unwrap_or(Spectrum::uniform(1.0));
-    let scale = params.get_one("scale").unwrap_or(Spectrum::uniform(1.0));
+    let radiance = params.get_one_or("L", Spectrum::uniform(1.0))?;
+    let scale = params.get_one_or("scale", Spectrum::uniform(1.0))?;
     let filename = params.get_one::<String>("mapname");
     let l2w = params.current_transform()?;
     let light = filename.map_or_else(
@@ -347,13 +507,139 @@ pub fn make_infinite_area_light(mut params: ParamSet, ctx: &Context) -> ParamRes
             let info = ImageTexInfo::new(
                 ctx.resolve(filename),
                 ImageWrap::Repeat,
-                scale[0], // TODO: scale by nonuniform spectrum
-                Some(false), // TODO: pbrt never gamma corrects here,
+                scale,
+                Some(ColorSpace::Linear), // TODO: pbrt never gamma corrects here,
                 false
             );
-            let mipmap = get_mipmap(info).unwrap();
+            let mipmap = get_mipmap(info, ctx.decoded_images()).unwrap();
             InfiniteAreaLight::new_envmap(mipmap, l2w)
         }
     );
     Ok(light)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::spectrum::named_spectrum;
+
+    fn triangle_params() -> ParamSet {
+        let mut params = ParamSet::new();
+        params
+            .with("object_to_world", Transform::identity())
+            .with("reverse_orientation", false)
+            .with("indices", vec![0i32, 1, 2])
+            .with("P", vec![Point3f::new(0.0, 0.0, 0.0), Point3f::new(1.0, 0.0, 0.0), Point3f::new(0.0, 1.0, 0.0)]);
+        params
+    }
+
+    #[test]
+    fn test_flat_uv_array() {
+        let mut params = triangle_params();
+        params.with("uv", vec![0.0 as Float, 0.0, 1.0, 0.5, 0.25, 1.0]);
+        let tex_coords = get_tex_coords(&mut params, 3).unwrap().unwrap();
+        assert_eq!(tex_coords, vec![Point2f::new(0.0, 0.0), Point2f::new(1.0, 0.5), Point2f::new(0.25, 1.0)]);
+
+        let mut params = triangle_params();
+        params.with("st", vec![Point2f::new(0.0, 0.0), Point2f::new(1.0, 0.5), Point2f::new(0.25, 1.0)]);
+        assert_eq!(get_tex_coords(&mut params, 3).unwrap().unwrap(), tex_coords);
+
+        let mut params = triangle_params();
+        params.with("uv", vec![0.0 as Float, 0.0, 1.0, 0.5]);
+        assert!(make_triangle_mesh(params, &Context::new(PathBuf::new())).is_err());
+
+        let mut params = triangle_params();
+        params.with("uv", vec![0.0 as Float, 0.0, 1.0, 0.5, 0.25, 1.0]);
+        assert!(make_triangle_mesh(params, &Context::new(PathBuf::new())).is_ok());
+    }
+
+    #[test]
+    fn test_unbound_texture() {
+        let ctx = Context::new(PathBuf::new());
+        let mut params = ParamSet::new();
+        params.with("Kd", ParamVal::TextureRef("wood".to_string()));
+        match make_matte(params, &ctx) {
+            Err(ConstructError::UnboundTexture { param, texture }) => {
+                assert_eq!(param, "Kd");
+                assert_eq!(texture, "wood");
+            },
+            _ => panic!("Expected an unbound texture error"),
+        }
+
+        // a missing parameter still falls back to the default
+        assert!(make_matte(ParamSet::new(), &ctx).is_ok());
+    }
+
+    #[test]
+    fn test_wrong_typed_param() {
+        let ctx = Context::new(PathBuf::new());
+        let mut params = ParamSet::new();
+        params
+            .with("object_to_world", Transform::identity())
+            .with("reverse_orientation", false)
+            .with("radius", 2i32);
+        assert!(matches!(make_sphere(params, &ctx), Err(ConstructError::ParamError(_))));
+    }
+
+    #[test]
+    fn test_named_metal_spectrum() {
+        let ctx = Context::new(PathBuf::new());
+        // the pbrt loader resolves spectrum names, so the constructor only sees the spectra
+        let mut params = ParamSet::new();
+        params
+            .with("eta", named_spectrum("metal-Au-eta").unwrap())
+            .with("k", named_spectrum("metal-Au-k").unwrap());
+        assert!(make_metal_material(params, &ctx).is_ok());
+
+        let mut params = ParamSet::new();
+        params
+            .with("eta", "metal-Au-eta".to_string())
+            .with("k", Spectrum::uniform(2.0));
+        assert!(matches!(make_metal_material(params, &ctx), Err(ConstructError::ParamError(_))));
+    }
+
+    #[test]
+    fn test_smooth_mesh_normals() {
+        use crate::Ray;
+        use crate::shapes::Shape;
+
+        // an octahedron, whose smooth normals are its vertex positions
+        let vertices = vec![
+            Point3f::new(1.0, 0.0, 0.0), Point3f::new(-1.0, 0.0, 0.0),
+            Point3f::new(0.0, 1.0, 0.0), Point3f::new(0.0, -1.0, 0.0),
+            Point3f::new(0.0, 0.0, 1.0), Point3f::new(0.0, 0.0, -1.0),
+        ];
+        let indices = vec![
+            0i32, 2, 4, 2, 1, 4, 1, 3, 4, 3, 0, 4,
+            2, 0, 5, 1, 2, 5, 3, 1, 5, 0, 3, 5,
+        ];
+        let mut params = ParamSet::new();
+        params
+            .with("object_to_world", Transform::identity())
+            .with("reverse_orientation", false)
+            .with("indices", indices)
+            .with("P", vertices)
+            .with("smooth", true);
+        let mesh = Arc::new(make_triangle_mesh(params, &Context::new(PathBuf::new())).unwrap());
+        let triangles: Vec<_> = mesh.iter_triangles().collect();
+
+        // hit the faces on either side of the edge from +x to +z, just next to its midpoint
+        let hit = |y: Float| {
+            let ray = Ray::new(Point3f::new(5.0, y, 5.0), Vec3f::new(-1.0, 0.0, -1.0));
+            triangles.iter()
+                .filter_map(|tri| tri.intersect(&ray))
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+                .unwrap().1
+        };
+        let a = hit(1e-3);
+        let b = hit(-1e-3);
+
+        // the geometric normals are the faces' but the shading normals vary continuously
+        assert!(a.hit.n.0.dot(b.hit.n.0) < 0.5, "{:?} {:?}", a.hit.n, b.hit.n);
+        assert!(a.shading_n.0.dot(b.shading_n.0) > 0.999, "{:?} {:?}", a.shading_n, b.shading_n);
+        let expected = Vec3f::new(1.0, 0.0, 1.0).normalize();
+        assert!(a.shading_n.0.dot(expected) > 0.999, "{:?}", a.shading_n);
+        assert!(a.hit.n.0.dot(a.shading_n.0) > 0.0);
+    }
+}
@@ -6,9 +6,11 @@ use crate::spectrum::Spectrum;
 use std::sync::Arc;
 use crate::texture::{Texture, ConstantTexture};
 use crate::material::Material;
+use crate::loaders::constructors::ConstructError;
 use std::any::type_name;
 use std::path::{PathBuf, Path};
 use std::fs::File;
+use crate::imageio::DecodedImages;
 
 pub mod pbrt;
 pub mod constructors;
@@ -29,6 +31,8 @@ pub enum ParamVal {
     FloatTexture(Arc<dyn Texture<Output=Float>>),
     SpectrumTexture(Arc<dyn Texture<Output=Spectrum>>),
     Material(Arc<dyn Material>),
+    /// A reference to a named texture that hadn't been defined when the parameter was read.
+    TextureRef(String),
 }
 
 pub struct TryFromParamErr<V>(&'static str, V);
@@ -39,6 +43,14 @@ pub struct ParamError {
     pub expected_name: &'static str,
 }
 
+impl std::fmt::Display for ParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Expected parameter \"{}\" of type {}", self.expected_name, self.expected_ty)
+    }
+}
+
+impl std::error::Error for ParamError {}
+
 impl TryFrom<ParamVal> for Transform {
     type Error = TryFromParamErr<ParamVal>;
 
@@ -186,6 +198,18 @@ impl ParamSet {
 
     }
 
+    /// Like `get_one`, but gives `default` if the parameter isn't present. A parameter that is
+    /// present with the wrong type is still an error.
+    pub fn get_one_or<T>(&mut self, name: &'static str, default: T) -> Result<T, ParamError>
+        where T: TryFrom<ParamVal, Error=TryFromParamErr<ParamVal>>
+    {
+        if self.params.contains_key(name) {
+            self.get_one(name)
+        } else {
+            Ok(default)
+        }
+    }
+
     pub fn get_one_ref<'a, T>(&'a self, name: &'static str) -> Result<&'a T, ParamError>
         where &'a T: TryFrom<&'a ParamVal, Error=TryFromParamErr<&'a ParamVal>>
     {
@@ -198,6 +222,18 @@ impl ParamSet {
 
     }
 
+    /// Like `get_one_ref`, but gives `default` if the parameter isn't present. A parameter that
+    /// is present with the wrong type is still an error.
+    pub fn get_one_ref_or<'a, T>(&'a self, name: &'static str, default: &'a T) -> Result<&'a T, ParamError>
+        where &'a T: TryFrom<&'a ParamVal, Error=TryFromParamErr<&'a ParamVal>>
+    {
+        if self.params.contains_key(name) {
+            self.get_one_ref(name)
+        } else {
+            Ok(default)
+        }
+    }
+
     pub fn get_many<T>(&mut self, name: &'static str) -> Result<Vec<T>, ParamError>
         where Vec<T>: TryFrom<ParamVal, Error=TryFromParamErr<ParamVal>>
     {
@@ -213,29 +249,46 @@ impl ParamSet {
         Ok(Arc::new(ConstantTexture(val)))
     }
 
-    pub fn get_texture_or_const<T>(&mut self, name: &'static str) -> Result<Arc<dyn Texture<Output=T>>, ParamError>
+    /// Gets the texture bound to `name`, or a constant texture if it's given as a plain value.
+    /// A reference to an undefined texture is an error naming that texture rather than a missing
+    /// parameter.
+    pub fn get_texture_or_const<T>(&mut self, name: &'static str) -> Result<Arc<dyn Texture<Output=T>>, ConstructError>
         where
             T: TryFrom<ParamVal, Error=TryFromParamErr<ParamVal>> + Copy + Sync + Send + 'static,
             Arc<dyn Texture<Output=T>>: TryFrom<ParamVal, Error=TryFromParamErr<ParamVal>>
     {
         let val = self.params.remove(name).ok_or_else(|| ParamError { expected_name: name, expected_ty: type_name::<T>()})?;
+        if let ParamVal::TextureRef(texture) = val {
+            return Err(ConstructError::UnboundTexture { param: name, texture });
+        }
         val.try_into()
             .or_else(|e: TryFromParamErr<ParamVal>| {
                 let tex_value: T = e.1.try_into().map_err(|e: TryFromParamErr<ParamVal>| ParamError { expected_name: name, expected_ty: e.0 })?;
                 Ok(Arc::new(ConstantTexture(tex_value)) as Arc<dyn Texture<Output=T>>)
             })
+            .map_err(ConstructError::ParamError)
 //        self.get_one::<Arc<dyn Texture<Output=T>>>(name).or_else(|_| self.get_constant_texture(name))
     }
 
-    pub fn get_texture_or_default<T>(&mut self, name: &'static str, default: T) -> Result<Arc<dyn Texture<Output=T>>, ParamError>
+    /// Like `get_texture_or_const`, but uses `default` if the parameter isn't given at all.
+    pub fn get_texture_or_default<T>(&mut self, name: &'static str, default: T) -> Result<Arc<dyn Texture<Output=T>>, ConstructError>
         where
             T: TryFrom<ParamVal, Error=TryFromParamErr<ParamVal>> + Copy + Sync + Send + 'static,
             Arc<dyn Texture<Output=T>>: TryFrom<ParamVal, Error=TryFromParamErr<ParamVal>>
     {
-        self.get_texture_or_const(name)
-            .or_else(|err| {
-                Ok(Arc::new(ConstantTexture(default)))
-            })
+        Ok(self.get_texture_opt(name)?.unwrap_or_else(|| Arc::new(ConstantTexture(default))))
+    }
+
+    /// Like `get_texture_or_const`, but gives `None` if the parameter isn't given at all.
+    pub fn get_texture_opt<T>(&mut self, name: &'static str) -> Result<Option<Arc<dyn Texture<Output=T>>>, ConstructError>
+        where
+            T: TryFrom<ParamVal, Error=TryFromParamErr<ParamVal>> + Copy + Sync + Send + 'static,
+            Arc<dyn Texture<Output=T>>: TryFrom<ParamVal, Error=TryFromParamErr<ParamVal>>
+    {
+        if !self.params.contains_key(name) {
+            return Ok(None);
+        }
+        self.get_texture_or_const(name).map(Some)
     }
 
     pub fn current_transform(&mut self) -> Result<Transform, ParamError> {
@@ -256,11 +309,12 @@ impl ParamSet {
 
 pub struct Context {
     base_path: PathBuf,
+    images: DecodedImages,
 }
 
 impl Context {
     pub fn new(base_path: PathBuf) -> Self {
-        Context { base_path }
+        Context { base_path, images: DecodedImages::new() }
     }
 
     pub fn open_relative(&self, path: impl AsRef<Path>) -> std::io::Result<File> {
@@ -271,6 +325,11 @@ impl Context {
     pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
         self.base_path.join(path)
     }
+
+    /// Image files decoded while loading this scene, shared between its textures.
+    pub fn decoded_images(&self) -> &DecodedImages {
+        &self.images
+    }
 }
 
 
@@ -278,5 +337,15 @@ impl Context {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_get_one_or() {
+        let mut params = ParamSet::new();
+        params.with("radius", 2.0 as Float).with("zmin", "low".to_string());
+        assert_eq!(params.get_one_or::<Float>("radius", 1.0).unwrap(), 2.0);
+        assert_eq!(params.get_one_or::<Float>("zmax", 1.0).unwrap(), 1.0);
+        assert!(params.get_one_or::<Float>("zmin", 0.0).is_err());
 
+        assert_eq!(*params.get_one_ref_or::<Float>("zmax", &3.0).unwrap(), 3.0);
+        assert!(params.get_one_ref_or::<Float>("zmin", &0.0).is_err());
+    }
 }
\ No newline at end of file
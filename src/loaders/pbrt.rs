@@ -1,30 +1,41 @@
 use std::sync::Arc;
 use crate::material::Material;
-use crate::{Transform, Point3f, Vec3f, Point2f, Bounds2f, Point2i};
+use crate::material::mix::MixMaterial;
+use crate::{Transform, Point3f, Vec3f, Point2f, Vec2f, Bounds2f, Point2i};
 use crate::Float;
 use crate::light::diffuse::DiffuseAreaLightBuilder;
 use pbrt_parser as parser;
 use pbrt_parser::{WorldStmt, TransformStmt, HeaderStmt};
 use crate::loaders::{ParamSet, ParamVal, ParamError, Context};
-use crate::spectrum::Spectrum;
+use crate::spectrum::{Spectrum, named_spectrum};
 use std::collections::HashMap;
 use crate::texture::Texture;
-use crate::loaders::constructors::{make_sphere, make_matte, make_triangle_mesh, make_diffuse_area_light, ConstructError, make_checkerboard_spect, make_checkerboard_float, make_point_light, make_distant_light, make_imagemap_spect, make_infinite_area_light, make_triangle_mesh_from_ply, make_glass, make_metal_material, make_plastic_material, make_mirror_material, make_uv_spect};
+use crate::loaders::constructors::{make_sphere, make_cone, make_paraboloid, make_matte, make_triangle_mesh, make_diffuse_area_light, ConstructError, make_checkerboard_spect, make_checkerboard_float, make_point_light, make_distant_light, make_imagemap_spect, make_infinite_area_light, make_gonio_light, make_spot_light, make_triangle_mesh_from_ply, make_heightfield, make_glass, make_metal_material, make_plastic_material, make_fourier_material, make_mirror_material, make_uv_spect};
 use crate::light::{AreaLightBuilder, Light};
 use crate::primitive::{GeometricPrimitive, Primitive};
 use crate::shapes::triangle::TriangleMesh;
 
 use crate::texture::{SpectrumTexture, FloatTexture};
-use crate::scene::Scene;
-use crate::bvh::BVH;
-use crate::camera::{Camera, PerspectiveCamera};
+use crate::scene::{Accelerator, Scene};
+use crate::bvh::{BVH, SplitMethod};
+use crate::kdtree::KdTreeAccel;
+use crate::camera::{Camera, PerspectiveCamera, check_screen_window};
+use crate::camera::realistic::RealisticCamera;
 use crate::sampler::Sampler;
-use crate::filter::BoxFilter;
+use crate::filter::{Filter, BoxFilter, GaussianFilter, MitchellFilter, TriangleFilter, LanczosSincFilter};
 use crate::sampler::random::RandomSampler;
-use crate::film::Film;
+use crate::sampler::stratified::StratifiedSampler;
+use crate::film::{Film, check_crop_window};
 use cgmath::Deg;
 use std::fmt::{Formatter, Error};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use crate::integrator::{IntegratorRadiance, SamplerIntegrator, TileOrder};
+use crate::integrator::bdpt::BDPTIntegrator;
+use crate::integrator::direct_lighting::DirectLightingIntegrator;
+use crate::integrator::path::PathIntegrator;
+use crate::integrator::sppm::SPPMIntegrator;
+use crate::integrator::volpath::VolPathIntegrator;
+use crate::integrator::whitted::WhittedIntegrator;
 
 pub struct PbrtSceneBuilder {
     graphics_state: Vec<GraphicsState>,
@@ -35,9 +46,14 @@ pub struct PbrtSceneBuilder {
 
     primitives: Vec<Box<dyn Primitive>>,
     meshes: Vec<Arc<TriangleMesh>>,
-    lights: Vec<Arc<dyn Light>>,
+    lights: Vec<Box<dyn Light>>,
 
+    accelerator_params: ParamSet,
     ctx: Context,
+    strict: bool,
+    /// Set between `ObjectBegin` and `ObjectEnd`. Instancing isn't supported, so shapes defining
+    /// an object are dropped rather than being added to the world at the prototype's position.
+    in_object: bool,
 }
 
 #[derive(Clone)]
@@ -50,13 +66,15 @@ struct GraphicsState {
 #[derive(Debug)]
 pub enum PbrtEvalError {
     ConstructError(ConstructError),
-    TextureError {
-        expected: String
-    },
     MaterialError {
         expected: String
     },
     UnknownName(String),
+    /// A statement the renderer doesn't support yet.
+    Unsupported(String),
+    /// An `Include` statement reached the scene builder. Files should be read with
+    /// `PbrtParser::parse_with_includes`, which splices included files into the statements.
+    UnresolvedInclude,
 }
 
 impl From<ParamError> for PbrtEvalError {
@@ -102,15 +120,32 @@ impl PbrtSceneBuilder {
             primitives: vec![],
             meshes: vec![],
             lights: vec![],
+            accelerator_params: ParamSet::default(),
             ctx,
+            strict: false,
+            in_object: false,
         }
     }
 
-    pub fn create_scene(self) -> Scene {
-        let bvh = BVH::build(self.primitives);
+    /// In strict mode, unsupported statements are an error rather than being skipped with a
+    /// warning.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Build the scene's acceleration structure as described by the parameters of the header's
+    /// `Accelerator` statement (see `PbrtHeader::accelerator_params`).
+    pub fn with_accelerator(mut self, params: ParamSet) -> Self {
+        self.accelerator_params = params;
+        self
+    }
+
+    pub fn create_scene(mut self) -> Result<Scene, PbrtEvalError> {
+        let accelerator = make_accelerator(&mut self.accelerator_params, self.primitives)?;
         let lights = self.lights;
-        let scene = Scene::new(bvh, lights, self.meshes);
-        scene
+        let scene = Scene::new(accelerator, lights, self.meshes);
+        Ok(scene)
     }
 
     // TODO: convert in place!
@@ -125,22 +160,28 @@ impl PbrtSceneBuilder {
             parser::ParamVal::Normal3(v) => ParamVal::Normal3(convert_vec(v).into()),
             parser::ParamVal::Bool(v) => ParamVal::Bool(v.into()),
             parser::ParamVal::String(v) => ParamVal::String(v.into_iter().map(|s| s.to_string()).collect::<Vec<_>>().into()),
-            parser::ParamVal::Texture(s) => self.lookup_texture(&s[0])?, // TODO: no vec for textures
+            parser::ParamVal::Texture(s) => self.lookup_texture(&s[0]), // TODO: no vec for textures
             parser::ParamVal::SpectrumRgb(v) => {
                 ParamVal::Spectrum(v.into_iter().map(|s| s.into()).collect::<Vec<Spectrum>>().into())
             },
-            parser::ParamVal::SpectrumXyz(_) => unimplemented!(),
-            parser::ParamVal::SpectrumSampled(_) => unimplemented!(),
-            parser::ParamVal::SpectrumBlackbody(_) => unimplemented!(),
+            parser::ParamVal::SpectrumXyz(v) => {
+                ParamVal::Spectrum(v.into_iter().map(|s| Spectrum::from_xyz(s.into())).collect::<Vec<Spectrum>>().into())
+            },
+            parser::ParamVal::SpectrumSampled(names) => convert_named_spectra(names)?,
+            parser::ParamVal::SpectrumBlackbody(_) => {
+                return Err(PbrtEvalError::Unsupported("blackbody spectrum".to_string()));
+            },
         };
         Ok(value)
     }
 
-    fn lookup_texture(&self, name: &str) -> Result<ParamVal, PbrtEvalError> {
+    /// Undefined textures are kept as a reference by name, so that the error can be reported
+    /// by whatever reads the parameter.
+    fn lookup_texture(&self, name: &str) -> ParamVal {
         self.spectrum_textures.get(name)
             .map(|t| ParamVal::from(t.clone()))
             .or_else(|| self.float_textures.get(name).map(|t| ParamVal::from(t.clone())))
-            .ok_or_else(|| PbrtEvalError::TextureError { expected: name.to_string() })
+            .unwrap_or_else(|| ParamVal::TextureRef(name.to_string()))
     }
 
     fn add_spect_tex(&mut self, name: String, tex: Arc<dyn SpectrumTexture>) {
@@ -176,6 +217,10 @@ impl PbrtSceneBuilder {
     }
 
     pub fn exec_stmt(&mut self, stmt: parser::WorldStmt) -> Result<(), PbrtEvalError> {
+        skip_unsupported(self.exec_world_stmt(stmt), self.strict)
+    }
+
+    fn exec_world_stmt(&mut self, stmt: parser::WorldStmt) -> Result<(), PbrtEvalError> {
         match stmt {
             WorldStmt::AttributeBegin => {
                 let new_state = self.graphics_state.last().unwrap().clone();
@@ -194,24 +239,30 @@ impl PbrtSceneBuilder {
                 self.tf_state.pop().unwrap();
             },
             WorldStmt::ObjectBegin(_) => {
-                unimplemented!()
+                self.in_object = true;
+                return Err(PbrtEvalError::Unsupported("ObjectBegin".to_string()));
             },
             WorldStmt::ObjectEnd => {
-                unimplemented!()
+                self.in_object = false;
+                return Err(PbrtEvalError::Unsupported("ObjectEnd".to_string()));
             },
             WorldStmt::ReverseOrientation => {
                 self.graphics_state_mut().rev_orientation = true;
             },
             WorldStmt::Transform(tf_stmt) => {
-                let ctm = self.tf_state.pop().unwrap();
-                let ctm = eval_transform_stmt(tf_stmt, &ctm)?;
-                self.tf_state.push(ctm)
+                let ctm = eval_transform_stmt(tf_stmt, self.tf_state.last().unwrap())?;
+                *self.current_tf_mut() = ctm;
+            },
+            WorldStmt::Shape(_, _) if self.in_object => {
+                tracing::debug!("Dropping shape inside an unsupported object definition");
             },
             WorldStmt::Shape(name, params) => {
                 let params = self.make_param_set(params)?;
                 self.shape(name, params)?;
             },
-            WorldStmt::ObjectInstance(_) => {},
+            WorldStmt::ObjectInstance(_) => {
+                return Err(PbrtEvalError::Unsupported("ObjectInstance".to_string()));
+            },
             WorldStmt::LightSource(name, params) => {
                 let params = self.make_param_set(params)?;
                 self.light_source(name.as_ref(), params)?;
@@ -241,14 +292,17 @@ impl PbrtSceneBuilder {
                 let params = self.make_param_set(tex_stmt.params)?;
                 self.texture(&tex_stmt.name, &tex_stmt.ty, &tex_stmt.class, params)?;
             },
+            // Media are only available when building a scene through the API, so every primitive
+            // made here sits in a vacuum.
             WorldStmt::MakeNamedMedium(_, _) => {
-                unimplemented!()
+                return Err(PbrtEvalError::Unsupported("MakeNamedMedium".to_string()));
             },
             WorldStmt::MediumInterface(_, _) => {
-                unimplemented!()
+                return Err(PbrtEvalError::Unsupported("MediumInterface".to_string()));
             },
             WorldStmt::Include(_) => {
-                unimplemented!()
+                // not Unsupported, since skipping an include would silently drop part of the scene
+                return Err(PbrtEvalError::UnresolvedInclude);
             },
         };
         Ok(())
@@ -266,34 +320,34 @@ impl PbrtSceneBuilder {
                 let prim = GeometricPrimitive {
                     shape,
                     material: graphics_state.material.clone(),
-                    light
+                    light,
+                    medium_interface: None
                 };
                 self.primitives.push(Box::new(prim));
             },
 
-            "trianglemesh" => {
-                let mesh = make_triangle_mesh(params, &self.ctx)?;
-                let mesh = Arc::new(mesh);
-                self.meshes.push(mesh.clone());
-                self.primitives.extend(mesh.iter_triangles()
-                    .map(|shape| {
-                        let shape = Arc::new(shape);
-                        let light = graphics_state.area_light.clone()
-                            .map(|builder| builder.create(shape.clone()));
-                        let light = light.map(|l| Arc::new(l));
-                        let material = graphics_state.material.clone();
-                        let prim = GeometricPrimitive {
-                            shape,
-                            material,
-                            light
-                        };
-                        Box::new(prim) as Box<dyn Primitive>
-                    })
-                );
+            "cone" => {
+                let shape = Arc::new(make_cone(params, &self.ctx)?);
+                let mut prim = GeometricPrimitive::new(shape.clone(), graphics_state.material.clone());
+                prim.light = graphics_state.area_light.clone()
+                    .map(|builder| Arc::new(builder.create(shape)));
+                self.primitives.push(Box::new(prim));
             },
 
-            "plymesh" => {
-                let mesh = make_triangle_mesh_from_ply(params, &self.ctx)?;
+            "paraboloid" => {
+                let shape = Arc::new(make_paraboloid(params, &self.ctx)?);
+                let mut prim = GeometricPrimitive::new(shape.clone(), graphics_state.material.clone());
+                prim.light = graphics_state.area_light.clone()
+                    .map(|builder| Arc::new(builder.create(shape)));
+                self.primitives.push(Box::new(prim));
+            },
+
+            "trianglemesh" | "plymesh" | "heightfield" => {
+                let mesh = match name.as_ref() {
+                    "trianglemesh" => make_triangle_mesh(params, &self.ctx)?,
+                    "plymesh" => make_triangle_mesh_from_ply(params, &self.ctx)?,
+                    _ => make_heightfield(params, &self.ctx)?,
+                };
                 let mesh = Arc::new(mesh);
                 self.meshes.push(mesh.clone());
                 self.primitives.extend(mesh.iter_triangles()
@@ -306,7 +360,8 @@ impl PbrtSceneBuilder {
                         let prim = GeometricPrimitive {
                             shape,
                             material,
-                            light
+                            light,
+                            medium_interface: None
                         };
                         Box::new(prim) as Box<dyn Primitive>
                     })
@@ -320,7 +375,14 @@ impl PbrtSceneBuilder {
         Ok(())
     }
 
-    fn material(&mut self, name: &str, params: ParamSet) -> Result<Arc<dyn Material>, PbrtEvalError> {
+    fn named_material(&self, name: &str) -> Result<Arc<dyn Material>, PbrtEvalError> {
+        self.named_materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| PbrtEvalError::MaterialError { expected: name.to_string() })
+    }
+
+    fn material(&mut self, name: &str, mut params: ParamSet) -> Result<Arc<dyn Material>, PbrtEvalError> {
         let material: Arc<dyn Material> = match name {
             "matte" => {
                 Arc::new(make_matte(params, &self.ctx)?)
@@ -337,6 +399,15 @@ impl PbrtSceneBuilder {
             "plastic" => {
                 Arc::new(make_plastic_material(params, &self.ctx)?)
             }
+            "fourier" => {
+                Arc::new(make_fourier_material(params, &self.ctx)?)
+            }
+            "mix" => {
+                let m1: String = params.get_one("namedmaterial1")?;
+                let m2: String = params.get_one("namedmaterial2")?;
+                let amount = params.get_texture_or_default("amount", Spectrum::uniform(0.5))?;
+                Arc::new(MixMaterial::new(self.named_material(&m1)?, self.named_material(&m2)?, amount))
+            }
             _ => {
                 return Err(PbrtEvalError::UnknownName(name.to_string()))
             }
@@ -388,15 +459,23 @@ impl PbrtSceneBuilder {
         match name {
             "point" => {
                 let light = make_point_light(params, &self.ctx)?;
-                self.lights.push(Arc::new(light));
+                self.lights.push(Box::new(light));
+            },
+            "spot" => {
+                let light = make_spot_light(params, &self.ctx)?;
+                self.lights.push(Box::new(light));
+            },
+            "goniometric" => {
+                let light = make_gonio_light(params, &self.ctx)?;
+                self.lights.push(Box::new(light));
             },
             "distant" => {
                 let light = make_distant_light(params, &self.ctx)?;
-                self.lights.push(Arc::new(light));
+                self.lights.push(Box::new(light));
             },
             "infinite" => {
                 let light = make_infinite_area_light(params, &self.ctx)?;
-                self.lights.push(Arc::new(light));
+                self.lights.push(Box::new(light));
             }
             _ => return Err(PbrtEvalError::UnknownName(name.to_string())),
         };
@@ -410,6 +489,11 @@ pub struct PbrtHeader {
     camera_tf: Transform,
     sampler_params: ParamSet,
     pub film_params: ParamSet,
+    filter_params: ParamSet,
+    pub integrator_params: ParamSet,
+    pub accelerator_params: ParamSet,
+    ctx: Context,
+    strict: bool,
 }
 
 impl PbrtHeader {
@@ -419,10 +503,28 @@ impl PbrtHeader {
             camera_params: ParamSet::new(),
             camera_tf: Transform::identity(),
             sampler_params: Default::default(),
-            film_params: Default::default()
+            film_params: Default::default(),
+            filter_params: Default::default(),
+            integrator_params: Default::default(),
+            accelerator_params: Default::default(),
+            ctx: Context::new(PathBuf::new()),
+            strict: false,
         }
     }
 
+    /// In strict mode, unsupported statements are an error rather than being skipped with a
+    /// warning.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Resolve files referenced from the header (e.g. lens descriptions) relative to `base_path`.
+    pub fn with_base_path(mut self, base_path: PathBuf) -> Self {
+        self.ctx = Context::new(base_path);
+        self
+    }
+
     pub fn make_camera(&mut self) -> Result<Box<dyn Camera>, PbrtEvalError> {
         let name: String = self.camera_params.get_one("name")?;
         match name.as_ref() {
@@ -430,17 +532,24 @@ impl PbrtHeader {
                 // According to pbrt format reference, transform statements
                 // here describe the world to camera transform so we invert it.
                 let cam2world = self.camera_tf.inverse();
-                let fov = self.camera_params.get_one("fov").unwrap_or(90.0);
-                let lens_radius = self.camera_params.get_one("lensradius").unwrap_or(0.0);
-                let focal_dist = self.camera_params.get_one("focaldistance").unwrap_or(1e6);
-                let shutter_open = self.camera_params.get_one("shutteropen").unwrap_or(0.0);
-                let shutter_close = self.camera_params.get_one("shutterclose").unwrap_or(1.0);
-                let xres = self.film_params.get_one_ref("xresolution").map(|i| *i).unwrap_or(640);
-                let yres = *self.film_params.get_one_ref("yresolution").unwrap_or(&480);
+                let fov = self.camera_params.get_one_or("fov", 90.0)?;
+                let lens_radius = self.camera_params.get_one_or("lensradius", 0.0)?;
+                let focal_dist = self.camera_params.get_one_or("focaldistance", 1e6)?;
+                let shutter_open = self.camera_params.get_one_or("shutteropen", 0.0)?;
+                let shutter_close = self.camera_params.get_one_or("shutterclose", 1.0)?;
+                let clip_near = Some(self.camera_params.get_one_or("clipnear", 0.0)?).filter(|&near| near > 0.0);
+                let clip_far = Some(self.camera_params.get_one_or("clipfar", Float::INFINITY)?).filter(|far| far.is_finite());
+                // the film reads these again, so they're left in its parameters
+                let xres = *self.film_params.get_one_ref_or("xresolution", &640)?;
+                let yres = *self.film_params.get_one_ref_or("yresolution", &480)?;
                 let full_resolution = Point2i::new(xres, yres);
-                let frame_aspect_ratio = self.camera_params.get_one("frameaspectratio")
-                    .unwrap_or(xres as f32 / yres as f32);
-                let screen_window = if frame_aspect_ratio > 1.0 {
+                let frame_aspect_ratio = self.camera_params.get_one_or("frameaspectratio", xres as f32 / yres as f32)?;
+                let screen_window = if let Ok(window) = self.camera_params.get_many::<Float>("screenwindow") {
+                    if window.len() != 4 {
+                        return Err(ConstructError::ValueError("screenwindow must have 4 values".to_string()).into());
+                    }
+                    Bounds2f::with_bounds(Point2f::new(window[0], window[2]), Point2f::new(window[1], window[3]))
+                } else if frame_aspect_ratio > 1.0 {
                     let pmin = Point2f::new(-frame_aspect_ratio, -1.0);
                     let pmax = Point2f::new(frame_aspect_ratio, 1.0);
                     Bounds2f::with_bounds(pmin, pmax)
@@ -449,6 +558,7 @@ impl PbrtHeader {
                     let pmax = Point2f::new(1.0, 1.0 / frame_aspect_ratio);
                     Bounds2f::with_bounds(pmin, pmax)
                 };
+                check_screen_window(&screen_window).map_err(ConstructError::ValueError)?;
 
                 let camera = PerspectiveCamera::new(
                     cam2world,
@@ -458,92 +568,194 @@ impl PbrtHeader {
                     lens_radius,
                     focal_dist,
                     fov,
-                );
+                ).with_clipping(clip_near, clip_far);
+                Ok(Box::new(camera))
+            },
+            "realistic" => {
+                let camera = self.make_realistic_camera()?;
                 Ok(Box::new(camera))
             },
             _ => Err(PbrtEvalError::UnknownName(name)),
         }
     }
 
-    pub fn make_sampler(&mut self, override_samples: Option<usize>) -> Result<RandomSampler, PbrtEvalError> {
+    fn make_realistic_camera(&mut self) -> Result<RealisticCamera, PbrtEvalError> {
+        let cam2world = self.camera_tf.inverse();
+        let shutter_open = self.camera_params.get_one_or("shutteropen", 0.0)?;
+        let shutter_close = self.camera_params.get_one_or("shutterclose", 1.0)?;
+        let lens_file: String = self.camera_params.get_one("lensfile")?;
+        let aperture_diameter = self.camera_params.get_one_or("aperturediameter", 1.0)?;
+        let focus_distance = self.camera_params.get_one_or("focusdistance", 10.0)?;
+        let simple_weighting = self.camera_params.get_one_or("simpleweighting", true)?;
+        let xres = *self.film_params.get_one_ref_or("xresolution", &640)?;
+        let yres = *self.film_params.get_one_ref_or("yresolution", &480)?;
+        let diagonal = *self.film_params.get_one_ref_or::<Float>("diagonal", &35.0)?;
+
+        let lens_path = self.ctx.resolve(&lens_file);
+        let lens_src = std::fs::read_to_string(&lens_path)
+            .map_err(|e| ConstructError::ValueError(format!("Couldn't read lens file {:?}: {}", lens_path, e)))?;
+        let lens_data = parse_lens_data(&lens_src)?;
+
+        let camera = RealisticCamera::new(
+            cam2world,
+            (shutter_open, shutter_close),
+            &lens_data,
+            aperture_diameter,
+            focus_distance,
+            simple_weighting,
+            Point2i::new(xres, yres),
+            diagonal,
+        ).map_err(ConstructError::ValueError)?;
+        Ok(camera)
+    }
+
+    /// The sampler named by the `Sampler` statement. `override_samples` replaces the number of
+    /// samples per pixel; stratified samplers take the smallest square grid with at least that
+    /// many.
+    pub fn make_sampler(&mut self, override_samples: Option<usize>) -> Result<Box<dyn Sampler>, PbrtEvalError> {
         let name: String = self.sampler_params.get_one("name")?;
-        let spp = override_samples.unwrap_or_else(|| {
-            self.sampler_params.get_one("pixelsamples").unwrap_or(16) as usize
-        });
         match name.as_ref() {
-            "random" => {
-                let sampler = RandomSampler::new_with_seed(spp, 0);
-                Ok(sampler)
-            },
-            name @ _ => {
-                tracing::warn!("Unsupported sampler {}, falling back to random", name);
-                let sampler = RandomSampler::new_with_seed(spp, 0);
-                Ok(sampler)
+            "stratified" => {
+                let (x_samples, y_samples) = match override_samples {
+                    Some(spp) => {
+                        let side = (spp as Float).sqrt().ceil() as usize;
+                        (side, side)
+                    }
+                    None => (
+                        self.sampler_params.get_one_or::<i32>("xsamples", 4)?.max(1) as usize,
+                        self.sampler_params.get_one_or::<i32>("ysamples", 4)?.max(1) as usize,
+                    ),
+                };
+                let jitter = self.sampler_params.get_one_or("jitter", true)?;
+                let n_dimensions = self.sampler_params.get_one_or::<i32>("dimensions", 4)?.max(0) as usize;
+                Ok(Box::new(StratifiedSampler::new_with_seed(x_samples, y_samples, jitter, n_dimensions, 0)))
+            },
+            name => {
+                if name != "random" {
+                    tracing::warn!("Unsupported sampler {}, falling back to random", name);
+                }
+                let spp = match override_samples {
+                    Some(spp) => spp,
+                    None => self.sampler_params.get_one_or::<i32>("pixelsamples", 16)?.max(1) as usize,
+                };
+                Ok(Box::new(RandomSampler::new_with_seed(spp, 0)))
             }
-            // _ => Err(PbrtEvalError::UnknownName(name))
         }
     }
 
-    pub fn make_film(&mut self) -> Result<Film<BoxFilter>, PbrtEvalError> {
-        let xres = *self.film_params.get_one_ref("xresolution").unwrap_or(&640);
-        let yres = *self.film_params.get_one_ref("yresolution").unwrap_or(&480);
+    pub fn make_filter(&mut self) -> Result<Box<dyn Filter + Send + Sync>, PbrtEvalError> {
+        let name: String = self.filter_params.get_one_or("name", "box".to_string())?;
+        let default_radius = match name.as_ref() {
+            "box" => 0.5,
+            "sinc" => 4.0,
+            _ => 2.0,
+        };
+        let radius = Vec2f::new(
+            self.filter_params.get_one_or("xwidth", default_radius)?,
+            self.filter_params.get_one_or("ywidth", default_radius)?,
+        );
+
+        let filter: Box<dyn Filter + Send + Sync> = match name.as_ref() {
+            "box" => Box::new(BoxFilter::new(radius)),
+            "gaussian" => {
+                let alpha = self.filter_params.get_one_or("alpha", 2.0)?;
+                Box::new(GaussianFilter::new(radius, alpha))
+            },
+            "mitchell" => {
+                let b = self.filter_params.get_one_or("B", 1.0 / 3.0)?;
+                let c = self.filter_params.get_one_or("C", 1.0 / 3.0)?;
+                Box::new(MitchellFilter::new(radius, b, c))
+            },
+            "triangle" => Box::new(TriangleFilter::new(radius)),
+            "sinc" => {
+                let tau = self.filter_params.get_one_or("tau", 3.0)?;
+                Box::new(LanczosSincFilter::new(radius, tau))
+            },
+            _ => return Err(PbrtEvalError::UnknownName(name))
+        };
+        Ok(filter)
+    }
+
+    pub fn make_film(&mut self) -> Result<Film<Box<dyn Filter + Send + Sync>>, PbrtEvalError> {
+        let xres = self.film_params.get_one_or("xresolution", 640)?;
+        let yres = self.film_params.get_one_or("yresolution", 480)?;
 
-        let cropwindow: Vec<Float> = self.film_params.get_many("cropwindow").unwrap_or_else(|_| vec![0.0, 1.0, 0.0, 1.0]);
+        let cropwindow: Vec<Float> = self.film_params.get_one_or("cropwindow", vec![0.0, 1.0, 0.0, 1.0])?;
         let cropwindow = Bounds2f::with_bounds(
             Point2f::new(cropwindow[0], cropwindow[2]),
             Point2f::new(cropwindow[1], cropwindow[3])
         );
+        check_crop_window(&cropwindow).map_err(ConstructError::ValueError)?;
 
-        let filter = BoxFilter::default();
-        let film = Film::new(
+        let filter = self.make_filter()?;
+        let mut film = Film::new(
             Point2i::new(xres, yres),
             cropwindow,
             filter,
             35.0
         );
+        if let Ok(&k) = self.film_params.get_one_ref::<Float>("outlierthreshold") {
+            film = film.with_outlier_rejection(k);
+        }
         Ok(film)
     }
 
     pub fn exec_stmt(&mut self, stmt: parser::HeaderStmt) -> Result<(), PbrtEvalError> {
+        skip_unsupported(self.exec_header_stmt(stmt), self.strict)
+    }
+
+    fn exec_header_stmt(&mut self, stmt: parser::HeaderStmt) -> Result<(), PbrtEvalError> {
         match stmt {
             HeaderStmt::Transform(tf_stmt) => {
                 self.tf = eval_transform_stmt(tf_stmt, &self.tf)?;
             },
             HeaderStmt::Camera(name, params) => {
-                let mut params = Self::make_param_set(params);
+                let mut params = Self::make_param_set(params)?;
                 params.put_one("name".to_string(), vec![name]);
                 self.camera_params = params;
                 self.camera_tf = self.tf;
             },
             HeaderStmt::Sampler(name, params) => {
-                let mut params = Self::make_param_set(params);
+                let mut params = Self::make_param_set(params)?;
                 params.put_one("name".to_string(), vec![name]);
                 self.sampler_params = params;
             },
             HeaderStmt::Film(name, params) => {
-                let mut params = Self::make_param_set(params);
+                let mut params = Self::make_param_set(params)?;
                 params.put_one("name".to_string(), vec![name]);
                 self.film_params = params;
             },
-            HeaderStmt::Filter(_, _) => {},
-            HeaderStmt::Integrator(_, _) => {},
-            HeaderStmt::Accelerator(_, _) => {},
+            HeaderStmt::Filter(name, params) => {
+                let mut params = Self::make_param_set(params)?;
+                params.put_one("name".to_string(), vec![name]);
+                self.filter_params = params;
+            },
+            HeaderStmt::Integrator(name, params) => {
+                let mut params = Self::make_param_set(params)?;
+                params.put_one("name".to_string(), vec![name]);
+                self.integrator_params = params;
+            },
+            HeaderStmt::Accelerator(name, params) => {
+                let mut params = Self::make_param_set(params)?;
+                params.put_one("name".to_string(), vec![name]);
+                self.accelerator_params = params;
+            },
         };
         Ok(())
     }
 
-    fn make_param_set(params: Vec<parser::Param>) -> ParamSet {
+    fn make_param_set(params: Vec<parser::Param>) -> Result<ParamSet, PbrtEvalError> {
         let map = params.into_iter()
             .map(|param| {
-                let val = Self::convert_param_val(param.value);
-                (param.name.to_string(), val)
-            }).collect();
-        ParamSet { params: map }
+                let val = Self::convert_param_val(param.value)?;
+                Ok((param.name.to_string(), val))
+            }).collect::<Result<HashMap<String, ParamVal>, PbrtEvalError>>()?;
+        Ok(ParamSet { params: map })
     }
 
     // TODO: convert in place!
-    fn convert_param_val(val: parser::ParamVal) -> ParamVal {
-        match val {
+    fn convert_param_val(val: parser::ParamVal) -> Result<ParamVal, PbrtEvalError> {
+        let value = match val {
             parser::ParamVal::Int(v) => ParamVal::Int(v.into()),
             parser::ParamVal::Float(v) => ParamVal::Float(v.into()),
             parser::ParamVal::Point2(v) => ParamVal::Point2f(convert_vec(v).into()),
@@ -553,19 +765,118 @@ impl PbrtHeader {
             parser::ParamVal::Normal3(v) => ParamVal::Normal3(convert_vec(v).into()),
             parser::ParamVal::Bool(v) => ParamVal::Bool(v.into()),
             parser::ParamVal::String(v) => ParamVal::String(v.into_iter().map(|s| s.to_string()).collect::<Vec<_>>().into()),
-            parser::ParamVal::Texture(s) => {
-                unimplemented!()
+            parser::ParamVal::Texture(_) => {
+                return Err(PbrtEvalError::Unsupported("texture parameter outside the world block".to_string()));
             }
             parser::ParamVal::SpectrumRgb(v) => {
                 ParamVal::Spectrum(v.into_iter().map(|s| s.into()).collect::<Vec<Spectrum>>().into())
             },
-            parser::ParamVal::SpectrumXyz(_) => unimplemented!(),
-            parser::ParamVal::SpectrumSampled(_) => unimplemented!(),
-            parser::ParamVal::SpectrumBlackbody(_) => unimplemented!(),
+            parser::ParamVal::SpectrumXyz(v) => {
+                ParamVal::Spectrum(v.into_iter().map(|s| Spectrum::from_xyz(s.into())).collect::<Vec<Spectrum>>().into())
+            },
+            parser::ParamVal::SpectrumSampled(names) => convert_named_spectra(names)?,
+            parser::ParamVal::SpectrumBlackbody(_) => {
+                return Err(PbrtEvalError::Unsupported("blackbody spectrum".to_string()));
+            },
+        };
+        Ok(value)
+    }
+}
+
+/// Resolves spectrum parameters given by name, like `"spectrum eta" "metal-Au-eta"`, to the known
+/// spectra in `spectrum::named_spectrum`.
+fn convert_named_spectra<S: AsRef<str>>(names: impl IntoIterator<Item=S>) -> Result<ParamVal, PbrtEvalError> {
+    let spectra = names.into_iter()
+        .map(|name| {
+            let name = name.as_ref();
+            named_spectrum(name).ok_or_else(|| PbrtEvalError::UnknownName(format!("spectrum {}", name)))
+        })
+        .collect::<Result<Vec<Spectrum>, PbrtEvalError>>()?;
+    Ok(ParamVal::Spectrum(spectra.into()))
+}
+
+/// Parses the pbrt scene at `path`, renders it with the integrator its header describes, and saves
+/// the image to `out_path`.
+pub fn render_pbrt_file(path: impl AsRef<Path>, out_path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let base_path = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let parsed = parser::PbrtParser::parse_with_includes(path)?;
+
+    let mut header = PbrtHeader::new().with_base_path(base_path.clone());
+    for stmt in parsed.header {
+        header.exec_stmt(stmt)?;
+    }
+
+    let mut scene_builder = PbrtSceneBuilder::new(base_path)
+        .with_accelerator(std::mem::take(&mut header.accelerator_params));
+    for stmt in parsed.world {
+        scene_builder.exec_stmt(stmt)?;
+    }
+    let scene = scene_builder.create_scene()?;
+
+    let camera = header.make_camera()?;
+    let sampler = header.make_sampler(None)?;
+    let film = header.make_film()?;
+
+    let params = &mut header.integrator_params;
+    let name: String = params.get_one_or("name", "path".to_string())?;
+    match name.as_ref() {
+        "path" => render_sampler_integrator(camera, PathIntegrator::from_params(params)?, &scene, &film, sampler),
+        "volpath" => render_sampler_integrator(camera, VolPathIntegrator::from_params(params)?, &scene, &film, sampler),
+        "bdpt" => render_sampler_integrator(camera, BDPTIntegrator::from_params(params)?, &scene, &film, sampler),
+        "directlighting" => render_sampler_integrator(camera, DirectLightingIntegrator::from_params(params)?, &scene, &film, sampler),
+        "whitted" => render_sampler_integrator(camera, WhittedIntegrator::from_params(params)?, &scene, &film, sampler),
+        "sppm" => SPPMIntegrator::from_params(camera, params)?.render(&scene, &film, sampler),
+        name => {
+            tracing::warn!("Unsupported integrator {}, falling back to path", name);
+            render_sampler_integrator(camera, PathIntegrator::from_params(params)?, &scene, &film, sampler)
+        }
+    }
+
+    film.save(out_path)
+}
+
+/// Builds the acceleration structure named by an `Accelerator` statement's parameters. Without
+/// one, this is a BVH built with the surface area heuristic, as in pbrt.
+fn make_accelerator(params: &mut ParamSet, prims: Vec<Box<dyn Primitive>>) -> Result<Accelerator, PbrtEvalError> {
+    let name: String = params.get_one_or("name", "bvh".to_string())?;
+    match name.as_ref() {
+        "kdtree" => Ok(KdTreeAccel::build(prims).into()),
+        name => {
+            if name != "bvh" {
+                tracing::warn!("Unsupported accelerator {}, falling back to bvh", name);
+            }
+            let max_prims = params.get_one_or::<i32>("maxnodeprims", 4)?.max(1) as usize;
+            let split_method: String = params.get_one_or("splitmethod", "sah".to_string())?;
+            let split_method = match split_method.as_ref() {
+                "sah" => SplitMethod::SAH,
+                "middle" => SplitMethod::Middle,
+                "equal" => SplitMethod::EqualCounts,
+                other => {
+                    tracing::warn!("Unsupported BVH split method {}, using sah", other);
+                    SplitMethod::SAH
+                }
+            };
+            Ok(BVH::build_with_split_method(prims, split_method, max_prims).into())
         }
     }
 }
 
+fn render_sampler_integrator(
+    camera: Box<dyn Camera>,
+    radiance: impl IntegratorRadiance,
+    scene: &Scene,
+    film: &Film<impl Filter + Sync>,
+    sampler: Box<dyn Sampler>,
+) {
+    let mut integrator = SamplerIntegrator {
+        camera,
+        radiance,
+        tile_order: TileOrder::default(),
+    };
+    integrator.render_dyn(scene, film, sampler);
+}
+
 fn eval_transform_stmt(stmt: parser::TransformStmt, current_tf: &Transform) -> Result<Transform, PbrtEvalError> {
     let tf = match stmt {
         parser::TransformStmt::Identity => {
@@ -589,10 +900,10 @@ fn eval_transform_stmt(stmt: parser::TransformStmt, current_tf: &Transform) -> R
             *current_tf * Transform::look_at(eye, look_at, up)
         },
         parser::TransformStmt::CoordinateSystem(_) => {
-            unimplemented!()
+            return Err(PbrtEvalError::Unsupported("CoordinateSystem".to_string()));
         },
         parser::TransformStmt::CoordSysTransform(_) => {
-            unimplemented!()
+            return Err(PbrtEvalError::Unsupported("CoordSysTransform".to_string()));
         },
         parser::TransformStmt::Transform(m) => {
             Transform::from_flat(*m)
@@ -605,7 +916,167 @@ fn eval_transform_stmt(stmt: parser::TransformStmt, current_tf: &Transform) -> R
 }
 
 
+/// Turns an `Unsupported` error into a warning unless `strict` is set, so that scenes using
+/// features we can safely ignore still load.
+fn skip_unsupported(result: Result<(), PbrtEvalError>, strict: bool) -> Result<(), PbrtEvalError> {
+    match result {
+        Err(PbrtEvalError::Unsupported(stmt)) if !strict => {
+            tracing::warn!("Skipping unsupported statement {}", stmt);
+            Ok(())
+        },
+        result => result,
+    }
+}
+
+/// Parses a lens description: whitespace separated numbers, four per element, with `#` comments.
+fn parse_lens_data(src: &str) -> Result<Vec<Float>, ConstructError> {
+    let lens_data = src.lines()
+        .map(|line| line.split('#').next().unwrap())
+        .flat_map(|line| line.split_whitespace())
+        .map(|val| {
+            val.parse::<Float>()
+                .map_err(|_| ConstructError::ValueError(format!("Invalid number {} in lens file", val)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if lens_data.len() % 4 != 0 {
+        return Err(ConstructError::ValueError(
+            format!("Lens file has {} values, expected a multiple of 4", lens_data.len())
+        ));
+    }
+    Ok(lens_data)
+}
 
 fn convert_vec<T, U: From<T>>(v: Vec<T>) -> Vec<U> {
     v.into_iter().map(Into::into).collect()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accelerator_statement() {
+        let path = std::env::temp_dir().join("fountain_accelerator_test.pbrt");
+        std::fs::write(&path, r#"
+Accelerator "bvh" "string splitmethod" ["sah"] "integer maxnodeprims" [1]
+WorldBegin
+Shape "sphere"
+Translate 3 0 0
+Shape "sphere"
+Translate 3 0 0
+Shape "sphere"
+WorldEnd
+"#).unwrap();
+        let parsed = parser::PbrtParser::parse_with_includes(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut header = PbrtHeader::new();
+        for stmt in parsed.header {
+            header.exec_stmt(stmt).unwrap();
+        }
+        let mut builder = PbrtSceneBuilder::new(PathBuf::new())
+            .with_accelerator(std::mem::take(&mut header.accelerator_params));
+        for stmt in parsed.world {
+            builder.exec_stmt(stmt).unwrap();
+        }
+        match builder.create_scene().unwrap().primitives_aggregate {
+            Accelerator::BVH(bvh) => {
+                let stats = bvh.stats();
+                assert_eq!(stats.split_method, SplitMethod::SAH);
+                assert_eq!(stats.n_leaves, 3);
+            },
+            _ => panic!("Expected a BVH"),
+        }
+
+        // without an Accelerator statement the scene also gets an SAH BVH
+        let mut builder = PbrtSceneBuilder::new(PathBuf::new());
+        builder.exec_stmt(WorldStmt::Shape("sphere".into(), vec![])).unwrap();
+        match builder.create_scene().unwrap().primitives_aggregate {
+            Accelerator::BVH(bvh) => assert_eq!(bvh.stats().split_method, SplitMethod::SAH),
+            _ => panic!("Expected a BVH"),
+        }
+    }
+
+    #[test]
+    fn test_skip_object_definitions() {
+        let src = r#"
+WorldBegin
+ObjectBegin "thing"
+Shape "sphere"
+Shape "sphere" "float radius" 2
+ObjectEnd
+ObjectInstance "thing"
+Shape "sphere"
+WorldEnd
+"#;
+        let mut builder = PbrtSceneBuilder::new(PathBuf::new());
+        for stmt in parse_world("fountain_object_block_test.pbrt", src) {
+            builder.exec_stmt(stmt).unwrap();
+        }
+        // only the sphere outside the object block makes it into the scene
+        assert_eq!(builder.create_scene().unwrap().num_primitives(), 1);
+    }
+
+    #[test]
+    fn test_skip_unsupported_statements() {
+        let mut builder = PbrtSceneBuilder::new(PathBuf::new());
+        builder.exec_stmt(WorldStmt::ObjectEnd).unwrap();
+        builder.exec_stmt(WorldStmt::Shape("sphere".into(), vec![])).unwrap();
+        let scene = builder.create_scene().unwrap();
+        assert_eq!(scene.num_primitives(), 1);
+
+        let mut strict = PbrtSceneBuilder::new(PathBuf::new()).with_strict(true);
+        match strict.exec_stmt(WorldStmt::ObjectEnd) {
+            Err(PbrtEvalError::Unsupported(stmt)) => assert_eq!(stmt, "ObjectEnd"),
+            _ => panic!("Expected an unsupported statement error"),
+        }
+    }
+
+    fn parse_world(name: &str, src: &str) -> Vec<WorldStmt> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, src).unwrap();
+        let parsed = parser::PbrtParser::parse_with_includes(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        parsed.world
+    }
+
+    #[test]
+    fn test_named_spectrum_parameters() {
+        let src = r#"
+WorldBegin
+Material "metal" "spectrum eta" "metal-Au-eta" "spectrum k" "metal-Au-k"
+Shape "sphere"
+WorldEnd
+"#;
+        let mut builder = PbrtSceneBuilder::new(PathBuf::new()).with_strict(true);
+        for stmt in parse_world("fountain_named_spectrum_test.pbrt", src) {
+            builder.exec_stmt(stmt).unwrap();
+        }
+        assert_eq!(builder.create_scene().unwrap().num_primitives(), 1);
+
+        let builder = PbrtSceneBuilder::new(PathBuf::new());
+        let material_params = parse_world("fountain_named_spectrum_test.pbrt", src).into_iter()
+            .find_map(|stmt| match stmt {
+                WorldStmt::Material(_, params) => Some(params),
+                _ => None,
+            })
+            .unwrap();
+        let mut params = builder.make_param_set(material_params).unwrap();
+        let eta: Spectrum = params.get_one("eta").unwrap();
+        let k: Spectrum = params.get_one("k").unwrap();
+        assert_eq!(eta, named_spectrum("metal-Au-eta").unwrap());
+        assert_eq!(k, named_spectrum("metal-Au-k").unwrap());
+
+        let src = r#"
+WorldBegin
+Material "metal" "spectrum eta" "metal-Unobtainium-eta"
+WorldEnd
+"#;
+        let mut builder = PbrtSceneBuilder::new(PathBuf::new()).with_strict(true);
+        let results = parse_world("fountain_unknown_spectrum_test.pbrt", src).into_iter()
+            .map(|stmt| builder.exec_stmt(stmt))
+            .collect::<Vec<_>>();
+        assert!(results.iter().any(|r| matches!(r, Err(PbrtEvalError::UnknownName(_)))), "{:?}", results);
+    }
+}
@@ -0,0 +1,27 @@
+use bumpalo::Bump;
+use crate::SurfaceInteraction;
+use crate::material::{Material, TransportMode};
+use crate::reflection::bsdf::Bsdf;
+use crate::reflection::fourier::{FourierBSDF, FourierBSDFTable};
+
+/// A material whose scattering is given by a tabulated (typically measured) Fourier BSDF.
+pub struct FourierMaterial {
+    table: &'static FourierBSDFTable,
+}
+
+impl FourierMaterial {
+    pub fn new(table: &'static FourierBSDFTable) -> Self {
+        Self { table }
+    }
+}
+
+impl Material for FourierMaterial {
+    fn compute_scattering_functions<'a>(&self, si: &SurfaceInteraction, arena: &'a Bump, mode: TransportMode, _allow_multiple_lobes: bool) -> Bsdf<'a> {
+        let mut bsdf = Bsdf::new(si, 1.0);
+        // an empty table means the file couldn't be read, so the surface doesn't scatter
+        if self.table.n_channels > 0 {
+            bsdf.add(arena.alloc(FourierBSDF::new(self.table, mode)));
+        }
+        bsdf
+    }
+}
@@ -5,10 +5,13 @@ use crate::{Float, SurfaceInteraction};
 use crate::material::{Material, TransportMode};
 use bumpalo::Bump;
 use crate::reflection::bsdf::Bsdf;
-use crate::reflection::{SpecularReflection, SpecularTransmission, MicrofacetReflection, MicrofacetTransmission};
+use crate::reflection::{SpecularReflection, SpecularTransmission, MicrofacetReflection, MicrofacetTransmission, FresnelSpecular};
 use crate::fresnel::FresnelDielectric;
 use crate::reflection::microfacet::TrowbridgeReitzDistribution;
 
+/// Roughness below which the glass is treated as perfectly smooth.
+const SMOOTH_ROUGHNESS: Float = 1e-4;
+
 pub struct GlassMaterial {
     reflectance: Arc<dyn Texture<Output = Spectrum>>,
     transmittance: Arc<dyn Texture<Output = Spectrum>>,
@@ -55,16 +58,18 @@ impl Material for GlassMaterial {
         let t = self.transmittance.evaluate(si).clamp_positive();
         let mut u_rough = self.u_roughness.evaluate(si);
         let mut v_rough = self.v_roughness.evaluate(si);
+        let mut bsdf = Bsdf::new(si, eta);
+
+        // Decide on the unmapped roughness, since the remapping never produces an alpha of zero
+        let is_specular = u_rough < SMOOTH_ROUGHNESS && v_rough < SMOOTH_ROUGHNESS;
         if self.remap_roughness {
             u_rough = TrowbridgeReitzDistribution::roughness_to_alpha(u_rough);
             v_rough = TrowbridgeReitzDistribution::roughness_to_alpha(v_rough);
         }
-        let mut bsdf = Bsdf::new(si, eta);
-
-        let is_specular = u_rough == 0.0 && v_rough == 0.0;
 
         if is_specular && allow_multiple_lobes {
-            todo!("FresnelSpecular")
+            let specular = arena.alloc(FresnelSpecular::new(r, t, 1.0, eta, mode));
+            bsdf.add(specular);
         } else {
             if !r.is_black() {
                 let fresnel = FresnelDielectric::new(1.0, eta);
@@ -91,4 +96,37 @@ impl Material for GlassMaterial {
         }
         bsdf
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::sphere_hit;
+    use crate::reflection::BxDFType;
+
+    fn glass_bsdf_types(roughness: Float, allow_multiple_lobes: bool) -> (usize, usize) {
+        let glass = GlassMaterial::new(
+            Arc::new(ConstantTexture(Spectrum::uniform(1.0))),
+            Arc::new(ConstantTexture(Spectrum::uniform(1.0))),
+            Arc::new(ConstantTexture(roughness)),
+            Arc::new(ConstantTexture(roughness)),
+            Arc::new(ConstantTexture(1.5)),
+            true,
+        );
+        let si = sphere_hit();
+
+        let arena = Bump::new();
+        let bsdf = glass.compute_scattering_functions(&si, &arena, TransportMode::Radiance, allow_multiple_lobes);
+        let specular = bsdf.num_components(BxDFType::all() - BxDFType::GLOSSY - BxDFType::DIFFUSE);
+        let glossy = bsdf.num_components(BxDFType::all() - BxDFType::SPECULAR - BxDFType::DIFFUSE);
+        (specular, glossy)
+    }
+
+    #[test]
+    fn test_glass_lobe_selection() {
+        assert_eq!(glass_bsdf_types(0.0, true), (1, 0));
+        assert_eq!(glass_bsdf_types(0.0, false), (2, 0));
+        assert_eq!(glass_bsdf_types(0.5, true), (0, 2));
+        assert_eq!(glass_bsdf_types(0.5, false), (0, 2));
+    }
+}
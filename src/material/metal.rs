@@ -35,7 +35,7 @@ impl MetalMaterial {
 }
 
 impl Material for MetalMaterial {
-    fn compute_scattering_functions<'a>(&self, si: &SurfaceInteraction, arena: &'a Bump, mode: TransportMode, allow_multiple_lobes: bool) -> Bsdf<'a> {
+    fn compute_scattering_functions<'a>(&self, si: &SurfaceInteraction, arena: &'a Bump, _mode: TransportMode, _allow_multiple_lobes: bool) -> Bsdf<'a> {
         let (u_rough, v_rough) = match &self.roughness {
             RoughnessTex::Anisotropic { u_rough, v_rough} => {
                 (u_rough.evaluate(si), v_rough.evaluate(si))
@@ -45,6 +45,7 @@ impl Material for MetalMaterial {
                 (r, r)
             }
         };
+        // Roughness values are used directly as the distribution's alpha unless remapping is on
         let (u_rough, v_rough) = if self.remap_roughness {
             (TrowbridgeReitzDistribution::roughness_to_alpha(u_rough), TrowbridgeReitzDistribution::roughness_to_alpha(v_rough))
         } else { (u_rough, v_rough) };
@@ -63,4 +64,56 @@ impl Material for MetalMaterial {
         bsdf.add(arena.alloc(bxdf));
         bsdf
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use cgmath::InnerSpace;
+    use crate::Vec3f;
+    use crate::material::sphere_hit;
+    use crate::reflection::BxDFType;
+    use crate::texture::ConstantTexture;
+
+    fn highlight(roughness: RoughnessTex, remap: bool) -> Spectrum {
+        let metal = MetalMaterial::new(
+            Arc::new(ConstantTexture(Spectrum::new([0.2, 0.9, 1.1]))),
+            Arc::new(ConstantTexture(Spectrum::new([3.9, 2.4, 2.2]))),
+            roughness,
+            remap,
+        );
+        let si = sphere_hit();
+
+        let arena = Bump::new();
+        let bsdf = metal.compute_scattering_functions(&si, &arena, TransportMode::Radiance, true);
+        let wo = Vec3f::new(0.3, 0.0, -1.0).normalize();
+        let wi = Vec3f::new(-0.25, 0.05, -1.0).normalize();
+        bsdf.f(wo, wi, BxDFType::all())
+    }
+
+    #[test]
+    fn test_remapped_roughness_matches_alpha() {
+        let alpha = 0.1;
+        // Invert the (monotonic) remapping by bisection
+        let (mut lo, mut hi) = (1e-3, 1.0);
+        for _ in 0..50 {
+            let mid = 0.5 * (lo + hi);
+            if TrowbridgeReitzDistribution::roughness_to_alpha(mid) < alpha { lo = mid; } else { hi = mid; }
+        }
+        let roughness = 0.5 * (lo + hi);
+
+        let raw = highlight(RoughnessTex::Isotropic(Arc::new(ConstantTexture(alpha))), false);
+        let remapped = highlight(RoughnessTex::Isotropic(Arc::new(ConstantTexture(roughness))), true);
+        let anisotropic = highlight(RoughnessTex::Anisotropic {
+            u_rough: Arc::new(ConstantTexture(roughness)),
+            v_rough: Arc::new(ConstantTexture(roughness)),
+        }, true);
+
+        assert!(!raw.is_black());
+        for i in 0..3 {
+            assert!((raw[i] - remapped[i]).abs() < 0.01 * raw[i], "{:?} {:?}", raw, remapped);
+            assert!((raw[i] - anisotropic[i]).abs() < 0.01 * raw[i], "{:?} {:?}", raw, anisotropic);
+        }
+    }
+}
@@ -1,20 +1,33 @@
-use crate::texture::Texture;
+use crate::texture::{Texture, TextureRef};
 use crate::spectrum::Spectrum;
 use std::sync::Arc;
 use crate::material::{Material, TransportMode};
-use crate::SurfaceInteraction;
+use crate::{Float, SurfaceInteraction};
 use bumpalo::Bump;
 use crate::reflection::bsdf::Bsdf;
-use crate::reflection::SpecularReflection;
+use crate::reflection::{SpecularReflection, MicrofacetReflection};
+use crate::reflection::microfacet::TrowbridgeReitzDistribution;
 use crate::fresnel::FresnelNoOp;
 
 pub struct MirrorMaterial {
     reflectance: Arc<dyn Texture<Output = Spectrum>>,
+
+    /// Microfacet roughness for a blurry reflection. Where this is zero (or unset) the mirror is
+    /// perfectly specular.
+    roughness: Option<TextureRef<Float>>,
+
+    remap_roughness: bool,
 }
 
 impl MirrorMaterial {
     pub fn new(reflectance: Arc<dyn Texture<Output = Spectrum>>) -> Self {
-        Self { reflectance }
+        Self { reflectance, roughness: None, remap_roughness: true }
+    }
+
+    pub fn with_roughness(mut self, roughness: TextureRef<Float>, remap_roughness: bool) -> Self {
+        self.roughness = Some(roughness);
+        self.remap_roughness = remap_roughness;
+        self
     }
 }
 
@@ -22,10 +35,49 @@ impl Material for MirrorMaterial {
     fn compute_scattering_functions<'a>(&self, si: &SurfaceInteraction, arena: &'a Bump, mode: TransportMode, allow_multiple_lobes: bool) -> Bsdf<'a> {
         let mut bsdf = Bsdf::new(si, 1.0);
         let r = self.reflectance.evaluate(si).clamp_positive();
-        if !r.is_black() {
-            let reflection = arena.alloc(SpecularReflection::new(r, FresnelNoOp));
-            bsdf.add(reflection);
+        if r.is_black() {
+            return bsdf;
+        }
+
+        let rough = self.roughness.as_ref().map_or(0.0, |rough| rough.evaluate(si));
+        if rough > 0.0 {
+            let alpha = if self.remap_roughness {
+                TrowbridgeReitzDistribution::roughness_to_alpha(rough)
+            } else { rough };
+            let distribution = TrowbridgeReitzDistribution::new(alpha, alpha);
+            bsdf.add(arena.alloc(MicrofacetReflection::new(r, distribution, FresnelNoOp)));
+        } else {
+            bsdf.add(arena.alloc(SpecularReflection::new(r, FresnelNoOp)));
         }
         bsdf
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::sphere_hit;
+    use crate::reflection::BxDFType;
+    use crate::texture::ConstantTexture;
+
+    #[test]
+    fn test_roughness_makes_glossy() {
+        let si = sphere_hit();
+        let arena = Bump::new();
+        let kr = Arc::new(ConstantTexture(Spectrum::uniform(0.9)));
+        let specular = BxDFType::REFLECTION | BxDFType::SPECULAR;
+        let glossy = BxDFType::REFLECTION | BxDFType::GLOSSY;
+
+        let smooth = MirrorMaterial::new(kr.clone())
+            .with_roughness(Arc::new(ConstantTexture(0.0)), true);
+        let bsdf = smooth.compute_scattering_functions(&si, &arena, TransportMode::Radiance, true);
+        assert_eq!(bsdf.num_components(specular), 1);
+        assert_eq!(bsdf.num_components(glossy), 0);
+
+        let rough = MirrorMaterial::new(kr)
+            .with_roughness(Arc::new(ConstantTexture(0.1)), true);
+        let bsdf = rough.compute_scattering_functions(&si, &arena, TransportMode::Radiance, true);
+        assert_eq!(bsdf.num_components(specular), 0);
+        assert_eq!(bsdf.num_components(glossy), 1);
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::{Arc, Once};
+
+use bumpalo::Bump;
+
+use crate::SurfaceInteraction;
+use crate::material::{Material, TransportMode};
+use crate::reflection::bsdf::Bsdf;
+use crate::reflection::ScaledBxDF;
+use crate::spectrum::Spectrum;
+use crate::texture::TextureRef;
+
+/// Blends two materials, weighting the second by `amount` and the first by `1 - amount`.
+pub struct MixMaterial {
+    m1: Arc<dyn Material>,
+    m2: Arc<dyn Material>,
+    amount: TextureRef<Spectrum>,
+}
+
+impl MixMaterial {
+    pub fn new(m1: Arc<dyn Material>, m2: Arc<dyn Material>, amount: TextureRef<Spectrum>) -> Self {
+        Self { m1, m2, amount }
+    }
+}
+
+impl Material for MixMaterial {
+    fn compute_scattering_functions<'a>(&self, si: &SurfaceInteraction, arena: &'a Bump, mode: TransportMode, allow_multiple_lobes: bool) -> Bsdf<'a> {
+        let s2 = self.amount.evaluate(si).clamp(0.0, 1.0);
+        let s1 = (Spectrum::uniform(1.0) - s2).clamp(0.0, 1.0);
+
+        let bsdf1 = self.m1.compute_scattering_functions(si, arena, mode, allow_multiple_lobes);
+        let bsdf2 = self.m2.compute_scattering_functions(si, arena, mode, allow_multiple_lobes);
+
+        let mut bsdf = Bsdf::new(si, bsdf1.eta);
+        // Lobes with zero weight are left out so they don't take part in lobe selection
+        for (child, scale) in [(&bsdf1, s1), (&bsdf2, s2)].iter() {
+            if scale.is_black() {
+                continue;
+            }
+            for &bxdf in child.bxdfs() {
+                // deeply nested mixes can have more lobes than a BSDF holds
+                if !bsdf.try_add(arena.alloc(ScaledBxDF::new(bxdf, *scale))) {
+                    static WARN_TOO_MANY_LOBES: Once = Once::new();
+                    WARN_TOO_MANY_LOBES.call_once(|| {
+                        tracing::warn!("Mix material has too many lobes, dropping the extra ones");
+                    });
+                    return bsdf;
+                }
+            }
+        }
+        bsdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+    use crate::Vec3f;
+    use crate::material::matte::MatteMaterial;
+    use crate::material::plastic::PlasticMaterial;
+    use crate::material::sphere_hit;
+    use crate::reflection::BxDFType;
+    use crate::texture::ConstantTexture;
+
+    #[test]
+    fn test_mix_amount_zero_is_first_material() {
+        let matte: Arc<dyn Material> = Arc::new(MatteMaterial::constant(Spectrum::new([0.8, 0.4, 0.2])));
+        let plastic: Arc<dyn Material> = Arc::new(PlasticMaterial::new(
+            Arc::new(ConstantTexture(Spectrum::uniform(0.25))),
+            Arc::new(ConstantTexture(Spectrum::uniform(0.5))),
+            Arc::new(ConstantTexture(0.1)),
+            true,
+        ));
+        let mix = MixMaterial::new(matte.clone(), plastic, Arc::new(ConstantTexture(Spectrum::uniform(0.0))));
+
+        let si = sphere_hit();
+
+        let arena = Bump::new();
+        let expected = matte.compute_scattering_functions(&si, &arena, TransportMode::Radiance, true);
+        let mixed = mix.compute_scattering_functions(&si, &arena, TransportMode::Radiance, true);
+        assert_eq!(mixed.num_components(BxDFType::all()), expected.num_components(BxDFType::all()));
+
+        let wo = Vec3f::new(0.3, 0.0, -1.0).normalize();
+        let wi = Vec3f::new(-0.5, 0.2, -1.0).normalize();
+        assert_eq!(mixed.f(wo, wi, BxDFType::all()), expected.f(wo, wi, BxDFType::all()));
+        assert_eq!(mixed.pdf(wo, wi, BxDFType::all()), expected.pdf(wo, wi, BxDFType::all()));
+    }
+
+    #[test]
+    fn test_nested_mix_drops_extra_lobes() {
+        // each plastic has a diffuse and a glossy lobe, so five of them don't fit in one BSDF
+        let plastic: Arc<dyn Material> = Arc::new(PlasticMaterial::new(
+            Arc::new(ConstantTexture(Spectrum::uniform(0.25))),
+            Arc::new(ConstantTexture(Spectrum::uniform(0.5))),
+            Arc::new(ConstantTexture(0.1)),
+            true,
+        ));
+        let mut nested = plastic.clone();
+        for _ in 0..4 {
+            let amount = Arc::new(ConstantTexture(Spectrum::uniform(0.5)));
+            nested = Arc::new(MixMaterial::new(nested, plastic.clone(), amount));
+        }
+
+        let si = sphere_hit();
+        let arena = Bump::new();
+        let bsdf = nested.compute_scattering_functions(&si, &arena, TransportMode::Radiance, true);
+        assert_eq!(bsdf.num_components(BxDFType::all()), 8);
+    }
+}
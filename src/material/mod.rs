@@ -7,6 +7,8 @@ pub mod mirror;
 pub mod glass;
 pub mod metal;
 pub mod plastic;
+pub mod mix;
+pub mod fourier;
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum TransportMode {
@@ -22,4 +24,24 @@ pub trait Material: Sync + Send {
         mode: TransportMode,
         allow_multiple_lobes: bool
     ) -> Bsdf<'a>;
+}
+
+/// A ray from z = -5 along +z, which hits the unit sphere at the origin head on.
+#[cfg(test)]
+pub(crate) fn ray_to_unit_sphere() -> crate::Ray {
+    crate::Ray::new(crate::Point3f::new(0.0, 0.0, -5.0), crate::Vec3f::new(0.0, 0.0, 1.0))
+}
+
+/// The point where `ray_to_unit_sphere` hits the unit sphere, for tests that evaluate a material
+/// at a typical surface point.
+#[cfg(test)]
+pub(crate) fn sphere_hit() -> SurfaceInteraction<'static> {
+    use crate::shapes::Shape;
+    use crate::shapes::sphere::Sphere;
+    use once_cell::sync::Lazy;
+
+    static SPHERE: Lazy<Sphere> = Lazy::new(|| {
+        Sphere::whole(crate::Transform::IDENTITY, crate::Transform::IDENTITY, 1.0)
+    });
+    SPHERE.intersect(&ray_to_unit_sphere()).unwrap().1
 }
\ No newline at end of file
@@ -46,4 +46,48 @@ impl Material for PlasticMaterial {
         }
         bsdf
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use cgmath::InnerSpace;
+    use crate::Vec3f;
+    use crate::material::sphere_hit;
+    use crate::reflection::BxDFType;
+    use crate::texture::ConstantTexture;
+
+    fn reflectance(ks: Spectrum, wi: Vec3f) -> Spectrum {
+        let plastic = PlasticMaterial::new(
+            Arc::new(ConstantTexture(Spectrum::uniform(0.5))),
+            Arc::new(ConstantTexture(ks)),
+            // used directly as the microfacet alpha
+            Arc::new(ConstantTexture(0.01)),
+            false,
+        );
+        let si = sphere_hit();
+
+        let arena = Bump::new();
+        let bsdf = plastic.compute_scattering_functions(&si, &arena, TransportMode::Radiance, true);
+        let wo = Vec3f::new(0.3, 0.0, -1.0).normalize();
+        bsdf.f(wo, wi.normalize(), BxDFType::all())
+    }
+
+    #[test]
+    fn test_glossy_highlight() {
+        let mirror = Vec3f::new(-0.3, 0.0, -1.0);
+        let off_specular = Vec3f::new(0.8, 0.5, -1.0);
+        let glossy = reflectance(Spectrum::uniform(0.5), mirror);
+        let matte = reflectance(Spectrum::uniform(0.0), mirror);
+
+        // without a specular lobe, plastic is Lambertian
+        assert!((matte[0] - 0.5 * std::f32::consts::FRAC_1_PI).abs() < 1e-5, "{:?}", matte);
+        assert!(glossy[0] > 10.0 * matte[0], "{:?} {:?}", glossy, matte);
+
+        // and the specular lobe is concentrated around the mirror direction
+        let glossy_off = reflectance(Spectrum::uniform(0.5), off_specular);
+        let matte_off = reflectance(Spectrum::uniform(0.0), off_specular);
+        assert!(glossy_off[0] - matte_off[0] < 0.01, "{:?} {:?}", glossy_off, matte_off);
+    }
+}
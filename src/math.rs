@@ -1,4 +1,4 @@
-use crate::{EFloat, Vec2f, Vec3f};
+use crate::{EFloat, Point2f, Vec2f, Vec3f, spherical_phi, spherical_theta};
 use crate::err_float::MACHINE_EPSILON;
 //use crate::ComponentWiseExt;
 use cgmath::{Matrix2, SquareMatrix, InnerSpace};
@@ -79,10 +79,27 @@ pub fn spherical_direction(sin_theta: Float, cos_theta: Float, phi: Float) -> Ve
     )
 }
 
+/// Maps a direction to (u, v) coordinates in an equirectangular (latitude-longitude) image,
+/// with u going around the z axis with phi and v going from +z to -z with theta.
+pub fn dir_to_equirect_uv(w: Vec3f) -> Point2f {
+    Point2f::new(
+        spherical_phi(w) * (0.5 * std::f32::consts::FRAC_1_PI),
+        spherical_theta(w) * std::f32::consts::FRAC_1_PI
+    )
+}
+
+/// The inverse of `dir_to_equirect_uv`, giving a normalized direction.
+pub fn equirect_uv_to_dir(uv: Point2f) -> Vec3f {
+    let theta = uv.y * std::f32::consts::PI;
+    let phi = uv.x * 2.0 * std::f32::consts::PI;
+    spherical_direction(theta.sin(), theta.cos(), phi)
+}
+
 #[cfg(test)]
 mod test {
     use cgmath::Matrix2;
-    use crate::{Vec2f, solve_linear_system_2x2};
+    use crate::{Point2f, Vec2f, Vec3f, solve_linear_system_2x2, dir_to_equirect_uv, equirect_uv_to_dir};
+    use cgmath::{assert_abs_diff_eq, InnerSpace};
 
     #[test]
     fn test_solve_linear_system() {
@@ -100,4 +117,22 @@ mod test {
 
         assert_eq!(res, Some(Vec2f::new(9.0, -5.0)));
     }
+
+    #[test]
+    fn test_equirect_round_trip() {
+        let dirs = [
+            Vec3f::new(1.0, 0.0, 0.0), Vec3f::new(0.3, -0.7, 0.2), Vec3f::new(-0.5, 0.1, -0.8),
+            Vec3f::new(0.0, 1.0, 0.0),
+        ];
+        for &dir in &dirs {
+            let dir = dir.normalize();
+            let uv = dir_to_equirect_uv(dir);
+            assert!(uv.x >= 0.0 && uv.x <= 1.0 && uv.y >= 0.0 && uv.y <= 1.0);
+            assert_abs_diff_eq!(equirect_uv_to_dir(uv), dir, epsilon = 1e-5);
+        }
+
+        // +z is the top row of the image
+        assert_abs_diff_eq!(dir_to_equirect_uv(Vec3f::new(0.0, 0.0, 1.0)).y, 0.0);
+        assert_abs_diff_eq!(equirect_uv_to_dir(Point2f::new(0.25, 0.5)), Vec3f::new(0.0, 1.0, 0.0), epsilon = 1e-6);
+    }
 }
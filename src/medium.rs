@@ -1,4 +0,0 @@
-
-pub trait Medium {
-    
-}
\ No newline at end of file
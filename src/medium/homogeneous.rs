@@ -0,0 +1,60 @@
+use cgmath::{InnerSpace, Zero};
+
+use crate::{Float, Normal3, Ray, Vec3f};
+use crate::interaction::SurfaceHit;
+use crate::medium::{HenyeyGreenstein, Medium, MediumInteraction};
+use crate::sampler::Sampler;
+use crate::spectrum::Spectrum;
+
+/// A medium with the same absorption and scattering coefficients (per unit distance) everywhere.
+#[derive(Debug)]
+pub struct HomogeneousMedium {
+    sigma_a: Spectrum,
+    sigma_s: Spectrum,
+    sigma_t: Spectrum,
+    g: Float,
+}
+
+impl HomogeneousMedium {
+    pub fn new(sigma_a: Spectrum, sigma_s: Spectrum, g: Float) -> Self {
+        Self { sigma_a, sigma_s, sigma_t: sigma_a + sigma_s, g }
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    fn tr(&self, ray: &Ray, _sampler: &mut dyn Sampler) -> Spectrum {
+        let dist = Float::min(ray.t_max * ray.dir.magnitude(), Float::MAX);
+        (-self.sigma_t * dist).exp()
+    }
+
+    fn sample(&self, ray: &Ray, sampler: &mut dyn Sampler) -> (Spectrum, Option<MediumInteraction>) {
+        // sample a distance with the extinction of a randomly chosen channel, and weight by the
+        // average density over all of them
+        let channel = ((sampler.get_1d() * 3.0) as usize).min(2);
+        let dir_len = ray.dir.magnitude();
+        let dist = -(1.0 - sampler.get_1d()).ln() / self.sigma_t[channel];
+        let t = Float::min(dist / dir_len, ray.t_max);
+        let sampled_medium = t < ray.t_max;
+
+        let tr = (-self.sigma_t * Float::min(t * dir_len, Float::MAX)).exp();
+        let density = if sampled_medium { self.sigma_t * tr } else { tr };
+        let pdf = (density[0] + density[1] + density[2]) / 3.0;
+        let pdf = if pdf == 0.0 { 1.0 } else { pdf };
+
+        if sampled_medium {
+            let interaction = MediumInteraction {
+                hit: SurfaceHit {
+                    p: ray.at(t),
+                    p_err: Vec3f::zero(),
+                    time: ray.time,
+                    n: Normal3(Vec3f::zero()),
+                },
+                wo: -ray.dir / dir_len,
+                phase: HenyeyGreenstein::new(self.g),
+            };
+            (tr * self.sigma_s / pdf, Some(interaction))
+        } else {
+            (tr / pdf, None)
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use cgmath::InnerSpace;
+
+use crate::{coordinate_system, consts, Float, Normal3, Point2f, Ray, Vec3f};
+use crate::interaction::SurfaceHit;
+use crate::sampler::Sampler;
+use crate::spectrum::Spectrum;
+
+pub mod homogeneous;
+
+/// A participating medium that absorbs and scatters light travelling through it.
+pub trait Medium: Sync + Send {
+    /// The fraction of light that travels along `ray` from its origin to `t_max` without being
+    /// absorbed or scattered away.
+    fn tr(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Spectrum;
+
+    /// Samples where along `ray` (up to `t_max`) light is scattered. Returns the interaction if it
+    /// is before `t_max`, or None if the ray passes through to the surface at its end, along with
+    /// the weight to multiply the path throughput by in either case.
+    fn sample(&self, ray: &Ray, sampler: &mut dyn Sampler) -> (Spectrum, Option<MediumInteraction>);
+}
+
+/// A scattering event inside a medium.
+#[derive(Clone, Copy, Debug)]
+pub struct MediumInteraction {
+    /// The scattering point. There is no surface here, so the normal is zero.
+    pub hit: SurfaceHit,
+
+    pub wo: Vec3f,
+
+    pub phase: HenyeyGreenstein,
+}
+
+/// The media on either side of a surface, with "outside" being the side its normal points to.
+/// Surfaces with an interface but no material only mark the boundary of a medium.
+#[derive(Clone, Default)]
+pub struct MediumInterface {
+    pub inside: Option<Arc<dyn Medium>>,
+    pub outside: Option<Arc<dyn Medium>>,
+}
+
+impl MediumInterface {
+    pub fn new(inside: Option<Arc<dyn Medium>>, outside: Option<Arc<dyn Medium>>) -> Self {
+        Self { inside, outside }
+    }
+
+    /// The medium on the side of a surface with normal `n` that `w` points into.
+    pub fn medium(&self, w: Vec3f, n: Normal3) -> Option<&dyn Medium> {
+        let medium = if n.dot(w) > 0.0 { &self.outside } else { &self.inside };
+        medium.as_deref()
+    }
+}
+
+/// The Henyey-Greenstein phase function, where the asymmetry `g` in (-1, 1) is the average cosine
+/// between the incident and scattered directions: positive values scatter light forwards, negative
+/// ones back, and zero is isotropic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HenyeyGreenstein {
+    pub g: Float,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: Float) -> Self {
+        Self { g }
+    }
+
+    /// The phase function for light arriving from `wi` and leaving towards `wo`, where both point
+    /// away from the scattering point.
+    pub fn p(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        phase_hg(wo.dot(wi), self.g)
+    }
+
+    /// Samples an incident direction for `wo` exactly in proportion to the phase function,
+    /// returning it along with its value (which is also its density).
+    pub fn sample_p(&self, wo: Vec3f, u: Point2f) -> (Float, Vec3f) {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u[0]
+        } else {
+            let sqr_term = (1.0 - g * g) / (1.0 + g - 2.0 * g * u[0]);
+            -(1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)
+        };
+
+        let sin_theta = Float::max(0.0, 1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * consts::PI * u[1];
+        let (v1, v2) = coordinate_system(wo);
+        let wi = sin_theta * phi.cos() * v1 + sin_theta * phi.sin() * v2 + cos_theta * wo;
+        (phase_hg(cos_theta, g), wi)
+    }
+}
+
+fn phase_hg(cos_theta: Float, g: Float) -> Float {
+    let denom = 1.0 + g * g + 2.0 * g * cos_theta;
+    consts::FRAC_1_PI / 4.0 * (1.0 - g * g) / (denom * denom.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hg_normalized_and_sampled_consistently() {
+        let wo = Vec3f::new(0.0, 0.0, 1.0);
+        for &g in &[-0.7, 0.0, 0.3, 0.9] {
+            let hg = HenyeyGreenstein::new(g);
+
+            // integrate over the sphere of directions in cos(theta) and phi, where it only depends
+            // on theta
+            let n = 100_000;
+            let integral: Float = (0..n)
+                .map(|i| {
+                    let cos_theta = -1.0 + 2.0 * (i as Float + 0.5) / n as Float;
+                    2.0 * consts::PI * phase_hg(cos_theta, g) * 2.0 / n as Float
+                })
+                .sum();
+            assert!((integral - 1.0).abs() < 1e-3, "g = {}: {}", g, integral);
+
+            // the mean cosine of sampled directions with -wo is g
+            let m = 64;
+            let mut mean_cos = 0.0;
+            for i in 0..m {
+                for j in 0..m {
+                    let u = Point2f::new((i as Float + 0.5) / m as Float, (j as Float + 0.5) / m as Float);
+                    let (p, wi) = hg.sample_p(wo, u);
+                    assert!((wi.magnitude() - 1.0).abs() < 1e-4);
+                    assert!((p - hg.p(wo, wi)).abs() < 1e-3 * p.max(1.0), "{} {}", p, hg.p(wo, wi));
+                    mean_cos += -wi.dot(wo) / (m * m) as Float;
+                }
+            }
+            assert!((mean_cos - g).abs() < 0.02, "g = {}: {}", g, mean_cos);
+        }
+    }
+}
@@ -13,7 +13,13 @@ impl Texel for Spectrum {}
 
 #[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
 pub enum ImageWrap {
-    Repeat, Black, Clamp,
+    Repeat, Black, Clamp, Mirror,
+}
+
+/// Reflects `i` back into `0..size`, so that the image repeats with every other copy flipped.
+fn mirror_index(i: i32, size: i32) -> i32 {
+    let i = i.rem_euclid(2 * size);
+    if i >= size { 2 * size - 1 - i } else { i }
 }
 
 pub struct MIPMap<T> {
@@ -172,6 +178,7 @@ impl<T: Texel> MIPMap<T> {
                             ImageWrap::Repeat => orig_s.rem_euclid(resolution.0 as i32),
                             ImageWrap::Black => orig_s,
                             ImageWrap::Clamp => orig_s.clamp(0, resolution.0 as i32 - 1),
+                            ImageWrap::Mirror => mirror_index(orig_s, resolution.0 as i32),
                         };
 
                         if orig_s >= 0 && orig_s < resolution.0 as i32 {
@@ -192,6 +199,7 @@ impl<T: Texel> MIPMap<T> {
                             ImageWrap::Repeat => orig_t.rem_euclid(resolution.1 as i32),
                             ImageWrap::Black => orig_t,
                             ImageWrap::Clamp => orig_t.clamp(0, resolution.1 as i32 - 1),
+                            ImageWrap::Mirror => mirror_index(orig_t, resolution.1 as i32),
                         };
 
                         if orig_t >= 0 && orig_t < resolution.1 as i32 {
@@ -290,6 +298,18 @@ impl<T: Texel> MIPMap<T> {
         self.resolution
     }
 
+    /// The texel at (s, t) in the full resolution level of the pyramid, after any resampling done
+    /// when building it.
+    pub fn texel_at(&self, s: usize, t: usize) -> T {
+        self.pyramid[0][(s, t)]
+    }
+
+    /// All texels of the full resolution level, in row-major order.
+    pub fn iter_level0(&self) -> impl Iterator<Item=T> + '_ {
+        let level = &self.pyramid[0];
+        (0..level.v_size()).flat_map(move |t| (0..level.u_size()).map(move |s| level[(s, t)]))
+    }
+
     fn texel(&self, level: usize, s: i32, t: i32) -> T {
         Self::get_texel_from_level(&self.pyramid[level], s, t, self.wrap_mode)
     }
@@ -300,6 +320,7 @@ impl<T: Texel> MIPMap<T> {
         let (s, t) = match wrap_mode {
             ImageWrap::Repeat => (s.rem_euclid(s_size), t.rem_euclid(t_size)),
             ImageWrap::Clamp => (s.clamp(0, s_size - 1), t.clamp(0, t_size - 1)),
+            ImageWrap::Mirror => (mirror_index(s, s_size), mirror_index(t, t_size)),
             ImageWrap::Black => {
                 if s < 0 || s >= s_size || t < 0 || t >= t_size {
                     return 0.0.into()
@@ -344,7 +365,7 @@ mod tests {
     use super::*;
     use ndarray::prelude::*;
     use approx::{assert_ulps_eq, assert_relative_eq};
-    use crate::imageio::{get_mipmap, ImageTexInfo, load_image};
+    use crate::imageio::{get_mipmap, ImageTexInfo, ColorSpace, load_image, DecodedImages};
 
     #[test]
     fn test_mipmap_creation() {
@@ -360,6 +381,37 @@ mod tests {
         let _mipmap = MIPMap::new(dims, img, ImageWrap::Repeat);
     }
 
+    #[test]
+    fn test_level0_texels() {
+        let val = 0.5;
+        let mipmap = MIPMap::new_custom((16, 8), vec![val; 16 * 8], ImageWrap::Repeat);
+        assert_eq!(mipmap.texel_at(0, 0), val);
+        assert_eq!(mipmap.texel_at(15, 7), val);
+        assert_eq!(mipmap.iter_level0().count(), 16 * 8);
+        assert!(mipmap.iter_level0().all(|t| t == val));
+
+        // resampled up to a power of two
+        let mipmap = MIPMap::new_custom((20, 10), vec![val; 200], ImageWrap::Repeat);
+        assert_eq!(mipmap.iter_level0().count(), 32 * 16);
+        assert_ulps_eq!(mipmap.texel_at(31, 15), val, max_ulps = 6);
+    }
+
+    #[test]
+    fn test_mirror_wrap() {
+        assert_eq!(mirror_index(-1, 8), 0);
+        assert_eq!(mirror_index(8, 8), 7);
+        assert_eq!(mirror_index(3, 8), 3);
+        assert_eq!(mirror_index(-9, 8), 7);
+        assert_eq!(mirror_index(16, 8), 0);
+
+        let dims = (4, 2);
+        let img: Vec<Float> = (0..8).map(|i| i as Float).collect();
+        let mipmap = MIPMap::new_custom(dims, img, ImageWrap::Mirror);
+        assert_eq!(mipmap.texel(0, -1, 0), mipmap.texel(0, 0, 0));
+        assert_eq!(mipmap.texel(0, 4, 1), mipmap.texel(0, 3, 1));
+        assert_eq!(mipmap.texel(0, 2, -1), mipmap.texel(0, 2, 0));
+    }
+
     #[test]
     fn test_mipmap_lookup() {
         let val = 0.5;
@@ -386,11 +438,11 @@ mod tests {
         let info = ImageTexInfo::new(
             "uvgrid.exr".to_string(),
             ImageWrap::Repeat,
-            1.0,
-            Some(false),
+            Spectrum::uniform(1.0),
+            Some(ColorSpace::Linear),
             false,
         );
-        let mipmap = get_mipmap(info)?;
+        let mipmap = get_mipmap(info, &DecodedImages::new())?;
         let (image, (w, h)) = load_image("uvgrid.exr")?;
 
         for i in 0..w {
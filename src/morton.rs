@@ -13,6 +13,11 @@ pub fn morton3(x: f32, y: f32, z: f32) -> u32 {
 
 }
 
+/// Interleaves the low 16 bits of `x` and `y` into a 2D morton code, with x in the even bits.
+pub fn morton2(x: u32, y: u32) -> u32 {
+    (spread_bits(y) << 1) | spread_bits(x)
+}
+
 fn to_fixed_point(val: f32) -> u32 {
     debug_assert!(0.0 <= val && val < 1.0);
 
@@ -35,6 +40,18 @@ fn expand_bits(val: u32) -> u32 {
     val
 }
 
+/// Expands a 16-bit u32 into 32 bits by inserting a 0 after each bit
+fn spread_bits(val: u32) -> u32 {
+    debug_assert!(val & !0xFFFF == 0);
+    let mut val = val;
+    val = (val | (val << 8)) & 0x00FF00FF;
+    val = (val | (val << 4)) & 0x0F0F0F0F;
+    val = (val | (val << 2)) & 0x33333333;
+    val = (val | (val << 1)) & 0x55555555;
+
+    val
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -47,6 +64,15 @@ mod tests {
         assert_eq!(morton3(x, y, z), 0b00_101101101101101101101101101101);
     }
 
+    #[test]
+    fn test_morton2() {
+        assert_eq!(morton2(0, 0), 0);
+        assert_eq!(morton2(1, 0), 0b01);
+        assert_eq!(morton2(0, 1), 0b10);
+        assert_eq!(morton2(3, 5), 0b100111);
+        assert_eq!(morton2(0xFFFF, 0), 0x55555555);
+    }
+
     #[test]
     fn test_expand_bits() {
         let v = 0x3FF;
@@ -1,40 +1,108 @@
 use std::sync::Arc;
 
-use crate::{Ray, SurfaceInteraction};
+use bumpalo::Bump;
+
+use crate::{AnimatedTransform, Float, Ray, RayShear, SurfaceInteraction};
 use crate::geometry::bounds::Bounds3f;
-use crate::material::Material;
+use crate::material::{Material, TransportMode};
+use crate::reflection::bsdf::Bsdf;
 use crate::shapes::Shape;
 use crate::light::{AreaLight, Light};
 use crate::spectrum::Spectrum;
 use crate::light::diffuse::DiffuseAreaLight;
+use crate::medium::MediumInterface;
 
 pub trait Primitive: Sync {
     fn world_bound(&self) -> Bounds3f;
 
     fn intersect(&self, ray: &mut Ray) -> Option<SurfaceInteraction>;
 
+    /// Like `intersect`, given the ray's precomputed `RayShear` so that accelerators only compute
+    /// it once for all the primitives they test. By default this just calls `intersect`.
+    fn intersect_sheared(&self, ray: &mut Ray, _shear: &RayShear) -> Option<SurfaceInteraction> {
+        self.intersect(ray)
+    }
+
+    /// Like `intersect_sheared`, but only finds the distance to the hit, shortening the ray's
+    /// `t_max` to it. By default this calls `intersect_sheared` and discards the interaction.
+    fn intersect_t(&self, ray: &mut Ray, shear: &RayShear) -> Option<Float> {
+        self.intersect_sheared(ray, shear).map(|_| ray.t_max)
+    }
+
     fn intersect_test(&self, ray: &Ray) -> bool;
 
+    /// Like `intersect_test`, given the ray's precomputed `RayShear`. By default this just calls
+    /// `intersect_test`.
+    fn intersect_test_sheared(&self, ray: &Ray, _shear: &RayShear) -> bool {
+        self.intersect_test(ray)
+    }
+
     fn material(&self) -> Option<&dyn Material>;
 
     fn area_light(&self) -> Option<&dyn AreaLight>;
     
     fn light_arc_cloned(&self) -> Option<Arc<dyn Light>>;
+
+    /// The media on either side of the primitive's surface, if it separates two media. Rays that
+    /// pass through primitives without one stay in the medium they were in.
+    fn medium_interface(&self) -> Option<&MediumInterface> { None }
+
+    /// Builds the BSDF at `si` using the primitive's material. Primitives without a material, such
+    /// as pure emitters, get a BSDF with no components.
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &SurfaceInteraction,
+        arena: &'a Bump,
+        mode: TransportMode,
+        allow_multiple_lobes: bool
+    ) -> Bsdf<'a> {
+        match self.material() {
+            Some(material) => material.compute_scattering_functions(si, arena, mode, allow_multiple_lobes),
+            None => Bsdf::new(si, 1.0),
+        }
+    }
 }
 
 pub struct GeometricPrimitive<S: Shape> {
     pub shape: Arc<S>,
     pub material: Option<Arc<dyn Material>>,
     pub light: Option<Arc<DiffuseAreaLight<S>>>,
+    pub medium_interface: Option<MediumInterface>,
 }
 
 impl<S: Shape> GeometricPrimitive<S> {
+    /// A primitive that doesn't emit light or separate media.
+    pub fn new(shape: Arc<S>, material: Option<Arc<dyn Material>>) -> Self {
+        Self { shape, material, light: None, medium_interface: None }
+    }
+
+    /// Sets the media on either side of the primitive's surface. Media can only be set up through
+    /// the API; the pbrt loader doesn't support them.
+    pub fn with_medium_interface(mut self, medium_interface: MediumInterface) -> Self {
+        self.medium_interface = Some(medium_interface);
+        self
+    }
+
+    /// A primitive that emits `emit` from its surface with a one-sided diffuse area light, taking
+    /// `n_samples` light samples.
+    pub fn with_area_light(
+        shape: Arc<S>,
+        material: Option<Arc<dyn Material>>,
+        emit: Spectrum,
+        n_samples: usize
+    ) -> Self {
+        let mut prim = Self::new(shape, material);
+        prim.set_emitter(emit, n_samples);
+        prim
+    }
+
     pub fn set_emitter(&mut self, emit: Spectrum, n_samples: usize) {
         // FIXME: transform
         let light = DiffuseAreaLight::new(
             emit,
             self.shape.clone(),
             n_samples,
+            false,
         );
         self.light = Some(Arc::new(light))
     }
@@ -53,10 +121,28 @@ impl<S: 'static +  Shape> Primitive for GeometricPrimitive<S> {
         Some(intersect)
     }
 
+    fn intersect_sheared(&self, ray: &mut Ray, shear: &RayShear) -> Option<SurfaceInteraction> {
+        let (t_hit, mut intersect) = self.shape.intersect_sheared(ray, shear)?;
+
+        ray.t_max = t_hit;
+        intersect.primitive = Some(self);
+        Some(intersect)
+    }
+
+    fn intersect_t(&self, ray: &mut Ray, shear: &RayShear) -> Option<Float> {
+        let t_hit = self.shape.intersect_t(ray, shear)?;
+        ray.t_max = t_hit;
+        Some(t_hit)
+    }
+
     fn intersect_test(&self, ray: &Ray) -> bool {
         self.shape.intersect_test(ray)
     }
 
+    fn intersect_test_sheared(&self, ray: &Ray, shear: &RayShear) -> bool {
+        self.shape.intersect_test_sheared(ray, shear)
+    }
+
     fn material(&self) -> Option<&dyn Material> {
         self.material.as_ref().map(|m| m.as_ref()) // ugly?
     }
@@ -68,4 +154,144 @@ impl<S: 'static +  Shape> Primitive for GeometricPrimitive<S> {
     fn light_arc_cloned(&self) -> Option<Arc<dyn Light>> {
         self.light.as_ref().map(|l| l.clone() as Arc<dyn Light>)
     }
-}
\ No newline at end of file
+
+    fn medium_interface(&self) -> Option<&MediumInterface> {
+        self.medium_interface.as_ref()
+    }
+}
+
+/// A primitive placed in the world by a possibly animated transform, for instancing and object
+/// motion blur.
+pub struct TransformedPrimitive {
+    pub primitive: Box<dyn Primitive>,
+    pub prim_to_world: AnimatedTransform,
+}
+
+impl TransformedPrimitive {
+    pub fn new(primitive: Box<dyn Primitive>, prim_to_world: AnimatedTransform) -> Self {
+        Self { primitive, prim_to_world }
+    }
+}
+
+impl Primitive for TransformedPrimitive {
+    fn world_bound(&self) -> Bounds3f {
+        self.prim_to_world.motion_bounds(self.primitive.world_bound())
+    }
+
+    fn intersect(&self, ray: &mut Ray) -> Option<SurfaceInteraction> {
+        let prim_to_world = self.prim_to_world.interpolate(ray.time);
+        let mut prim_ray = prim_to_world.inverse().transform(*ray);
+        let isect = self.primitive.intersect(&mut prim_ray)?;
+        ray.t_max = prim_ray.t_max;
+        Some(prim_to_world.transform(isect))
+    }
+
+    fn intersect_test(&self, ray: &Ray) -> bool {
+        let prim_to_world = self.prim_to_world.interpolate(ray.time);
+        self.primitive.intersect_test(&prim_to_world.inverse().transform(*ray))
+    }
+
+    // the interactions returned by `intersect` refer to the inner primitive, which has the material
+    // and area light
+    fn material(&self) -> Option<&dyn Material> {
+        None
+    }
+
+    fn area_light(&self) -> Option<&dyn AreaLight> {
+        None
+    }
+
+    fn light_arc_cloned(&self) -> Option<Arc<dyn Light>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::sphere::Sphere;
+    use crate::{Point3f, Transform, Vec3f};
+    use crate::material::matte::MatteMaterial;
+    use crate::material::ray_to_unit_sphere;
+    use crate::reflection::BxDFType;
+
+    #[test]
+    fn test_with_area_light_is_emitter() {
+        let shape = Arc::new(Sphere::whole(Transform::identity(), Transform::identity(), 1.0));
+        let prim = GeometricPrimitive::with_area_light(shape, None, Spectrum::uniform(2.0), 4);
+
+        let light = prim.light_arc_cloned().expect("primitive should be an emitter");
+        assert_eq!(light.n_samples(), 4);
+
+        // estimate_direct identifies the emitter hit by a ray by comparing data pointers
+        let area_light = prim.area_light().unwrap();
+        assert!(std::ptr::eq(
+            area_light.as_light() as *const dyn Light as *const u8,
+            light.as_ref() as *const dyn Light as *const u8
+        ));
+    }
+
+    #[test]
+    fn test_le() {
+        let shape = Arc::new(Sphere::whole(Transform::identity(), Transform::identity(), 1.0));
+        let emitter = GeometricPrimitive::with_area_light(shape.clone(), None, Spectrum::uniform(2.0), 1);
+        let plain = GeometricPrimitive::new(shape, None);
+
+        let mut ray = ray_to_unit_sphere();
+        let si = emitter.intersect(&mut ray).unwrap();
+        assert_eq!(si.le(-ray.dir), Spectrum::uniform(2.0));
+        assert!(emitter.area_light().unwrap().is_same_light(emitter.light_arc_cloned().unwrap().as_ref()));
+
+        let mut ray = ray_to_unit_sphere();
+        let si = plain.intersect(&mut ray).unwrap();
+        assert!(si.le(-ray.dir).is_black());
+    }
+
+    #[test]
+    fn test_compute_scattering_functions() {
+        let shape = Arc::new(Sphere::whole(Transform::identity(), Transform::identity(), 1.0));
+        let matte = GeometricPrimitive::new(
+            shape.clone(),
+            Some(Arc::new(MatteMaterial::constant(Spectrum::uniform(0.5))) as Arc<dyn Material>),
+        );
+        let emitter = GeometricPrimitive::with_area_light(shape, None, Spectrum::uniform(1.0), 1);
+
+        let arena = Bump::new();
+        for (prim, n_components) in [(&matte as &dyn Primitive, 1), (&emitter as &dyn Primitive, 0)].iter() {
+            let mut ray = ray_to_unit_sphere();
+            let si = prim.intersect(&mut ray).unwrap();
+            let bsdf = prim.compute_scattering_functions(&si, &arena, TransportMode::Radiance, true);
+            assert_eq!(bsdf.num_components(BxDFType::all()), *n_components);
+        }
+    }
+
+    #[test]
+    fn test_animated_sphere() {
+        let shape = Arc::new(Sphere::whole(Transform::identity(), Transform::identity(), 1.0));
+        let sphere = GeometricPrimitive::new(shape, None);
+        let motion = AnimatedTransform::new(
+            Transform::identity(), 0.0,
+            Transform::translate(Vec3f::new(4.0, 0.0, 0.0)), 1.0
+        );
+        let prim = TransformedPrimitive::new(Box::new(sphere), motion);
+
+        let hit_x = |time| {
+            let mut ray = Ray::new(Point3f::new(-10.0, 0.0, 0.0), Vec3f::new(1.0, 0.0, 0.0));
+            ray.time = time;
+            let isect = prim.intersect(&mut ray).unwrap();
+            isect.hit.p.x
+        };
+        assert!((hit_x(0.0) - -1.0).abs() < 1e-4);
+        assert!((hit_x(1.0) - 3.0).abs() < 1e-4);
+        assert!((hit_x(0.5) - 1.0).abs() < 1e-4);
+
+        // a ray along the sphere's path misses it at the start of the shutter interval
+        let mut ray = Ray::new(Point3f::new(4.0, -10.0, 0.0), Vec3f::new(0.0, 1.0, 0.0));
+        assert!(prim.intersect(&mut ray).is_none());
+        ray.time = 1.0;
+        assert!(prim.intersect_test(&ray));
+
+        let bound = prim.world_bound();
+        assert!(bound.min.x <= -1.0 && bound.max.x >= 5.0);
+    }
+}
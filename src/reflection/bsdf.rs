@@ -4,6 +4,7 @@ use crate::reflection::{BxDF, BxDFType, ScatterSample};
 use crate::interaction::SurfaceInteraction;
 use cgmath::InnerSpace;
 use crate::spectrum::Spectrum;
+use crate::sampler::Sampler;
 
 pub struct Bsdf<'a> {
 
@@ -49,6 +50,15 @@ impl<'a> Bsdf<'a> {
         self.bxdfs.push(bxdf);
     }
 
+    /// Adds `bxdf` if there's room for it, returning whether it was added.
+    pub fn try_add(&mut self, bxdf: &'a dyn BxDF) -> bool {
+        self.bxdfs.try_push(bxdf).is_ok()
+    }
+
+    pub fn bxdfs(&self) -> &[&'a dyn BxDF] {
+        self.bxdfs.as_slice()
+    }
+
     pub fn num_components(&self, flags: BxDFType) -> usize {
         self.bxdfs.as_slice().iter().filter(|bxdf| bxdf.matches_flags(flags)).count()
     }
@@ -146,4 +156,53 @@ impl<'a> Bsdf<'a> {
     pub fn iter_matching(&self, flags: BxDFType) -> impl Iterator<Item=&& dyn BxDF> + '_ {
         self.bxdfs.as_slice().iter().filter(move |bxdf| bxdf.matches_flags(flags))
     }
+
+    /// The hemispherical-directional reflectance of the matching components in direction
+    /// `wo_world`. See `BxDF::rho`.
+    pub fn rho(&self, wo_world: Vec3f, samples: &[Point2f], flags: BxDFType) -> Spectrum {
+        let wo = self.world_to_local(wo_world);
+        self.iter_matching(flags).map(|bxdf| bxdf.rho(wo, samples)).sum()
+    }
+
+    /// Reflectance of the non-specular components in direction `wo_world`, estimated with a few
+    /// samples from `sampler`. Suitable for an albedo buffer for denoising.
+    pub fn albedo(&self, wo_world: Vec3f, sampler: &mut dyn Sampler) -> Spectrum {
+        const N_SAMPLES: usize = 16;
+        let samples: ArrayVec<[Point2f; N_SAMPLES]> = (0..N_SAMPLES).map(|_| sampler.get_2d()).collect();
+        let non_specular = BxDFType::REFLECTION | BxDFType::TRANSMISSION | BxDFType::DIFFUSE | BxDFType::GLOSSY;
+        self.rho(wo_world, &samples, non_specular)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bumpalo::Bump;
+    use crate::material::{Material, TransportMode, sphere_hit};
+    use crate::material::matte::MatteMaterial;
+    use crate::material::mirror::MirrorMaterial;
+    use crate::sampler::random::RandomSampler;
+    use crate::texture::ConstantTexture;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_albedo() {
+        let si = sphere_hit();
+        let arena = Bump::new();
+        let mut sampler = RandomSampler::new_with_seed(1, 0);
+        let wo = Vec3f::new(0.2, 0.1, -1.0).normalize();
+
+        let diffuse = Spectrum::new([0.2, 0.5, 0.8]);
+        let matte = MatteMaterial::constant(diffuse);
+        let bsdf = matte.compute_scattering_functions(&si, &arena, TransportMode::Radiance, true);
+        let albedo = bsdf.albedo(wo, &mut sampler);
+        for i in 0..3 {
+            assert!((albedo[i] - diffuse[i]).abs() < 0.01, "{:?}", albedo);
+        }
+
+        // specular lobes don't contribute
+        let mirror = MirrorMaterial::new(Arc::new(ConstantTexture(Spectrum::uniform(0.9))));
+        let bsdf = mirror.compute_scattering_functions(&si, &arena, TransportMode::Radiance, true);
+        assert!(bsdf.albedo(wo, &mut sampler).is_black());
+    }
 }
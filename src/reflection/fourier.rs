@@ -0,0 +1,653 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use arrayvec::ArrayVec;
+use cgmath::InnerSpace;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::{consts, Float, Point2f, Vec3f};
+use crate::material::TransportMode;
+use crate::reflection::{BxDF, BxDFType, ScatterSample, cos_theta, sin2_theta};
+use crate::sampling::search_sorted;
+use crate::spectrum::Spectrum;
+
+const HEADER: &[u8; 8] = b"SCATFUN\x01";
+
+/// The most coefficients, over all channels, that a table may store for a `(mu_i, mu_o)` pair, so
+/// that they can be interpolated on the stack.
+const MAX_COEFFS: usize = 4096;
+
+/// A tabulated BSDF in the format used by pbrt's `.bsdf` files. For each pair of zenith angle
+/// cosines `(mu_i, mu_o)` the table stores the coefficients of a cosine series in the azimuth
+/// difference between the two directions. Coefficients are interpolated between the `mu` nodes with
+/// Catmull-Rom splines.
+#[derive(Debug, Default)]
+pub struct FourierBSDFTable {
+    /// Relative index of refraction of the surface.
+    pub eta: Float,
+
+    /// The largest number of coefficients stored for any `(mu_i, mu_o)` pair.
+    pub m_max: usize,
+
+    /// Either 1 (luminance) or 3 (luminance, red and blue).
+    pub n_channels: usize,
+
+    /// The zenith angle cosines, in increasing order from -1 to 1.
+    pub mu: Vec<Float>,
+
+    /// The number of coefficients stored for each `(mu_i, mu_o)` pair, indexed `o * n_mu + i`.
+    pub m: Vec<usize>,
+
+    /// The offset into `a` of the coefficients of each `(mu_i, mu_o)` pair.
+    pub a_offset: Vec<usize>,
+
+    /// The coefficients of all pairs. A pair with `m` coefficients stores `m` for each channel.
+    pub a: Vec<Float>,
+
+    /// The first (constant) luminance coefficient of each pair.
+    pub a0: Vec<Float>,
+
+    /// For each `mu_o`, the running integral of `a0` over `mu_i`, used to sample `mu_i`.
+    pub cdf: Vec<Float>,
+
+    /// Reciprocals `1 / k` used when integrating the cosine series.
+    pub recip: Vec<Float>,
+}
+
+impl FourierBSDFTable {
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::from_reader(&mut reader)
+            .map_err(|e| anyhow::anyhow!("Error reading Fourier BSDF table {:?}: {}", path, e))
+    }
+
+    /// Only the subset of files pbrt supports is handled: monochromatic or RGB tables with a
+    /// single basis.
+    pub fn from_reader(reader: &mut impl Read) -> anyhow::Result<Self> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        if &header != HEADER {
+            anyhow::bail!("Not a Fourier BSDF file");
+        }
+
+        let flags = read_i32(reader)?;
+        let n_mu = read_i32(reader)? as usize;
+        let n_coeffs = read_i32(reader)? as usize;
+        let m_max = read_i32(reader)? as usize;
+        let n_channels = read_i32(reader)? as usize;
+        let n_bases = read_i32(reader)?;
+        for _ in 0..3 {
+            read_i32(reader)?;
+        }
+        let eta = Float::from_bits(read_i32(reader)? as u32);
+        for _ in 0..4 {
+            read_i32(reader)?;
+        }
+
+        if flags != 1 || (n_channels != 1 && n_channels != 3) || n_bases != 1 {
+            anyhow::bail!("Unsupported Fourier BSDF file (flags {}, {} channels, {} bases)", flags, n_channels, n_bases);
+        }
+        if n_mu < 2 {
+            anyhow::bail!("Fourier BSDF table needs at least 2 zenith angles, found {}", n_mu);
+        }
+        if m_max * n_channels > MAX_COEFFS {
+            anyhow::bail!("Fourier BSDF table has {} coefficients per channel, at most {} are supported", m_max, MAX_COEFFS / n_channels);
+        }
+
+        let mu = read_floats(reader, n_mu)?;
+        let cdf = read_floats(reader, n_mu * n_mu)?;
+        let offset_and_length = (0..2 * n_mu * n_mu)
+            .map(|_| Ok(read_i32(reader)? as usize))
+            .collect::<anyhow::Result<Vec<usize>>>()?;
+        let a = read_floats(reader, n_coeffs)?;
+
+        let a_offset: Vec<usize> = offset_and_length.iter().step_by(2).copied().collect();
+        let m: Vec<usize> = offset_and_length.iter().skip(1).step_by(2).copied().collect();
+        // every pair's coefficients must lie within the table so that lookups can't go out of
+        // bounds
+        for (&offset, &m) in a_offset.iter().zip(&m) {
+            if m > m_max || offset + m * n_channels > a.len() {
+                anyhow::bail!(
+                    "Fourier BSDF coefficients at offset {} with length {} don't fit in the table of {} (maximum length {})",
+                    offset, m, a.len(), m_max
+                );
+            }
+        }
+        if mu.windows(2).any(|w| !(w[0] < w[1])) {
+            anyhow::bail!("Fourier BSDF zenith angles must be increasing");
+        }
+        Ok(Self::new(eta, m_max, n_channels, mu, m, a_offset, a, cdf))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        eta: Float,
+        m_max: usize,
+        n_channels: usize,
+        mu: Vec<Float>,
+        m: Vec<usize>,
+        a_offset: Vec<usize>,
+        a: Vec<Float>,
+        cdf: Vec<Float>,
+    ) -> Self {
+        let a0 = m.iter().zip(&a_offset)
+            .map(|(&m, &offset)| if m > 0 { a[offset] } else { 0.0 })
+            .collect();
+        let recip = (0..m_max).map(|k| 1.0 / k as Float).collect();
+        Self { eta, m_max, n_channels, mu, m, a_offset, a, a0, cdf, recip }
+    }
+
+    pub fn n_mu(&self) -> usize {
+        self.mu.len()
+    }
+
+    /// The coefficients for the node pair `(offset_i, offset_o)`, for all channels.
+    fn get_ak(&self, offset_i: usize, offset_o: usize) -> (&[Float], usize) {
+        let idx = offset_o * self.n_mu() + offset_i;
+        let m = self.m[idx];
+        let offset = self.a_offset[idx];
+        (&self.a[offset..offset + m * self.n_channels], m)
+    }
+
+    /// Interpolates the coefficients of each channel for `(mu_i, mu_o)`, returning them laid out
+    /// as `m_max` entries per channel along with the number of coefficients actually used. Returns
+    /// `None` if either cosine lies outside the table.
+    fn interpolate_ak(&self, mu_i: Float, mu_o: Float, n_channels: usize) -> Option<(ArrayVec<[Float; MAX_COEFFS]>, usize)> {
+        let (offset_i, weights_i) = catmull_rom_weights(&self.mu, mu_i)?;
+        let (offset_o, weights_o) = catmull_rom_weights(&self.mu, mu_o)?;
+
+        let mut ak = ArrayVec::new();
+        ak.extend(std::iter::repeat(0.0).take(self.m_max * n_channels));
+        let mut m_max = 0;
+        for (b, &weight_o) in weights_o.iter().enumerate() {
+            for (a, &weight_i) in weights_i.iter().enumerate() {
+                let weight = weight_i * weight_o;
+                if weight == 0.0 {
+                    continue;
+                }
+                let (ap, m) = self.get_ak((offset_i + a as isize) as usize, (offset_o + b as isize) as usize);
+                m_max = m_max.max(m);
+                for c in 0..n_channels {
+                    for k in 0..m {
+                        ak[c * self.m_max + k] += weight * ap[c * m + k];
+                    }
+                }
+            }
+        }
+        Some((ak, m_max))
+    }
+}
+
+fn read_i32(reader: &mut impl Read) -> std::io::Result<i32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_floats(reader: &mut impl Read, n: usize) -> std::io::Result<Vec<Float>> {
+    (0..n).map(|_| Ok(Float::from_bits(read_i32(reader)? as u32))).collect()
+}
+
+/// Loads the table at `path`, or returns the one already loaded from it. Tables are kept for the
+/// life of the program so that materials and the BxDFs they create can borrow them.
+pub fn get_fourier_table(path: impl Into<PathBuf>) -> anyhow::Result<&'static FourierBSDFTable> {
+    // Global cache of tables that have been loaded.
+    static TABLES: Lazy<Mutex<HashMap<PathBuf, &'static FourierBSDFTable>>> = Lazy::new(|| {
+        Mutex::new(HashMap::new())
+    });
+
+    let mut cache = TABLES.lock();
+    match cache.entry(path.into()) {
+        Entry::Occupied(e) => {
+            Ok(*e.get())
+        },
+        Entry::Vacant(e) => {
+            let table = FourierBSDFTable::read(e.key())?;
+            Ok(*e.insert(Box::leak(Box::new(table))))
+        },
+    }
+}
+
+#[derive(Debug)]
+pub struct FourierBSDF<'a> {
+    table: &'a FourierBSDFTable,
+    mode: TransportMode,
+}
+
+impl<'a> FourierBSDF<'a> {
+    pub fn new(table: &'a FourierBSDFTable, mode: TransportMode) -> Self {
+        Self { table, mode }
+    }
+
+    /// Converts the evaluated series for `(mu_i, mu_o)` to a spectrum, dividing out the cosine
+    /// factor that's included in the tabulated values.
+    fn to_spectrum(&self, ak: &[Float], m: usize, mu_i: Float, mu_o: Float, y: Float, cos_phi: Float) -> Spectrum {
+        let mut scale = if mu_i != 0.0 { 1.0 / mu_i.abs() } else { 0.0 };
+
+        // account for the non-symmetry of transmission under radiance transport
+        if self.mode == TransportMode::Radiance && mu_i * mu_o > 0.0 {
+            let eta = if mu_i > 0.0 { 1.0 / self.table.eta } else { self.table.eta };
+            scale *= eta * eta;
+        }
+
+        if self.table.n_channels == 1 {
+            Spectrum::uniform(y * scale)
+        } else {
+            let r = fourier(&ak[self.table.m_max..], m, cos_phi);
+            let b = fourier(&ak[2 * self.table.m_max..], m, cos_phi);
+            let g = 1.39829 * y - 0.100913 * b - 0.297375 * r;
+            Spectrum::new([r * scale, g * scale, b * scale]).clamp_positive()
+        }
+    }
+}
+
+impl<'a> BxDF for FourierBSDF<'a> {
+    fn get_type(&self) -> BxDFType {
+        BxDFType::REFLECTION | BxDFType::TRANSMISSION | BxDFType::GLOSSY
+    }
+
+    fn f(&self, wo: Vec3f, wi: Vec3f) -> Spectrum {
+        let mu_i = cos_theta(-wi);
+        let mu_o = cos_theta(wo);
+        let cos_phi = cos_d_phi(-wi, wo);
+
+        let (ak, m) = match self.table.interpolate_ak(mu_i, mu_o, self.table.n_channels) {
+            Some(ak) => ak,
+            None => return Spectrum::uniform(0.0),
+        };
+        let y = Float::max(0.0, fourier(&ak, m, cos_phi));
+        self.to_spectrum(&ak, m, mu_i, mu_o, y, cos_phi)
+    }
+
+    fn sample_f(&self, wo: Vec3f, sample: Point2f) -> Option<ScatterSample> {
+        // sample the zenith angle of the incident direction from the interpolated a0 ...
+        let mu_o = cos_theta(wo);
+        let (mu_i, pdf_mu) = sample_catmull_rom_2d(
+            &self.table.mu, &self.table.mu, &self.table.a0, &self.table.cdf, mu_o, sample[1]
+        )?;
+
+        // ... then the azimuth from the luminance series
+        let (ak, m) = self.table.interpolate_ak(mu_i, mu_o, self.table.n_channels)?;
+        let (y, pdf_phi, phi) = sample_fourier(&ak, &self.table.recip, m, sample[0]);
+        let pdf = Float::max(0.0, pdf_phi * pdf_mu);
+
+        let sin2_theta_i = Float::max(0.0, 1.0 - mu_i * mu_i);
+        let mut norm = Float::sqrt(sin2_theta_i / sin2_theta(wo));
+        if norm.is_infinite() {
+            norm = 0.0;
+        }
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        // renormalize, since rounding error can otherwise accumulate over repeated bounces
+        let wi = -Vec3f::new(
+            norm * (cos_phi * wo.x - sin_phi * wo.y),
+            norm * (sin_phi * wo.x + cos_phi * wo.y),
+            mu_i
+        ).normalize();
+
+        let f = self.to_spectrum(&ak, m, mu_i, mu_o, y, cos_phi);
+        Some(ScatterSample {
+            f,
+            wi,
+            pdf,
+            sampled_type: self.get_type(),
+        })
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        let mu_i = cos_theta(-wi);
+        let mu_o = cos_theta(wo);
+        let cos_phi = cos_d_phi(-wi, wo);
+
+        let (ak, m) = match self.table.interpolate_ak(mu_i, mu_o, 1) {
+            Some(ak) => ak,
+            None => return 0.0,
+        };
+
+        // the total of the interpolated a0 over mu_i, for this mu_o
+        let n_mu = self.table.n_mu();
+        let (offset_o, weights_o) = match catmull_rom_weights(&self.table.mu, mu_o) {
+            Some(weights) => weights,
+            None => return 0.0,
+        };
+        let rho: Float = weights_o.iter().enumerate()
+            .filter(|(_, &weight)| weight != 0.0)
+            .map(|(o, &weight)| {
+                let row = (offset_o + o as isize) as usize;
+                weight * self.table.cdf[row * n_mu + n_mu - 1] * (2.0 * consts::PI)
+            })
+            .sum();
+
+        let y = fourier(&ak, m, cos_phi);
+        if rho > 0.0 && y > 0.0 { y / rho } else { 0.0 }
+    }
+}
+
+/// The cosine of the azimuth angle between `wa` and `wb`.
+fn cos_d_phi(wa: Vec3f, wb: Vec3f) -> Float {
+    let waxy = wa.x * wa.x + wa.y * wa.y;
+    let wbxy = wb.x * wb.x + wb.y * wb.y;
+    if waxy == 0.0 || wbxy == 0.0 {
+        return 1.0;
+    }
+    ((wa.x * wb.x + wa.y * wb.y) / Float::sqrt(waxy * wbxy)).clamp(-1.0, 1.0)
+}
+
+/// Evaluates the cosine series `sum a[k] cos(k phi)` for the first `m` coefficients, using the
+/// recurrence `cos(k phi) = 2 cos(phi) cos((k - 1) phi) - cos((k - 2) phi)`.
+pub fn fourier(a: &[Float], m: usize, cos_phi: Float) -> Float {
+    let cos_phi = cos_phi as f64;
+    let mut value = 0.0f64;
+    let mut cos_k_minus_one_phi = cos_phi;
+    let mut cos_k_phi = 1.0f64;
+    for &ak in &a[..m] {
+        value += ak as f64 * cos_k_phi;
+        let cos_k_plus_one_phi = 2.0 * cos_phi * cos_k_phi - cos_k_minus_one_phi;
+        cos_k_minus_one_phi = cos_k_phi;
+        cos_k_phi = cos_k_plus_one_phi;
+    }
+    value as Float
+}
+
+/// Samples `phi` in `[0, 2 pi)` proportionally to the cosine series with coefficients `ak`.
+/// Returns the value of the series at `phi`, the pdf, and `phi`.
+fn sample_fourier(ak: &[Float], recip: &[Float], m: usize, u: Float) -> (Float, Float, Float) {
+    // the series is symmetric about pi, so sample one half and mirror
+    let flip = u >= 0.5;
+    let u = if flip { 1.0 - 2.0 * (u - 0.5) } else { u * 2.0 };
+    let u = u as f64;
+
+    let pi = std::f64::consts::PI;
+    let (mut a, mut b) = (0.0f64, pi);
+    let mut phi = 0.5 * pi;
+    let mut f;
+    loop {
+        // evaluate the integral F(phi) and its derivative f(phi) with sine and cosine recurrences
+        let cos_phi = phi.cos();
+        let sin_phi = f64::max(0.0, 1.0 - cos_phi * cos_phi).sqrt();
+        let (mut cos_phi_prev, mut cos_phi_cur) = (cos_phi, 1.0f64);
+        let (mut sin_phi_prev, mut sin_phi_cur) = (-sin_phi, 0.0f64);
+
+        let mut integral = ak[0] as f64 * phi;
+        f = ak[0] as f64;
+        for k in 1..m {
+            let sin_phi_next = 2.0 * cos_phi * sin_phi_cur - sin_phi_prev;
+            let cos_phi_next = 2.0 * cos_phi * cos_phi_cur - cos_phi_prev;
+            sin_phi_prev = sin_phi_cur;
+            sin_phi_cur = sin_phi_next;
+            cos_phi_prev = cos_phi_cur;
+            cos_phi_cur = cos_phi_next;
+
+            integral += ak[k] as f64 * recip[k] as f64 * sin_phi_next;
+            f += ak[k] as f64 * cos_phi_next;
+        }
+        integral -= u * ak[0] as f64 * pi;
+
+        if integral > 0.0 { b = phi; } else { a = phi; }
+        if integral.abs() < 1e-6 || b - a < 1e-6 {
+            break;
+        }
+
+        // Newton step, falling back to bisection if it leaves the bracket
+        phi -= integral / f;
+        if !(phi > a && phi < b) {
+            phi = 0.5 * (a + b);
+        }
+    }
+
+    if flip {
+        phi = 2.0 * pi - phi;
+    }
+    let pdf = f / (2.0 * pi * ak[0] as f64);
+    (f as Float, pdf as Float, phi as Float)
+}
+
+/// Computes the offset of the first of the four spline nodes around `x` and their weights, or
+/// `None` if `x` is outside of `nodes`. The offset may be -1, in which case the first weight is 0.
+pub fn catmull_rom_weights(nodes: &[Float], x: Float) -> Option<(isize, [Float; 4])> {
+    let size = nodes.len();
+    if !(x >= nodes[0] && x <= nodes[size - 1]) {
+        return None;
+    }
+
+    let idx = search_sorted(size, |i| nodes[i] <= x);
+    let offset = idx as isize - 1;
+    let x0 = nodes[idx];
+    let x1 = nodes[idx + 1];
+
+    let t = (x - x0) / (x1 - x0);
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    let mut weights = [0.0; 4];
+    weights[1] = 2.0 * t3 - 3.0 * t2 + 1.0;
+    weights[2] = -2.0 * t3 + 3.0 * t2;
+
+    // the derivative at an end node is approximated with a one-sided difference
+    if idx > 0 {
+        let w0 = (t3 - 2.0 * t2 + t) * (x1 - x0) / (x1 - nodes[idx - 1]);
+        weights[0] = -w0;
+        weights[2] += w0;
+    } else {
+        let w0 = t3 - 2.0 * t2 + t;
+        weights[0] = 0.0;
+        weights[1] -= w0;
+        weights[2] += w0;
+    }
+
+    if idx + 2 < size {
+        let w3 = (t3 - t2) * (x1 - x0) / (nodes[idx + 2] - x0);
+        weights[1] -= w3;
+        weights[3] = w3;
+    } else {
+        let w3 = t3 - t2;
+        weights[1] -= w3;
+        weights[2] += w3;
+        weights[3] = 0.0;
+    }
+    Some((offset, weights))
+}
+
+/// Samples the second dimension of a 2D spline-interpolated function tabulated at `nodes1` x
+/// `nodes2`, given `alpha` in the first dimension. `cdf` holds the running integrals of each row of
+/// `values`. Returns the sampled position and its pdf.
+fn sample_catmull_rom_2d(
+    nodes1: &[Float],
+    nodes2: &[Float],
+    values: &[Float],
+    cdf: &[Float],
+    alpha: Float,
+    u: Float,
+) -> Option<(Float, Float)> {
+    let size2 = nodes2.len();
+    let (offset, weights) = catmull_rom_weights(nodes1, alpha)?;
+    let interpolate = |array: &[Float], idx: usize| -> Float {
+        weights.iter().enumerate()
+            .filter(|(_, &weight)| weight != 0.0)
+            .map(|(i, &weight)| array[(offset + i as isize) as usize * size2 + idx] * weight)
+            .sum()
+    };
+
+    // find the spline segment by inverting the interpolated cdf
+    let maximum = interpolate(cdf, size2 - 1);
+    let u = u * maximum;
+    let idx = search_sorted(size2, |i| interpolate(cdf, i) <= u);
+
+    let f0 = interpolate(values, idx);
+    let f1 = interpolate(values, idx + 1);
+    let x0 = nodes2[idx];
+    let x1 = nodes2[idx + 1];
+    let width = x1 - x0;
+    let u = (u - interpolate(cdf, idx)) / width;
+
+    let d0 = if idx > 0 {
+        width * (f1 - interpolate(values, idx - 1)) / (x1 - nodes2[idx - 1])
+    } else {
+        f1 - f0
+    };
+    let d1 = if idx + 2 < size2 {
+        width * (interpolate(values, idx + 2) - f0) / (nodes2[idx + 2] - x0)
+    } else {
+        f1 - f0
+    };
+
+    // invert the integral over the segment, starting from the solution for a linear interpolant
+    let mut t = if f0 != f1 {
+        (f0 - Float::sqrt(Float::max(0.0, f0 * f0 + 2.0 * u * (f1 - f0)))) / (f0 - f1)
+    } else {
+        u / f0
+    };
+    let (mut a, mut b) = (0.0, 1.0);
+    let mut f_hat;
+    loop {
+        if !(t >= a && t <= b) {
+            t = 0.5 * (a + b);
+        }
+
+        let big_f_hat = t * (f0 + t * (0.5 * d0 + t * ((1.0 / 3.0) * (-2.0 * d0 - d1) + f1 - f0
+            + t * (0.25 * (d0 + d1) + 0.5 * (f0 - f1)))));
+        f_hat = f0 + t * (d0 + t * (-2.0 * d0 - d1 + 3.0 * (f1 - f0) + t * (d0 + d1 + 2.0 * (f0 - f1))));
+
+        if (big_f_hat - u).abs() < 1e-6 || b - a < 1e-6 {
+            break;
+        }
+        if big_f_hat - u < 0.0 { a = t; } else { b = t; }
+        t -= (big_f_hat - u) / f_hat;
+    }
+
+    Some((x0 + width * t, f_hat / maximum))
+}
+
+/// Integrates the spline through `values` at `nodes`, returning the running integral at each
+/// node.
+pub fn integrate_catmull_rom(nodes: &[Float], values: &[Float]) -> Vec<Float> {
+    let n = nodes.len();
+    let mut cdf = Vec::with_capacity(n);
+    let mut sum = 0.0;
+    cdf.push(sum);
+    for i in 0..n - 1 {
+        let (x0, x1) = (nodes[i], nodes[i + 1]);
+        let (f0, f1) = (values[i], values[i + 1]);
+        let width = x1 - x0;
+        let d0 = if i > 0 { width * (f1 - values[i - 1]) / (x1 - nodes[i - 1]) } else { f1 - f0 };
+        let d1 = if i + 2 < n { width * (values[i + 2] - f0) / (nodes[i + 2] - x0) } else { f1 - f0 };
+        sum += ((d0 - d1) * (1.0 / 12.0) + (f0 + f1) * 0.5) * width;
+        cdf.push(sum);
+    }
+    cdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SERIES: [Float; 3] = [1.0, 0.3, 0.1];
+
+    /// A monochrome table whose BSDF is `(1 + (mu_o - mu_i) / 2) * sum SERIES[k] cos(k phi)`,
+    /// serialized in the `.bsdf` file format.
+    fn synthetic_table_file() -> Vec<u8> {
+        let mu: Vec<Float> = (0..9).map(|i| -1.0 + 0.25 * i as Float).collect();
+        let n_mu = mu.len();
+
+        let mut coeffs = vec![];
+        let mut offset_and_length = vec![];
+        let mut cdf = vec![];
+        for &mu_o in &mu {
+            let mut a0_row = vec![];
+            for &mu_i in &mu {
+                // the tabulated values include the |mu_i| cosine factor
+                let scale = mu_i.abs() * (1.0 + 0.5 * (mu_o - mu_i));
+                offset_and_length.push(coeffs.len() as i32);
+                offset_and_length.push(SERIES.len() as i32);
+                coeffs.extend(SERIES.iter().map(|a| a * scale));
+                a0_row.push(SERIES[0] * scale);
+            }
+            cdf.extend(integrate_catmull_rom(&mu, &a0_row));
+        }
+
+        let mut file = HEADER.to_vec();
+        let header_ints = [1, n_mu as i32, coeffs.len() as i32, SERIES.len() as i32, 1, 1, 0, 0, 0];
+        for i in &header_ints {
+            file.extend(&i.to_le_bytes());
+        }
+        file.extend(&(1.0 as Float).to_le_bytes());
+        file.extend(&[0u8; 16]);
+        for x in mu.iter().chain(&cdf) {
+            file.extend(&x.to_le_bytes());
+        }
+        for i in &offset_and_length {
+            file.extend(&i.to_le_bytes());
+        }
+        for x in &coeffs {
+            file.extend(&x.to_le_bytes());
+        }
+        file
+    }
+
+    fn dir(theta: Float, phi: Float) -> Vec3f {
+        Vec3f::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+    }
+
+    #[test]
+    fn test_read_table_and_reciprocity() {
+        let table = FourierBSDFTable::from_reader(&mut Cursor::new(synthetic_table_file())).unwrap();
+        assert_eq!(table.n_mu(), 9);
+        assert_eq!(table.m_max, 3);
+        let bsdf = FourierBSDF::new(&table, TransportMode::Radiance);
+
+        // zenith angles whose cosines are interpolated exactly by the splines
+        let angles = [(0.8, 0.3), (1.1, 2.0), (0.9, -1.2)];
+        for &(theta_o, phi_o) in &angles {
+            for &(theta_i, phi_i) in &angles {
+                let wo = dir(theta_o, phi_o);
+                let wi = dir(theta_i, phi_i);
+                let f = bsdf.f(wo, wi)[0];
+                let f_swapped = bsdf.f(wi, wo)[0];
+                assert!(f > 0.0);
+                assert!((f - f_swapped).abs() < 1e-4 * f, "{} {}", f, f_swapped);
+
+                let cos_phi = cos_d_phi(-wi, wo);
+                let expected = (1.0 + 0.5 * (wo.z + wi.z)) * fourier(&SERIES, 3, cos_phi);
+                assert!((f - expected).abs() < 1e-4 * expected, "{} {}", f, expected);
+            }
+        }
+
+        assert!(FourierBSDFTable::from_reader(&mut Cursor::new(b"NOTABSDF".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_reject_out_of_bounds_coefficients() {
+        // the file ends with the offset and length of the last pair followed by the coefficients
+        let mut file = synthetic_table_file();
+        let n_coeffs = 9 * 9 * SERIES.len();
+        let last_offset = file.len() - 4 * (n_coeffs + 2);
+        let past_end = (n_coeffs - 1) as i32;
+        file[last_offset..last_offset + 4].copy_from_slice(&past_end.to_le_bytes());
+        assert!(FourierBSDFTable::from_reader(&mut Cursor::new(file)).is_err());
+
+        let mut file = synthetic_table_file();
+        let too_long = (SERIES.len() + 1) as i32;
+        file[last_offset + 4..last_offset + 8].copy_from_slice(&too_long.to_le_bytes());
+        assert!(FourierBSDFTable::from_reader(&mut Cursor::new(file)).is_err());
+    }
+
+    #[test]
+    fn test_sample_f_matches_pdf() {
+        let table = FourierBSDFTable::from_reader(&mut Cursor::new(synthetic_table_file())).unwrap();
+        let bsdf = FourierBSDF::new(&table, TransportMode::Radiance);
+        let wo = dir(0.9, 0.4);
+        for &u in &[(0.1, 0.2), (0.5, 0.5), (0.7, 0.35), (0.95, 0.8)] {
+            let sample = bsdf.sample_f(wo, Point2f::new(u.0, u.1)).unwrap();
+            let pdf = bsdf.pdf(wo, sample.wi);
+            assert!((sample.wi.magnitude() - 1.0).abs() < 1e-4);
+            assert!((sample.pdf - pdf).abs() < 1e-3 * pdf, "{} {}", sample.pdf, pdf);
+            let f = bsdf.f(wo, sample.wi);
+            assert!((sample.f[0] - f[0]).abs() < 1e-3 * f[0], "{:?} {:?}", sample.f, f);
+        }
+    }
+}
@@ -10,6 +10,7 @@ use crate::reflection::microfacet::MicrofacetDistribution;
 
 pub mod bsdf;
 pub mod microfacet;
+pub mod fourier;
 
 bitflags! {
     pub struct BxDFType: u8 {
@@ -110,6 +111,17 @@ pub trait BxDF {
 
     fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float;
 
+    /// Monte Carlo estimate of the hemispherical-directional reflectance, i.e. the total
+    /// reflection in direction `wo` due to constant illumination over the hemisphere, using
+    /// `samples` to sample incident directions.
+    fn rho(&self, wo: Vec3f, samples: &[Point2f]) -> Spectrum {
+        let sum: Spectrum = samples.iter()
+            .filter_map(|&u| self.sample_f(wo, u))
+            .filter(|s| s.pdf > 0.0)
+            .map(|s| s.f * abs_cos_theta(s.wi) / s.pdf)
+            .sum();
+        sum / samples.len() as Float
+    }
 }
 
 // TODO: better name - CosineSampledBxDF?
@@ -249,6 +261,107 @@ impl BxDF for SpecularTransmission {
 
 }
 
+/// Combined specular reflection and transmission for a smooth dielectric, choosing between the
+/// two lobes according to the Fresnel reflectance.
+#[derive(Debug)]
+pub struct FresnelSpecular {
+    r: Spectrum,
+    t: Spectrum,
+    eta_a: Float,
+    eta_b: Float,
+    fresnel: FresnelDielectric,
+    mode: TransportMode,
+}
+
+impl FresnelSpecular {
+    pub fn new(r: Spectrum, t: Spectrum, eta_a: Float, eta_b: Float, mode: TransportMode) -> Self {
+        Self {
+            r, t, eta_a, eta_b, mode, fresnel: FresnelDielectric::new(eta_a, eta_b)
+        }
+    }
+}
+
+impl BxDF for FresnelSpecular {
+    fn get_type(&self) -> BxDFType {
+        BxDFType::REFLECTION | BxDFType::TRANSMISSION | BxDFType::SPECULAR
+    }
+
+    fn f(&self, _wo: Vec3f, _wi: Vec3f) -> Spectrum {
+        Spectrum::uniform(0.0)
+    }
+
+    fn sample_f(&self, wo: Vec3f, sample: Point2f) -> Option<ScatterSample> {
+        let f = self.fresnel.evaluate(cos_theta(wo))[0];
+        if sample.x < f {
+            let wi = Vec3f::new(-wo.x, -wo.y, wo.z);
+            Some(ScatterSample {
+                f: self.r * f / abs_cos_theta(wi),
+                wi,
+                pdf: f,
+                sampled_type: BxDFType::REFLECTION | BxDFType::SPECULAR
+            })
+        } else {
+            let entering = cos_theta(wo) > 0.0;
+            let eta_i = if entering { self.eta_a } else { self.eta_b };
+            let eta_t = if entering { self.eta_b } else { self.eta_a };
+
+            let wi = refract(
+                wo,
+                Normal3::new(0.0, 0.0, 1.0).faceforward(wo),
+                eta_i / eta_t
+            )?;
+
+            let mut ft = self.t * (1.0 - f);
+            // Account for the compression of solid angle when radiance crosses the boundary
+            if self.mode == TransportMode::Radiance {
+                ft = ft * (eta_i * eta_i) / (eta_t * eta_t);
+            }
+            Some(ScatterSample {
+                f: ft / abs_cos_theta(wi),
+                wi,
+                pdf: 1.0 - f,
+                sampled_type: BxDFType::TRANSMISSION | BxDFType::SPECULAR
+            })
+        }
+    }
+
+    fn pdf(&self, _wo: Vec3f, _wi: Vec3f) -> Float {
+        0.0
+    }
+}
+
+/// Wraps another BxDF, scaling its contribution by a spectrum.
+pub struct ScaledBxDF<'a> {
+    bxdf: &'a dyn BxDF,
+    scale: Spectrum,
+}
+
+impl<'a> ScaledBxDF<'a> {
+    pub fn new(bxdf: &'a dyn BxDF, scale: Spectrum) -> Self {
+        Self { bxdf, scale }
+    }
+}
+
+impl<'a> BxDF for ScaledBxDF<'a> {
+    fn get_type(&self) -> BxDFType {
+        self.bxdf.get_type()
+    }
+
+    fn f(&self, wo: Vec3f, wi: Vec3f) -> Spectrum {
+        self.scale * self.bxdf.f(wo, wi)
+    }
+
+    fn sample_f(&self, wo: Vec3f, sample: Point2f) -> Option<ScatterSample> {
+        let mut scattered = self.bxdf.sample_f(wo, sample)?;
+        scattered.f = self.scale * scattered.f;
+        Some(scattered)
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        self.bxdf.pdf(wo, wi)
+    }
+}
+
 #[derive(Debug)]
 pub struct OrenNayar {
     pub r: Spectrum,
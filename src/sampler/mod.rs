@@ -2,10 +2,9 @@ use cgmath::EuclideanSpace;
 
 use crate::{Float, Point2f, Point2i};
 use crate::camera::CameraSample;
-use std::cell::Cell;
-use std::sync::Arc;
 
 pub mod random;
+pub mod stratified;
 
 #[derive(Copy, Clone)]
 pub struct SampleArrayId {
@@ -38,6 +37,10 @@ pub trait Sampler: Send {
 
     fn clone_with_seed(&self, seed: u64) -> Self where Self: Sized;
 
+    /// `clone_with_seed` for a sampler behind a `Box<dyn Sampler>`, e.g. one chosen at runtime by
+    /// a scene file.
+    fn clone_box(&self, seed: u64) -> Box<dyn Sampler>;
+
     fn samples_per_pixel(&self) -> usize;
 
     fn get_camera_sample(&mut self, p_raster: Point2i) -> CameraSample {
@@ -50,27 +53,79 @@ pub trait Sampler: Send {
         }
     }
 
+    /// Skips ahead to sample `sample_num` of the current pixel, returning false if that is past
+    /// the samples per pixel.
     fn set_sample_number(&mut self, sample_num: u64) -> bool;
 }
 
+impl Sampler for Box<dyn Sampler> {
+    fn start_pixel(&mut self, pixel: Point2i) {
+        (**self).start_pixel(pixel)
+    }
+
+    fn start_next_sample(&mut self) -> bool {
+        (**self).start_next_sample()
+    }
+
+    fn get_1d(&mut self) -> Float {
+        (**self).get_1d()
+    }
+
+    fn get_2d(&mut self) -> Point2f {
+        (**self).get_2d()
+    }
+
+    fn request_1d_array(&mut self, len: usize) -> SampleArrayId {
+        (**self).request_1d_array(len)
+    }
+
+    fn request_2d_array(&mut self, len: usize) -> SampleArrayId {
+        (**self).request_2d_array(len)
+    }
+
+    fn get_1d_array(&self, id: SampleArrayId) -> &[Float] {
+        (**self).get_1d_array(id)
+    }
+
+    fn get_2d_array(&self, id: SampleArrayId) -> &[Point2f] {
+        (**self).get_2d_array(id)
+    }
+
+    fn round_count(&self, n: usize) -> usize {
+        (**self).round_count(n)
+    }
+
+    fn clone_with_seed(&self, seed: u64) -> Self {
+        (**self).clone_box(seed)
+    }
+
+    fn clone_box(&self, seed: u64) -> Box<dyn Sampler> {
+        (**self).clone_box(seed)
+    }
+
+    fn samples_per_pixel(&self) -> usize {
+        (**self).samples_per_pixel()
+    }
+
+    fn get_camera_sample(&mut self, p_raster: Point2i) -> CameraSample {
+        (**self).get_camera_sample(p_raster)
+    }
+
+    fn set_sample_number(&mut self, sample_num: u64) -> bool {
+        (**self).set_sample_number(sample_num)
+    }
+}
+
 #[derive(Clone)]
 pub struct SamplerState {
     samples_per_pixel: usize,
     current_pixel: Point2i,
     current_pixel_sample_num: usize,
 
-//    sample_array_1d: Vec<Array2<Float>>,
-//    sample_array_2d: Vec<Array2<Point2f>>,
-
-    // Store a vector of grouped samples. For each group, store an array of samples of the
-    // requested size for
-//    sample_array_1d: Vec<Vec<Float>>,
-//    sample_array_2d: Vec<Vec<Point2f>>,
-//    samples_1d_array_sizes: Vec<usize>,
-//    samples_2d_array_sizes: Vec<usize>,
-//    array_1d_offset: Cell<usize>,
-//    array_2d_offset: Cell<usize>,
-
+    // For each requested array, the samples for every sample in the pixel stored contiguously,
+    // i.e. `samples_per_pixel` consecutive runs of the requested length.
+    sample_array_1d: Vec<Vec<Float>>,
+    sample_array_2d: Vec<Vec<Point2f>>,
 }
 
 impl SamplerState {
@@ -79,68 +134,60 @@ impl SamplerState {
             samples_per_pixel,
             current_pixel: Point2i::new(0, 0),
             current_pixel_sample_num: 0,
-//            sample_array_1d: vec![],
-//            sample_array_2d: vec![],
+            sample_array_1d: vec![],
+            sample_array_2d: vec![],
         }
     }
 
     pub fn start_pixel(&mut self, p: Point2i) {
         self.current_pixel = p;
         self.current_pixel_sample_num = 0;
-//        self.array_1d_offset = 0.into();
-//        self.array_2d_offset = 0.into();
+    }
+
+    /// Sets the number of samples already taken in the current pixel, so the next call to
+    /// `start_next_sample` begins sample `sample_num`.
+    pub fn set_sample_number(&mut self, sample_num: u64) -> bool {
+        self.current_pixel_sample_num = sample_num as usize;
+        self.current_pixel_sample_num < self.samples_per_pixel
     }
 
     pub fn start_next_sample(&mut self) -> bool {
-//        self.array_1d_offset = 0.into();
-//        self.array_2d_offset = 0.into();
         self.current_pixel_sample_num += 1;
         self.current_pixel_sample_num <= self.samples_per_pixel
     }
 
     pub fn request_1d_array(&mut self, len: usize) -> SampleArrayId {
-//        let id = SampleArrayId {
-//            idx: self.sample_array_1d.len(),
-//            len
-//        };
-//        self.sample_array_1d.push(Array2::zeros((self.samples_per_pixel, len)));
-//        id
-//        self.sample_array_1d.push(vec!(0.0; len * self.samples_per_pixel as usize))
-        unimplemented!()
+        let id = SampleArrayId {
+            idx: self.sample_array_1d.len(),
+            len
+        };
+        self.sample_array_1d.push(vec![0.0; len * self.samples_per_pixel]);
+        id
     }
 
     pub fn request_2d_array(&mut self, len: usize) -> SampleArrayId {
-//        let id = SampleArrayId {
-//            idx: self.sample_array_2d.len(),
-//            len
-//        };
-//        self.sample_array_2d.push(Array2::from_elem((self.samples_per_pixel, len), Point2f::origin()));
-//        id
-        unimplemented!()
+        let id = SampleArrayId {
+            idx: self.sample_array_2d.len(),
+            len
+        };
+        self.sample_array_2d.push(vec![Point2f::origin(); len * self.samples_per_pixel]);
+        id
     }
 
-    pub fn get_1d_array(&self, id: SampleArrayId) -> &[Float] {
-        unimplemented!()
-//        let sample_array = &self.sample_array_1d[id.idx];
-//        let arr = sample_array.row(self.current_pixel_sample_num);
-//        arr.as_slice().unwrap()
+    /// The index of the sample currently being taken. `start_next_sample` is called before each
+    /// sample, so this is one behind the count of started samples.
+    fn current_sample_index(&self) -> usize {
+        self.current_pixel_sample_num.saturating_sub(1).min(self.samples_per_pixel - 1)
+    }
 
-//        let range = (self.current_pixel_sample_num * len .. (self.current_pixel_sample_num + 1) * len);
-//        let array = &self.sample_array_1d[self.array_1d_offset.get()][range];
-//        self.array_1d_offset.replace(self.array_1d_offset.get() + 1);
-//        array
+    pub fn get_1d_array(&self, id: SampleArrayId) -> &[Float] {
+        let start = self.current_sample_index() * id.len;
+        &self.sample_array_1d[id.idx][start..start + id.len]
     }
 
     pub fn get_2d_array(&self, id: SampleArrayId) -> &[Point2f] {
-        unimplemented!()
-//        let sample_array = &self.sample_array_2d[id.idx];
-//        let arr = sample_array.row(self.current_pixel_sample_num);
-//        arr.as_slice().unwrap()
-
-//        let range = (self.current_pixel_sample_num * len .. (self.current_pixel_sample_num + 1) * len);
-//        let array = &self.sample_array_2d[self.array_2d_offset.get()][range];
-//        self.array_2d_offset.replace(self.array_2d_offset.get() + 1);
-//        array
+        let start = self.current_sample_index() * id.len;
+        &self.sample_array_2d[id.idx][start..start + id.len]
     }
 }
 
@@ -149,55 +196,30 @@ mod tests {
     use super::*;
     use crate::sampler::random::RandomSampler;
 
-//    #[test]
-//    fn test_get_sample_arrays() {
-//        let mut sampler = RandomSampler::new_with_seed(2, 0);
-//
-//        let id1 = sampler.request_1d_array(10);
-//        let id2 = sampler.request_1d_array(7);
-//
-//        sampler.start_pixel((0, 0).into());
-//
-//        let arr = sampler.get_1d_array(id1);
-//        assert_eq!(arr.len(), 10);
-//        assert!(arr.iter().any(|&x| x > 0.0));
-//
-//        let arr = sampler.get_1d_array(id2);
-//        assert_eq!(arr.len(), 7);
-//        assert!(arr.iter().any(|&x| x > 0.0));
-//
-//        assert!(sampler.start_next_sample());
-//
-//        let arr = sampler.get_1d_array(id1);
-//        assert_eq!(arr.len(), 10);
-//        assert!(arr.iter().any(|&x| x > 0.0));
-//
-//        let arr = sampler.get_1d_array(id2);
-//        assert_eq!(arr.len(), 7);
-//        assert!(arr.iter().any(|&x| x > 0.0));
-//
-//        assert!(!sampler.start_next_sample());
-//
-//        sampler.start_pixel((1, 1).into());
-//
-//        let arr = sampler.get_1d_array(id1);
-//        assert_eq!(arr.len(), 10);
-//        assert!(arr.iter().any(|&x| x > 0.0));
-//
-//        let arr = sampler.get_1d_array(id2);
-//        assert_eq!(arr.len(), 7);
-//        assert!(arr.iter().any(|&x| x > 0.0));
-//
-//        assert!(sampler.start_next_sample());
-//
-//        let arr = sampler.get_1d_array(id1);
-//        assert_eq!(arr.len(), 10);
-//        assert!(arr.iter().any(|&x| x > 0.0));
-//
-//        let arr = sampler.get_1d_array(id2);
-//        assert_eq!(arr.len(), 7);
-//        assert!(arr.iter().any(|&x| x > 0.0));
-//
-//        assert!(!sampler.start_next_sample());
-//    }
+    #[test]
+    fn test_get_sample_arrays() {
+        let mut sampler = RandomSampler::new_with_seed(2, 0);
+
+        let id1 = sampler.request_1d_array(10);
+        let id2 = sampler.request_2d_array(7);
+
+        for &pixel in &[(0, 0), (1, 1)] {
+            sampler.start_pixel(pixel.into());
+
+            let mut previous: Option<Vec<Float>> = None;
+            while sampler.start_next_sample() {
+                let arr = sampler.get_1d_array(id1);
+                assert_eq!(arr.len(), 10);
+                assert!(arr.iter().all(|&x| x >= 0.0 && x < 1.0));
+                assert!(arr.iter().any(|&x| x > 0.0));
+                // Each sample in the pixel gets its own array
+                assert_ne!(previous.as_deref(), Some(arr));
+                previous = Some(arr.to_vec());
+
+                let arr = sampler.get_2d_array(id2);
+                assert_eq!(arr.len(), 7);
+                assert!(arr.iter().any(|p| p.x > 0.0 && p.y > 0.0));
+            }
+        }
+    }
 }
@@ -5,6 +5,7 @@ use crate::sampler::{Sampler, SamplerState, SampleArrayId};
 
 pub struct RandomSampler {
     rng: Xoshiro256Plus,
+    seed: u64,
     state: SamplerState,
 }
 
@@ -12,6 +13,7 @@ impl RandomSampler {
     pub fn new_with_seed(samples_per_pixel: usize, seed: u64) -> Self {
         Self {
             rng: Xoshiro256Plus::seed_from_u64(seed),
+            seed,
             state: SamplerState::new(samples_per_pixel),
         }
     }
@@ -21,13 +23,13 @@ impl Sampler for RandomSampler {
     fn start_pixel(&mut self, pixel: Point2i) {
         self.state.start_pixel(pixel);
         let rng = &mut self.rng;
-//        self.state.sample_array_1d.iter_mut().flatten().for_each(|x| {
-//            *x = rng.gen();
-//        });
-//
-//        self.state.sample_array_2d.iter_mut().flatten().for_each(|p| {
-//            *p = Point2f::new(rng.gen(), rng.gen());
-//        });
+        self.state.sample_array_1d.iter_mut().flatten().for_each(|x| {
+            *x = rng.gen();
+        });
+
+        self.state.sample_array_2d.iter_mut().flatten().for_each(|p| {
+            *p = Point2f::new(rng.gen(), rng.gen());
+        });
     }
 
     fn start_next_sample(&mut self) -> bool {
@@ -62,15 +64,28 @@ impl Sampler for RandomSampler {
         // TODO: how to base off initial seed or do we need to?
         Self {
             rng: Xoshiro256Plus::seed_from_u64(seed),
+            seed,
             state: self.state.clone(),
         }
     }
 
+    fn clone_box(&self, seed: u64) -> Box<dyn Sampler> {
+        Box::new(self.clone_with_seed(seed))
+    }
+
     fn samples_per_pixel(&self) -> usize {
         self.state.samples_per_pixel
     }
 
     fn set_sample_number(&mut self, sample_num: u64) -> bool {
-        unimplemented!()
+        // Samples are independent, but reseed so that skipping ahead doesn't replay the same
+        // random stream that produced the earlier samples.
+        let pixel = self.state.current_pixel;
+        let seed = self.seed
+            ^ (sample_num << 32)
+            ^ ((pixel.x as u64 & 0xFFFF) << 16)
+            ^ (pixel.y as u64 & 0xFFFF);
+        self.rng = Xoshiro256Plus::seed_from_u64(seed);
+        self.state.set_sample_number(sample_num)
     }
 }
@@ -0,0 +1,205 @@
+use crate::{Point2i, Point2f, Float};
+use rand_xoshiro::Xoshiro256Plus;
+use rand::{SeedableRng, Rng};
+use rand::seq::SliceRandom;
+use crate::sampler::{Sampler, SamplerState, SampleArrayId};
+use crate::sampling::{stratified_sample_1d, stratified_sample_2d, latin_hypercube_2d};
+
+/// Divides each pixel into an `x_samples` by `y_samples` grid and takes one sample in each cell.
+/// The first `n_dimensions` 1D and 2D sample dimensions are stratified, with the strata shuffled
+/// independently per dimension; any dimensions past that are uniformly random.
+pub struct StratifiedSampler {
+    rng: Xoshiro256Plus,
+    seed: u64,
+    x_samples: usize,
+    y_samples: usize,
+    jitter: bool,
+    samples_1d: Vec<Vec<Float>>,
+    samples_2d: Vec<Vec<Point2f>>,
+    current_1d_dim: usize,
+    current_2d_dim: usize,
+    state: SamplerState,
+}
+
+impl StratifiedSampler {
+    pub fn new_with_seed(x_samples: usize, y_samples: usize, jitter: bool, n_dimensions: usize, seed: u64) -> Self {
+        let spp = x_samples * y_samples;
+        Self {
+            rng: Xoshiro256Plus::seed_from_u64(seed),
+            seed,
+            x_samples,
+            y_samples,
+            jitter,
+            samples_1d: vec![vec![0.0; spp]; n_dimensions],
+            samples_2d: vec![vec![Point2f::new(0.0, 0.0); spp]; n_dimensions],
+            current_1d_dim: 0,
+            current_2d_dim: 0,
+            state: SamplerState::new(spp),
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel(&mut self, pixel: Point2i) {
+        self.state.start_pixel(pixel);
+        self.current_1d_dim = 0;
+        self.current_2d_dim = 0;
+        let rng = &mut self.rng;
+        let jitter = self.jitter;
+
+        for samples in &mut self.samples_1d {
+            stratified_sample_1d(samples, rng, jitter);
+            samples.shuffle(rng);
+        }
+        for samples in &mut self.samples_2d {
+            stratified_sample_2d(samples, self.x_samples, self.y_samples, rng, jitter);
+            samples.shuffle(rng);
+        }
+
+        let spp = self.state.samples_per_pixel;
+        for array in self.state.sample_array_1d.iter_mut().filter(|a| !a.is_empty()) {
+            let len = array.len() / spp;
+            for samples in array.chunks_mut(len) {
+                stratified_sample_1d(samples, rng, jitter);
+                samples.shuffle(rng);
+            }
+        }
+        for array in self.state.sample_array_2d.iter_mut().filter(|a| !a.is_empty()) {
+            let len = array.len() / spp;
+            let root = (len as Float).sqrt() as usize;
+            for samples in array.chunks_mut(len) {
+                if root * root == len {
+                    stratified_sample_2d(samples, root, root, rng, jitter);
+                    samples.shuffle(rng);
+                } else {
+                    latin_hypercube_2d(samples, rng);
+                }
+            }
+        }
+    }
+
+    fn start_next_sample(&mut self) -> bool {
+        self.current_1d_dim = 0;
+        self.current_2d_dim = 0;
+        self.state.start_next_sample()
+    }
+
+    fn get_1d(&mut self) -> Float {
+        match self.samples_1d.get(self.current_1d_dim) {
+            Some(samples) => {
+                self.current_1d_dim += 1;
+                samples[self.state.current_sample_index()]
+            }
+            None => self.rng.gen(),
+        }
+    }
+
+    fn get_2d(&mut self) -> Point2f {
+        match self.samples_2d.get(self.current_2d_dim) {
+            Some(samples) => {
+                self.current_2d_dim += 1;
+                samples[self.state.current_sample_index()]
+            }
+            None => Point2f::new(self.rng.gen(), self.rng.gen()),
+        }
+    }
+
+    fn request_1d_array(&mut self, len: usize) -> SampleArrayId {
+        self.state.request_1d_array(len)
+    }
+
+    fn request_2d_array(&mut self, len: usize) -> SampleArrayId {
+        self.state.request_2d_array(len)
+    }
+
+    fn get_1d_array(&self, id: SampleArrayId) -> &[Float] {
+        self.state.get_1d_array(id)
+    }
+
+    fn get_2d_array(&self, id: SampleArrayId) -> &[Point2f] {
+        self.state.get_2d_array(id)
+    }
+
+    /// 2D sample arrays are stratified over a square grid, so round up to a perfect square.
+    fn round_count(&self, n: usize) -> usize {
+        let mut root = 0;
+        while root * root < n {
+            root += 1;
+        }
+        root * root
+    }
+
+    fn clone_with_seed(&self, seed: u64) -> Self where Self: Sized {
+        Self {
+            rng: Xoshiro256Plus::seed_from_u64(seed),
+            seed,
+            x_samples: self.x_samples,
+            y_samples: self.y_samples,
+            jitter: self.jitter,
+            samples_1d: self.samples_1d.clone(),
+            samples_2d: self.samples_2d.clone(),
+            current_1d_dim: 0,
+            current_2d_dim: 0,
+            state: self.state.clone(),
+        }
+    }
+
+    fn clone_box(&self, seed: u64) -> Box<dyn Sampler> {
+        Box::new(self.clone_with_seed(seed))
+    }
+
+    fn samples_per_pixel(&self) -> usize {
+        self.state.samples_per_pixel
+    }
+
+    fn set_sample_number(&mut self, sample_num: u64) -> bool {
+        // the stratified samples for the pixel are already generated, so only the random
+        // dimensions past `n_dimensions` need a fresh stream
+        let pixel = self.state.current_pixel;
+        let seed = self.seed
+            ^ (sample_num << 32)
+            ^ ((pixel.x as u64 & 0xFFFF) << 16)
+            ^ (pixel.y as u64 & 0xFFFF);
+        self.rng = Xoshiro256Plus::seed_from_u64(seed);
+        self.current_1d_dim = 0;
+        self.current_2d_dim = 0;
+        self.state.set_sample_number(sample_num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_count() {
+        let sampler = StratifiedSampler::new_with_seed(2, 2, true, 4, 0);
+        assert_eq!(sampler.round_count(5), 9);
+        assert_eq!(sampler.round_count(4), 4);
+        assert_eq!(sampler.round_count(1), 1);
+    }
+
+    #[test]
+    fn test_pixel_samples_are_stratified() {
+        let mut sampler = StratifiedSampler::new_with_seed(2, 2, true, 1, 0);
+        let n = sampler.round_count(5);
+        let id = sampler.request_2d_array(n);
+        sampler.start_pixel(Point2i::new(0, 0));
+
+        let mut cells = vec![];
+        while sampler.start_next_sample() {
+            let p = sampler.get_2d();
+            cells.push(((p.x * 2.0) as usize, (p.y * 2.0) as usize));
+
+            // one array sample in each cell of a 3x3 grid
+            let mut array_cells: Vec<_> = sampler.get_2d_array(id).iter()
+                .map(|p| ((p.x * 3.0) as usize, (p.y * 3.0) as usize))
+                .collect();
+            array_cells.sort_unstable();
+            array_cells.dedup();
+            assert_eq!(array_cells.len(), 9);
+        }
+        cells.sort_unstable();
+        assert_eq!(cells, vec![(0, 0), (0, 1), (1, 0), (1, 1)]);
+    }
+}
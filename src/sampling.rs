@@ -1,6 +1,7 @@
 use crate::{Point2f, Vec2f, Vec3f, Float, Point3f};
 use std::f32;
 use rand::Rng;
+use rand::seq::SliceRandom;
 
 pub fn concentric_sample_disk(u: Point2f) -> Point2f {
     // map sample from [0, 1] to [-1, 1]
@@ -41,8 +42,23 @@ pub fn uniform_sample_sphere(u: Point2f) -> Vec3f {
     Vec3f::new(r * phi.cos(), r * phi.sin(), z)
 }
 
+/// The pdf of `uniform_sample_sphere` with respect to solid angle, 1/(4π).
 pub const fn uniform_sphere_pdf() -> Float {
-    std::f32::consts::FRAC_1_PI * 4.0
+    std::f32::consts::FRAC_1_PI / 4.0
+}
+
+/// Uniformly samples a direction within the cone around +z whose half-angle has cosine
+/// `cos_theta_max`.
+pub fn uniform_sample_cone(u: Point2f, cos_theta_max: Float) -> Vec3f {
+    let cos_theta = (1.0 - u[0]) + u[0] * cos_theta_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u[1];
+    Vec3f::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// The pdf of `uniform_sample_cone` with respect to solid angle, 1/(2π(1 - cos θmax)).
+pub fn uniform_cone_pdf(cos_theta_max: Float) -> Float {
+    1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_theta_max))
 }
 
 pub fn uniform_sample_triangle(u: Point2f) -> Point2f {
@@ -50,12 +66,118 @@ pub fn uniform_sample_triangle(u: Point2f) -> Point2f {
     Point2f::new(1.0 - su0, u[1] * su0)
 }
 
+/// The largest float less than one, for keeping samples in [0, 1).
+pub const ONE_MINUS_EPSILON: Float = 1.0 - f32::EPSILON / 2.0;
+
+/// Fills `samples` with one sample in each of `samples.len()` equal strata of [0, 1), placed
+/// randomly within the stratum if `jitter` is set or in its center otherwise.
+pub fn stratified_sample_1d(samples: &mut [Float], rng: &mut impl Rng, jitter: bool) {
+    let inv_n = 1.0 / samples.len() as Float;
+    for (i, s) in samples.iter_mut().enumerate() {
+        let delta: Float = if jitter { rng.gen() } else { 0.5 };
+        *s = Float::min((i as Float + delta) * inv_n, ONE_MINUS_EPSILON);
+    }
+}
+
+/// Fills `samples` with one sample in each cell of an `nx` by `ny` grid over [0, 1)^2, in
+/// row-major order.
+pub fn stratified_sample_2d(samples: &mut [Point2f], nx: usize, ny: usize, rng: &mut impl Rng, jitter: bool) {
+    assert_eq!(samples.len(), nx * ny);
+    let dx = 1.0 / nx as Float;
+    let dy = 1.0 / ny as Float;
+    for (i, s) in samples.iter_mut().enumerate() {
+        let (x, y) = (i % nx, i / nx);
+        let jx: Float = if jitter { rng.gen() } else { 0.5 };
+        let jy: Float = if jitter { rng.gen() } else { 0.5 };
+        *s = Point2f::new(
+            Float::min((x as Float + jx) * dx, ONE_MINUS_EPSILON),
+            Float::min((y as Float + jy) * dy, ONE_MINUS_EPSILON),
+        );
+    }
+}
+
+/// Fills `samples` so that, projected onto either axis, there is exactly one sample in each of
+/// `samples.len()` equal strata.
+pub fn latin_hypercube_2d(samples: &mut [Point2f], rng: &mut impl Rng) {
+    let n = samples.len();
+    let inv_n = 1.0 / n as Float;
+    let mut xs: Vec<Float> = (0..n)
+        .map(|i| Float::min((i as Float + rng.gen::<Float>()) * inv_n, ONE_MINUS_EPSILON))
+        .collect();
+    let mut ys: Vec<Float> = (0..n)
+        .map(|i| Float::min((i as Float + rng.gen::<Float>()) * inv_n, ONE_MINUS_EPSILON))
+        .collect();
+    // permute each dimension independently
+    xs.shuffle(rng);
+    ys.shuffle(rng);
+    for (s, (x, y)) in samples.iter_mut().zip(xs.into_iter().zip(ys)) {
+        *s = Point2f::new(x, y);
+    }
+}
+
+/// Reflects the digits of `a` in `base` about the radix point, giving the `a`th point of the van
+/// der Corput sequence in that base. Using a different prime base for each dimension gives the
+/// Halton sequence.
+pub fn radical_inverse(base: u64, mut a: u64) -> Float {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed = 0u64;
+    while a > 0 {
+        let next = a / base;
+        let digit = a - next * base;
+        reversed = reversed * base + digit;
+        inv_base_n *= inv_base;
+        a = next;
+    }
+    ((reversed as f64 * inv_base_n) as Float).min(ONE_MINUS_EPSILON)
+}
+
 pub fn power_heuristic(nf: u32, f_pdf: Float, ng: u32, g_pdf: Float) -> Float {
     let f = nf as Float * f_pdf;
     let g = ng  as Float * g_pdf;
     (f * f) / (f * f + g * g)
 }
 
+pub fn balance_heuristic(nf: u32, f_pdf: Float, ng: u32, g_pdf: Float) -> Float {
+    let f = nf as Float * f_pdf;
+    let g = ng as Float * g_pdf;
+    f / (f + g)
+}
+
+/// The heuristic used to weight samples from different strategies with multiple importance
+/// sampling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MisMode {
+    Balance,
+    Power,
+}
+
+impl MisMode {
+    /// Parses `"balance"` or `"power"`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "balance" => Some(MisMode::Balance),
+            "power" => Some(MisMode::Power),
+            _ => None,
+        }
+    }
+
+    /// The weight for a sample from strategy `f`, taking `nf` samples with density `f_pdf`, when
+    /// combined with `ng` samples from strategy `g` with density `g_pdf`.
+    pub fn weight(self, nf: u32, f_pdf: Float, ng: u32, g_pdf: Float) -> Float {
+        match self {
+            MisMode::Balance => balance_heuristic(nf, f_pdf, ng, g_pdf),
+            MisMode::Power => power_heuristic(nf, f_pdf, ng, g_pdf),
+        }
+    }
+}
+
+impl Default for MisMode {
+    fn default() -> Self {
+        MisMode::Power
+    }
+}
+
 #[derive(Debug)]
 pub struct Distribution1D {
     func: Vec<Float>,
@@ -132,6 +254,24 @@ impl Distribution1D {
         let x = (idx as Float + du) / self.func.len() as Float;
         (x, pdf, idx)
     }
+
+    /// Uses `u` to choose one of the function's values with probability proportional to it,
+    /// returning its index and that probability.
+    pub fn sample_discrete(&self, u: Float) -> (usize, Float) {
+        let idx = search_sorted(self.cdf.len(), |i| self.cdf[i] <= u);
+        (idx, self.discrete_pdf(idx))
+    }
+
+    /// The probability of `sample_discrete` choosing `idx`. Every index is equally likely if the
+    /// function is zero everywhere.
+    pub fn discrete_pdf(&self, idx: usize) -> Float {
+        let n = self.func.len() as Float;
+        if self.func_integral == 0.0 {
+            1.0 / n
+        } else {
+            self.func[idx] / (self.func_integral * n)
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -183,6 +323,31 @@ impl Distribution2D {
 mod tests {
     use super::*;
     use cgmath::{EuclideanSpace, InnerSpace};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_mis_heuristics() {
+        for &mode in &[MisMode::Balance, MisMode::Power] {
+            assert_eq!(mode.weight(1, 0.7, 1, 0.7), 0.5);
+            // weights of the two strategies sum to one
+            let w = mode.weight(1, 2.0, 1, 0.5) + mode.weight(1, 0.5, 1, 2.0);
+            assert!((w - 1.0).abs() < 1e-6);
+        }
+        let balance = balance_heuristic(1, 2.0, 1, 0.5);
+        let power = power_heuristic(1, 2.0, 1, 0.5);
+        assert!((balance - 0.8).abs() < 1e-6);
+        assert!(power > balance && power < 1.0);
+        assert!(power_heuristic(1, 0.5, 1, 2.0) < balance_heuristic(1, 0.5, 1, 2.0));
+    }
+
+    #[test]
+    fn test_radical_inverse() {
+        assert_eq!(radical_inverse(2, 0), 0.0);
+        assert_eq!(radical_inverse(2, 1), 0.5);
+        assert_eq!(radical_inverse(2, 6), 0.375);
+        assert!((radical_inverse(3, 5) - 7.0 / 9.0).abs() < 1e-6);
+    }
 
     #[test]
     fn test_distribution_1d() {
@@ -197,6 +362,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_distribution_1d_discrete() {
+        let distr = Distribution1D::new(vec![1.0, 0.0, 3.0]);
+        for &(u, expected_idx, expected_pmf) in &[(0.1, 0, 0.25), (0.3, 2, 0.75), (0.99, 2, 0.75)] {
+            let (idx, pmf) = distr.sample_discrete(u);
+            assert_eq!(idx, expected_idx);
+            assert!((pmf - expected_pmf).abs() < 1e-6, "{}", pmf);
+        }
+        assert_eq!(distr.discrete_pdf(1), 0.0);
+
+        let zero = Distribution1D::new(vec![0.0, 0.0]);
+        assert_eq!(zero.sample_discrete(0.7), (1, 0.5));
+    }
+
     #[test]
     fn test_concentric_sample_disk() {
         for _ in 0..100 {
@@ -206,5 +385,66 @@ mod tests {
             assert!(dist <= 1.0, "Sampled point outside unit disk: distance {}", dist);
         }
     }
-}
 
+    #[test]
+    fn test_uniform_sample_sphere() {
+        let mut rng = StdRng::from_seed([5; 32]);
+        let n = 20000;
+        let mut sum = Vec3f::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let u: Point2f = Point2f::new(rng.gen(), rng.gen());
+            let v = uniform_sample_sphere(u);
+            assert!((v.magnitude() - 1.0).abs() < 1e-5, "Sample not on unit sphere: {:?}", v);
+            sum += v;
+        }
+        let mean = sum / n as Float;
+        assert!(mean.magnitude() < 0.03, "Mean of samples not near origin: {:?}", mean);
+
+        assert!((uniform_sphere_pdf() * 4.0 * std::f32::consts::PI - 1.0).abs() < 1e-6);
+    }
+
+    /// RMS relative error of the sample counts in square bins of the projected disk against
+    /// the counts expected from a cos(theta) / pi density, for an n x n grid of samples.
+    fn projected_histogram_error(n: usize, sample: impl Fn(Point2f) -> Vec3f) -> Float {
+        let bins = 32;
+        let mut counts = vec![0usize; bins * bins];
+        for i in 0..n {
+            for j in 0..n {
+                let u = Point2f::new((i as Float + 0.5) / n as Float, (j as Float + 0.5) / n as Float);
+                let w = sample(u);
+                assert!(w.z >= 0.0);
+                let bin = |x: Float| usize::min(((x + 1.0) * 0.5 * bins as Float) as usize, bins - 1);
+                counts[bin(w.y) * bins + bin(w.x)] += 1;
+            }
+        }
+
+        // a cosine-weighted direction projects to a uniform point on the disk, so the expected
+        // count is proportional to bin area; only bins entirely inside the disk are compared
+        let bin_width = 2.0 / bins as Float;
+        let expected = (n * n) as Float * bin_width * bin_width * std::f32::consts::FRAC_1_PI;
+        let corner = |i: usize| -1.0 + i as Float * bin_width;
+        let (err_sum, n_bins) = (0..bins * bins)
+            .filter(|idx| {
+                let (bx, by) = (idx % bins, idx / bins);
+                [(bx, by), (bx + 1, by), (bx, by + 1), (bx + 1, by + 1)].iter()
+                    .all(|&(x, y)| corner(x) * corner(x) + corner(y) * corner(y) < 1.0)
+            })
+            .map(|idx| (counts[idx] as Float - expected) / expected)
+            .fold((0.0, 0), |(sum, n), e| (sum + e * e, n + 1));
+        Float::sqrt(err_sum / n_bins as Float)
+    }
+
+    #[test]
+    fn test_cosine_sample_hemisphere_distribution() {
+        let naive = |u: Point2f| {
+            let r = u.x.sqrt();
+            let phi = 2.0 * std::f32::consts::PI * u.y;
+            Vec3f::new(r * phi.cos(), r * phi.sin(), Float::sqrt(Float::max(0.0, 1.0 - r * r)))
+        };
+
+        let concentric_err = projected_histogram_error(64, cosine_sample_hemisphere);
+        let naive_err = projected_histogram_error(64, naive);
+        assert!(concentric_err < 0.25, "{}", concentric_err);
+        assert!(concentric_err < 0.75 * naive_err, "{} {}", concentric_err, naive_err);
+    }
+}
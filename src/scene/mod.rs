@@ -1,26 +1,150 @@
 use crate::bvh::BVH;
-use crate::{SurfaceInteraction, Ray, Bounds3f, RayDifferential};
-use crate::light::Light;
+use crate::kdtree::KdTreeAccel;
+use crate::{SurfaceInteraction, Ray, Bounds3f, RayDifferential, Vec3f};
+use crate::interaction::SurfaceHit;
+use crate::light::{Light, LightFlags};
+use crate::medium::Medium;
+use crate::sampler::Sampler;
 use std::sync::Arc;
 use crate::primitive::Primitive;
 use crate::shapes::triangle::TriangleMesh;
 use std::fmt::{Debug, Formatter};
 use crate::spectrum::Spectrum;
 
+/// The radiance arriving along rays that escape a scene without any lights at infinity, e.g. a
+/// constant color or `crate::background`'s sky gradient. Any `Fn(Vec3f) -> Spectrum` closure is a
+/// background.
+pub trait Background: Send + Sync {
+    /// The radiance arriving from the normalized direction `dir`.
+    fn radiance(&self, dir: Vec3f) -> Spectrum;
+}
+
+impl<F: Fn(Vec3f) -> Spectrum + Send + Sync> Background for F {
+    fn radiance(&self, dir: Vec3f) -> Spectrum {
+        self(dir)
+    }
+}
+
+/// Collects the lights and meshes of a scene. Lights are owned by the builder until `build`,
+/// so they can be preprocessed with the final scene bounds before anything else can hold a
+/// reference to them.
+#[derive(Default)]
 pub struct SceneBuilder {
+    lights: Vec<Box<dyn Light>>,
+    meshes: Vec<Arc<TriangleMesh>>,
+    background: Option<Box<dyn Background>>,
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn light(&mut self, light: Box<dyn Light>) -> &mut Self {
+        self.lights.push(light);
+        self
+    }
+
+    pub fn mesh(&mut self, mesh: Arc<TriangleMesh>) -> &mut Self {
+        self.meshes.push(mesh);
+        self
+    }
+
+    /// Sets the radiance seen along escaping rays. It is ignored if the scene has a light at
+    /// infinity, which provides the radiance instead.
+    pub fn background(&mut self, background: impl Background + 'static) -> &mut Self {
+        self.background = Some(Box::new(background));
+        self
+    }
+
+    /// Preprocesses the lights against the primitives and creates the scene. Area lights
+    /// attached to primitives are added to the scene's lights as well.
+    pub fn build(self, primitives: impl Into<Accelerator>) -> Scene {
+        let primitives = primitives.into();
+        let mut lights: Vec<Arc<dyn Light>> = self.lights.into_iter()
+            .map(|mut light| {
+                light.preprocess(&primitives);
+                Arc::from(light)
+            })
+            .collect();
+
+        for prim in primitives.prims() {
+            if let Some(light) = prim.light_arc_cloned() {
+                lights.push(light)
+            }
+        }
 
+        let has_infinite_light = lights.iter().any(|l| matches!(l.flags(), LightFlags::Infinite));
+        let background = self.background.filter(|_| !has_infinite_light);
+
+        Scene {
+            primitives_aggregate: primitives,
+            lights,
+            meshes: self.meshes,
+            background,
+        }
+    }
+}
+
+/// The acceleration structure used to intersect rays with the scene's primitives.
+pub enum Accelerator {
+    BVH(BVH),
+    KdTree(KdTreeAccel),
+}
+
+impl Accelerator {
+    pub fn prims(&self) -> &[Box<dyn Primitive>] {
+        match self {
+            Accelerator::BVH(bvh) => &bvh.prims,
+            Accelerator::KdTree(kdtree) => &kdtree.prims,
+        }
+    }
+
+    pub fn bounds(&self) -> Bounds3f {
+        match self {
+            Accelerator::BVH(bvh) => bvh.bounds,
+            Accelerator::KdTree(kdtree) => kdtree.bounds,
+        }
+    }
+
+    pub fn intersect(&self, ray: &mut Ray) -> Option<SurfaceInteraction> {
+        match self {
+            Accelerator::BVH(bvh) => bvh.intersect(ray),
+            Accelerator::KdTree(kdtree) => kdtree.intersect(ray),
+        }
+    }
+
+    pub fn intersect_test(&self, ray: &Ray) -> bool {
+        match self {
+            Accelerator::BVH(bvh) => bvh.intersect_test(ray),
+            Accelerator::KdTree(kdtree) => kdtree.intersect_test(ray),
+        }
+    }
+}
+
+impl From<BVH> for Accelerator {
+    fn from(bvh: BVH) -> Self {
+        Accelerator::BVH(bvh)
+    }
+}
+
+impl From<KdTreeAccel> for Accelerator {
+    fn from(kdtree: KdTreeAccel) -> Self {
+        Accelerator::KdTree(kdtree)
+    }
 }
 
 pub struct Scene {
-    pub primitives_aggregate: BVH,
+    pub primitives_aggregate: Accelerator,
     pub lights: Vec<Arc<dyn Light>>,
     pub meshes: Vec<Arc<TriangleMesh>>,
+    background: Option<Box<dyn Background>>,
 }
 
 impl Debug for Scene {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        let n_prims = self.primitives_aggregate.prims.len();
-        let scene_bounds = self.primitives_aggregate.bounds;
+        let n_prims = self.primitives_aggregate.prims().len();
+        let scene_bounds = self.primitives_aggregate.bounds();
         let n_lights = self.lights.len();
         let n_meshes = self.meshes.len();
         writeln!(f, "Scene{{ {} prims, {} lights, {} meshes, bounds {:?} }}", n_prims, n_lights, n_meshes, scene_bounds)
@@ -29,34 +153,76 @@ impl Debug for Scene {
 
 impl Scene {
 
-    pub fn new(primitives: BVH, mut lights: Vec<Arc<dyn Light>>, meshes: Vec<Arc<TriangleMesh>>) -> Self {
-        // TODO: this is kind of weird, maybe find a better way to do preprocess
-        for light in &mut lights {
-            Arc::get_mut(light).unwrap().preprocess(&primitives);
-        }
-
-        for prim in &primitives.prims {
-            if let Some(light) = prim.light_arc_cloned() {
-                lights.push(light)
-            }
+    pub fn new(primitives: impl Into<Accelerator>, lights: Vec<Box<dyn Light>>, meshes: Vec<Arc<TriangleMesh>>) -> Self {
+        let mut builder = SceneBuilder::new();
+        for light in lights {
+            builder.light(light);
         }
-
-        Self {
-            primitives_aggregate: primitives,
-            lights,
-            meshes
+        for mesh in meshes {
+            builder.mesh(mesh);
         }
+        builder.build(primitives)
     }
 
     pub fn intersect(&self, ray: &mut Ray) -> Option<SurfaceInteraction> {
         self.primitives_aggregate.intersect(ray)
     }
 
+    /// Intersects the main ray of `ray_diff` and computes the texture differentials of the hit
+    /// from its differentials, for filtering textures.
+    pub fn intersect_with_differentials(&self, ray_diff: &mut RayDifferential) -> Option<SurfaceInteraction> {
+        let mut si = self.intersect(&mut ray_diff.ray)?;
+        si.compute_differentials(ray_diff);
+        Some(si)
+    }
+
     pub fn intersect_test(&self, ray: &Ray) -> bool {
         self.primitives_aggregate.intersect_test(ray)
     }
 
+    /// Tests whether the segment between two surface points is blocked. The segment's endpoints
+    /// are offset by their error bounds and it stops just short of `p1`, so neither surface counts
+    /// as occluding it.
+    pub fn intersect_p(&self, p0: SurfaceHit, p1: SurfaceHit) -> bool {
+        self.intersect_test(&p0.spawn_ray_to_hit(p1))
+    }
+
+    /// Finds the first surface along `ray` that scatters or emits light, passing through those
+    /// that only separate media. Also returns the transmittance of the media along the way, where
+    /// the ray starts in `medium`.
+    pub fn intersect_tr<'s>(
+        &'s self,
+        mut ray: Ray,
+        medium: Option<&'s dyn Medium>,
+        sampler: &mut dyn Sampler,
+    ) -> (Option<SurfaceInteraction<'s>>, Spectrum) {
+        let mut tr = Spectrum::uniform(1.0);
+        let mut medium = medium;
+        loop {
+            let si = self.intersect(&mut ray);
+            if let Some(medium) = medium {
+                tr *= medium.tr(&ray, sampler);
+            }
+            let si = match si {
+                Some(si) => si,
+                None => return (None, tr),
+            };
+            let boundary = si.primitive
+                .map_or(false, |prim| prim.material().is_none() && prim.area_light().is_none());
+            if !boundary {
+                return (Some(si), tr);
+            }
+            medium = si.medium(ray.dir, medium);
+            ray = si.hit.spawn_ray(ray.dir);
+        }
+    }
+
+    /// The radiance arriving along a ray that escapes the scene, from its lights at infinity or
+    /// otherwise its background.
     pub fn environment_emitted_radiance(&self, ray: &RayDifferential) -> Spectrum {
+        if let Some(background) = &self.background {
+            return background.radiance(ray.ray.dir.normalize());
+        }
         // TODO: this is inefficient
         self.lights.iter()
             .map(|l| l.environment_emitted_radiance(ray))
@@ -64,6 +230,182 @@ impl Scene {
     }
 
     pub fn world_bound(&self) -> Bounds3f {
-        self.primitives_aggregate.bounds
+        self.primitives_aggregate.bounds()
+    }
+
+    /// The number of lights in the scene, including the area lights attached to primitives.
+    pub fn num_lights(&self) -> usize {
+        self.lights.len()
     }
-}
\ No newline at end of file
+
+    pub fn num_primitives(&self) -> usize {
+        self.primitives_aggregate.prims().len()
+    }
+
+    pub fn light(&self, i: usize) -> &dyn Light {
+        self.lights[i].as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point2f, Transform, Vec3f};
+    use crate::primitive::GeometricPrimitive;
+    use crate::shapes::Shape;
+    use crate::shapes::sphere::Sphere;
+
+    fn sphere(center: Vec3f, radius: f32) -> Arc<Sphere> {
+        let o2w = Transform::translate(center);
+        Arc::new(Sphere::whole(o2w, o2w.inverse(), radius))
+    }
+
+    #[test]
+    fn test_intersect_p_endpoints() {
+        let light_shape = sphere(Vec3f::new(0.0, 0.0, 5.0), 1.0);
+        let receiver_shape = sphere(Vec3f::new(0.0, 0.0, -5.0), 1.0);
+        let prims: Vec<Box<dyn Primitive>> = vec![
+            Box::new(GeometricPrimitive::new(light_shape.clone(), None)),
+            Box::new(GeometricPrimitive::new(receiver_shape.clone(), None)),
+        ];
+        let scene = Scene::new(BVH::build(prims), vec![], vec![]);
+
+        // Points on the facing sides of the two spheres, with their error bounds
+        let p0 = receiver_shape.sample(Point2f::new(0.0, 0.0));
+        let p1 = light_shape.sample(Point2f::new(1.0, 0.0));
+        assert!(p0.p.z > -4.01 && p1.p.z < 4.01);
+        assert!(!scene.intersect_p(p0, p1));
+        assert!(!scene.intersect_p(p1, p0));
+
+        let occluder = sphere(Vec3f::new(0.0, 0.0, 0.0), 0.5);
+        let prims: Vec<Box<dyn Primitive>> = vec![
+            Box::new(GeometricPrimitive::new(light_shape, None)),
+            Box::new(GeometricPrimitive::new(receiver_shape, None)),
+            Box::new(GeometricPrimitive::new(occluder, None)),
+        ];
+        let scene = Scene::new(BVH::build(prims), vec![], vec![]);
+        assert!(scene.intersect_p(p0, p1));
+    }
+
+    #[test]
+    fn test_intersect_with_differentials() {
+        use crate::{Differential, Point3f};
+        use crate::interaction::TextureDifferentials;
+
+        let prims: Vec<Box<dyn Primitive>> = vec![
+            Box::new(GeometricPrimitive::new(sphere(Vec3f::new(0.0, 0.0, 0.0), 1.0), None)),
+        ];
+        let scene = Scene::new(BVH::build(prims), vec![], vec![]);
+
+        let origin = Point3f::new(0.2, 0.1, -5.0);
+        let dir = Vec3f::new(0.0, 0.0, 1.0);
+        let mut ray_diff = RayDifferential {
+            ray: Ray::new(origin, dir),
+            diff: Some(Differential {
+                rx_origin: origin + Vec3f::new(0.01, 0.0, 0.0),
+                ry_origin: origin + Vec3f::new(0.0, 0.01, 0.0),
+                rx_dir: dir,
+                ry_dir: dir,
+            }),
+        };
+        let si = scene.intersect_with_differentials(&mut ray_diff).unwrap();
+        let diffs = si.tex_diffs;
+        assert!((diffs.dpdx.x - 0.01).abs() < 1e-3, "{:?}", diffs.dpdx);
+        assert!((diffs.dpdy.y - 0.01).abs() < 1e-3, "{:?}", diffs.dpdy);
+        assert!(diffs.dudx != 0.0 || diffs.dvdx != 0.0);
+        assert!(diffs.dudy != 0.0 || diffs.dvdy != 0.0);
+        assert!(ray_diff.ray.t_max < 5.0);
+
+        // without differentials they are zero
+        let mut ray_diff = RayDifferential { ray: Ray::new(origin, dir), diff: None };
+        let si = scene.intersect_with_differentials(&mut ray_diff).unwrap();
+        assert_eq!(si.tex_diffs, TextureDifferentials::default());
+    }
+
+    #[test]
+    fn test_background() {
+        use bumpalo::Bump;
+        use crate::integrator::IntegratorRadiance;
+        use crate::integrator::path::PathIntegrator;
+        use crate::light::infinite::InfiniteAreaLight;
+        use crate::sampler::random::RandomSampler;
+
+        let color = Spectrum::from([0.2, 0.4, 0.8]);
+        let prims = || -> Vec<Box<dyn Primitive>> {
+            vec![Box::new(GeometricPrimitive::new(sphere(Vec3f::new(0.0, 0.0, 0.0), 1.0), None))]
+        };
+        let mut builder = SceneBuilder::new();
+        builder.background(move |_: Vec3f| color);
+        let scene = builder.build(BVH::build(prims()));
+
+        let integrator = PathIntegrator::new(5, 1.0);
+        let mut sampler = RandomSampler::new_with_seed(1, 0);
+        let arena = Bump::new();
+        for &dir in &[Vec3f::new(0.0, 0.0, -1.0), Vec3f::new(1.0, 2.0, 0.5), Vec3f::new(-3.0, 0.0, 1.0)] {
+            let mut ray = RayDifferential { ray: Ray::new(crate::Point3f::new(0.0, 0.0, -2.0), dir), diff: None };
+            assert_eq!(integrator.incident_radiance(&mut ray, &scene, &mut sampler, &arena, 0), color);
+        }
+
+        // a light at infinity takes precedence over the background
+        let mut builder = SceneBuilder::new();
+        builder
+            .background(move |_: Vec3f| color)
+            .light(Box::new(InfiniteAreaLight::new_uniform(Spectrum::uniform(1.0), Transform::identity())));
+        let scene = builder.build(BVH::build(prims()));
+        let ray = RayDifferential { ray: Ray::new(crate::Point3f::new(0.0, 0.0, -2.0), Vec3f::new(0.0, 0.0, -1.0)), diff: None };
+        assert_eq!(scene.environment_emitted_radiance(&ray), Spectrum::uniform(1.0));
+    }
+
+    #[test]
+    fn test_counts() {
+        use crate::Point3f;
+        use crate::light::point::PointLight;
+
+        let prims: Vec<Box<dyn Primitive>> = (0..3)
+            .map(|i| {
+                let shape = sphere(Vec3f::new(3.0 * i as f32, 0.0, 0.0), 1.0);
+                Box::new(GeometricPrimitive::new(shape, None)) as Box<dyn Primitive>
+            })
+            .collect();
+        let lights: Vec<Box<dyn Light>> = vec![
+            Box::new(PointLight::new(Transform::translate(Vec3f::new(0.0, 5.0, 0.0)), Spectrum::uniform(1.0))),
+            Box::new(PointLight::new(Transform::translate(Vec3f::new(0.0, -5.0, 0.0)), Spectrum::uniform(2.0))),
+        ];
+        let scene = Scene::new(BVH::build(prims), lights, vec![]);
+
+        assert_eq!(scene.num_primitives(), 3);
+        assert_eq!(scene.num_lights(), 2);
+        let origin = Point3f::new(0.0, 0.0, 0.0);
+        assert_eq!(scene.light(1).light_to_world().transform(origin), Point3f::new(0.0, -5.0, 0.0));
+    }
+
+    #[test]
+    fn test_builder_with_shared_light() {
+        use crate::light::diffuse::DiffuseAreaLight;
+        use crate::light::distant::DistantLight;
+
+        let shape = sphere(Vec3f::new(0.0, 0.0, 0.0), 2.0);
+        let area_light = Arc::new(DiffuseAreaLight::new(Spectrum::uniform(1.0), shape.clone(), 1, false));
+        // keep our own reference to the area light while the scene is built
+        let shared = area_light.clone();
+        let prims: Vec<Box<dyn Primitive>> = vec![
+            Box::new(GeometricPrimitive { shape, material: None, light: Some(area_light), medium_interface: None }),
+        ];
+
+        let mut builder = SceneBuilder::new();
+        builder.light(Box::new(DistantLight::new(Spectrum::uniform(1.0), Vec3f::new(0.0, 0.0, 1.0))));
+        let scene = builder.build(BVH::build(prims));
+
+        assert_eq!(scene.num_lights(), 2);
+        assert!(Arc::strong_count(&shared) >= 3);
+
+        // The distant light needs the scene's bounding sphere, and is preprocessed before it's
+        // shared. Its power is zero until then.
+        let distant = scene.lights[0].clone();
+        assert!(Arc::strong_count(&distant) >= 2);
+        // the bounding sphere of the sphere's bounding box
+        let radius = 2.0 * 3.0f32.sqrt();
+        let expected = std::f32::consts::PI * radius * radius;
+        assert!((distant.power()[0] - expected).abs() < 1e-2 * expected, "{:?}", distant.power());
+    }
+}
@@ -0,0 +1,236 @@
+use cgmath::InnerSpace;
+
+use crate::{Float, Normal3, Point2f, Point3f, Vec3f};
+use crate::EFloat;
+use crate::err_float::gamma;
+use crate::geometry::{Ray, Transform};
+use crate::geometry::bounds::Bounds3f;
+use crate::interaction::{DiffGeom, SurfaceHit, SurfaceInteraction};
+use crate::math::quadratic;
+use crate::shapes::Shape;
+
+/// A cone with its base of radius `radius` centered at the origin in the xy plane and its apex at
+/// `(0, 0, height)`, swept `phi_max` around the z axis.
+#[derive(Debug)]
+pub struct Cone {
+    object_to_world: Transform,
+    world_to_object: Transform,
+    reverse_orientation: bool,
+
+    radius: Float,
+    height: Float,
+    phi_max: Float,
+}
+
+impl Cone {
+    pub fn new(
+        object_to_world: Transform,
+        world_to_object: Transform,
+        reverse_orientation: bool,
+        radius: Float,
+        height: Float,
+        phi_max: Float,
+    ) -> Self {
+        Self {
+            object_to_world, world_to_object, reverse_orientation,
+            radius,
+            height,
+            phi_max: phi_max.clamp(0.0, 360.0).to_radians(),
+        }
+    }
+
+    /// The hit point and its azimuth for the ray at `t`, or None if it is clipped.
+    fn hit_at(&self, ray: &Ray, t: EFloat, o: [EFloat; 3], d: [EFloat; 3]) -> Option<(Point3f, Vec3f, Float)> {
+        let px = o[0] + t * d[0];
+        let py = o[1] + t * d[1];
+        let pz = o[2] + t * d[2];
+        let p_hit = ray.at(t.into());
+        let mut phi = Float::atan2(p_hit.y, p_hit.x);
+        if phi < 0.0 { phi += 2.0 * std::f32::consts::PI }
+
+        if p_hit.z < 0.0 || p_hit.z > self.height || phi > self.phi_max {
+            return None;
+        }
+        let p_err = Vec3f::new(px.absolute_err(), py.absolute_err(), pz.absolute_err());
+        Some((p_hit, p_err, phi))
+    }
+}
+
+impl Shape for Cone {
+    fn object_bound(&self) -> Bounds3f {
+        Bounds3f::with_bounds(
+            Point3f::new(-self.radius, -self.radius, 0.0),
+            Point3f::new(self.radius, self.radius, self.height),
+        )
+    }
+
+    fn object_to_world(&self) -> &Transform {
+        &self.object_to_world
+    }
+
+    fn world_to_object(&self) -> &Transform {
+        &self.world_to_object
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+
+    fn area(&self) -> Float {
+        self.radius * Float::sqrt(self.height * self.height + self.radius * self.radius) * self.phi_max / 2.0
+    }
+
+    #[allow(non_snake_case)]
+    #[allow(clippy::many_single_char_names)]
+    fn intersect(&self, ray: &Ray) -> Option<(Float, SurfaceInteraction)> {
+        let (ray, (origin_err, dir_err)) = self.world_to_object().tf_exact_to_err(*ray);
+
+        let ox = EFloat::with_err(ray.origin.x, origin_err.x);
+        let oy = EFloat::with_err(ray.origin.y, origin_err.y);
+        let oz = EFloat::with_err(ray.origin.z, origin_err.z);
+        let dirx = EFloat::with_err(ray.dir.x, dir_err.x);
+        let diry = EFloat::with_err(ray.dir.y, dir_err.y);
+        let dirz = EFloat::with_err(ray.dir.z, dir_err.z);
+
+        let k = EFloat::new(self.radius) / EFloat::new(self.height);
+        let k = k * k;
+        let h = EFloat::new(self.height);
+        let a = dirx * dirx + diry * diry - k * dirz * dirz;
+        let b = 2.0 * (dirx * ox + diry * oy - k * dirz * (oz - h));
+        let c = ox * ox + oy * oy - k * (oz - h) * (oz - h);
+
+        let (t0, t1) = quadratic(a, b, c)?;
+
+        if t0.upper_bound() > ray.t_max || t1.lower_bound() <= 0.0 {
+            return None;
+        }
+
+        let o = [ox, oy, oz];
+        let d = [dirx, diry, dirz];
+
+        // find the closest valid intersection that isn't clipped
+        let (t_shape_hit, (p_hit, p_err, phi)) = if t0.lower_bound() > 0.0 {
+            match self.hit_at(&ray, t0, o, d) {
+                Some(hit) => (t0, hit),
+                None if t1.upper_bound() <= ray.t_max => (t1, self.hit_at(&ray, t1, o, d)?),
+                None => return None,
+            }
+        } else {
+            if t1.upper_bound() > ray.t_max {
+                return None;
+            }
+            (t1, self.hit_at(&ray, t1, o, d)?)
+        };
+
+        let u = phi / self.phi_max;
+        let v = p_hit.z / self.height;
+
+        // the derivatives are written in terms of phi rather than the hit point's x and y so that
+        // they stay finite at the apex, where the radius (and so dpdu) would be zero
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let r_hit = Float::max(self.radius * (1.0 - v), 1.0e-5 * self.radius);
+        let dpdu = self.phi_max * r_hit * Vec3f::new(-sin_phi, cos_phi, 0.0);
+        let dpdv = Vec3f::new(-self.radius * cos_phi, -self.radius * sin_phi, self.height);
+
+        let d2pduu = -self.phi_max * self.phi_max * r_hit * Vec3f::new(cos_phi, sin_phi, 0.0);
+        let d2pduv = self.phi_max * self.radius * Vec3f::new(sin_phi, -cos_phi, 0.0);
+        let d2pdvv = Vec3f::new(0.0, 0.0, 0.0);
+
+        let E = dpdu.dot(dpdu);
+        let F = dpdu.dot(dpdv);
+        let G = dpdv.dot(dpdv);
+
+        let mut N = dpdu.cross(dpdv).normalize();
+
+        let e = N.dot(d2pduu);
+        let f = N.dot(d2pduv);
+        let g = N.dot(d2pdvv);
+
+        let invEGF2 = 1.0 / (E * G - F * F);
+
+        let dndu = Normal3((f * F - e * G) * invEGF2 * dpdu + (e * F - f * E) * invEGF2 * dpdv);
+
+        let dndv = Normal3((g * F - f * G) * invEGF2 * dpdu + (f * F - g * E) * invEGF2 * dpdv);
+
+        if self.reverse_orientation() {
+            N *= -1.0;
+        }
+
+        let interact = SurfaceInteraction::new(
+            p_hit,
+            p_err,
+            ray.time,
+            Point2f::new(u, v),
+            -ray.dir,
+            Normal3(N),
+            DiffGeom { dpdu, dpdv, dndu, dndv }
+        );
+
+        let world_intersect = self.object_to_world().transform(interact);
+
+        Some((t_shape_hit.into(), world_intersect))
+    }
+
+    fn sample(&self, u: Point2f) -> SurfaceHit {
+        // the area at each height is proportional to the radius there, which shrinks linearly
+        // towards the apex
+        let s = u[0].sqrt();
+        let phi = u[1] * self.phi_max;
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let p_obj = Point3f::new(self.radius * s * cos_phi, self.radius * s * sin_phi, self.height * (1.0 - s));
+        let n_obj = Normal3(Vec3f::new(self.height * cos_phi, self.height * sin_phi, self.radius));
+        let mut n = Normal3(self.object_to_world.transform(n_obj).normalize());
+        if self.reverse_orientation {
+            n *= -1.0;
+        }
+        let p_obj_err = gamma(5) * Vec3f::new(p_obj.x.abs(), p_obj.y.abs(), p_obj.z.abs());
+        let (p, p_err) = self.object_to_world.tf_err_to_err(p_obj, p_obj_err);
+        SurfaceHit {
+            p,
+            p_err,
+            time: 0.0,
+            n
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::assert_abs_diff_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_axial_ray_hits_apex() {
+        let o2w = Transform::translate(Vec3f::new(1.0, 2.0, 0.0));
+        let cone = Cone::new(o2w, o2w.inverse(), false, 1.0, 2.0, 360.0);
+
+        let ray = Ray::new(Point3f::new(1.0, 2.0, 5.0), Vec3f::new(0.0, 0.0, -1.0));
+        let (t, isect) = cone.intersect(&ray).expect("Should hit the apex");
+        assert!((t - 3.0).abs() < 1e-4, "{}", t);
+        assert_abs_diff_eq!(isect.hit.p, Point3f::new(1.0, 2.0, 2.0), epsilon = 1e-4);
+        assert!(isect.hit.n.0.z > 0.0 && isect.hit.n.0.magnitude() > 0.99);
+
+        // a ray through the side hits where the radius is half the base radius
+        let ray = Ray::new(Point3f::new(-4.0, 2.0, 1.0), Vec3f::new(1.0, 0.0, 0.0));
+        let (t, isect) = cone.intersect(&ray).unwrap();
+        assert!((t - 4.5).abs() < 1e-4, "{}", t);
+        assert!(isect.hit.n.0.x < 0.0);
+
+        // above the apex
+        let ray = Ray::new(Point3f::new(-4.0, 2.0, 2.5), Vec3f::new(1.0, 0.0, 0.0));
+        assert!(cone.intersect(&ray).is_none());
+    }
+
+    #[test]
+    fn test_sampled_points_lie_on_cone() {
+        let cone = Cone::new(Transform::IDENTITY, Transform::IDENTITY, false, 1.0, 2.0, 360.0);
+        for &(u0, u1) in &[(0.1, 0.2), (0.5, 0.5), (0.9, 0.75)] {
+            let hit = cone.sample(Point2f::new(u0, u1));
+            let radius_at_z = 1.0 - hit.p.z / 2.0;
+            assert!((Float::hypot(hit.p.x, hit.p.y) - radius_at_z).abs() < 1e-5, "{:?}", hit.p);
+            // the normal faces away from the axis
+            assert!(hit.n.0.dot(Vec3f::new(hit.p.x, hit.p.y, 0.0)) > 0.0);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use cgmath::InnerSpace;
+
+use crate::{Float, Normal3, Point2f, Point3f, Transform, Vec3f};
+use crate::shapes::triangle::TriangleMesh;
+
+/// A grid of `nu × nv` heights over the unit square in the xy plane. It isn't intersected
+/// directly, but is converted to a triangle mesh with two triangles per grid cell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightfield {
+    nu: usize,
+    nv: usize,
+    z: Vec<Float>,
+}
+
+impl Heightfield {
+    /// Creates a heightfield from the row-major heights `z`, where `z[v * nu + u]` is the height
+    /// of the point at `(u / (nu - 1), v / (nv - 1))`.
+    pub fn new(nu: usize, nv: usize, z: Vec<Float>) -> Self {
+        assert!(nu >= 2 && nv >= 2, "Heightfield must be at least 2x2");
+        assert_eq!(z.len(), nu * nv);
+        Self { nu, nv, z }
+    }
+
+    pub fn n_triangles(&self) -> usize {
+        2 * (self.nu - 1) * (self.nv - 1)
+    }
+
+    pub fn to_mesh(&self, object_to_world: Transform, reverse_orientation: bool) -> TriangleMesh {
+        let (nu, nv) = (self.nu, self.nv);
+        let vert = |u: usize, v: usize| (v * nu + u) as u32;
+
+        let mut vertices = Vec::with_capacity(nu * nv);
+        let mut tex_coords = Vec::with_capacity(nu * nv);
+        for v in 0..nv {
+            for u in 0..nu {
+                let s = u as Float / (nu - 1) as Float;
+                let t = v as Float / (nv - 1) as Float;
+                vertices.push(Point3f::new(s, t, self.z[v * nu + u]));
+                tex_coords.push(Point2f::new(s, t));
+            }
+        }
+
+        let mut indices = Vec::with_capacity(3 * self.n_triangles());
+        for v in 0..nv - 1 {
+            for u in 0..nu - 1 {
+                indices.extend_from_slice(&[vert(u, v), vert(u + 1, v), vert(u + 1, v + 1)]);
+                indices.extend_from_slice(&[vert(u, v), vert(u + 1, v + 1), vert(u, v + 1)]);
+            }
+        }
+
+        // accumulate the (area weighted) face normals at each vertex
+        let mut normals = vec![Vec3f::new(0.0, 0.0, 0.0); nu * nv];
+        for tri in indices.chunks_exact(3) {
+            let [p0, p1, p2] = [vertices[tri[0] as usize], vertices[tri[1] as usize], vertices[tri[2] as usize]];
+            let n = (p1 - p0).cross(p2 - p0);
+            for &i in tri {
+                normals[i as usize] += n;
+            }
+        }
+        let normals = normals.into_iter().map(|n| Normal3(n.normalize())).collect();
+
+        TriangleMesh::new(
+            object_to_world,
+            indices,
+            vertices,
+            Some(normals),
+            None,
+            Some(tex_coords),
+            reverse_orientation
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use cgmath::assert_abs_diff_eq;
+    use crate::shapes::Shape;
+
+    #[test]
+    fn test_flat_heightfield() {
+        let hf = Heightfield::new(4, 3, vec![0.5; 12]);
+        let mesh = Arc::new(hf.to_mesh(Transform::identity(), false));
+        assert_eq!(mesh.n_triangles as usize, hf.n_triangles());
+        assert_eq!(mesh.n_triangles, 12);
+
+        let mut area = 0.0;
+        for tri in mesh.iter_triangles() {
+            area += tri.area();
+            // the corners of the triangle, to check the winding gives a +z geometric normal
+            let p0 = tri.sample(Point2f::new(0.0, 0.0)).p;
+            let p1 = tri.sample(Point2f::new(1.0, 1.0)).p;
+            let p2 = tri.sample(Point2f::new(1.0, 0.0)).p;
+            let ng = (p1 - p0).cross(p2 - p0).normalize();
+            assert_abs_diff_eq!(ng, Vec3f::new(0.0, 0.0, 1.0), epsilon = 1e-6);
+
+            let hit = tri.sample(Point2f::new(0.3, 0.6));
+            assert_abs_diff_eq!(hit.p.z, 0.5, epsilon = 1e-6);
+            assert_abs_diff_eq!(hit.n.0, Vec3f::new(0.0, 0.0, 1.0), epsilon = 1e-6);
+        }
+        assert_abs_diff_eq!(area, 1.0, epsilon = 1e-5);
+    }
+}
@@ -1,10 +1,13 @@
 use crate::{Float, Transform, Point2f, Vec3f, distance_sq, abs_dot};
-use crate::geometry::Ray;
+use crate::geometry::{Ray, RayShear};
 use crate::geometry::bounds::Bounds3f;
 use crate::interaction::{SurfaceInteraction, SurfaceHit};
 
 pub mod sphere;
+pub mod cone;
+pub mod paraboloid;
 pub mod triangle;
+pub mod heightfield;
 pub mod loop_subdiv;
 
 pub trait Shape: Sync + Send {
@@ -32,10 +35,28 @@ pub trait Shape: Sync + Send {
 
     fn intersect(&self, ray: &Ray) -> Option<(Float, SurfaceInteraction)>;
 
+    /// Like `intersect`, given the ray's precomputed `RayShear`. Shapes that intersect in the
+    /// ray's sheared space, like triangles, use it; the rest just call `intersect`.
+    fn intersect_sheared(&self, ray: &Ray, _shear: &RayShear) -> Option<(Float, SurfaceInteraction)> {
+        self.intersect(ray)
+    }
+
+    /// The distance along `ray` to the closest hit, for queries that don't need the interaction.
+    /// By default this calls `intersect_sheared` and discards the interaction.
+    fn intersect_t(&self, ray: &Ray, shear: &RayShear) -> Option<Float> {
+        self.intersect_sheared(ray, shear).map(|(t, _)| t)
+    }
+
     fn intersect_test(&self, ray: &Ray) -> bool {
         self.intersect(ray).is_some()
     }
 
+    /// Like `intersect_test`, given the ray's precomputed `RayShear`. By default this just calls
+    /// `intersect_test`.
+    fn intersect_test_sheared(&self, ray: &Ray, _shear: &RayShear) -> bool {
+        self.intersect_test(ray)
+    }
+
     /// Choose a point on the surface of the shape using a sampling distribution with respect to
     /// surface area.
     fn sample(&self, u: Point2f) -> SurfaceHit;
@@ -53,16 +74,22 @@ pub trait Shape: Sync + Send {
     }
 
     fn pdf_from_ref(&self, reference: &SurfaceHit, wi: Vec3f) -> Float {
-        let ray = reference.spawn_ray(wi);
-
-        if let Some((_, isect_light)) = self.intersect(&ray) {
-            // convert from a density with respect to area to a density with respect
-            // to solid angle
-            distance_sq(reference.p, isect_light.hit.p) /
-                (abs_dot(isect_light.hit.n.0, -wi) * self.area())
-        } else {
-            0.0
-        }
+        area_pdf_from_ref(self, reference, wi)
     }
 
 }
+
+/// The density with respect to solid angle at `reference` of choosing a point uniformly by area on
+/// `shape`, in the direction `wi`.
+pub(crate) fn area_pdf_from_ref<S: Shape + ?Sized>(shape: &S, reference: &SurfaceHit, wi: Vec3f) -> Float {
+    let ray = reference.spawn_ray(wi);
+
+    if let Some((_, isect_light)) = shape.intersect(&ray) {
+        // convert from a density with respect to area to a density with respect
+        // to solid angle
+        distance_sq(reference.p, isect_light.hit.p) /
+            (abs_dot(isect_light.hit.n.0, -wi) * shape.area())
+    } else {
+        0.0
+    }
+}
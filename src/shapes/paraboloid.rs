@@ -0,0 +1,263 @@
+use cgmath::InnerSpace;
+
+use crate::{lerp, Float, Normal3, Point2f, Point3f, Vec3f};
+use crate::EFloat;
+use crate::err_float::gamma;
+use crate::geometry::{Ray, Transform};
+use crate::geometry::bounds::Bounds3f;
+use crate::interaction::{DiffGeom, SurfaceHit, SurfaceInteraction};
+use crate::math::quadratic;
+use crate::shapes::Shape;
+
+/// The paraboloid `z = z_max * (x^2 + y^2) / radius^2` between `z_min` and `z_max`, swept `phi_max`
+/// around the z axis.
+#[derive(Debug)]
+pub struct Paraboloid {
+    object_to_world: Transform,
+    world_to_object: Transform,
+    reverse_orientation: bool,
+
+    radius: Float,
+    z_min: Float,
+    z_max: Float,
+    phi_max: Float,
+}
+
+impl Paraboloid {
+    pub fn new(
+        object_to_world: Transform,
+        world_to_object: Transform,
+        reverse_orientation: bool,
+        radius: Float,
+        z_min: Float,
+        z_max: Float,
+        phi_max: Float,
+    ) -> Self {
+        Self {
+            object_to_world, world_to_object, reverse_orientation,
+            radius,
+            z_min: Float::min(z_min, z_max),
+            z_max: Float::max(z_min, z_max),
+            phi_max: phi_max.clamp(0.0, 360.0).to_radians(),
+        }
+    }
+
+    /// The hit point and its azimuth for the ray at `t`, or None if it is clipped.
+    fn hit_at(&self, ray: &Ray, t: EFloat, o: [EFloat; 3], d: [EFloat; 3]) -> Option<(Point3f, Vec3f, Float)> {
+        let px = o[0] + t * d[0];
+        let py = o[1] + t * d[1];
+        let pz = o[2] + t * d[2];
+        let p_hit = ray.at(t.into());
+        let mut phi = Float::atan2(p_hit.y, p_hit.x);
+        if phi < 0.0 { phi += 2.0 * std::f32::consts::PI }
+
+        if p_hit.z < self.z_min || p_hit.z > self.z_max || phi > self.phi_max {
+            return None;
+        }
+        let p_err = Vec3f::new(px.absolute_err(), py.absolute_err(), pz.absolute_err());
+        Some((p_hit, p_err, phi))
+    }
+}
+
+impl Shape for Paraboloid {
+    fn object_bound(&self) -> Bounds3f {
+        Bounds3f::with_bounds(
+            Point3f::new(-self.radius, -self.radius, self.z_min),
+            Point3f::new(self.radius, self.radius, self.z_max),
+        )
+    }
+
+    fn object_to_world(&self) -> &Transform {
+        &self.object_to_world
+    }
+
+    fn world_to_object(&self) -> &Transform {
+        &self.world_to_object
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+
+    fn area(&self) -> Float {
+        let radius2 = self.radius * self.radius;
+        let k = 4.0 * self.z_max / radius2;
+        (radius2 * radius2 * self.phi_max / (12.0 * self.z_max * self.z_max))
+            * (Float::powf(k * self.z_max + 1.0, 1.5) - Float::powf(k * self.z_min + 1.0, 1.5))
+    }
+
+    #[allow(non_snake_case)]
+    #[allow(clippy::many_single_char_names)]
+    fn intersect(&self, ray: &Ray) -> Option<(Float, SurfaceInteraction)> {
+        let (ray, (origin_err, dir_err)) = self.world_to_object().tf_exact_to_err(*ray);
+
+        let ox = EFloat::with_err(ray.origin.x, origin_err.x);
+        let oy = EFloat::with_err(ray.origin.y, origin_err.y);
+        let oz = EFloat::with_err(ray.origin.z, origin_err.z);
+        let dirx = EFloat::with_err(ray.dir.x, dir_err.x);
+        let diry = EFloat::with_err(ray.dir.y, dir_err.y);
+        let dirz = EFloat::with_err(ray.dir.z, dir_err.z);
+
+        let k = EFloat::new(self.z_max) / (EFloat::new(self.radius) * EFloat::new(self.radius));
+        let a = k * (dirx * dirx + diry * diry);
+        let b = 2.0 * k * (dirx * ox + diry * oy) - dirz;
+        let c = k * (ox * ox + oy * oy) - oz;
+
+        let (t0, t1) = quadratic(a, b, c)?;
+
+        if t0.upper_bound() > ray.t_max || t1.lower_bound() <= 0.0 {
+            return None;
+        }
+
+        let o = [ox, oy, oz];
+        let d = [dirx, diry, dirz];
+
+        // find the closest valid intersection that isn't clipped
+        let (t_shape_hit, (p_hit, p_err, phi)) = if t0.lower_bound() > 0.0 {
+            match self.hit_at(&ray, t0, o, d) {
+                Some(hit) => (t0, hit),
+                None if t1.upper_bound() <= ray.t_max => (t1, self.hit_at(&ray, t1, o, d)?),
+                None => return None,
+            }
+        } else {
+            if t1.upper_bound() > ray.t_max {
+                return None;
+            }
+            (t1, self.hit_at(&ray, t1, o, d)?)
+        };
+
+        let u = phi / self.phi_max;
+        let v = (p_hit.z - self.z_min) / (self.z_max - self.z_min);
+
+        // dpdu vanishes and dpdv is undefined at the vertex, so evaluate the derivatives at a point
+        // of the surface just next to it
+        let (x, y) = if p_hit.x == 0.0 && p_hit.y == 0.0 {
+            (1.0e-5 * self.radius, 0.0)
+        } else {
+            (p_hit.x, p_hit.y)
+        };
+        let z = Float::max(p_hit.z, self.z_max * (x * x + y * y) / (self.radius * self.radius));
+        let dz = self.z_max - self.z_min;
+        let dpdu = Vec3f::new(-self.phi_max * y, self.phi_max * x, 0.0);
+        let dpdv = dz * Vec3f::new(x / (2.0 * z), y / (2.0 * z), 1.0);
+
+        let d2pduu = -self.phi_max * self.phi_max * Vec3f::new(x, y, 0.0);
+        let d2pduv = dz * self.phi_max * Vec3f::new(-y / (2.0 * z), x / (2.0 * z), 0.0);
+        let d2pdvv = -dz * dz * Vec3f::new(x / (4.0 * z * z), y / (4.0 * z * z), 0.0);
+
+        let E = dpdu.dot(dpdu);
+        let F = dpdu.dot(dpdv);
+        let G = dpdv.dot(dpdv);
+
+        let mut N = dpdu.cross(dpdv).normalize();
+
+        let e = N.dot(d2pduu);
+        let f = N.dot(d2pduv);
+        let g = N.dot(d2pdvv);
+
+        let invEGF2 = 1.0 / (E * G - F * F);
+
+        let dndu = Normal3((f * F - e * G) * invEGF2 * dpdu + (e * F - f * E) * invEGF2 * dpdv);
+
+        let dndv = Normal3((g * F - f * G) * invEGF2 * dpdu + (f * F - g * E) * invEGF2 * dpdv);
+
+        if self.reverse_orientation() {
+            N *= -1.0;
+        }
+
+        let interact = SurfaceInteraction::new(
+            p_hit,
+            p_err,
+            ray.time,
+            Point2f::new(u, v),
+            -ray.dir,
+            Normal3(N),
+            DiffGeom { dpdu, dpdv, dndu, dndv }
+        );
+
+        let world_intersect = self.object_to_world().transform(interact);
+
+        Some((t_shape_hit.into(), world_intersect))
+    }
+
+    fn sample(&self, u: Point2f) -> SurfaceHit {
+        // With k = z_max / radius^2, the area between heights z and z + dz is proportional to
+        // sqrt(1 + 4kz) dz, so (1 + 4kz)^(3/2) is uniform over the surface.
+        let k = self.z_max / (self.radius * self.radius);
+        let w_at = |z: Float| Float::powf(4.0 * k * z + 1.0, 1.5);
+        let w = lerp(u[0], w_at(self.z_min), w_at(self.z_max));
+        let z = ((Float::powf(w, 2.0 / 3.0) - 1.0) / (4.0 * k)).clamp(self.z_min, self.z_max);
+        let r = (z / k).sqrt();
+        let phi = u[1] * self.phi_max;
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let p_obj = Point3f::new(r * cos_phi, r * sin_phi, z);
+        let n_obj = Normal3(Vec3f::new(2.0 * k * p_obj.x, 2.0 * k * p_obj.y, -1.0));
+        let mut n = Normal3(self.object_to_world.transform(n_obj).normalize());
+        if self.reverse_orientation {
+            n *= -1.0;
+        }
+        let p_obj_err = gamma(5) * Vec3f::new(p_obj.x.abs(), p_obj.y.abs(), p_obj.z.abs());
+        let (p, p_err) = self.object_to_world.tf_err_to_err(p_obj, p_obj_err);
+        SurfaceHit {
+            p,
+            p_err,
+            time: 0.0,
+            n
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_axial_rays_reflect_through_focus() {
+        // z = (x^2 + y^2) / 4, with its focus at z = 1
+        let paraboloid = Paraboloid::new(Transform::IDENTITY, Transform::IDENTITY, false, 2.0, 0.0, 1.0, 360.0);
+        let focus = Point3f::new(0.0, 0.0, 1.0);
+
+        for &(x, y) in &[(1.0, 0.0), (0.5, 0.5), (-0.3, 1.2), (1.5, -1.0)] {
+            let ray = Ray::new(Point3f::new(x, y, 5.0), Vec3f::new(0.0, 0.0, -1.0));
+            let (_, isect) = paraboloid.intersect(&ray).expect("Should hit the paraboloid");
+            assert!((isect.hit.p.z - (x * x + y * y) / 4.0).abs() < 1e-5, "{:?}", isect.hit.p);
+
+            // mirror the incoming direction about the normal and check it passes through the focus
+            let n = isect.hit.n.0;
+            let wo = -ray.dir;
+            let wr = -wo + 2.0 * wo.dot(n) * n;
+            let to_focus = (focus - isect.hit.p).normalize();
+            assert!(wr.normalize().dot(to_focus) > 1.0 - 1e-5, "{:?} {:?}", wr, to_focus);
+        }
+
+        // straight down the axis hits the vertex
+        let ray = Ray::new(Point3f::new(0.0, 0.0, 5.0), Vec3f::new(0.0, 0.0, -1.0));
+        let (t, isect) = paraboloid.intersect(&ray).unwrap();
+        assert!((t - 5.0).abs() < 1e-4, "{}", t);
+        assert!(isect.hit.n.0.z.abs() > 0.99, "{:?}", isect.hit.n);
+    }
+
+    #[test]
+    fn test_samples_are_uniform_by_area() {
+        let paraboloid = Paraboloid::new(Transform::IDENTITY, Transform::IDENTITY, false, 2.0, 0.25, 1.0, 270.0);
+        for &(u0, u1) in &[(0.0, 0.0), (0.1, 0.2), (0.5, 0.5), (0.9, 0.75), (1.0, 0.99)] {
+            let hit = paraboloid.sample(Point2f::new(u0, u1));
+            let p = hit.p;
+            assert!((p.z - (p.x * p.x + p.y * p.y) / 4.0).abs() < 1e-5, "{:?}", p);
+            assert!(p.z >= 0.25 - 1e-5 && p.z <= 1.0 + 1e-5, "{:?}", p);
+
+            // the normal agrees with the one found by intersecting the same point
+            let ray = Ray::new(p + Vec3f::new(0.0, 0.0, 5.0), Vec3f::new(0.0, 0.0, -1.0));
+            let (_, isect) = paraboloid.intersect(&ray).expect("Should hit the sampled point");
+            assert!(hit.n.0.dot(isect.hit.n.0) > 1.0 - 1e-4, "{:?} {:?}", hit.n, isect.hit.n);
+
+            // the part of the surface below the sample is u0 of the whole, found as the area of
+            // the same paraboloid cut off at the sampled height
+            let below = Paraboloid::new(
+                Transform::IDENTITY, Transform::IDENTITY, false, 2.0 * p.z.sqrt(), 0.25, p.z, 270.0
+            );
+            let fraction = if p.z > 0.25 { below.area() / paraboloid.area() } else { 0.0 };
+            assert!((fraction - u0).abs() < 1e-3, "{} {}", fraction, u0);
+        }
+    }
+}
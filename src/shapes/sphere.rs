@@ -1,6 +1,6 @@
 use cgmath::{EuclideanSpace, InnerSpace};
 
-use crate::{ComponentWiseExt, distance, Float, Normal3, Point2f, Vec3f, Point3f};
+use crate::{ComponentWiseExt, distance, distance_sq, coordinate_system, offset_ray_origin, Float, Normal3, Point2f, Vec3f, Point3f};
 use crate::EFloat;
 use crate::err_float::gamma;
 use crate::geometry::{Ray, Transform};
@@ -8,8 +8,8 @@ use crate::geometry::bounds::Bounds3;
 use crate::interaction::{DiffGeom, SurfaceHit};
 use crate::interaction::SurfaceInteraction;
 use crate::math::quadratic;
-use crate::shapes::Shape;
-use crate::sampling::uniform_sample_sphere;
+use crate::shapes::{Shape, area_pdf_from_ref};
+use crate::sampling::{uniform_sample_sphere, uniform_cone_pdf};
 use std::borrow::Borrow;
 
 #[derive(Debug, PartialEq)]
@@ -48,6 +48,19 @@ impl<T: Borrow<Transform>> Sphere<T> {
         }
     }
 
+    /// Returns the sphere's world space center and, if `reference` lies outside the sphere, the
+    /// cosine of the half-angle of the cone it subtends from there.
+    fn subtended_cone(&self, reference: &SurfaceHit) -> (Point3f, Option<Float>) {
+        let p_center = self.object_to_world.borrow().transform(Point3f::new(0.0, 0.0, 0.0));
+        let p_origin = offset_ray_origin(reference.p, reference.p_err, reference.n, p_center - reference.p);
+        if distance_sq(p_origin, p_center) <= self.radius * self.radius {
+            return (p_center, None);
+        }
+        let sin2_theta_max = self.radius * self.radius / distance_sq(reference.p, p_center);
+        let cos_theta_max = Float::max(0.0, 1.0 - sin2_theta_max).sqrt();
+        (p_center, Some(cos_theta_max))
+    }
+
     pub fn whole(
         object_to_world: T,
         world_to_object: T,
@@ -59,7 +72,31 @@ impl<T: Borrow<Transform>> Sphere<T> {
 
 impl<T: Borrow<Transform> + Sync + Send> Shape for Sphere<T> {
     fn object_bound(&self) -> Bounds3<f32> {
-        bounds3f!((-self.radius, -self.radius, self.z_min), (self.radius, self.radius, self.z_max))
+        // the distance from the z axis is largest at the equator (if it's included) and smallest
+        // at one of the clipping planes
+        let r_at = |z: Float| Float::sqrt(Float::max(0.0, self.radius * self.radius - z * z));
+        let r_outer = if self.z_min <= 0.0 && self.z_max >= 0.0 {
+            self.radius
+        } else {
+            Float::max(r_at(self.z_min), r_at(self.z_max))
+        };
+        let r_inner = Float::min(r_at(self.z_min), r_at(self.z_max));
+
+        // the xy extent of the swept annular sector is reached either at the ends of the phi range
+        // or where the outer arc crosses an axis
+        let (sin_phi, cos_phi) = self.phi_max.sin_cos();
+        let mut bounds = Bounds3::empty()
+            .join_point(Point3f::new(r_outer, 0.0, self.z_min))
+            .join_point(Point3f::new(r_inner, 0.0, self.z_max))
+            .join_point(Point3f::new(r_outer * cos_phi, r_outer * sin_phi, self.z_min))
+            .join_point(Point3f::new(r_inner * cos_phi, r_inner * sin_phi, self.z_max));
+        let axis_points = [(0.0, 1.0), (-1.0, 0.0), (0.0, -1.0)];
+        for (i, &(x, y)) in axis_points.iter().enumerate() {
+            if self.phi_max >= (i + 1) as Float * std::f32::consts::FRAC_PI_2 {
+                bounds = bounds.join_point(Point3f::new(x * r_outer, y * r_outer, self.z_min));
+            }
+        }
+        bounds
     }
 
     fn object_to_world(&self) -> &Transform {
@@ -165,7 +202,7 @@ impl<T: Borrow<Transform> + Sync + Send> Shape for Sphere<T> {
         let F = dpdu.dot(dpdv);
         let G = dpdv.dot(dpdv);
 
-        let mut N = dpdu.cross(dpdv).normalize();
+        let N = dpdu.cross(dpdv).normalize();
 
         let e = N.dot(d2pduu);
         let f = N.dot(d2pduv);
@@ -179,11 +216,6 @@ impl<T: Borrow<Transform> + Sync + Send> Shape for Sphere<T> {
 
         let p_err: Vec3f = gamma(5) * p_hit.to_vec().abs();
 
-        // FIXME
-        if self.reverse_orientation() {
-            N *= -1.0;
-        }
-
         let interact = SurfaceInteraction::new(
             p_hit,
             p_err,
@@ -194,7 +226,12 @@ impl<T: Borrow<Transform> + Sync + Send> Shape for Sphere<T> {
             DiffGeom { dpdu, dpdv, dndu, dndv }
         );
 
-        let world_intersect = self.object_to_world().borrow().transform(interact);
+        let mut world_intersect = self.object_to_world().borrow().transform(interact);
+
+        if self.flip_normals() {
+            world_intersect.hit.n *= -1.0;
+            world_intersect.shading_n *= -1.0;
+        }
 
         Some((t_shape_hit.into(), world_intersect))
     }
@@ -217,6 +254,65 @@ impl<T: Borrow<Transform> + Sync + Send> Shape for Sphere<T> {
         }
     }
 
+    /// Samples uniformly within the cone of directions the sphere subtends from `reference`, falling
+    /// back to sampling by area when `reference` is inside the sphere.
+    fn sample_from_ref(&self, reference: &SurfaceHit, u: Point2f) -> SurfaceHit {
+        let (p_center, cos_theta_max) = self.subtended_cone(reference);
+        let cos_theta_max = match cos_theta_max {
+            Some(c) => c,
+            None => return self.sample(u),
+        };
+
+        let dc = distance(reference.p, p_center);
+        let wc = (p_center - reference.p) / dc;
+        let (wc_x, wc_y) = coordinate_system(wc);
+
+        let sin_theta_max = self.radius / dc;
+        let sin2_theta_max = sin_theta_max * sin_theta_max;
+        let mut cos_theta = (cos_theta_max - 1.0) * u[0] + 1.0;
+        let mut sin2_theta = 1.0 - cos_theta * cos_theta;
+        if sin2_theta_max < 0.00068523 {
+            // fall back to a Taylor expansion for small angles, where 1 - cos_theta loses
+            // too much precision
+            sin2_theta = sin2_theta_max * u[0];
+            cos_theta = (1.0 - sin2_theta).sqrt();
+        }
+
+        // angle from the center of the sphere to the sampled point
+        let cos_alpha = sin2_theta / sin_theta_max
+            + cos_theta * Float::max(0.0, 1.0 - sin2_theta / sin2_theta_max).sqrt();
+        let sin_alpha = Float::max(0.0, 1.0 - cos_alpha * cos_alpha).sqrt();
+        let phi = u[1] * 2.0 * std::f32::consts::PI;
+
+        let n_world = -(sin_alpha * phi.cos() * wc_x + sin_alpha * phi.sin() * wc_y + cos_alpha * wc);
+        let p = p_center + self.radius * n_world;
+        let mut n = Normal3(n_world);
+        if self.reverse_orientation {
+            n *= -1.0;
+        }
+        SurfaceHit {
+            p,
+            p_err: gamma(5) * p.to_vec().abs(),
+            time: reference.time,
+            n,
+        }
+    }
+
+    fn pdf_from_ref(&self, reference: &SurfaceHit, wi: Vec3f) -> Float {
+        let (p_center, cos_theta_max) = self.subtended_cone(reference);
+        match cos_theta_max {
+            Some(cos_theta_max) => {
+                let wc = (p_center - reference.p).normalize();
+                if wi.normalize().dot(wc) < cos_theta_max {
+                    0.0
+                } else {
+                    uniform_cone_pdf(cos_theta_max)
+                }
+            },
+            None => area_pdf_from_ref(self, reference, wi),
+        }
+    }
+
 //    fn intersect_test(&self, ray: &Ray) -> bool {
 //        unimplemented!()
 //    }
@@ -271,4 +367,92 @@ mod tests {
         let ray = shoot_ray(orig, close_miss);
         assert!(sphere.intersect(&ray).is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_reverse_orientation_normals() {
+        let o2w = Transform::translate((0.0, 0.0, 2.0).into());
+        let w2o = o2w.inverse();
+        let center = Point3f::new(0.0, 0.0, 2.0);
+        let ray = shoot_ray(Point3f::new(3.0, 1.0, 4.0), center);
+
+        let outward = Sphere::new(&o2w, &w2o, false, 1.0, -1.0, 1.0, 360.0);
+        let (_, isect) = outward.intersect(&ray).unwrap();
+        assert!(isect.hit.n.0.dot(isect.hit.p - center) > 0.0);
+
+        let reversed = Sphere::new(&o2w, &w2o, true, 1.0, -1.0, 1.0, 360.0);
+        let (_, isect) = reversed.intersect(&ray).unwrap();
+        assert!(isect.hit.n.0.dot(isect.hit.p - center) < 0.0);
+        assert!(isect.shading_n.0.dot(isect.hit.p - center) < 0.0);
+
+        // a mirroring transform also swaps the orientation, so reversing it again points outward
+        let mirror = o2w * Transform::scale(-1.0, 1.0, 1.0);
+        let mirror_inv = mirror.inverse();
+        let mirrored = Sphere::new(&mirror, &mirror_inv, true, 1.0, -1.0, 1.0, 360.0);
+        let (_, isect) = mirrored.intersect(&ray).unwrap();
+        assert!(isect.hit.n.0.dot(isect.hit.p - center) > 0.0);
+    }
+
+    #[test]
+    fn test_partial_sphere_object_bound() {
+        let o2w = Transform::identity();
+        let w2o = Transform::identity();
+        let full = Sphere::whole(&o2w, &w2o, 2.0).object_bound();
+        assert_eq!(full, bounds3f!((-2, -2, -2), (2, 2, 2)));
+
+        let quarter = Sphere::new(&o2w, &w2o, false, 2.0, 0.0, 2.0, 90.0).object_bound();
+        assert_abs_diff_eq!(quarter.min, Point3f::new(0.0, 0.0, 0.0), epsilon = 1e-5);
+        assert_abs_diff_eq!(quarter.max, Point3f::new(2.0, 2.0, 2.0), epsilon = 1e-5);
+        assert!(quarter.diagonal().x < full.diagonal().x && quarter.diagonal().y < full.diagonal().y);
+
+        // a band that doesn't include the equator never reaches the full radius
+        let cap = Sphere::new(&o2w, &w2o, false, 2.0, 1.0, 2.0, 360.0).object_bound();
+        let r = Float::sqrt(3.0);
+        assert_abs_diff_eq!(cap.min, Point3f::new(-r, -r, 1.0), epsilon = 1e-5);
+        assert_abs_diff_eq!(cap.max, Point3f::new(r, r, 2.0), epsilon = 1e-5);
+    }
+
+    #[test]
+    fn test_cone_pdf_from_outside() {
+        use rand::Rng;
+        use crate::sampling::uniform_sphere_pdf;
+
+        let o2w = Transform::translate(Vec3f::new(0.0, 0.0, 1.0));
+        let w2o = o2w.inverse();
+        let sphere = Sphere::whole(&o2w, &w2o, 1.0);
+        let reference = SurfaceHit {
+            p: Point3f::new(0.0, 0.0, -3.0),
+            p_err: Vec3f::new(0.0, 0.0, 0.0),
+            time: 0.0,
+            n: Normal3(Vec3f::new(0.0, 0.0, 1.0)),
+        };
+        let mut rng = rand::rngs::SmallRng::from_seed([7; 16]);
+        let n = 200_000;
+
+        // sampled points lie on the visible side of the sphere and have the cone pdf
+        let cone_pdf = uniform_cone_pdf(Float::sqrt(1.0 - 1.0 / 16.0));
+        for _ in 0..100 {
+            let u = Point2f::new(rng.gen(), rng.gen());
+            let hit = sphere.sample_from_ref(&reference, u);
+            assert_abs_diff_eq!(distance(hit.p, Point3f::new(0.0, 0.0, 1.0)), 1.0, epsilon = 1e-4);
+            let wi = (hit.p - reference.p).normalize();
+            assert!(hit.n.0.dot(wi) <= 1e-4);
+            assert_abs_diff_eq!(sphere.pdf_from_ref(&reference, wi), cone_pdf, epsilon = 1e-3);
+        }
+
+        // integrate the pdf over the sphere of directions and estimate the solid angle by
+        // counting the directions that hit the sphere
+        let mut integral = 0.0;
+        let mut hits = 0;
+        for _ in 0..n {
+            let wi = uniform_sample_sphere(Point2f::new(rng.gen(), rng.gen()));
+            integral += sphere.pdf_from_ref(&reference, wi) / uniform_sphere_pdf();
+            if sphere.intersect(&reference.spawn_ray(wi)).is_some() {
+                hits += 1;
+            }
+        }
+        let integral = integral / n as Float;
+        let solid_angle = hits as Float / (n as Float * uniform_sphere_pdf());
+        assert!((integral - 1.0).abs() < 0.05, "{}", integral);
+        assert!((solid_angle * cone_pdf - 1.0).abs() < 0.05, "{}", solid_angle);
+    }
+}
@@ -1,5 +1,8 @@
-use crate::{Point3f, Transform, Bounds3f, Ray, Float, SurfaceInteraction, Normal3, Vec3f, Point2f, ComponentWiseExt, max_dimension, permute_vec, permute_point, coordinate_system, faceforward};
+use crate::{Point3f, Transform, Bounds3f, Ray, Float, SurfaceInteraction, Normal3, Vec3f, Vec2f, Point2f, ComponentWiseExt, permute_point, coordinate_system, faceforward};
+use crate::geometry::RayShear;
 use std::sync::Arc;
+use std::collections::HashMap;
+use rayon::prelude::*;
 use crate::shapes::Shape;
 use cgmath::{EuclideanSpace, InnerSpace};
 use crate::interaction::{DiffGeom, SurfaceHit};
@@ -23,6 +26,8 @@ pub struct TriangleMesh {
     reverse_orientation: bool,
 
     object_to_world: Transform,
+
+    world_to_object: Transform,
 }
 
 impl TriangleMesh {
@@ -39,22 +44,17 @@ impl TriangleMesh {
         let n_triangles = vertex_indices.len() as u32 / 3;
         let n_vertices = vertices.len();
 
-        for v in &mut vertices {
-            *v = object_to_world.transform(*v);
-        }
+        // these are independent per vertex, and large meshes can have millions of them
+        vertices.par_iter_mut().for_each(|v| *v = object_to_world.transform(*v));
 
         if let Some(ref mut normals) = normals {
             assert_eq!(normals.len(), n_vertices);
-            for n in normals {
-                *n = object_to_world.transform(*n);
-            }
+            normals.par_iter_mut().for_each(|n| *n = object_to_world.transform(*n));
         }
 
         if let Some(ref mut tangents) = tangents {
             assert_eq!(tangents.len(), n_vertices);
-            for t in tangents {
-                *t = object_to_world.transform(*t);
-            }
+            tangents.par_iter_mut().for_each(|t| *t = object_to_world.transform(*t));
         }
 
         if let Some(ref tex_coords) = tex_coords {
@@ -69,10 +69,139 @@ impl TriangleMesh {
             tangents,
             tex_coords,
             reverse_orientation,
-            object_to_world
+            object_to_world,
+            world_to_object: object_to_world.inverse(),
         }
     }
 
+    /// Merges vertices that lie within `epsilon` of each other and remaps the vertex indices to the
+    /// merged vertices. The normals, tangents and texture coordinates of merged vertices are
+    /// averaged, and triangles that collapse to a line or point are removed.
+    pub fn weld(&mut self, epsilon: Float) {
+        // bucket vertices into a grid of cells `epsilon` wide, so that any vertex within `epsilon`
+        // of another is in the same or a neighboring cell
+        let cell_of = |p: Point3f| -> [i64; 3] {
+            if epsilon > 0.0 {
+                [(p.x / epsilon).floor() as i64, (p.y / epsilon).floor() as i64, (p.z / epsilon).floor() as i64]
+            } else {
+                [p.x.to_bits() as i64, p.y.to_bits() as i64, p.z.to_bits() as i64]
+            }
+        };
+        let neighbors: &[i64] = if epsilon > 0.0 { &[-1, 0, 1] } else { &[0] };
+
+        let mut grid: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        let mut remap = Vec::with_capacity(self.vertices.len());
+        let mut welded: Vec<Point3f> = Vec::new();
+        let mut counts: Vec<u32> = Vec::new();
+        for &p in &self.vertices {
+            let cell = cell_of(p);
+            let mut found = None;
+            'search: for &dx in neighbors {
+                for &dy in neighbors {
+                    for &dz in neighbors {
+                        let key = [cell[0] + dx, cell[1] + dy, cell[2] + dz];
+                        let candidates = grid.get(&key).map(|v| v.as_slice()).unwrap_or(&[]);
+                        for &j in candidates {
+                            if (welded[j as usize] - p).magnitude2() <= epsilon * epsilon {
+                                found = Some(j);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let j = match found {
+                Some(j) => j,
+                None => {
+                    let j = welded.len() as u32;
+                    welded.push(p);
+                    counts.push(0);
+                    grid.entry(cell).or_insert_with(Vec::new).push(j);
+                    j
+                }
+            };
+            counts[j as usize] += 1;
+            remap.push(j);
+        }
+
+        let n_welded = welded.len();
+        if let Some(ref mut normals) = self.normals {
+            let mut sums = vec![Vec3f::new(0.0, 0.0, 0.0); n_welded];
+            for (n, &j) in normals.iter().zip(&remap) {
+                sums[j as usize] += n.0;
+            }
+            *normals = sums.into_iter()
+                .map(|n| Normal3(if n.magnitude2() > 0.0 { n.normalize() } else { n }))
+                .collect();
+        }
+        if let Some(ref mut tangents) = self.tangents {
+            let mut sums = vec![Vec3f::new(0.0, 0.0, 0.0); n_welded];
+            for (t, &j) in tangents.iter().zip(&remap) {
+                sums[j as usize] += *t;
+            }
+            *tangents = sums.into_iter()
+                .zip(&counts)
+                .map(|(t, &c)| t / c as Float)
+                .collect();
+        }
+        if let Some(ref mut tex_coords) = self.tex_coords {
+            let mut sums = vec![Vec2f::new(0.0, 0.0); n_welded];
+            for (uv, &j) in tex_coords.iter().zip(&remap) {
+                sums[j as usize] += uv.to_vec();
+            }
+            *tex_coords = sums.into_iter()
+                .zip(&counts)
+                .map(|(uv, &c)| Point2f::from_vec(uv / c as Float))
+                .collect();
+        }
+
+        let vertex_indices: Vec<u32> = self.vertex_indices
+            .chunks_exact(3)
+            .map(|tri| [remap[tri[0] as usize], remap[tri[1] as usize], remap[tri[2] as usize]])
+            .filter(|tri| tri[0] != tri[1] && tri[1] != tri[2] && tri[0] != tri[2])
+            .flat_map(|tri| tri.to_vec())
+            .collect();
+        self.n_triangles = vertex_indices.len() as u32 / 3;
+        self.vertex_indices = vertex_indices;
+        self.vertices = welded;
+    }
+
+    /// Gives a mesh without normals smooth shading by generating per-vertex normals, averaging the
+    /// normals of the triangles around each vertex weighted by their areas. The normals face the
+    /// same way as the triangles' geometric normals. Triangles only share vertices that are shared
+    /// by index, so meshes with duplicated vertices may need to be welded first.
+    pub fn generate_smooth_normals(&mut self) {
+        if self.normals.is_some() {
+            return;
+        }
+        let flip = self.reverse_orientation ^ self.object_to_world.swaps_handedness();
+        let mut sums = vec![Vec3f::new(0.0, 0.0, 0.0); self.vertices.len()];
+        for tri in self.vertex_indices.chunks_exact(3) {
+            let p0 = self.vertices[tri[0] as usize];
+            let p1 = self.vertices[tri[1] as usize];
+            let p2 = self.vertices[tri[2] as usize];
+            // the same orientation as the geometric normal in `intersect`, with a length of twice
+            // the triangle's area
+            let n = (p0 - p2).cross(p1 - p2);
+            for &i in tri {
+                sums[i as usize] += n;
+            }
+        }
+        let normals = sums.into_iter()
+            .map(|n| {
+                let n = if n.magnitude2() > 0.0 { n.normalize() } else { n };
+                Normal3(if flip { -n } else { n })
+            })
+            .collect();
+        self.normals = Some(normals);
+    }
+
+    /// Bounds of all of the mesh's vertices, which are stored in world space.
+    pub fn world_bound(&self) -> Bounds3f {
+        self.vertices.iter().fold(Bounds3f::empty(), |b, &p| b.join_point(p))
+    }
+
     pub fn iter_triangles(self: Arc<Self>) -> impl Iterator<Item=Triangle> {
         (0..self.n_triangles).map(move |tri_id| {
             Triangle::new(Arc::clone(&self), tri_id)
@@ -141,39 +270,10 @@ impl Triangle {
             }
         )
     }
-}
 
-impl Shape for Triangle {
-    fn object_bound(&self) -> Bounds3f {
-        unimplemented!()
-    }
-
-    fn world_bound(&self) -> Bounds3f {
-        let v = self.vertex_indices();
-        let p0 = self.mesh.vertices[v[0] as usize];
-        let p1 = self.mesh.vertices[v[1] as usize];
-        let p2 = self.mesh.vertices[v[2] as usize];
-        Bounds3f::empty().join_point(p0).join_point(p1).join_point(p2)
-    }
-
-    fn object_to_world(&self) -> &Transform {
-        &self.mesh.object_to_world
-    }
-
-    fn world_to_object(&self) -> &Transform {
-        unimplemented!()
-    }
-
-    fn reverse_orientation(&self) -> bool {
-        self.mesh.reverse_orientation
-    }
-
-    fn area(&self) -> Float {
-        let [p0, p1, p2] = self.get_vertices();
-        0.5 * (p1 - p0).cross(p2 - p0).magnitude()
-    }
-
-    fn intersect(&self, ray: &Ray) -> Option<(Float, SurfaceInteraction)> {
+    /// The distance along `ray` to the triangle and the barycentric coordinates of the hit, found
+    /// in the space where the ray is sheared onto the +z axis.
+    fn intersect_barycentric(&self, ray: &Ray, shear: &RayShear) -> Option<(Float, [Float; 3])> {
         let v = self.vertex_indices();
         let p0 = self.mesh.vertices[v[0] as usize];
         let p1 = self.mesh.vertices[v[1] as usize];
@@ -187,11 +287,8 @@ impl Shape for Triangle {
         let mut p1t = p1 - ray.origin.to_vec();
         let mut p2t = p2 - ray.origin.to_vec();
 
-        // permute components of triangle vertices and ray dir
-        let kz = max_dimension(ray.dir.abs());
-        let kx = (kz + 1) % 3;
-        let ky = (kx + 1) % 3;
-        let dir = permute_vec(ray.dir, kx, ky, kz);
+        // permute components of triangle vertices
+        let RayShear { kx, ky, kz, shear_x, shear_y, shear_z } = *shear;
         p0t = permute_point(p0t, kx, ky, kz);
         p1t = permute_point(p1t, kx, ky, kz);
         p2t = permute_point(p2t, kx, ky, kz);
@@ -199,10 +296,6 @@ impl Shape for Triangle {
         // Apply a shear transformation to align the ray with the +z axis.
         // Only shear the x and y dimensions of the vertices at first, wait to apply the z shear
         // if the ray actually intersects the triangle.
-        // TODO: cache shear coefficients in ray
-        let shear_x = -dir.x / dir.z;
-        let shear_y = -dir.y / dir.z;
-        let shear_z = 1.0 / dir.z;
         p0t.x += shear_x * p0t.z;
         p0t.y += shear_y * p0t.z;
         p1t.x += shear_x * p1t.z;
@@ -267,6 +360,63 @@ impl Shape for Triangle {
             inv_det.abs();
         if t <= delta_t { return None; }
 
+        Some((t, [b0, b1, b2]))
+    }
+}
+
+impl Shape for Triangle {
+    fn object_bound(&self) -> Bounds3f {
+        unimplemented!()
+    }
+
+    fn world_bound(&self) -> Bounds3f {
+        let v = self.vertex_indices();
+        let p0 = self.mesh.vertices[v[0] as usize];
+        let p1 = self.mesh.vertices[v[1] as usize];
+        let p2 = self.mesh.vertices[v[2] as usize];
+        Bounds3f::empty().join_point(p0).join_point(p1).join_point(p2)
+    }
+
+    fn object_to_world(&self) -> &Transform {
+        &self.mesh.object_to_world
+    }
+
+    fn world_to_object(&self) -> &Transform {
+        &self.mesh.world_to_object
+    }
+
+    fn reverse_orientation(&self) -> bool {
+        self.mesh.reverse_orientation
+    }
+
+    fn area(&self) -> Float {
+        let [p0, p1, p2] = self.get_vertices();
+        0.5 * (p1 - p0).cross(p2 - p0).magnitude()
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<(Float, SurfaceInteraction)> {
+        self.intersect_sheared(ray, &RayShear::new(ray))
+    }
+
+    fn intersect_t(&self, ray: &Ray, shear: &RayShear) -> Option<Float> {
+        self.intersect_barycentric(ray, shear).map(|(t, _)| t)
+    }
+
+    fn intersect_test(&self, ray: &Ray) -> bool {
+        self.intersect_test_sheared(ray, &RayShear::new(ray))
+    }
+
+    fn intersect_test_sheared(&self, ray: &Ray, shear: &RayShear) -> bool {
+        self.intersect_barycentric(ray, shear).is_some()
+    }
+
+    fn intersect_sheared(&self, ray: &Ray, shear: &RayShear) -> Option<(Float, SurfaceInteraction)> {
+        let (t, [b0, b1, b2]) = self.intersect_barycentric(ray, shear)?;
+        let v = self.vertex_indices();
+        let p0 = self.mesh.vertices[v[0] as usize];
+        let p1 = self.mesh.vertices[v[1] as usize];
+        let p2 = self.mesh.vertices[v[2] as usize];
+
         // compute triangle partial derivatives.
         let uv = self.get_uvs();
         let duv02 = uv[0] - uv[2];
@@ -453,4 +603,126 @@ mod tests {
     fn test_tri_isect() {
 
     }
+
+    #[test]
+    fn test_world_to_object_round_trip() {
+        let o2w = Transform::translate(Vec3f::new(1.0, -2.0, 3.0)) * Transform::scale(2.0, 2.0, 2.0);
+        let vertices = vec![Point3f::new(0.0, 0.0, 0.0), Point3f::new(1.0, 0.0, 0.0), Point3f::new(0.0, 1.0, 0.0)];
+        let mesh = Arc::new(TriangleMesh::new(o2w, vec![0, 1, 2], vertices, None, None, None, false));
+        let tri = mesh.iter_triangles().next().unwrap();
+
+        let p = Point3f::new(0.5, 4.0, -1.0);
+        let round_trip = tri.object_to_world().transform(tri.world_to_object().transform(p));
+        assert!((round_trip - p).magnitude() < 1e-5, "{:?}", round_trip);
+        let origin = tri.world_to_object().transform(Point3f::new(1.0, -2.0, 3.0));
+        assert!(origin.to_vec().magnitude() < 1e-5, "{:?}", origin);
+    }
+
+    #[test]
+    fn test_cached_shear_matches_uncached() {
+        let vertices = vec![
+            Point3f::new(-1.0, -1.0, 0.0), Point3f::new(1.0, -1.0, 0.2), Point3f::new(1.0, 1.0, -0.3),
+            Point3f::new(-1.0, 1.0, 0.1), Point3f::new(0.0, 0.0, 1.0),
+        ];
+        let indices = vec![0, 1, 2, 0, 2, 3, 0, 1, 4, 2, 3, 4];
+        let mesh = Arc::new(TriangleMesh::new(
+            Transform::identity(), indices, vertices, None, None, None, false
+        ));
+        let triangles: Vec<Triangle> = mesh.iter_triangles().collect();
+
+        let mut n_hits = 0;
+        for i in 0..200 {
+            let t = i as Float * 0.1;
+            let origin = Point3f::new(3.0 * t.cos(), 3.0 * t.sin(), 2.0 * (t * 0.7).sin());
+            let target = Point3f::new(0.3 * (t * 1.3).sin(), 0.3 * (t * 1.7).cos(), 0.2);
+            let ray = Ray::new(origin, target - origin);
+
+            let shear = RayShear::new(&ray);
+            for tri in &triangles {
+                let cached = tri.intersect_sheared(&ray, &shear);
+                let uncached = tri.intersect(&ray);
+                assert_eq!(cached.is_some(), uncached.is_some());
+                assert_eq!(tri.intersect_t(&ray, &shear), cached.as_ref().map(|(t, _)| *t));
+                assert_eq!(tri.intersect_test_sheared(&ray, &shear), cached.is_some());
+                if let (Some((t0, isect0)), Some((t1, isect1))) = (cached, uncached) {
+                    n_hits += 1;
+                    assert_eq!(t0, t1);
+                    assert_eq!(isect0.hit.p, isect1.hit.p);
+                    assert_eq!(isect0.uv, isect1.uv);
+                }
+            }
+        }
+        assert!(n_hits > 0);
+    }
+
+    #[test]
+    fn test_parallel_transform_matches_serial() {
+        let n = 20_000;
+        let vertices: Vec<Point3f> = (0..n)
+            .map(|i| {
+                let t = i as Float * 0.01;
+                Point3f::new(t.sin() * 3.0, t.cos() * 2.0, t * 0.1)
+            })
+            .collect();
+        let normals: Vec<Normal3> = vertices.iter().map(|&p| Normal3(p - Point3f::new(0.0, 0.0, 1.0))).collect();
+        let indices: Vec<u32> = (0..n as u32 - 2).flat_map(|i| vec![i, i + 1, i + 2]).collect();
+        let tf = Transform::translate(Vec3f::new(1.0, -2.0, 3.0))
+            * Transform::rotate_x(cgmath::Deg(30.0))
+            * Transform::scale(2.0, 1.0, 0.5);
+
+        let mesh = TriangleMesh::new(tf, indices, vertices.clone(), Some(normals.clone()), None, None, false);
+        for (i, (p, n)) in vertices.iter().zip(&normals).enumerate() {
+            assert_eq!(mesh.vertices[i], tf.transform(*p));
+            assert_eq!(mesh.normals.as_ref().unwrap()[i], tf.transform(*n));
+        }
+    }
+
+    #[test]
+    fn test_mesh_world_bound() {
+        let vertices = vec![
+            Point3f::new(-1.0, -1.0, 0.0), Point3f::new(1.0, -1.0, 0.2), Point3f::new(1.0, 1.0, -0.25),
+            Point3f::new(-1.0, 1.0, 0.1), Point3f::new(0.0, 0.0, 1.0),
+        ];
+        let indices = vec![0, 1, 2, 0, 2, 3, 0, 1, 4, 2, 3, 4];
+        let o2w = Transform::translate(Vec3f::new(2.0, 0.0, -1.0)) * Transform::scale(1.0, 3.0, 1.0);
+        let mesh = Arc::new(TriangleMesh::new(o2w, indices, vertices, None, None, None, false));
+
+        let bound = mesh.world_bound();
+        let union = mesh.clone().iter_triangles()
+            .fold(Bounds3f::empty(), |b, tri| b.join(&tri.world_bound()));
+        assert_eq!(bound, union);
+        assert_eq!(bound.min, Point3f::new(1.0, -3.0, -1.25));
+        assert_eq!(bound.max, Point3f::new(3.0, 3.0, 0.0));
+    }
+
+    #[test]
+    fn test_weld_coincident_vertices() {
+        // two triangles forming a quad, where the shared corner at (1, 1) is duplicated
+        let vertices = vec![
+            Point3f::new(0.0, 0.0, 0.0), Point3f::new(1.0, 0.0, 0.0), Point3f::new(1.0, 1.0, 0.0),
+            Point3f::new(0.0, 0.0, 0.0), Point3f::new(1.0, 1.0 + 1e-7, 0.0), Point3f::new(0.0, 1.0, 0.0),
+        ];
+        let normals = vec![
+            Normal3(Vec3f::new(0.0, 0.0, 1.0)), Normal3(Vec3f::new(0.0, 0.0, 1.0)), Normal3(Vec3f::new(1.0, 0.0, 0.0)),
+            Normal3(Vec3f::new(0.0, 0.0, 1.0)), Normal3(Vec3f::new(0.0, 1.0, 0.0)), Normal3(Vec3f::new(0.0, 0.0, 1.0)),
+        ];
+        let make_mesh = || TriangleMesh::new(
+            Transform::identity(), vec![0, 1, 2, 3, 4, 5], vertices.clone(), Some(normals.clone()), None, None, false
+        );
+
+        // exact welding merges only the bitwise identical origin
+        let mut exact = make_mesh();
+        exact.weld(0.0);
+        assert_eq!(exact.vertices.len(), 5);
+        assert_eq!(exact.vertex_indices, vec![0, 1, 2, 0, 3, 4]);
+
+        let mut mesh = make_mesh();
+        mesh.weld(1e-5);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.n_triangles, 2);
+        assert_eq!(mesh.vertex_indices, vec![0, 1, 2, 0, 2, 3]);
+        let n = mesh.normals.as_ref().unwrap()[2].0;
+        let expected = Vec3f::new(1.0, 1.0, 0.0).normalize();
+        assert!((n - expected).magnitude() < 1e-6);
+    }
 }
\ No newline at end of file
@@ -89,6 +89,14 @@ impl<const N: usize> CoefficientSpectrum<{N}> {
         Self::new_with(|i| self[i].sqrt())
     }
 
+    pub fn powf(self, e: Float) -> Self {
+        self.map(|v| v.powf(e))
+    }
+
+    pub fn exp(self) -> Self {
+        self.map(Float::exp)
+    }
+
     pub fn clamp(self, low: Float, high: Float) -> Self {
         Self::new_with(|i| self[i].clamp(low, high))
     }
@@ -116,6 +124,10 @@ impl CoefficientSpectrum<3> {
        rgb_to_xyz(self.0)
     }
 
+    pub fn from_xyz(xyz: [Float; 3]) -> Self {
+        Self(xyz_to_rgb(xyz))
+    }
+
     pub fn to_rgb(self) -> [Float; 3] {
         self.0
     }
@@ -299,6 +311,30 @@ impl<const N: usize> approx::UlpsEq for CoefficientSpectrum<{N}> {
     }
 }
 
+/// RGB approximations of the complex index of refraction of common conductors, under the names
+/// pbrt scenes use for them.
+#[allow(clippy::excessive_precision)]
+const NAMED_SPECTRA: &[(&str, Spectrum)] = &[
+    ("metal-Ag-eta", Spectrum::new([0.155265, 0.116723, 0.138342])),
+    ("metal-Ag-k", Spectrum::new([4.828350, 3.122250, 2.146960])),
+    ("metal-Al-eta", Spectrum::new([1.657460, 0.880369, 0.521229])),
+    ("metal-Al-k", Spectrum::new([9.223870, 6.269520, 4.837000])),
+    ("metal-Au-eta", Spectrum::new([0.143119, 0.374957, 1.442480])),
+    ("metal-Au-k", Spectrum::new([3.983160, 2.385720, 1.603220])),
+    ("metal-Cu-eta", Spectrum::new([0.200438, 0.924033, 1.102210])),
+    ("metal-Cu-k", Spectrum::new([3.912950, 2.452850, 2.142190])),
+    ("metal-CuZn-eta", Spectrum::new([0.444, 0.527, 1.094])),
+    ("metal-CuZn-k", Spectrum::new([3.695, 2.765, 1.829])),
+];
+
+/// Look up one of the named spectra that scenes may refer to instead of giving values, e.g.
+/// `"metal-Au-eta"`.
+pub fn named_spectrum(name: &str) -> Option<Spectrum> {
+    NAMED_SPECTRA.iter()
+        .find(|(n, _)| *n == name)
+        .map(|&(_, s)| s)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -310,4 +346,29 @@ mod tests {
         let sum: Spectrum = spectra.into_iter().sum();
         assert_eq!(sum, Spectrum::from([1.0, 2.0, 1.5]));
     }
+
+    #[test]
+    fn test_powf_exp() {
+        let s = Spectrum::from([0.5, 2.0, 3.0]);
+        assert_eq!(s.powf(2.0), s * s);
+        assert_eq!(s.powf(0.0), Spectrum::uniform(1.0));
+        assert_eq!(Spectrum::uniform(0.0).exp(), Spectrum::uniform(1.0));
+    }
+
+    #[test]
+    fn test_named_spectrum() {
+        let au = named_spectrum("metal-Au-eta").unwrap();
+        assert!(au[2] > au[0]);
+        assert!(named_spectrum("metal-Au-k").is_some());
+        assert!(named_spectrum("metal-Unobtainium-eta").is_none());
+    }
+
+    #[test]
+    fn test_xyz_round_trip() {
+        use approx::assert_relative_eq;
+        for &rgb in &[[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [0.2, 0.5, 0.9], [3.0, 0.1, 0.0]] {
+            let s = Spectrum::from(rgb);
+            assert_relative_eq!(Spectrum::from_xyz(s.to_xyz()), s, epsilon = 1e-4, max_relative = 1e-4);
+        }
+    }
 }
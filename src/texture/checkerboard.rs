@@ -1,5 +1,5 @@
 use crate::texture::{Texture, ConstantTexture};
-use crate::texture::mapping::{TexCoordsMap2D, TexCoords, UVMapping};
+use crate::texture::mapping::{TexCoordsMap2D, TexCoords, UVMapping, TexCoordsMap3D, TexCoords3D};
 use crate::SurfaceInteraction;
 use crate::spectrum::Spectrum;
 
@@ -63,3 +63,82 @@ impl<T1, T2, M> Texture for Checkerboard2DTexture<T1, T2, M>
         }
     }
 }
+
+/// Alternates between two textures in a 3D grid of unit cubes in texture space, for solid
+/// texturing that doesn't depend on the surface's uvs.
+pub struct Checkerboard3DTexture<T1, T2, M>
+    where
+        T1: Texture,
+        T2: Texture<Output=T1::Output>,
+        M: TexCoordsMap3D
+{
+    tex1: T1,
+    tex2: T2,
+    mapping: M,
+}
+
+impl<T1, T2, M> Checkerboard3DTexture<T1, T2, M>
+    where
+        M: TexCoordsMap3D,
+        T1: Texture,
+        T2: Texture<Output=T1::Output>
+{
+    pub fn new(tex1: T1, tex2: T2, mapping: M) -> Self {
+        Self { tex1, tex2, mapping }
+    }
+}
+
+impl<T1, T2, M> Texture for Checkerboard3DTexture<T1, T2, M>
+    where
+        M: TexCoordsMap3D,
+        T1: Texture,
+        T2: Texture<Output=T1::Output>
+{
+    type Output = T1::Output;
+
+    fn evaluate(&self, si: &SurfaceInteraction) -> Self::Output {
+        let TexCoords3D { p, .. } = self.mapping.evaluate(si);
+        if (p.x.floor() as i32 + p.y.floor() as i32 + p.z.floor() as i32) % 2 == 0 {
+            self.tex1.evaluate(si)
+        } else {
+            self.tex2.evaluate(si)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point2f, Point3f, Transform, Vec3f, Normal3};
+    use crate::interaction::DiffGeom;
+    use crate::texture::mapping::IdentityMapping3D;
+
+    fn interaction_at(p: Point3f, uv: Point2f) -> SurfaceInteraction<'static> {
+        let geom = DiffGeom {
+            dpdu: Vec3f::new(1.0, 0.0, 0.0),
+            dpdv: Vec3f::new(0.0, 1.0, 0.0),
+            dndu: Normal3::new(0.0, 0.0, 0.0),
+            dndv: Normal3::new(0.0, 0.0, 0.0),
+        };
+        SurfaceInteraction::new(p, Vec3f::new(0.0, 0.0, 0.0), 0.0, uv, Vec3f::new(0.0, 0.0, 1.0), Normal3::new(0.0, 0.0, 1.0), geom)
+    }
+
+    #[test]
+    fn test_3d_checkerboard_uses_position() {
+        let black = Spectrum::uniform(0.0);
+        let white = Spectrum::uniform(1.0);
+        let checker = Checkerboard3DTexture::new(
+            ConstantTexture(black),
+            ConstantTexture(white),
+            IdentityMapping3D::new(Transform::scale(2.0, 2.0, 2.0)),
+        );
+
+        // the same uv everywhere, and cells half a unit wide in world space
+        let uv = Point2f::new(0.25, 0.25);
+        assert_eq!(checker.evaluate(&interaction_at(Point3f::new(0.1, 0.1, 0.1), uv)), black);
+        assert_eq!(checker.evaluate(&interaction_at(Point3f::new(0.6, 0.1, 0.1), uv)), white);
+        assert_eq!(checker.evaluate(&interaction_at(Point3f::new(0.6, 0.6, 0.1), uv)), black);
+        assert_eq!(checker.evaluate(&interaction_at(Point3f::new(0.6, 0.6, 0.6), uv)), white);
+        assert_eq!(checker.evaluate(&interaction_at(Point3f::new(0.1, 0.1, 1.1), uv)), black);
+    }
+}
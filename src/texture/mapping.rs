@@ -1,4 +1,6 @@
-use crate::{Point2f, Vec2f, SurfaceInteraction, Float};
+use cgmath::{EuclideanSpace, InnerSpace};
+
+use crate::{Point2f, Point3f, Vec2f, Vec3f, SurfaceInteraction, Float, Transform, dir_to_equirect_uv};
 use crate::texture::Texture;
 
 #[derive(Copy, Clone)]
@@ -52,3 +54,82 @@ impl Texture for UVMapping {
         }
     }
 }
+
+/// Maps points to the direction from the origin of texture space, using the same equirectangular
+/// parametrization as environment maps, so an image on a large sphere around the origin lines up
+/// with an `InfiniteAreaLight` using that image.
+pub struct SphericalMapping2D {
+    world_to_texture: Transform,
+}
+
+impl SphericalMapping2D {
+    pub fn new(world_to_texture: Transform) -> Self {
+        Self { world_to_texture }
+    }
+
+    fn sphere(&self, p: Point3f) -> Point2f {
+        let w = self.world_to_texture.transform(p).to_vec().normalize();
+        dir_to_equirect_uv(w)
+    }
+
+    /// Finite difference estimate of the change in st, taking the shorter way around the seam
+    /// where u wraps from 1 back to 0.
+    fn st_differential(&self, st: Point2f, p: Point3f, dpd: Vec3f) -> Vec2f {
+        const DELTA: Float = 0.1;
+        let mut dst = self.sphere(p + DELTA * dpd) - st;
+        if dst.x > 0.5 {
+            dst.x -= 1.0;
+        } else if dst.x < -0.5 {
+            dst.x += 1.0;
+        }
+        dst / DELTA
+    }
+}
+
+impl Texture for SphericalMapping2D {
+    type Output = TexCoords;
+
+    fn evaluate(&self, si: &SurfaceInteraction) -> Self::Output {
+        let p = si.hit.p;
+        let st = self.sphere(p);
+        TexCoords {
+            st,
+            dst_dx: self.st_differential(st, p, si.tex_diffs.dpdx),
+            dst_dy: self.st_differential(st, p, si.tex_diffs.dpdy),
+        }
+    }
+}
+
+/// A point in a 3D texture space and its screen-space differentials, for solid textures.
+#[derive(Copy, Clone)]
+pub struct TexCoords3D {
+    pub p: Point3f,
+    pub dpdx: Vec3f,
+    pub dpdy: Vec3f,
+}
+
+pub trait TexCoordsMap3D = Texture<Output = TexCoords3D>;
+
+/// Uses the hit point itself, transformed into texture space, so meshes without uvs can still be
+/// textured.
+pub struct IdentityMapping3D {
+    world_to_texture: Transform,
+}
+
+impl IdentityMapping3D {
+    pub fn new(world_to_texture: Transform) -> Self {
+        Self { world_to_texture }
+    }
+}
+
+impl Texture for IdentityMapping3D {
+    type Output = TexCoords3D;
+
+    fn evaluate(&self, si: &SurfaceInteraction) -> Self::Output {
+        TexCoords3D {
+            p: self.world_to_texture.transform(si.hit.p),
+            dpdx: self.world_to_texture.transform(si.tex_diffs.dpdx),
+            dpdy: self.world_to_texture.transform(si.tex_diffs.dpdy),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+/*!
+Renders a small Cornell box built in code, with red and green side walls and an area light in the
+ceiling, and checks properties of the image that any correct global illumination result has.
+*/
+
+use std::sync::Arc;
+
+use raytracer::{Bounds2f, Float, Point2i, Point3f, Transform, Vec3f};
+use raytracer::bvh::BVH;
+use raytracer::camera::PerspectiveCamera;
+use raytracer::film::Film;
+use raytracer::filter::BoxFilter;
+use raytracer::integrator::{IntegratorRadiance, SamplerIntegrator, TileOrder};
+use raytracer::integrator::path::PathIntegrator;
+use raytracer::material::Material;
+use raytracer::material::matte::MatteMaterial;
+use raytracer::primitive::{GeometricPrimitive, Primitive};
+use raytracer::sampler::random::RandomSampler;
+use raytracer::scene::Scene;
+use raytracer::shapes::triangle::{Triangle, TriangleMesh};
+use raytracer::spectrum::Spectrum;
+
+const RESOLUTION: i32 = 33;
+const LIGHT_RADIANCE: Float = 8.0;
+const LIGHT_HALF_WIDTH: Float = 0.3;
+const LIGHT_HEIGHT: Float = 0.99;
+const WHITE: Float = 0.75;
+
+/// The two triangles of the quad with the given corners, in order around its edge.
+fn quad(corners: [Point3f; 4]) -> impl Iterator<Item=Triangle> {
+    let mesh = Arc::new(TriangleMesh::new(
+        Transform::identity(), vec![0, 1, 2, 0, 2, 3], corners.to_vec(), None, None, None, false
+    ));
+    mesh.iter_triangles()
+}
+
+fn walls(corners: [Point3f; 4], material: &Arc<dyn Material>) -> Vec<Box<dyn Primitive>> {
+    quad(corners)
+        .map(|tri| Box::new(GeometricPrimitive::new(Arc::new(tri), Some(material.clone()))) as Box<dyn Primitive>)
+        .collect()
+}
+
+/// The box spans [-1, 1] on every axis, open towards the camera at z = -1, with the red wall at
+/// x = -1 and the green wall at x = 1.
+fn cornell_box() -> Scene {
+    let white: Arc<dyn Material> = Arc::new(MatteMaterial::constant(Spectrum::uniform(WHITE)));
+    let red: Arc<dyn Material> = Arc::new(MatteMaterial::constant(Spectrum::from([0.63, 0.065, 0.05])));
+    let green: Arc<dyn Material> = Arc::new(MatteMaterial::constant(Spectrum::from([0.14, 0.45, 0.09])));
+    let black: Arc<dyn Material> = Arc::new(MatteMaterial::constant(Spectrum::uniform(0.0)));
+    let p = Point3f::new;
+
+    let mut prims = Vec::new();
+    prims.extend(walls([p(-1.0, -1.0, -1.0), p(1.0, -1.0, -1.0), p(1.0, -1.0, 1.0), p(-1.0, -1.0, 1.0)], &white));
+    prims.extend(walls([p(-1.0, 1.0, -1.0), p(1.0, 1.0, -1.0), p(1.0, 1.0, 1.0), p(-1.0, 1.0, 1.0)], &white));
+    prims.extend(walls([p(-1.0, -1.0, 1.0), p(1.0, -1.0, 1.0), p(1.0, 1.0, 1.0), p(-1.0, 1.0, 1.0)], &white));
+    prims.extend(walls([p(-1.0, -1.0, -1.0), p(-1.0, 1.0, -1.0), p(-1.0, 1.0, 1.0), p(-1.0, -1.0, 1.0)], &red));
+    prims.extend(walls([p(1.0, -1.0, -1.0), p(1.0, 1.0, -1.0), p(1.0, 1.0, 1.0), p(1.0, -1.0, 1.0)], &green));
+
+    // the panel just below the ceiling, wound so that it faces down into the box
+    let (w, h) = (LIGHT_HALF_WIDTH, LIGHT_HEIGHT);
+    for tri in quad([p(-w, h, -w), p(w, h, -w), p(w, h, w), p(-w, h, w)]) {
+        let emitter = GeometricPrimitive::with_area_light(
+            Arc::new(tri), Some(black.clone()), Spectrum::uniform(LIGHT_RADIANCE), 1
+        );
+        prims.push(Box::new(emitter));
+    }
+
+    Scene::new(BVH::build(prims), vec![], vec![])
+}
+
+fn render(scene: &Scene, radiance: impl IntegratorRadiance, spp: usize) -> Vec<Spectrum> {
+    let resolution = Point2i::new(RESOLUTION, RESOLUTION);
+    let camera = PerspectiveCamera::new(
+        Transform::translate(Vec3f::new(0.0, 0.0, -3.0)),
+        resolution,
+        Bounds2f::whole_screen(),
+        (0.0, 1.0),
+        0.0,
+        1.0,
+        50.0
+    );
+    let film = Film::new(resolution, Bounds2f::unit(), BoxFilter::default(), 1.0);
+    let mut integrator = SamplerIntegrator {
+        camera: Box::new(camera),
+        radiance,
+        tile_order: TileOrder::Scanline,
+    };
+    integrator.render_parallel(scene, &film, RandomSampler::new_with_seed(spp, 0), None);
+    film.into_spectrum_buffer().0
+}
+
+fn pixel(img: &[Spectrum], x: i32, y: i32) -> Spectrum {
+    img[(y * RESOLUTION + x) as usize]
+}
+
+/// The mean of the pixels with columns in `xs` and rows in `ys`.
+fn region_mean(img: &[Spectrum], xs: std::ops::Range<i32>, ys: std::ops::Range<i32>) -> Spectrum {
+    let mut sum = Spectrum::uniform(0.0);
+    let mut n = 0;
+    for y in ys {
+        for x in xs.clone() {
+            sum += pixel(img, x, y);
+            n += 1;
+        }
+    }
+    sum / n as Float
+}
+
+#[test]
+fn test_cornell_box_energy_and_color_bleeding() {
+    let scene = cornell_box();
+    let img = render(&scene, PathIntegrator::new(5, 1.0), 64);
+
+    // Every surface reflects less than it receives, so nothing in the box is brighter than the
+    // light. Single pixels can still be noisy, but their mean can't be.
+    for s in &img {
+        for &c in s.into_array().iter() {
+            assert!(c.is_finite() && c >= 0.0, "{:?}", s);
+        }
+    }
+    let mean = img.iter().map(|s| s.luminance()).sum::<Float>() / img.len() as Float;
+    assert!(mean > 0.0 && mean < LIGHT_RADIANCE, "{}", mean);
+
+    // The camera sees the floor along the bottom rows. Light reflected from the side walls tints
+    // the floor next to them, so it is redder on the left than on the right, and greener on the
+    // right than on the left.
+    let floor_rows = RESOLUTION - 5..RESOLUTION;
+    let left = region_mean(&img, 6..RESOLUTION / 2 - 2, floor_rows.clone());
+    let right = region_mean(&img, RESOLUTION / 2 + 3..RESOLUTION - 6, floor_rows);
+    let red_over_green = |s: Spectrum| s[0] / s[1];
+    assert!(red_over_green(left) > 1.1 * red_over_green(right), "{:?} {:?}", left, right);
+    assert!(left[0] > right[0], "{:?} {:?}", left, right);
+    assert!(right[1] > left[1], "{:?} {:?}", left, right);
+}
+
+#[test]
+fn test_cornell_box_center_direct_lighting() {
+    let scene = cornell_box();
+    // with a depth of one the path tracer only finds the direct lighting
+    let img = render(&scene, PathIntegrator::new(1, 1.0), 256);
+    let center = pixel(&img, RESOLUTION / 2, RESOLUTION / 2).luminance();
+
+    // The center pixel sees the middle of the back wall, at (0, 0, 1) facing -z, with nothing
+    // between it and the light. Its radiance is the white albedo over pi times the integral over
+    // the light of L cos(theta_wall) cos(theta_light) / r^2, found here with the midpoint rule.
+    let n = 200;
+    let cell = 2.0 * LIGHT_HALF_WIDTH / n as Float;
+    let mut irradiance = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let x = -LIGHT_HALF_WIDTH + (i as Float + 0.5) * cell;
+            let z = -LIGHT_HALF_WIDTH + (j as Float + 0.5) * cell;
+            let r2 = x * x + LIGHT_HEIGHT * LIGHT_HEIGHT + (1.0 - z) * (1.0 - z);
+            irradiance += LIGHT_RADIANCE * (1.0 - z) * LIGHT_HEIGHT / (r2 * r2) * cell * cell;
+        }
+    }
+    let expected = WHITE / raytracer::consts::PI * irradiance;
+
+    assert!((center - expected).abs() < 0.05 * expected, "{} {}", center, expected);
+}
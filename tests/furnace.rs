@@ -43,11 +43,7 @@ fn furnace_test_path_no_rr() -> anyhow::Result<()> {
 #[test]
 fn furnace_test_directlighting() -> anyhow::Result<()> {
     let (img, (w, h)) =
-        do_render(DirectLightingIntegrator {
-            strategy: LightStrategy::UniformSampleOne,
-            max_depth: 3,
-            n_light_samples: vec![]
-        }, "testscenes/furnace_empty.pbrt")?;
+        do_render(DirectLightingIntegrator::new(LightStrategy::UniformSampleOne, 3), "testscenes/furnace_empty.pbrt")?;
 
     let expected = 1.0 + 0.5;
     for s in img {
@@ -70,12 +66,13 @@ fn do_render(integrator: impl IntegratorRadiance, fname: impl AsRef<Path>) -> an
     let filename = header.film_params.get_one("filename").unwrap_or("render.exr".to_string());
     assert!(filename.contains(".exr"));
 
-    let mut scene_builder = PbrtSceneBuilder::new(env!("CARGO_MANIFEST_DIR").into());
+    let mut scene_builder = PbrtSceneBuilder::new(env!("CARGO_MANIFEST_DIR").into())
+        .with_accelerator(std::mem::take(&mut header.accelerator_params));
     for stmt in parsed.world {
         scene_builder.exec_stmt(stmt)?;
     }
 
-    let scene = scene_builder.create_scene();
+    let scene = scene_builder.create_scene()?;
 
     let camera = header.make_camera()?;
     let sampler = header.make_sampler(None)?;
@@ -83,12 +80,13 @@ fn do_render(integrator: impl IntegratorRadiance, fname: impl AsRef<Path>) -> an
 
     let mut integrator = SamplerIntegrator {
         camera,
-        radiance: integrator
+        radiance: integrator,
+        tile_order: Default::default(),
     };
 
     let parallel = true;
     if parallel {
-        integrator.render_parallel(&scene, &film, sampler);
+        integrator.render_parallel(&scene, &film, sampler, None);
     } else {
         integrator.render(&scene, &film, sampler);
     }
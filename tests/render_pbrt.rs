@@ -0,0 +1,38 @@
+use raytracer::imageio::load_image;
+use raytracer::loaders::pbrt::render_pbrt_file;
+use std::path::Path;
+
+/// Renders `scene` and checks that something in the image was lit.
+fn assert_renders_lit_image(scene: impl AsRef<Path>, out_name: &str) -> anyhow::Result<()> {
+    let out_path = std::env::temp_dir().join(out_name);
+    let _ = std::fs::remove_file(&out_path);
+
+    render_pbrt_file(scene, &out_path)?;
+
+    let (image, dims) = load_image(&out_path)?;
+    assert_eq!(dims, (16, 16));
+    assert!(image.iter().any(|s| !s.is_black()), "Rendered image is all black");
+    std::fs::remove_file(&out_path)?;
+    Ok(())
+}
+
+#[test]
+fn test_render_minimal_scene() -> anyhow::Result<()> {
+    let scene = concat!(env!("CARGO_MANIFEST_DIR"), "/testscenes/minimal.pbrt");
+    assert_renders_lit_image(scene, "fountain_render_minimal.exr")
+}
+
+#[test]
+fn test_render_minimal_scene_sppm() -> anyhow::Result<()> {
+    let src = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/testscenes/minimal.pbrt"))?;
+    let src = src.replace(
+        r#"Integrator "path" "integer maxdepth" [3]"#,
+        r#"Integrator "sppm" "integer numiterations" [4] "float radius" [0.25]"#,
+    );
+    let scene = std::env::temp_dir().join("fountain_render_minimal_sppm.pbrt");
+    std::fs::write(&scene, src)?;
+
+    let result = assert_renders_lit_image(&scene, "fountain_render_minimal_sppm.exr");
+    std::fs::remove_file(&scene)?;
+    result
+}
@@ -50,11 +50,7 @@ fn load_mesh(path: impl AsRef<Path>) -> anyhow::Result<Scene> {
     let prims = mesh.clone().iter_triangles()
         .map(|tri| {
             let tri = Arc::new(tri);
-            let prim = GeometricPrimitive {
-                shape: tri,
-                material: None,
-                light: None
-            };
+            let prim = GeometricPrimitive::new(tri, None);
             Box::new(prim) as Box<dyn Primitive>
         })
         .collect();